@@ -0,0 +1,154 @@
+// A run against a mock YNAB serving the vcr test's recorded responses, & how long YNAB's
+// transactions take to decode, so a change to what's requested or how it's read can be measured.
+// Run with `cargo bench`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use serde_json::Value;
+use std::{env, fs, path::PathBuf};
+use tokio::runtime::Runtime;
+use wiremock::{
+    matchers::{method, path},
+    Mock as MockResponse, MockServer, ResponseTemplate,
+};
+use ynab_updater::{providers::mock::Mock, update_ynab, ynab::TransactionsResponse, Config};
+
+static ACCOUNT: &str = "investments";
+
+// The cassette's first run, which creates a reconciliation, & so does each run against it
+static CASSETTE: &str = "tests/cassettes/ynab_create_then_update.json";
+static RUN_INTERACTIONS: usize = 4;
+
+static CONFIG: &str = r#"
+PUSHOVER_USER_KEY = "user"
+PUSHOVER_API_KEY = "token"
+YNAB_BEARER_TOKEN = "token"
+YNAB_BUDGET_ID = "7a6f1c2e-3b4d-4e5f-8a9b-0c1d2e3f4a5b"
+YNAB_RECONCILIATION_PAYEE_ID = "9e8d7c6b-5a4f-4e3d-2c1b-0a9f8e7d6c5b"
+SNAPSHOT_FLAG_COLOR = "blue"
+NOTIFIER = "pushover"
+# The runs would otherwise wait on the limiter after the first few
+YNAB_RATE_LIMIT = { REQUESTS_PER_HOUR = 100000000 }
+
+[accounts.investments]
+PROVIDER = "mock"
+YNAB_ACCOUNT_ID = "1b2c3d4e-5f6a-4b7c-8d9e-0f1a2b3c4d5e"
+BALANCE = 1250.0
+"#;
+
+fn interactions() -> Vec<Value> {
+    let cassette = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join(CASSETTE);
+
+    serde_json::from_str::<Value>(&fs::read_to_string(cassette).unwrap()).unwrap()["interactions"]
+        .as_array()
+        .unwrap()
+        .clone()
+}
+
+// Serves each of the run's requests with its recorded response
+async fn mock_ynab() -> MockServer {
+    let server = MockServer::start().await;
+
+    for interaction in interactions().iter().take(RUN_INTERACTIONS) {
+        let url = reqwest::Url::parse(interaction["url"].as_str().unwrap()).unwrap();
+
+        MockResponse::given(method(interaction["method"].as_str().unwrap()))
+            .and(path(url.path()))
+            .respond_with(
+                ResponseTemplate::new(interaction["status"].as_u64().unwrap() as u16).set_body_raw(
+                    interaction["body"].as_str().unwrap(),
+                    interaction["content_type"].as_str().unwrap(),
+                ),
+            )
+            .mount(&server)
+            .await;
+    }
+
+    server
+}
+
+fn config(api_url: &str) -> Config {
+    let mut config = config::Config::builder()
+        .add_source(config::File::from_str(CONFIG, config::FileFormat::Toml))
+        .build()
+        .unwrap()
+        .try_deserialize::<Config>()
+        .unwrap();
+
+    let config_path = env::temp_dir().join(format!("ynab-updater-bench-{}", std::process::id()));
+    fs::create_dir_all(&config_path).unwrap();
+
+    config.config_path = config_path.to_str().unwrap().to_owned();
+    config.ynab_api_url = Some(format!("{}/v1", api_url));
+
+    config
+}
+
+async fn run(config: &Config) {
+    let account_config = &config.accounts[ACCOUNT];
+
+    update_ynab(config, ACCOUNT, Mock::new(account_config).unwrap())
+        .await
+        .unwrap();
+}
+
+fn update_ynab_run(c: &mut Criterion) {
+    let runtime = Runtime::new().unwrap();
+    let server = runtime.block_on(mock_ynab());
+    let config = config(&server.uri());
+
+    // What each run costs YNAB's rate limit
+    runtime.block_on(run(&config));
+    let requests = runtime
+        .block_on(server.received_requests())
+        .unwrap_or_default()
+        .len();
+    println!("update_ynab sends {} requests a run", requests);
+
+    c.bench_function("update_ynab", |b| {
+        b.to_async(&runtime).iter(|| run(&config))
+    });
+
+    fs::remove_dir_all(&config.config_path).unwrap();
+}
+
+// The account's recorded transactions, repeated up to `count`, as YNAB would send them
+fn transactions_body(count: usize) -> String {
+    let interactions = interactions();
+    let mut response = serde_json::from_str::<Value>(
+        interactions
+            .iter()
+            .find(|interaction| {
+                interaction["url"]
+                    .as_str()
+                    .unwrap()
+                    .ends_with("/transactions")
+            })
+            .unwrap()["body"]
+            .as_str()
+            .unwrap(),
+    )
+    .unwrap();
+
+    let recorded = response["data"]["transactions"].as_array().unwrap().clone();
+    response["data"]["transactions"] =
+        Value::Array(recorded.iter().cycle().take(count).cloned().collect());
+
+    response.to_string()
+}
+
+fn decode_transactions(c: &mut Criterion) {
+    let mut group = c.benchmark_group("decode_transactions");
+
+    for count in [10, 1000, 10000] {
+        let body = transactions_body(count);
+
+        group.bench_with_input(BenchmarkId::from_parameter(count), &body, |b, body| {
+            b.iter(|| serde_json::from_str::<TransactionsResponse>(body).unwrap())
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, update_ynab_run, decode_transactions);
+criterion_main!(benches);
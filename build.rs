@@ -0,0 +1,265 @@
+// Generates `ynab`'s types from YNAB's OpenAPI spec, each schema under `components` as a struct,
+// an enum of its strings or an alias, named as it is in the spec. An inline schema is named after
+// the schema & property it's in, e.g. `AccountResponseData`. `allOf` is flattened into the one
+// struct, with a `From` to each schema it's made of.
+
+use serde_yaml::{Mapping, Value};
+use std::{collections::BTreeSet, env, fs, path::Path};
+
+static SPEC: &str = "src/ynab/open_api_spec.yaml";
+
+static KEYWORDS: [&str; 8] = ["type", "ref", "match", "mod", "use", "impl", "fn", "self"];
+
+struct Field<'a> {
+    name: &'a str,
+    schema: &'a Value,
+    // The schema it's declared in, which its inline types are named after
+    owner: String,
+    required: bool,
+}
+
+struct Generator<'a> {
+    schemas: &'a Mapping,
+    generated: BTreeSet<String>,
+    out: String,
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed={}", SPEC);
+
+    let spec = serde_yaml::from_str::<Value>(&fs::read_to_string(SPEC).unwrap()).unwrap();
+    let schemas = spec["components"]["schemas"]
+        .as_mapping()
+        .expect("The spec has no components.schemas");
+
+    let mut generator = Generator {
+        schemas,
+        generated: BTreeSet::new(),
+        out: format!("// Generated by build.rs from {}, don't edit\n", SPEC),
+    };
+
+    for (name, schema) in schemas {
+        generator.schema(name.as_str().unwrap(), schema);
+    }
+
+    fs::write(
+        Path::new(&env::var("OUT_DIR").unwrap()).join("ynab_types.rs"),
+        generator.out,
+    )
+    .unwrap();
+}
+
+// e.g. `otherAsset` to `OtherAsset`, `TB` to `Tb` & `date_format` to `DateFormat`
+fn pascal_case(name: &str) -> String {
+    name.split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|part| !part.is_empty())
+        .map(|part| {
+            let part = match part.chars().all(|c| !c.is_ascii_lowercase()) {
+                true => part.to_ascii_lowercase(),
+                false => part.to_owned(),
+            };
+            part[..1].to_ascii_uppercase() + &part[1..]
+        })
+        .collect()
+}
+
+fn field_name(name: &str) -> String {
+    match KEYWORDS.contains(&name) {
+        true => format!("r#{}", name),
+        false => name.to_owned(),
+    }
+}
+
+fn docs(schema: &Value, indent: &str) -> String {
+    schema["description"]
+        .as_str()
+        .map(|description| {
+            description
+                .lines()
+                .map(|line| format!("{}/// {}\n", indent, line.trim_end()))
+                .collect()
+        })
+        .unwrap_or_default()
+}
+
+fn ref_name(schema: &Value) -> Option<&str> {
+    schema["$ref"]
+        .as_str()
+        .map(|reference| reference.rsplit('/').next().unwrap())
+}
+
+impl<'a> Generator<'a> {
+    fn resolve(&self, schema: &'a Value) -> &'a Value {
+        match ref_name(schema) {
+            Some(name) => self
+                .schemas
+                .get(name)
+                .unwrap_or_else(|| panic!("No schema {}", name)),
+            None => schema,
+        }
+    }
+
+    fn nullable(&self, schema: &'a Value) -> bool {
+        self.resolve(schema)["nullable"].as_bool().unwrap_or(false)
+    }
+
+    fn schema(&mut self, name: &str, schema: &'a Value) {
+        if !self.generated.insert(name.to_owned()) {
+            return;
+        }
+
+        if schema["enum"].is_sequence() {
+            self.enumeration(name, schema);
+        } else if schema["allOf"].is_sequence() || schema["properties"].is_mapping() {
+            self.structure(name, schema);
+        } else {
+            let rust_type = self.rust_type(name, "", schema);
+            self.out += &format!("\n{}pub type {} = {};\n", docs(schema, ""), name, rust_type);
+        }
+    }
+
+    // The schema's properties, with those of each schema it's `allOf`
+    fn fields(&self, owner: &str, schema: &'a Value, bases: &mut Vec<String>) -> Vec<Field<'a>> {
+        let mut fields = vec![];
+
+        for part in schema["allOf"].as_sequence().into_iter().flatten() {
+            match ref_name(part) {
+                Some(name) => {
+                    bases.push(name.to_owned());
+                    fields.extend(self.fields(name, self.resolve(part), &mut vec![]));
+                }
+                None => fields.extend(self.fields(owner, part, bases)),
+            }
+        }
+
+        let required = schema["required"]
+            .as_sequence()
+            .into_iter()
+            .flatten()
+            .filter_map(|name| name.as_str())
+            .collect::<Vec<_>>();
+
+        for (name, property) in schema["properties"].as_mapping().into_iter().flatten() {
+            let name = name.as_str().unwrap();
+
+            fields.push(Field {
+                name,
+                schema: property,
+                owner: owner.to_owned(),
+                required: required.contains(&name),
+            });
+        }
+
+        fields
+    }
+
+    fn rust_type(&mut self, owner: &str, property: &str, schema: &'a Value) -> String {
+        if let Some(name) = ref_name(schema) {
+            return name.to_owned();
+        }
+
+        let inline_name = || format!("{}{}", owner, pascal_case(property));
+
+        if schema["enum"].is_sequence() || schema["properties"].is_mapping() {
+            let name = inline_name();
+            self.schema(&name, schema);
+            return name;
+        }
+
+        match (schema["type"].as_str(), schema["format"].as_str()) {
+            (Some("string"), Some("date")) => "chrono::NaiveDate".to_owned(),
+            (Some("string"), _) => "String".to_owned(),
+            (Some("integer"), Some("int32")) => "i32".to_owned(),
+            (Some("integer"), _) => "i64".to_owned(),
+            (Some("number"), _) => "f64".to_owned(),
+            (Some("boolean"), _) => "bool".to_owned(),
+            (Some("array"), _) => format!(
+                "Vec<{}>",
+                self.rust_type(owner, &format!("{}_item", property), &schema["items"])
+            ),
+            (Some("object"), _) if schema["additionalProperties"].is_mapping() => format!(
+                "std::collections::BTreeMap<String, {}>",
+                self.rust_type(
+                    owner,
+                    &format!("{}_value", property),
+                    &schema["additionalProperties"]
+                )
+            ),
+            _ => "serde_json::Value".to_owned(),
+        }
+    }
+
+    fn structure(&mut self, name: &str, schema: &'a Value) {
+        let mut bases = vec![];
+        let fields = self.fields(name, schema, &mut bases);
+
+        let mut body = String::new();
+        for field in &fields {
+            let rust_type = self.rust_type(&field.owner, field.name, field.schema);
+
+            body += &docs(field.schema, "    ");
+            if field.required && !self.nullable(field.schema) {
+                body += &format!("    pub {}: {},\n", field_name(field.name), rust_type);
+            } else {
+                body += "    #[serde(default, skip_serializing_if = \"Option::is_none\")]\n";
+                body += &format!(
+                    "    pub {}: Option<{}>,\n",
+                    field_name(field.name),
+                    rust_type
+                );
+            }
+        }
+
+        self.out += &format!(
+            "\n{}#[derive(Clone, Debug, Default, PartialEq, serde::Serialize, serde::Deserialize)]\n\
+             pub struct {} {{\n{}    \
+             // What the spec doesn't have, so nothing's lost when YNAB adds a field\n    \
+             #[serde(flatten)]\n    \
+             pub other: serde_json::Map<String, serde_json::Value>,\n}}\n",
+            docs(schema, ""),
+            name,
+            body
+        );
+
+        for base in bases {
+            let base_fields = self
+                .fields(&base, &self.schemas[base.as_str()], &mut vec![])
+                .iter()
+                .map(|field| format!("            {0}: value.{0},\n", field_name(field.name)))
+                .collect::<String>();
+
+            self.out += &format!(
+                "\nimpl From<{name}> for {base} {{\n    \
+                 fn from(value: {name}) -> Self {{\n        \
+                 {base} {{\n{base_fields}            \
+                 other: value.other,\n        }}\n    }}\n}}\n"
+            );
+        }
+    }
+
+    fn enumeration(&mut self, name: &str, schema: &'a Value) {
+        let variants = schema["enum"]
+            .as_sequence()
+            .unwrap()
+            .iter()
+            .filter_map(|value| value.as_str())
+            .enumerate()
+            .map(|(i, value)| {
+                format!(
+                    "{}    #[serde(rename = \"{}\")]\n    {},\n",
+                    if i == 0 { "    #[default]\n" } else { "" },
+                    value,
+                    pascal_case(value)
+                )
+            })
+            .collect::<String>();
+
+        self.out += &format!(
+            "\n{}#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]\n\
+             pub enum {} {{\n{}}}\n",
+            docs(schema, ""),
+            name,
+            variants
+        );
+    }
+}
@@ -33,6 +33,8 @@
 
         cargoLock.lockFile = ./Cargo.lock;
 
+        buildFeatures = [ "journald" ];
+
         nativeBuildInputs = [ pkgs.pkg-config ];
 
         buildInputs = [ pkgs.openssl ];
@@ -53,8 +55,21 @@
           YNAB_CONFIG_PATH=''${YNAB_CONFIG_PATH:-/home/james/dev/my/ynab_updater} \
           ${ynab-updater}/bin/saxo
         '';
+        ynab-updater = writeShellScriptBin "ynab-updater" ''
+          RUST_LOG=info \
+          RUST_BACKTRACE=1 \
+          YNAB_TAILSCALE_IP=$(${pkgs.tailscale}/bin/tailscale ip --4) \
+          YNAB_CONFIG_PATH=''${YNAB_CONFIG_PATH:-/home/james/dev/my/ynab_updater} \
+          exec ${ynab-updater}/bin/ynab-updater "$@"
+        '';
       };
 
+      # Catches a selector change that breaks parsing HL's checked-in pages, without logging in
+      checks.${system}.hl-fixtures = runCommand "hl-fixtures" { } ''
+        ${ynab-updater}/bin/ynab-updater scrape-test hl
+        touch $out
+      '';
+
       devShell.${system} = mkShell {
         buildInputs = [
           rust-analyzer
@@ -69,6 +84,7 @@
         with lib; with lib.types;
         let
           cfg = config.programs.ynab-updater;
+          loadCredentials = mapAttrsToList (name: path: "${name}:${path}") cfg.credentials;
         in
         {
           options.programs.ynab-updater = {
@@ -77,49 +93,138 @@
               type = types.str;
               description = lib.mdDoc "The directory of the config file & cache.";
             };
+            profiles = mkOption {
+              type = attrsOf str;
+              default = { };
+              example = { daily = "24h"; weekly = "7d"; };
+              description = lib.mdDoc "How often to run each profile defined in the config file.";
+            };
+            daemon = mkEnableOption "Run the profiles & accounts scheduled in the config file from one long-running service.";
+            credentials = mkOption {
+              type = attrsOf str;
+              default = { };
+              example = { ynab-token = "/run/keys/ynab-token"; };
+              description = lib.mdDoc "Files loaded as systemd credentials, which the config refers to as e.g. `YNAB_BEARER_TOKEN = \"credential:ynab-token\"`.";
+            };
+            journald = mkEnableOption "Log to journald with each run's PROVIDER, ACCOUNT & AMOUNT fields, e.g. for `journalctl --user PROVIDER=saxo`.";
           };
 
-          config = mkIf cfg.enable {
-            systemd.user.timers."ynab-updater-hl" = {
-              wantedBy = [ "timers.target" ];
-              timerConfig = {
-                OnBootSec = "10s";
-                OnUnitActiveSec = "24h";
-                Unit = "ynab-updater-hl.service";
-              };
-            };
-            systemd.user.services."ynab-updater-hl" = {
-              environment = {
-                RUST_LOG = "info";
-                YNAB_CONFIG_PATH = cfg.configDir;
+          config = mkIf cfg.enable (mkMerge [
+            # The daemon runs the accounts itself, so these would run them twice
+            (mkIf (!cfg.daemon) {
+              systemd.user.timers."ynab-updater-hl" = {
+                wantedBy = [ "timers.target" ];
+                timerConfig = {
+                  OnBootSec = "10s";
+                  OnUnitActiveSec = "24h";
+                  Unit = "ynab-updater-hl.service";
+                };
               };
-              serviceConfig = {
-                Type = "oneshot";
-                ExecStart = "${self.packages.${system}.hl}/bin/hl";
+              systemd.user.services."ynab-updater-hl" = {
+                onFailure = [ "ynab-updater-notify-failure@%n.service" ];
+                environment = {
+                  RUST_LOG = "info";
+                  YNAB_LOG = mkIf cfg.journald "journald";
+                  YNAB_CONFIG_PATH = cfg.configDir;
+                };
+                serviceConfig = {
+                  Type = "oneshot";
+                  LoadCredential = loadCredentials;
+                  ExecStart = "${self.packages.${system}.hl}/bin/hl";
+                };
               };
-            };
 
-            systemd.user.timers."ynab-updater-saxo" = {
-              wantedBy = [ "timers.target" ];
-              timerConfig = {
-                OnBootSec = "10s";
-                # 55m since the refresh_token duration is 1h
-                # - so we want to refresh it before it expires
-                OnUnitActiveSec = "55m";
-                Unit = "ynab-updater-saxo.service";
+              systemd.user.timers."ynab-updater-saxo" = {
+                wantedBy = [ "timers.target" ];
+                timerConfig = {
+                  OnBootSec = "10s";
+                  # 55m since the refresh_token duration is 1h
+                  # - so we want to refresh it before it expires
+                  OnUnitActiveSec = "55m";
+                  Unit = "ynab-updater-saxo.service";
+                };
               };
-            };
-            systemd.user.services."ynab-updater-saxo" = {
-              environment = {
-                RUST_LOG = "info";
-                YNAB_CONFIG_PATH = cfg.configDir;
+              systemd.user.services."ynab-updater-saxo" = {
+                onFailure = [ "ynab-updater-notify-failure@%n.service" ];
+                environment = {
+                  RUST_LOG = "info";
+                  YNAB_LOG = mkIf cfg.journald "journald";
+                  YNAB_CONFIG_PATH = cfg.configDir;
+                };
+                serviceConfig = {
+                  Type = "oneshot";
+                  LoadCredential = loadCredentials;
+                  ExecStart = "${self.packages.${system}.saxo}/bin/saxo";
+                };
               };
-              serviceConfig = {
-                Type = "oneshot";
-                ExecStart = "${self.packages.${system}.saxo}/bin/saxo";
+            })
+            {
+              # Started by the units' `OnFailure=` with the failed unit's name, to say why it failed
+              systemd.user.services."ynab-updater-notify-failure@" = {
+                environment = {
+                  RUST_LOG = "info";
+                  YNAB_LOG = mkIf cfg.journald "journald";
+                  YNAB_CONFIG_PATH = cfg.configDir;
+                };
+                serviceConfig = {
+                  Type = "oneshot";
+                  LoadCredential = loadCredentials;
+                  ExecStart = "${self.packages.${system}.ynab-updater}/bin/ynab-updater notify-failure %i";
+                };
               };
-            };
-          };
+            }
+            {
+              systemd.user.timers = mapAttrs'
+                (profile: interval: nameValuePair "ynab-updater-profile-${profile}" {
+                  wantedBy = [ "timers.target" ];
+                  timerConfig = {
+                    OnBootSec = "10s";
+                    OnUnitActiveSec = interval;
+                    Unit = "ynab-updater-profile-${profile}.service";
+                  };
+                })
+                cfg.profiles;
+              systemd.user.services = mapAttrs'
+                (profile: _: nameValuePair "ynab-updater-profile-${profile}" {
+                  onFailure = [ "ynab-updater-notify-failure@%n.service" ];
+                  environment = {
+                    RUST_LOG = "info";
+                    YNAB_LOG = mkIf cfg.journald "journald";
+                    YNAB_CONFIG_PATH = cfg.configDir;
+                  };
+                  serviceConfig = {
+                    Type = "oneshot";
+                    LoadCredential = loadCredentials;
+                    ExecStart = "${self.packages.${system}.ynab-updater}/bin/ynab-updater run --profile ${profile}";
+                  };
+                })
+                cfg.profiles;
+            }
+            (mkIf cfg.daemon {
+              systemd.user.services."ynab-updater-daemon" = {
+                wantedBy = [ "default.target" ];
+                onFailure = [ "ynab-updater-notify-failure@%n.service" ];
+                environment = {
+                  RUST_LOG = "info";
+                  YNAB_LOG = mkIf cfg.journald "journald";
+                  YNAB_CONFIG_PATH = cfg.configDir;
+                };
+                serviceConfig = {
+                  Type = "notify";
+                  # The package's wrapper script execs the daemon, but it's allowed to notify anyway
+                  NotifyAccess = "all";
+                  LoadCredential = loadCredentials;
+                  # Holds the secrets with `SECRETS = "fd_store"`, kept across restarts
+                  FileDescriptorStoreMax = 64;
+                  FileDescriptorStorePreserve = "restart";
+                  ExecStart = "${self.packages.${system}.ynab-updater}/bin/ynab-updater daemon --shutdown-timeout-secs 60";
+                  # Leaves the daemon time to finish in-flight runs after SIGTERM
+                  TimeoutStopSec = "90s";
+                  Restart = "on-failure";
+                };
+              };
+            })
+          ]);
         };
     };
 }
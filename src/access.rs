@@ -0,0 +1,47 @@
+use ipnet::IpNet;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use std::net::IpAddr;
+
+// Who may use a listener, so an exposed port isn't an open door: only clients from the allowed
+// ranges, e.g. the tailnet's `100.64.0.0/10`, and only with the secret as the URLs' first path
+// segment. Either is skipped when it isn't configured.
+#[derive(Clone, Debug, Default, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct AccessConfig {
+    #[serde(default)]
+    pub allowed_cidrs: Vec<IpNet>,
+    pub path_secret: Option<SecretString>,
+}
+
+impl AccessConfig {
+    pub fn allows(&self, ip: IpAddr) -> bool {
+        // An IPv4 client of a dual-stack listener shows up as IPv4-mapped IPv6
+        let ip = match ip {
+            IpAddr::V6(ip) => ip.to_ipv4_mapped().map_or(IpAddr::V6(ip), IpAddr::V4),
+            ip => ip,
+        };
+
+        self.allowed_cidrs.is_empty() || self.allowed_cidrs.iter().any(|net| net.contains(&ip))
+    }
+
+    // Prepended to the listener's paths
+    pub fn path_prefix(&self) -> String {
+        match &self.path_secret {
+            Some(path_secret) => format!("/{}", path_secret.expose_secret()),
+            None => String::new(),
+        }
+    }
+
+    // The path without the secret, or `None` if it doesn't start with it
+    pub fn strip_path_secret<'a>(&self, path: &'a str) -> Option<&'a str> {
+        match &self.path_secret {
+            Some(_) => match path.strip_prefix(&self.path_prefix())? {
+                "" => Some("/"),
+                path if path.starts_with('/') => Some(path),
+                _ => None,
+            },
+            None => Some(path),
+        }
+    }
+}
@@ -0,0 +1,370 @@
+// Actual Budget, through actual-http-api (https://github.com/jhonderson/actual-http-api), which
+// serves Actual's own Node API over HTTP since its sync protocol isn't meant to be spoken by
+// anything else. Its accounts & transactions are translated to & from YNAB's, so they go through
+// the same reconciling.
+
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use reqwest::{Method, StatusCode};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+
+use crate::{
+    budget::BudgetSink,
+    http,
+    ynab::{
+        Account, BudgetSettings, ExistingTransaction, NewTransaction,
+        SaveTransactionWithIdOrImportId, SaveTransactionWithOptionalFields,
+        TransactionClearedStatus, TransactionDetail,
+    },
+    Config,
+};
+
+// e.g. `[actual] URL = "http://localhost:5007"`, with the API_KEY it's run with & the budget's
+// sync ID from Actual's advanced settings
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct ActualConfig {
+    pub url: String,
+    pub api_key: SecretString,
+    pub budget_sync_id: String,
+    // For an end-to-end encrypted budget
+    pub encryption_password: Option<SecretString>,
+    // Actual's payee IDs are its own, so this is YNAB_RECONCILIATION_PAYEE_ID's counterpart
+    pub reconciliation_payee_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Response<T> {
+    data: T,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ActualAccount {
+    id: String,
+    name: String,
+    #[serde(default)]
+    closed: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ActualTransaction {
+    id: String,
+    date: NaiveDate,
+    // Hundredths
+    amount: i64,
+    #[serde(default)]
+    payee: Option<String>,
+    #[serde(default)]
+    imported_id: Option<String>,
+    #[serde(default)]
+    cleared: bool,
+    #[serde(default)]
+    reconciled: bool,
+    #[serde(flatten)]
+    other: Map<String, Value>,
+}
+
+impl From<ActualTransaction> for TransactionDetail {
+    fn from(transaction: ActualTransaction) -> Self {
+        TransactionDetail {
+            id: transaction.id,
+            date: transaction.date,
+            amount: transaction.amount * 10,
+            payee_id: transaction.payee,
+            import_id: transaction.imported_id,
+            cleared: match (transaction.reconciled, transaction.cleared) {
+                (true, _) => TransactionClearedStatus::Reconciled,
+                (false, true) => TransactionClearedStatus::Cleared,
+                (false, false) => TransactionClearedStatus::Uncleared,
+            },
+            other: transaction.other,
+            ..Default::default()
+        }
+    }
+}
+
+// Only the fields that are set, as YNAB's are. Approval & flags have no counterpart in Actual.
+fn to_actual(transaction: SaveTransactionWithOptionalFields) -> Map<String, Value> {
+    let mut fields = Map::new();
+
+    if let Some(account_id) = &transaction.account_id {
+        fields.insert("account".to_owned(), json!(account_id));
+    }
+    if let Some(date) = transaction.date {
+        fields.insert("date".to_owned(), json!(date.to_string()));
+    }
+    if let Some(amount) = transaction.amount {
+        fields.insert(
+            "amount".to_owned(),
+            json!((amount as f64 / 10.0).round() as i64),
+        );
+    }
+    match (&transaction.payee_id, &transaction.payee_name) {
+        (Some(payee_id), _) => {
+            fields.insert("payee".to_owned(), json!(payee_id));
+        }
+        (None, Some(payee_name)) => {
+            fields.insert("payee_name".to_owned(), json!(payee_name));
+        }
+        (None, None) => {}
+    }
+    if let Some(category_id) = &transaction.category_id {
+        fields.insert("category".to_owned(), json!(category_id));
+    }
+    if let Some(memo) = &transaction.memo {
+        fields.insert("notes".to_owned(), json!(memo));
+    }
+    if let Some(cleared) = transaction.cleared {
+        fields.insert(
+            "cleared".to_owned(),
+            json!(cleared != TransactionClearedStatus::Uncleared),
+        );
+        fields.insert(
+            "reconciled".to_owned(),
+            json!(cleared == TransactionClearedStatus::Reconciled),
+        );
+    }
+    if let Some(subtransactions) = &transaction.subtransactions {
+        fields.insert(
+            "subtransactions".to_owned(),
+            subtransactions
+                .iter()
+                .map(|subtransaction| {
+                    json!({
+                        "amount": (subtransaction.amount as f64 / 10.0).round() as i64,
+                        "category": subtransaction.category_id,
+                        "notes": subtransaction.memo,
+                    })
+                })
+                .collect(),
+        );
+    }
+
+    fields
+}
+
+#[derive(Clone, Debug)]
+pub struct ActualClient {
+    client: reqwest::Client,
+    config: ActualConfig,
+}
+
+impl ActualClient {
+    pub fn new(config: &Config) -> Result<Self> {
+        Ok(ActualClient {
+            client: http::client(&config.http)?,
+            config: config
+                .actual
+                .clone()
+                .ok_or_else(|| anyhow!("An account's BUDGET is actual, but [actual] isn't set"))?,
+        })
+    }
+
+    pub fn reconciliation_payee_id(&self) -> &str {
+        &self.config.reconciliation_payee_id
+    }
+
+    fn request(&self, method: Method, path: String) -> reqwest::RequestBuilder {
+        let mut request = self
+            .client
+            .request(
+                method,
+                format!(
+                    "{}/v1/budgets/{}{}",
+                    self.config.url.trim_end_matches('/'),
+                    self.config.budget_sync_id,
+                    path
+                ),
+            )
+            .header("x-api-key", self.config.api_key.expose_secret());
+
+        if let Some(encryption_password) = &self.config.encryption_password {
+            request = request.header(
+                "budget-encryption-password",
+                encryption_password.expose_secret(),
+            );
+        }
+
+        request
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        error_for_status(http::send(&self.client, request).await?).await
+    }
+
+    async fn patch(&self, transaction_id: &str, fields: Map<String, Value>) -> Result<()> {
+        self.send(
+            self.request(Method::PATCH, format!("/transactions/{}", transaction_id))
+                .json(&json!({ "transaction": fields })),
+        )
+        .await?;
+
+        Ok(())
+    }
+}
+
+// With the body, which says what was wrong
+async fn error_for_status(response: reqwest::Response) -> Result<reqwest::Response> {
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Actual responded {}: {}",
+            response.status(),
+            response.text().await?
+        ));
+    }
+
+    Ok(response)
+}
+
+impl BudgetSink for ActualClient {
+    // Actual only has the balance, so the cleared one is added up from the transactions
+    async fn get_account(&self, account_id: &str) -> Result<Option<Account>> {
+        let response = http::send(
+            &self.client,
+            self.request(Method::GET, format!("/accounts/{}", account_id)),
+        )
+        .await?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let account = error_for_status(response)
+            .await?
+            .json::<Response<ActualAccount>>()
+            .await?
+            .data;
+
+        let transactions = self.get_transactions(account_id, None).await?;
+
+        let balance = transactions.iter().map(|t| t.amount).sum::<i64>();
+        let cleared_balance = transactions
+            .iter()
+            .filter(|t| t.cleared != TransactionClearedStatus::Uncleared)
+            .map(|t| t.amount)
+            .sum::<i64>();
+
+        Ok(Some(Account {
+            id: account.id,
+            name: account.name,
+            balance,
+            cleared_balance,
+            uncleared_balance: balance - cleared_balance,
+            closed: account.closed,
+            ..Default::default()
+        }))
+    }
+
+    // Actual's API doesn't say what currency a budget's in
+    async fn get_budget_settings(&self) -> Result<BudgetSettings> {
+        Ok(BudgetSettings::default())
+    }
+
+    async fn get_transactions(
+        &self,
+        account_id: &str,
+        since_date: Option<NaiveDate>,
+    ) -> Result<Vec<TransactionDetail>> {
+        // It's required, so the beginning of time stands in for all of them
+        let mut transactions = self
+            .send(
+                self.request(
+                    Method::GET,
+                    format!("/accounts/{}/transactions", account_id),
+                )
+                .query(&[("since_date", since_date.unwrap_or_default().to_string())]),
+            )
+            .await?
+            .json::<Response<Vec<ActualTransaction>>>()
+            .await?
+            .data
+            .into_iter()
+            .map(TransactionDetail::from)
+            .collect::<Vec<_>>();
+
+        // YNAB's are oldest first, which the last reconciliation is found by
+        transactions.sort_by_key(|t| t.date);
+
+        Ok(transactions)
+    }
+
+    async fn update_transaction(
+        &self,
+        transaction_id: &str,
+        transaction: &ExistingTransaction,
+    ) -> Result<()> {
+        self.patch(transaction_id, to_actual(transaction.clone().into()))
+            .await
+    }
+
+    // One at a time, since Actual has no batch update
+    async fn update_transactions(
+        &self,
+        transactions: &[SaveTransactionWithIdOrImportId],
+    ) -> Result<()> {
+        for transaction in transactions {
+            let transaction_id = transaction
+                .id
+                .clone()
+                .ok_or_else(|| anyhow!("An Actual transaction is only updated by its id"))?;
+
+            let fields = to_actual(transaction.clone().into());
+            if fields.is_empty() {
+                continue;
+            }
+
+            self.patch(&transaction_id, fields).await?;
+        }
+
+        Ok(())
+    }
+
+    // Actual doesn't return the new transaction's ID, so it's found by its import_id
+    async fn create_transaction(&self, transaction: &NewTransaction) -> Result<String> {
+        let (account_id, date, import_id) = match transaction {
+            NewTransaction {
+                account_id: Some(account_id),
+                date: Some(date),
+                import_id: Some(import_id),
+                ..
+            } => (account_id, *date, import_id),
+            _ => {
+                return Err(anyhow!(
+                    "An Actual transaction needs an account, date & import_id"
+                ))
+            }
+        };
+
+        let mut fields = to_actual(transaction.clone().into());
+        fields.insert("imported_id".to_owned(), json!(import_id));
+
+        self.send(
+            self.request(
+                Method::POST,
+                format!("/accounts/{}/transactions", account_id),
+            )
+            .json(&json!({
+                "learnCategories": false,
+                "runTransfers": false,
+                "transaction": fields,
+            })),
+        )
+        .await?;
+
+        self.get_transactions(account_id, Some(date))
+            .await?
+            .into_iter()
+            .find(|t| t.import_id.as_deref() == Some(import_id.as_str()))
+            .map(|t| t.id)
+            .ok_or_else(|| anyhow!("Actual didn't save the created transaction"))
+    }
+
+    async fn delete_transaction(&self, transaction_id: &str) -> Result<()> {
+        self.send(self.request(Method::DELETE, format!("/transactions/{}", transaction_id)))
+            .await?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::{anyhow, Result};
+
+// An amount as a site shows it, in whichever locale's format: `1,234.56`, `1.234,56`, `1 234,56`
+// or `1'234.56`, with or without a currency, e.g. `£1,234.56` or `1.234,56 EUR`. It's negative
+// for `-12.30`, `−12.30`, `12.30-`, `(12.30)` or a trailing `DR`.
+//
+// Without the decimal separator given, it's the last of `.` & `,` when both are there, or the only
+// one when it's there once & not followed by exactly three digits. `1,234` & `1.234` are taken to
+// be thousands, as they would be on a balance.
+pub fn parse(text: &str) -> Result<f32> {
+    parse_with(text, None)
+}
+
+pub fn parse_with(text: &str, decimal_separator: Option<char>) -> Result<f32> {
+    let trimmed = text.trim();
+
+    let is_negative = (trimmed.starts_with('(') && trimmed.ends_with(')'))
+        || trimmed.ends_with('-')
+        || trimmed.to_uppercase().ends_with("DR")
+        || trimmed
+            .chars()
+            .take_while(|c| !c.is_ascii_digit())
+            .any(|c| c == '-' || c == '−');
+
+    // Only the digits & the separators between them, dropping currencies & group separators
+    // that aren't `.` or `,`, e.g. spaces, non-breaking spaces & apostrophes
+    let number = trimmed
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit())
+        .filter(|c| c.is_ascii_digit() || *c == '.' || *c == ',')
+        .collect::<String>();
+    let number = number.trim_end_matches(['.', ',']);
+
+    if number.is_empty() {
+        return Err(anyhow!("{:?} isn't an amount", text));
+    }
+
+    let decimal_separator =
+        decimal_separator.or_else(|| match (number.rfind('.'), number.rfind(',')) {
+            (Some(dot), Some(comma)) => Some(if dot > comma { '.' } else { ',' }),
+            (Some(_), None) => single_separator(number, '.'),
+            (None, Some(_)) => single_separator(number, ','),
+            (None, None) => None,
+        });
+
+    let number = number
+        .chars()
+        .filter_map(|c| match c {
+            c if Some(c) == decimal_separator => Some('.'),
+            '.' | ',' => None,
+            c => Some(c),
+        })
+        .collect::<String>();
+
+    let amount = number
+        .parse::<f32>()
+        .map_err(|_| anyhow!("{:?} isn't an amount", text))?;
+
+    Ok(if is_negative { -amount } else { amount })
+}
+
+// Whether a separator that's the only kind there is the decimal one
+fn single_separator(number: &str, separator: char) -> Option<char> {
+    let mut parts = number.split(separator);
+    let _whole = parts.next();
+
+    match (parts.next(), parts.next()) {
+        (Some(fraction), None) if fraction.len() != 3 => Some(separator),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_parses(cases: &[(&str, f32)]) {
+        for (text, expected) in cases {
+            assert_eq!(parse(text).unwrap(), *expected, "{:?}", text);
+        }
+    }
+
+    #[test]
+    fn parses_each_locales_separators() {
+        assert_parses(&[
+            ("1234.56", 1234.56),
+            ("1,234.56", 1234.56),
+            ("1.234,56", 1234.56),
+            ("1\u{a0}234,56", 1234.56),
+            ("1 234,56", 1234.56),
+            ("1'234.56", 1234.56),
+            ("1,234,567.89", 1_234_567.9),
+            ("1.234.567,89", 1_234_567.9),
+            ("12.30", 12.3),
+            ("12,30", 12.3),
+            ("0,5", 0.5),
+        ]);
+    }
+
+    #[test]
+    fn parses_negatives() {
+        assert_parses(&[
+            ("-12.30", -12.3),
+            ("\u{2212}12.30", -12.3),
+            ("(12.30)", -12.3),
+            ("12.30-", -12.3),
+            ("12.30 DR", -12.3),
+            ("12.30DR", -12.3),
+            ("12.30 dr", -12.3),
+            ("12.30 CR", 12.3),
+            ("12.30CR", 12.3),
+        ]);
+    }
+
+    #[test]
+    fn parses_currencies_before_or_after() {
+        assert_parses(&[
+            ("£1,234.56", 1234.56),
+            ("1,234.56 £", 1234.56),
+            ("-£12.30", -12.3),
+            ("£-12.30", -12.3),
+            ("(£12.30)", -12.3),
+            ("€1.234,56", 1234.56),
+            ("1.234,56 €", 1234.56),
+            ("1.234,56\u{a0}€", 1234.56),
+            ("1.234,56 EUR", 1234.56),
+            ("GBP 1,234.56", 1234.56),
+            ("GBP1,234.56", 1234.56),
+            ("1,234.56 GBP", 1234.56),
+            ("1,234.56 GBP DR", -1234.56),
+        ]);
+    }
+
+    #[test]
+    fn takes_an_ambiguous_separator_as_thousands_unless_its_given() {
+        assert_parses(&[("1,234", 1234.0), ("1.234", 1234.0)]);
+
+        for (text, decimal_separator, expected) in [
+            ("1,234", ',', 1.234),
+            ("1.234", '.', 1.234),
+            ("1,234", '.', 1234.0),
+            ("1.234", ',', 1234.0),
+            ("1.234,56", ',', 1234.56),
+            ("1,234.56", '.', 1234.56),
+        ] {
+            assert_eq!(
+                parse_with(text, Some(decimal_separator)).unwrap(),
+                expected,
+                "{:?} with {:?}",
+                text,
+                decimal_separator
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_what_isnt_an_amount() {
+        for text in ["", "   ", "abc", "£", "-", "()", "N/A", "GBP"] {
+            assert!(parse(text).is_err(), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use anyhow::{anyhow, Result};
+use chrono::{Duration, Utc};
+use log::info;
+use rand::{distributions::Alphanumeric, Rng};
+use std::{
+    fmt,
+    time::{Duration as StdDuration, Instant},
+};
+
+use crate::{
+    history::{History, PendingApproval},
+    notify::{self, Event, Notification},
+    web::WebUiConfig,
+    Config,
+};
+
+// Beyond this an unanswered approval is abandoned and asked for again
+static PENDING_APPROVAL_MAX_AGE_HOURS: i64 = 24;
+
+static APPROVAL_POLL_SECS: u64 = 5;
+
+// Returned when an anomalous balance hasn't been approved or rejected yet, which skips the run
+// until the next one
+#[derive(Clone, Debug)]
+pub struct ApprovalPending {
+    pub provider: String,
+}
+
+impl fmt::Display for ApprovalPending {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} is waiting for the user to approve its balance",
+            self.provider
+        )
+    }
+}
+
+impl std::error::Error for ApprovalPending {}
+
+// Returned when an anomalous balance was rejected, which skips the run with a warning
+#[derive(Clone, Debug)]
+pub struct BalanceRejected {
+    pub provider: String,
+    pub balance: f32,
+}
+
+impl fmt::Display for BalanceRejected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}'s balance of {:.2} was rejected, skipping",
+            self.provider, self.balance
+        )
+    }
+}
+
+impl std::error::Error for BalanceRejected {}
+
+// Whether the balance moved by more than `threshold_percent` of the previous one. Any move from
+// nothing is anomalous.
+pub fn is_anomalous(previous: f32, balance: f32, threshold_percent: f32) -> bool {
+    if previous == 0.0 {
+        return balance != 0.0;
+    }
+
+    ((balance - previous) / previous).abs() * 100.0 > threshold_percent
+}
+
+fn approval_url(web_ui: &WebUiConfig, state: &str) -> String {
+    format!(
+        "{}{}/approve/{}",
+        web_ui.url.trim_end_matches('/'),
+        web_ui.access.path_prefix(),
+        state
+    )
+}
+
+// Sends the user a link to the web UI, where they approve or reject the balance before it's
+// reconciled. A balance that isn't answered within `AUTH_TIMEOUT_SECS` is left pending, to be
+// picked up by the next run if it fetches the same balance.
+pub async fn confirm(config: &Config, account: &str, previous: f32, balance: f32) -> Result<()> {
+    let web_ui = config.web_ui.as_ref().ok_or_else(|| {
+        anyhow!(
+            "{}'s balance moved from {:.2} to {:.2}, which needs approving through WEB_UI, but it isn't configured",
+            account,
+            previous,
+            balance
+        )
+    })?;
+
+    let history = History::open(&config.config_path)?;
+
+    let pending_approval = match history.get_pending_approval(account)? {
+        Some(pending_approval)
+            if pending_approval.balance == balance
+                && Utc::now() - pending_approval.created_at
+                    < Duration::hours(PENDING_APPROVAL_MAX_AGE_HOURS) =>
+        {
+            pending_approval
+        }
+        _ => {
+            let pending_approval = PendingApproval {
+                provider: account.to_owned(),
+                state: rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(32)
+                    .map(char::from)
+                    .collect::<String>(),
+                previous_balance: previous,
+                balance,
+                created_at: Utc::now(),
+                approved: None,
+            };
+
+            history.set_pending_approval(&pending_approval)?;
+
+            let notification = Notification {
+                event: Event::Approval,
+                title: account.to_owned(),
+                message: format!(
+                    "{}'s balance moved from {:.2} to {:.2}, approve it before it's reconciled",
+                    account, previous, balance
+                ),
+                url: Some(approval_url(web_ui, &pending_approval.state)),
+            };
+
+            notify::notify(config, &notification).await;
+
+            pending_approval
+        }
+    };
+
+    // The prompt may have been sent by an earlier run, so it's found by its link to be cancelled
+    let url = approval_url(web_ui, &pending_approval.state);
+
+    info!("Waiting for {}'s balance to be approved", account);
+
+    let deadline = Instant::now() + StdDuration::from_secs(config.auth_timeout_secs);
+
+    loop {
+        let approved = history
+            .get_pending_approval(account)?
+            .filter(|p| p.state == pending_approval.state)
+            .and_then(|p| p.approved);
+
+        match approved {
+            Some(true) => {
+                history.clear_pending_approval(account)?;
+                notify::prompt_answered(config, &url).await;
+                return Ok(());
+            }
+            Some(false) => {
+                history.clear_pending_approval(account)?;
+                notify::prompt_answered(config, &url).await;
+                return Err(BalanceRejected {
+                    provider: account.to_owned(),
+                    balance,
+                }
+                .into());
+            }
+            None => {}
+        }
+
+        if Instant::now() >= deadline {
+            return Err(ApprovalPending {
+                provider: account.to_owned(),
+            }
+            .into());
+        }
+
+        tokio::time::sleep(StdDuration::from_secs(APPROVAL_POLL_SECS)).await;
+    }
+}
@@ -0,0 +1,187 @@
+// Month-end snapshots created retroactively from a provider's history, so a newly added tracking
+// account has a curve in YNAB going back before it was added rather than a single jump. Each is
+// dated the 1st of the following month, as a run's snapshot would be, and adjusts the account to
+// the month's last value. Reconciliations after them aren't moved, so it's best run before the
+// account's first; otherwise the next run's adjustment takes up the difference.
+
+use anyhow::{anyhow, Result};
+use chrono::{Datelike, Local, Months, NaiveDate};
+use log::info;
+use std::collections::BTreeMap;
+
+use crate::{
+    budget::{Budget, BudgetSink},
+    currency, new_import_id, providers,
+    reconcile::{Milliunits, Policy},
+    split,
+    ynab::{NewTransaction, TransactionClearedStatus},
+    Config,
+};
+
+pub trait GetHistory {
+    // The account's value on each day the provider has one for, from `from` on
+    async fn get_history(&self, from: NaiveDate) -> Result<BTreeMap<NaiveDate, f32>>;
+}
+
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+    pub date: NaiveDate,
+    pub value: f32,
+    pub adjustment: Milliunits,
+}
+
+// The 1st of each month from `from`'s up to this month's, with the last value of the month before
+fn month_end_values(
+    history: &BTreeMap<NaiveDate, f32>,
+    from: NaiveDate,
+    today: NaiveDate,
+) -> Vec<(NaiveDate, f32)> {
+    let mut values = vec![];
+    let mut month = from.with_day(1).unwrap_or(from);
+
+    while let Some(next_month) = month.checked_add_months(Months::new(1)) {
+        if next_month > today {
+            break;
+        }
+
+        match history.range(month..next_month).next_back() {
+            Some((_, value)) => values.push((next_month, *value)),
+            None => info!("There's no value for {}", month.format("%Y-%m")),
+        }
+
+        month = next_month;
+    }
+
+    values
+}
+
+pub async fn backfill(
+    config: &Config,
+    account: &str,
+    from: NaiveDate,
+    dry_run: bool,
+) -> Result<Vec<Snapshot>> {
+    let account_config = config
+        .accounts
+        .get(account)
+        .ok_or_else(|| anyhow!("No account named {} is configured", account))?;
+
+    // Today's rate would make a curve that was never there
+    if account_config.currency.is_some() {
+        return Err(anyhow!(
+            "{} has a CURRENCY, and its history can't be converted at the rates of the time",
+            account
+        ));
+    }
+
+    let history = providers::history(config, account, account_config, from).await?;
+
+    let budget = Budget::for_account(config, account).await?;
+
+    let ynab_account = budget
+        .get_account(&account_config.ynab_account_id)
+        .await?
+        .ok_or_else(|| anyhow!("{}'s YNAB account doesn't exist", account))?;
+
+    let currency = budget.get_budget_settings().await?.currency_format;
+
+    let policy = Policy {
+        reconciliation_payee_id: budget.reconciliation_payee_id(config),
+        snapshot_flag_color: config.snapshot_flag_color.filter(|_| budget.has_flags()),
+        flag_color: account_config.flag_color.filter(|_| budget.has_flags()),
+        merges: account_config.split.is_none(),
+    };
+
+    let mut transactions = budget
+        .get_transactions(&ynab_account.id, None)
+        .await?
+        .into_iter()
+        .map(|t| (t.date, t.amount, policy.is_reconciliation(&t)))
+        .collect::<Vec<_>>();
+
+    let mut snapshots = vec![];
+
+    for (date, value) in month_end_values(&history, from, Local::now().date_naive()) {
+        if transactions
+            .iter()
+            .any(|(d, _, is_reconciliation)| *d == date && *is_reconciliation)
+        {
+            info!("There's already a reconciliation on {}", date);
+            continue;
+        }
+
+        let value = if account_config.liability {
+            -value
+        } else {
+            value
+        };
+
+        let balance = transactions
+            .iter()
+            .filter(|(d, _, _)| *d <= date)
+            .map(|(_, amount, _)| amount)
+            .sum::<Milliunits>();
+
+        let adjustment = currency::to_milliunits(currency.as_ref(), value) - balance;
+
+        if adjustment == 0 {
+            info!("{}'s balance on {} is already {}", account, date, value);
+            continue;
+        }
+
+        info!(
+            "{} {} on {}, adjusting by {}",
+            if dry_run {
+                "Would snapshot"
+            } else {
+                "Snapshotting"
+            },
+            currency::format(currency.as_ref(), value),
+            date,
+            currency::format(currency.as_ref(), adjustment as f32 / 1000.0)
+        );
+
+        if !dry_run {
+            budget
+                .create_transaction(&NewTransaction {
+                    account_id: Some(ynab_account.id.clone()),
+                    date: Some(date),
+                    amount: Some(adjustment),
+                    payee_id: Some(policy.reconciliation_payee_id.clone()),
+                    payee_name: Some("Reconciliation Balance Adjustment".to_owned()),
+                    category_id: None,
+                    memo: Some(format!(
+                        "Backfilled from {}'s history",
+                        account_config.provider.name()
+                    )),
+                    cleared: Some(TransactionClearedStatus::Reconciled),
+                    approved: Some(true),
+                    flag_color: policy.flag_color_on(date),
+                    import_id: Some(new_import_id(date)),
+                    subtransactions: account_config
+                        .split
+                        .as_ref()
+                        .map(|parts| {
+                            split::split(
+                                adjustment,
+                                parts,
+                                &policy.reconciliation_payee_id,
+                                currency.as_ref(),
+                            )
+                        })
+                        .transpose()?,
+                    ..Default::default()
+                })
+                .await?;
+        }
+
+        transactions.push((date, adjustment, true));
+        snapshots.push(Snapshot {
+            date,
+            value,
+            adjustment,
+        });
+    }
+
+    Ok(snapshots)
+}
@@ -0,0 +1,453 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+use rand::Rng;
+use reqwest::{
+    cookie::{CookieStore, Jar},
+    header::{self, HeaderMap, HeaderValue},
+    StatusCode,
+};
+use serde::Deserialize;
+use serde_json::json;
+use std::{fmt, sync::Arc, time::Duration};
+use tokio::process::Command;
+
+use crate::http::{self, HttpConfig};
+
+static DEFAULT_USER_AGENT: &str =
+    "Mozilla/5.0 (X11; Linux x86_64; rv:120.0) Gecko/20100101 Firefox/120.0";
+static DEFAULT_ACCEPT_LANGUAGE: &str = "en-GB,en;q=0.5";
+
+// How scraper providers present themselves, since sites like HL block requests that don't look
+// like they're from a browser
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct BrowserConfig {
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+    #[serde(default = "default_accept_language")]
+    pub accept_language: String,
+    // Bounds of the random pause between a scraper's requests
+    #[serde(default = "default_min_request_delay_millis")]
+    pub min_request_delay_millis: u64,
+    #[serde(default = "default_max_request_delay_millis")]
+    pub max_request_delay_millis: u64,
+
+    // Gets past a bot challenge, either a FlareSolverr instance or a command that's given the
+    // challenged URL & prints a `ChallengeSolution` as JSON
+    pub flaresolverr_url: Option<String>,
+    pub challenge_solver_command: Option<Vec<String>>,
+    // How long the provider is paused for after a challenge that couldn't be solved, rather than
+    // failing every run & risking the account being locked
+    #[serde(default = "default_challenge_pause_hours")]
+    pub challenge_pause_hours: i64,
+}
+
+fn default_user_agent() -> String {
+    DEFAULT_USER_AGENT.to_owned()
+}
+
+fn default_accept_language() -> String {
+    DEFAULT_ACCEPT_LANGUAGE.to_owned()
+}
+
+fn default_min_request_delay_millis() -> u64 {
+    1000
+}
+
+fn default_max_request_delay_millis() -> u64 {
+    4000
+}
+
+fn default_challenge_pause_hours() -> i64 {
+    24
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct ChallengeSolution {
+    pub cookies: Vec<SolvedCookie>,
+    // Clearance cookies are only accepted from the user agent that solved the challenge
+    pub user_agent: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct SolvedCookie {
+    pub name: String,
+    pub value: String,
+    pub domain: Option<String>,
+}
+
+// Returned by a scraper when a site's bot challenge couldn't be solved
+#[derive(Clone, Debug)]
+pub struct ChallengeDetected {
+    pub url: String,
+    pub pause: chrono::Duration,
+}
+
+impl fmt::Display for ChallengeDetected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Blocked by a bot challenge at {}", self.url)
+    }
+}
+
+impl std::error::Error for ChallengeDetected {}
+
+// Returned by a scraper when a site asks for a captcha, which a human has to complete
+#[derive(Clone, Debug)]
+pub struct CaptchaDetected {
+    pub url: String,
+}
+
+impl fmt::Display for CaptchaDetected {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Asked for a captcha at {}", self.url)
+    }
+}
+
+impl std::error::Error for CaptchaDetected {}
+
+// A scraper's client, which keeps its cookies & gets past bot challenges where it can
+pub struct Browser {
+    config: BrowserConfig,
+    http: HttpConfig,
+    jar: Arc<Jar>,
+    client: reqwest::Client,
+}
+
+impl Browser {
+    pub fn new(config: &BrowserConfig, http: &HttpConfig) -> Result<Self> {
+        let jar = Arc::new(Jar::default());
+        let client = build_client(config, http, &jar)?;
+
+        Ok(Browser {
+            config: config.clone(),
+            http: http.clone(),
+            jar,
+            client,
+        })
+    }
+
+    // A human takes a moment between pages
+    pub async fn pause(&self) {
+        let max = self
+            .config
+            .max_request_delay_millis
+            .max(self.config.min_request_delay_millis);
+        let millis = rand::thread_rng().gen_range(self.config.min_request_delay_millis..=max);
+        tokio::time::sleep(Duration::from_millis(millis)).await;
+    }
+
+    pub async fn get(&mut self, url: &str) -> Result<String> {
+        let response = self.client.get(url).send().await?;
+
+        match read_page(response).await? {
+            Page::Content(text) => return Ok(text),
+            Page::Captcha => return Err(captcha_detected(url)),
+            Page::Challenge => info!("Got a bot challenge from {}", url),
+        }
+
+        match self.solve(url).await {
+            Ok(Some(solution)) => self.apply(url, solution)?,
+            Ok(None) => return Err(self.challenge_detected(url)),
+            Err(e) => {
+                warn!("Failed to solve the bot challenge: {:#?}", e);
+                return Err(self.challenge_detected(url));
+            }
+        }
+
+        let response = self.client.get(url).send().await?;
+
+        match read_page(response).await? {
+            Page::Content(text) => Ok(text),
+            Page::Captcha => Err(captcha_detected(url)),
+            Page::Challenge => Err(self.challenge_detected(url)),
+        }
+    }
+
+    // Challenges are only solved for pages, since a solver can't replay the form
+    pub async fn post_form(&self, url: &str, params: &[(&str, &str)]) -> Result<String> {
+        let response = self.client.post(url).form(params).send().await?;
+
+        match read_page(response).await? {
+            Page::Content(text) => Ok(text),
+            Page::Captcha => Err(captcha_detected(url)),
+            Page::Challenge => Err(self.challenge_detected(url)),
+        }
+    }
+
+    // A site's own JSON API, called with the session's cookies, e.g. by the page once it's loaded
+    pub async fn get_json(&self, url: &str, headers: &[(&str, &str)]) -> Result<String> {
+        let mut request = self
+            .client
+            .get(url)
+            .header(header::ACCEPT, "application/json");
+
+        for (name, value) in headers {
+            request = request.header(*name, *value);
+        }
+
+        self.read_json(url, request.send().await?).await
+    }
+
+    pub async fn post_json(&self, url: &str, body: &serde_json::Value) -> Result<String> {
+        let response = self
+            .client
+            .post(url)
+            .header(header::ACCEPT, "application/json")
+            .json(body)
+            .send()
+            .await?;
+
+        self.read_json(url, response).await
+    }
+
+    async fn read_json(&self, url: &str, response: reqwest::Response) -> Result<String> {
+        let status = response.status();
+
+        match read_page(response).await? {
+            Page::Content(text) if status.is_success() => Ok(text),
+            Page::Content(text) => Err(anyhow!("{} answered {}: {}", url, status, text)),
+            Page::Captcha => Err(captcha_detected(url)),
+            Page::Challenge => Err(self.challenge_detected(url)),
+        }
+    }
+
+    // Adds the cookies of a `Cookie` header, e.g. from a login finished by hand
+    pub fn add_cookies(&self, url: &str, cookies: &str) -> Result<()> {
+        let url = reqwest::Url::parse(url)?;
+
+        for cookie in cookies.split(';').map(str::trim).filter(|c| !c.is_empty()) {
+            self.jar
+                .add_cookie_str(&format!("{}; Path=/", cookie), &url);
+        }
+
+        Ok(())
+    }
+
+    // One of the jar's cookies for the URL, e.g. one the site set on login
+    pub fn cookie(&self, url: &str, name: &str) -> Result<Option<String>> {
+        let url = reqwest::Url::parse(url)?;
+
+        let Some(cookies) = self.jar.cookies(&url) else {
+            return Ok(None);
+        };
+
+        Ok(cookies
+            .to_str()?
+            .split(';')
+            .map(str::trim)
+            .find_map(|cookie| {
+                cookie
+                    .strip_prefix(name)
+                    .and_then(|value| value.strip_prefix('='))
+                    .map(str::to_owned)
+            }))
+    }
+
+    fn challenge_detected(&self, url: &str) -> anyhow::Error {
+        ChallengeDetected {
+            url: url.to_owned(),
+            pause: chrono::Duration::hours(self.config.challenge_pause_hours),
+        }
+        .into()
+    }
+
+    async fn solve(&self, url: &str) -> Result<Option<ChallengeSolution>> {
+        if let Some(flaresolverr_url) = &self.config.flaresolverr_url {
+            info!("Solving the bot challenge with FlareSolverr");
+            return Ok(Some(
+                self.solve_with_flaresolverr(flaresolverr_url, url).await?,
+            ));
+        }
+
+        if let Some(command) = &self.config.challenge_solver_command {
+            info!("Solving the bot challenge with {:?}", command);
+            return Ok(Some(solve_with_command(command, url).await?));
+        }
+
+        Ok(None)
+    }
+
+    // https://github.com/FlareSolverr/FlareSolverr#-requestget
+    async fn solve_with_flaresolverr(
+        &self,
+        flaresolverr_url: &str,
+        url: &str,
+    ) -> Result<ChallengeSolution> {
+        #[derive(Clone, Debug, Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Solution {
+            cookies: Vec<SolvedCookie>,
+            user_agent: Option<String>,
+        }
+
+        #[derive(Clone, Debug, Deserialize)]
+        struct Response {
+            status: String,
+            message: Option<String>,
+            solution: Option<Solution>,
+        }
+
+        // FlareSolverr runs alongside the updater, so it's reached directly rather than through
+        // the proxy
+        let response = http::client_builder(&self.http)?
+            .no_proxy()
+            .build()?
+            .post(format!("{}/v1", flaresolverr_url.trim_end_matches('/')))
+            .json(&json!({
+                "cmd": "request.get",
+                "url": url,
+                "maxTimeout": 60000,
+            }))
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<Response>()
+            .await?;
+
+        match response.solution {
+            Some(solution) if response.status == "ok" => Ok(ChallengeSolution {
+                cookies: solution.cookies,
+                user_agent: solution.user_agent,
+            }),
+            _ => Err(anyhow!(
+                "FlareSolverr failed: {}",
+                response.message.unwrap_or(response.status)
+            )),
+        }
+    }
+
+    fn apply(&mut self, url: &str, solution: ChallengeSolution) -> Result<()> {
+        let url = reqwest::Url::parse(url)?;
+
+        for cookie in solution.cookies {
+            let cookie = match cookie.domain {
+                Some(domain) => format!(
+                    "{}={}; Domain={}; Path=/",
+                    cookie.name, cookie.value, domain
+                ),
+                None => format!("{}={}; Path=/", cookie.name, cookie.value),
+            };
+            self.jar.add_cookie_str(&cookie, &url);
+        }
+
+        if let Some(user_agent) = solution.user_agent {
+            self.config.user_agent = user_agent;
+            self.client = build_client(&self.config, &self.http, &self.jar)?;
+        }
+
+        Ok(())
+    }
+}
+
+fn build_client(
+    config: &BrowserConfig,
+    http: &HttpConfig,
+    jar: &Arc<Jar>,
+) -> Result<reqwest::Client> {
+    let client = http::client_builder(http)?
+        .cookie_provider(jar.clone())
+        .default_headers(browser_headers(config)?)
+        .build()?;
+
+    Ok(client)
+}
+
+enum Page {
+    Content(String),
+    Captcha,
+    Challenge,
+}
+
+fn captcha_detected(url: &str) -> anyhow::Error {
+    CaptchaDetected {
+        url: url.to_owned(),
+    }
+    .into()
+}
+
+async fn read_page(response: reqwest::Response) -> Result<Page> {
+    let status = response.status();
+    let headers = response.headers().clone();
+    let text = response.text().await?;
+
+    if is_challenge(status, &headers, &text) {
+        return Ok(Page::Challenge);
+    }
+
+    if is_captcha(&text) {
+        return Ok(Page::Captcha);
+    }
+
+    Ok(Page::Content(text))
+}
+
+// Cloudflare marks its challenges with `cf-mitigated`, though older ones are only recognisable
+// from the interstitial page itself
+fn is_challenge(status: StatusCode, headers: &HeaderMap, text: &str) -> bool {
+    if headers
+        .get("cf-mitigated")
+        .is_some_and(|value| value == "challenge")
+    {
+        return true;
+    }
+
+    let from_cloudflare = headers
+        .get(header::SERVER)
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"cloudflare"));
+
+    from_cloudflare
+        && matches!(
+            status,
+            StatusCode::FORBIDDEN | StatusCode::TOO_MANY_REQUESTS | StatusCode::SERVICE_UNAVAILABLE
+        )
+        && (text.contains("challenge-platform")
+            || text.contains("cf-chl")
+            || text.contains("<title>Just a moment...</title>"))
+}
+
+// Invisible captchas are passed by browsers without the user's help, so only visible widgets count
+fn is_captcha(text: &str) -> bool {
+    (text.contains(r#"class="g-recaptcha""#) && !text.contains(r#"data-size="invisible""#))
+        || text.contains(r#"class="h-captcha""#)
+}
+
+async fn solve_with_command(command: &[String], url: &str) -> Result<ChallengeSolution> {
+    let (program, args) = command
+        .split_first()
+        .ok_or_else(|| anyhow!("CHALLENGE_SOLVER_COMMAND is empty"))?;
+
+    let output = Command::new(program).args(args).arg(url).output().await?;
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "{} exited with {}: {}",
+            program,
+            output.status,
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+
+    Ok(serde_json::from_slice::<ChallengeSolution>(&output.stdout)?)
+}
+
+// The headers a browser sends when navigating to a page
+pub fn browser_headers(config: &BrowserConfig) -> Result<HeaderMap> {
+    let mut headers = HeaderMap::new();
+    headers.insert(header::USER_AGENT, config.user_agent.parse()?);
+    headers.insert(
+        header::ACCEPT,
+        HeaderValue::from_static(
+            "text/html,application/xhtml+xml,application/xml;q=0.9,image/avif,image/webp,*/*;q=0.8",
+        ),
+    );
+    headers.insert(header::ACCEPT_LANGUAGE, config.accept_language.parse()?);
+    headers.insert(header::DNT, HeaderValue::from_static("1"));
+    headers.insert(
+        header::UPGRADE_INSECURE_REQUESTS,
+        HeaderValue::from_static("1"),
+    );
+    headers.insert("Sec-Fetch-Dest", HeaderValue::from_static("document"));
+    headers.insert("Sec-Fetch-Mode", HeaderValue::from_static("navigate"));
+    headers.insert("Sec-Fetch-User", HeaderValue::from_static("?1"));
+    Ok(headers)
+}
@@ -0,0 +1,159 @@
+use anyhow::Result;
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+use crate::{
+    actual::ActualClient,
+    get_ynab_bearer_token,
+    ynab::{
+        Account, BudgetSettings, ExistingTransaction, NewTransaction,
+        SaveTransactionWithIdOrImportId, TransactionDetail, YnabClient,
+    },
+    Config,
+};
+
+// Which budget an account is reconciled in, `BUDGET = "actual"` for Actual Budget. Its
+// YNAB_ACCOUNT_ID is then the Actual account's ID.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BudgetKind {
+    #[default]
+    Ynab,
+    Actual,
+}
+
+// What reconciling needs from a budget. It's in YNAB's terms, which other budgets' are
+// translated to.
+pub trait BudgetSink {
+    // `None` when the account doesn't exist, e.g. it was deleted & then removed from the budget
+    async fn get_account(&self, account_id: &str) -> Result<Option<Account>>;
+
+    async fn get_budget_settings(&self) -> Result<BudgetSettings>;
+
+    async fn get_transactions(
+        &self,
+        account_id: &str,
+        since_date: Option<NaiveDate>,
+    ) -> Result<Vec<TransactionDetail>>;
+
+    async fn update_transaction(
+        &self,
+        transaction_id: &str,
+        transaction: &ExistingTransaction,
+    ) -> Result<()>;
+
+    async fn update_transactions(
+        &self,
+        transactions: &[SaveTransactionWithIdOrImportId],
+    ) -> Result<()>;
+
+    // Returns the new transaction's id
+    async fn create_transaction(&self, transaction: &NewTransaction) -> Result<String>;
+
+    async fn delete_transaction(&self, transaction_id: &str) -> Result<()>;
+}
+
+#[derive(Clone, Debug)]
+pub enum Budget {
+    Ynab(YnabClient),
+    Actual(ActualClient),
+}
+
+impl Budget {
+    pub async fn new(config: &Config, kind: BudgetKind) -> Result<Self> {
+        match kind {
+            BudgetKind::Ynab => {
+                let ynab_bearer_token = get_ynab_bearer_token(config).await?;
+
+                Ok(Budget::Ynab(YnabClient::new(config, &ynab_bearer_token)?))
+            }
+            BudgetKind::Actual => Ok(Budget::Actual(ActualClient::new(config)?)),
+        }
+    }
+
+    // The budget the account is reconciled in
+    pub async fn for_account(config: &Config, account: &str) -> Result<Self> {
+        let kind = config
+            .accounts
+            .get(account)
+            .map(|account_config| account_config.budget)
+            .unwrap_or_default();
+
+        Budget::new(config, kind).await
+    }
+
+    // The payee the updater's reconciliations are posted to
+    pub fn reconciliation_payee_id(&self, config: &Config) -> String {
+        match self {
+            Budget::Ynab(_) => config.ynab_reconciliation_payee_id.clone(),
+            Budget::Actual(actual) => actual.reconciliation_payee_id().to_owned(),
+        }
+    }
+
+    // Actual has no flags, so its snapshots are only the 1st's
+    pub fn has_flags(&self) -> bool {
+        matches!(self, Budget::Ynab(_))
+    }
+}
+
+impl BudgetSink for Budget {
+    async fn get_account(&self, account_id: &str) -> Result<Option<Account>> {
+        match self {
+            Budget::Ynab(ynab) => ynab.get_account(account_id).await,
+            Budget::Actual(actual) => actual.get_account(account_id).await,
+        }
+    }
+
+    async fn get_budget_settings(&self) -> Result<BudgetSettings> {
+        match self {
+            Budget::Ynab(ynab) => ynab.get_budget_settings().await,
+            Budget::Actual(actual) => actual.get_budget_settings().await,
+        }
+    }
+
+    async fn get_transactions(
+        &self,
+        account_id: &str,
+        since_date: Option<NaiveDate>,
+    ) -> Result<Vec<TransactionDetail>> {
+        match self {
+            Budget::Ynab(ynab) => ynab.get_transactions(account_id, since_date).await,
+            Budget::Actual(actual) => actual.get_transactions(account_id, since_date).await,
+        }
+    }
+
+    async fn update_transaction(
+        &self,
+        transaction_id: &str,
+        transaction: &ExistingTransaction,
+    ) -> Result<()> {
+        match self {
+            Budget::Ynab(ynab) => ynab.update_transaction(transaction_id, transaction).await,
+            Budget::Actual(actual) => actual.update_transaction(transaction_id, transaction).await,
+        }
+    }
+
+    async fn update_transactions(
+        &self,
+        transactions: &[SaveTransactionWithIdOrImportId],
+    ) -> Result<()> {
+        match self {
+            Budget::Ynab(ynab) => ynab.update_transactions(transactions).await,
+            Budget::Actual(actual) => actual.update_transactions(transactions).await,
+        }
+    }
+
+    async fn create_transaction(&self, transaction: &NewTransaction) -> Result<String> {
+        match self {
+            Budget::Ynab(ynab) => ynab.create_transaction(transaction).await,
+            Budget::Actual(actual) => actual.create_transaction(transaction).await,
+        }
+    }
+
+    async fn delete_transaction(&self, transaction_id: &str) -> Result<()> {
+        match self {
+            Budget::Ynab(ynab) => ynab.delete_transaction(transaction_id).await,
+            Budget::Actual(actual) => actual.delete_transaction(transaction_id).await,
+        }
+    }
+}
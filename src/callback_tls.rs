@@ -0,0 +1,135 @@
+// HTTPS for the OAuth callback listener, since some browsers, e.g. Brave & Safari on iOS, upgrade
+// the redirect to HTTPS whatever its scheme. The certificate's self-signed, generated on first use
+// into the config directory & kept, so its fingerprint can be checked against the one in the login
+// notification when the browser warns about it. Deleting it generates another, e.g. after the
+// callback's host changes. With `SECRETS = "fd_store"` the key's kept in the fd store instead.
+
+use anyhow::{Context, Result};
+use log::info;
+use openssl::{
+    asn1::Asn1Time,
+    bn::{BigNum, MsbOption},
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::PKey,
+    x509::{extension::SubjectAlternativeName, X509NameBuilder, X509},
+};
+use std::{net::IpAddr, path::Path, sync::Arc};
+use tokio_rustls::{
+    rustls::{
+        crypto::ring,
+        pki_types::{pem::PemObject, CertificateDer, PrivateKeyDer},
+        ServerConfig,
+    },
+    TlsAcceptor,
+};
+
+use crate::{fd_store, permissions};
+
+static CERT_FILENAME: &str = "callback-cert.pem";
+static KEY_FILENAME: &str = "callback-key.pem";
+static CERT_VALID_DAYS: u32 = 3650;
+
+pub struct CallbackTls {
+    pub acceptor: TlsAcceptor,
+    // The certificate's SHA-256, as browsers show it, e.g. `AB:CD:…`
+    pub fingerprint: String,
+}
+
+// The config directory's certificate, generated for `hosts` if there isn't one yet
+pub fn load_or_generate(config_path: &str, hosts: &[&str]) -> Result<CallbackTls> {
+    let cert_path = Path::new(config_path).join(CERT_FILENAME);
+    let key_path = Path::new(config_path).join(KEY_FILENAME);
+
+    let stored_key = if fd_store::is_enabled() {
+        fd_store::read(&key_path.display().to_string())?.map(|(key_pem, _)| key_pem)
+    } else {
+        std::fs::read(&key_path).ok()
+    };
+
+    let (cert_pem, key_pem) = match (std::fs::read(&cert_path).ok(), stored_key) {
+        (Some(cert_pem), Some(key_pem)) => (cert_pem, key_pem),
+        _ => {
+            info!(
+                "Generating a self-signed certificate for the callback, for {}",
+                hosts.join(", ")
+            );
+
+            let (cert_pem, key_pem) = generate(hosts)?;
+
+            std::fs::write(&cert_path, &cert_pem)
+                .with_context(|| format!("Failed to write {}", cert_path.display()))?;
+            if fd_store::is_enabled() {
+                fd_store::write(&key_path.display().to_string(), &key_pem)?;
+            } else {
+                permissions::write_private(&key_path, &key_pem)
+                    .with_context(|| format!("Failed to write {}", key_path.display()))?;
+            }
+
+            (cert_pem, key_pem)
+        }
+    };
+
+    let fingerprint = X509::from_pem(&cert_pem)?
+        .digest(MessageDigest::sha256())?
+        .iter()
+        .map(|byte| format!("{:02X}", byte))
+        .collect::<Vec<_>>()
+        .join(":");
+
+    let cert = CertificateDer::from_pem_slice(&cert_pem)
+        .with_context(|| format!("{} isn't a PEM certificate", cert_path.display()))?;
+    let key = PrivateKeyDer::from_pem_slice(&key_pem)
+        .with_context(|| format!("{} isn't a PEM private key", key_path.display()))?;
+
+    let config = ServerConfig::builder_with_provider(Arc::new(ring::default_provider()))
+        .with_safe_default_protocol_versions()?
+        .with_no_client_auth()
+        .with_single_cert(vec![cert], key)?;
+    let acceptor = TlsAcceptor::from(Arc::new(config));
+
+    Ok(CallbackTls {
+        acceptor,
+        fingerprint,
+    })
+}
+
+// A P-256 key & a certificate for it naming each host, as an IP or DNS name
+fn generate(hosts: &[&str]) -> Result<(Vec<u8>, Vec<u8>)> {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+    let key = PKey::from_ec_key(EcKey::generate(&group)?)?;
+
+    let mut name = X509NameBuilder::new()?;
+    name.append_entry_by_nid(Nid::COMMONNAME, "ynab-updater callback")?;
+    let name = name.build();
+
+    let mut serial = BigNum::new()?;
+    serial.rand(128, MsbOption::MAYBE_ZERO, false)?;
+    let serial = serial.to_asn1_integer()?;
+    let not_before = Asn1Time::days_from_now(0)?;
+    let not_after = Asn1Time::days_from_now(CERT_VALID_DAYS)?;
+
+    let mut builder = X509::builder()?;
+    builder.set_version(2)?;
+    builder.set_serial_number(&serial)?;
+    builder.set_subject_name(&name)?;
+    builder.set_issuer_name(&name)?;
+    builder.set_pubkey(&key)?;
+    builder.set_not_before(&not_before)?;
+    builder.set_not_after(&not_after)?;
+
+    let mut san = SubjectAlternativeName::new();
+    for host in hosts {
+        match host.parse::<IpAddr>() {
+            Ok(_) => san.ip(host),
+            Err(_) => san.dns(host),
+        };
+    }
+    let san = san.build(&builder.x509v3_context(None, None))?;
+    builder.append_extension(san)?;
+
+    builder.sign(&key, MessageDigest::sha256())?;
+
+    Ok((builder.build().to_pem()?, key.private_key_to_pem_pkcs8()?))
+}
@@ -0,0 +1,113 @@
+// An investment account's adjustment split into what was paid in & what it grew by, so YNAB's
+// reports can tell saving apart from returns. With `SPLIT_CONTRIBUTIONS = true`, the deposits less
+// withdrawals since the last run are posted as a transaction of their own to
+// `CONTRIBUTION_PAYEE_NAME`, left unapproved to be categorized or matched to the transfer, and
+// only the rest is reconciled. They're counted from the first run with it set.
+
+use anyhow::Result;
+use chrono::{Local, NaiveDate};
+use log::info;
+
+use crate::{
+    budget::{Budget, BudgetSink},
+    currency, fx,
+    history::History,
+    providers,
+    reconcile::Milliunits,
+    ynab::{Account, CurrencyFormat, NewTransaction, TransactionClearedStatus},
+    AccountConfig, Config,
+};
+
+pub trait GetCashflows {
+    // What was paid in less what was taken out, from `from` up to but not including `until`
+    async fn get_cashflows(&self, from: NaiveDate, until: NaiveDate) -> Result<f32>;
+}
+
+fn default_payee_name() -> String {
+    "Contributions".to_owned()
+}
+
+pub struct Booked {
+    // In the budget's currency
+    pub contributions: f32,
+    // What the account's balance in YNAB went up by, nothing if they'd been posted already
+    pub posted: Milliunits,
+}
+
+// Posts the cash flows since they were last posted, `None` if there's no window to post yet
+pub async fn book(
+    config: &Config,
+    history: &History,
+    budget: &Budget,
+    account: &Account,
+    provider: &str,
+    account_config: &AccountConfig,
+    currency: Option<&CurrencyFormat>,
+) -> Result<Option<Booked>> {
+    let today = Local::now().date_naive();
+
+    let from = match history.get_cashflows_booked_until(provider)? {
+        Some(from) if from < today => from,
+        Some(_) => return Ok(None),
+        None => {
+            history.set_cashflows_booked_until(provider, today)?;
+            return Ok(None);
+        }
+    };
+
+    let cashflows = providers::cashflows(config, provider, account_config, from, today).await?;
+
+    let cashflows = fx::to_budget_currency(
+        config,
+        cashflows,
+        account_config.currency.as_deref(),
+        currency,
+    )
+    .await?;
+
+    let amount = currency::to_milliunits(currency, cashflows);
+
+    // The same for each window, so a run interrupted before it was recorded can't post it twice
+    let import_id = format!("YNAB-UPDATER:{}:contrib", from);
+
+    let posted = amount == 0
+        || budget
+            .get_transactions(&account.id, Some(from))
+            .await?
+            .iter()
+            .any(|t| t.import_id.as_deref() == Some(import_id.as_str()));
+
+    if !posted {
+        info!(
+            "Posting {} of contributions since {}",
+            currency::format(currency, cashflows),
+            from
+        );
+
+        budget
+            .create_transaction(&NewTransaction {
+                account_id: Some(account.id.clone()),
+                date: Some(today),
+                amount: Some(amount),
+                payee_name: Some(
+                    account_config
+                        .contribution_payee_name
+                        .clone()
+                        .unwrap_or_else(default_payee_name),
+                ),
+                memo: Some(format!("Contributions since {}", from)),
+                cleared: Some(TransactionClearedStatus::Cleared),
+                approved: Some(false),
+                import_id: Some(import_id),
+                ..Default::default()
+            })
+            .await?;
+    }
+
+    history.set_cashflows_booked_until(provider, today)?;
+
+    Ok(Some(Booked {
+        contributions: cashflows,
+        posted: if posted { 0 } else { amount },
+    }))
+}
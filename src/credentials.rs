@@ -0,0 +1,60 @@
+// Any setting can be read from a systemd credential rather than written in the config, e.g.
+// `YNAB_BEARER_TOKEN = "credential:ynab-token"` with the unit's `LoadCredentialEncrypted=ynab-token`,
+// so the config can be kept without its secrets. They're only substituted in memory, & the config
+// file keeps the reference.
+
+use anyhow::{anyhow, Result};
+use std::{env, fs, path::Path};
+use toml_edit::{visit_mut::VisitMut, DocumentMut, Formatted};
+
+static PREFIX: &str = "credential:";
+
+struct Substitute<'a> {
+    credentials_dir: Option<&'a Path>,
+    errors: Vec<String>,
+}
+
+impl VisitMut for Substitute<'_> {
+    fn visit_string_mut(&mut self, node: &mut Formatted<String>) {
+        let Some(name) = node.value().strip_prefix(PREFIX) else {
+            return;
+        };
+
+        let Some(credentials_dir) = self.credentials_dir else {
+            self.errors.push(format!(
+                "{:?} needs running by systemd with the credential loaded, but CREDENTIALS_DIRECTORY isn't set",
+                node.value()
+            ));
+            return;
+        };
+
+        match fs::read_to_string(credentials_dir.join(name)) {
+            Ok(secret) => *node = Formatted::new(secret.trim_end_matches('\n').to_owned()),
+            Err(e) => self
+                .errors
+                .push(format!("Failed to read credential {}: {}", name, e)),
+        }
+    }
+}
+
+// The config with each `credential:<name>` replaced by the credential
+pub fn resolve(config: &str) -> Result<String> {
+    if !config.contains(PREFIX) {
+        return Ok(config.to_owned());
+    }
+
+    let mut document = config.parse::<DocumentMut>()?;
+
+    let credentials_dir = env::var_os("CREDENTIALS_DIRECTORY");
+    let mut substitute = Substitute {
+        credentials_dir: credentials_dir.as_deref().map(Path::new),
+        errors: vec![],
+    };
+    substitute.visit_document_mut(&mut document);
+
+    if !substitute.errors.is_empty() {
+        return Err(anyhow!("{}", substitute.errors.join("\n")));
+    }
+
+    Ok(document.to_string())
+}
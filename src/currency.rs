@@ -0,0 +1,59 @@
+use crate::{reconcile::Milliunits, ynab::CurrencyFormat};
+
+// Amounts are shown with two decimal places & no symbol until the budget's currency is known
+fn decimal_digits(currency: Option<&CurrencyFormat>) -> u32 {
+    currency
+        .map(|c| c.decimal_digits.max(0) as u32)
+        .unwrap_or(2)
+}
+
+// YNAB stores every currency in milliunits, but only to the currency's own precision, e.g. whole
+// milliunits of yen are multiples of 1000
+pub fn to_milliunits(currency: Option<&CurrencyFormat>, amount: f32) -> Milliunits {
+    let step = 10f64.powi(3 - decimal_digits(currency).min(3) as i32);
+
+    ((amount as f64 * 1000.0 / step).round() * step) as Milliunits
+}
+
+// The amount as the budget shows it, e.g. `£1,234.56` or `¥123,456`
+pub fn format(currency: Option<&CurrencyFormat>, amount: f32) -> String {
+    let currency = match currency {
+        Some(currency) => currency,
+        None => return format!("{:.2}", amount),
+    };
+
+    let digits = currency.decimal_digits as usize;
+    // Rounded as YNAB would, rather than to even
+    let number = format!(
+        "{:.*}",
+        digits,
+        (to_milliunits(Some(currency), amount) as f64 / 1000.0).abs()
+    );
+    let (whole, fraction) = number.split_once('.').unwrap_or((&number, ""));
+
+    let mut grouped = String::new();
+    for (i, c) in whole.chars().enumerate() {
+        if i > 0 && (whole.len() - i) % 3 == 0 {
+            grouped.push_str(&currency.group_separator);
+        }
+        grouped.push(c);
+    }
+
+    if !fraction.is_empty() {
+        grouped.push_str(&currency.decimal_separator);
+        grouped.push_str(fraction);
+    }
+
+    // Not `-0.00` for an amount that rounds to nothing
+    let sign = if amount < 0.0 && number.chars().any(|c| c.is_ascii_digit() && c != '0') {
+        "-"
+    } else {
+        ""
+    };
+
+    match (currency.display_symbol, currency.symbol_first) {
+        (false, _) => format!("{}{}", sign, grouped),
+        (true, true) => format!("{}{}{}", sign, currency.currency_symbol, grouped),
+        (true, false) => format!("{}{}{}", sign, grouped, currency.currency_symbol),
+    }
+}
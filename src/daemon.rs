@@ -0,0 +1,408 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Local, Utc};
+use cron::Schedule;
+use log::{error, info, warn};
+use std::{rc::Rc, str::FromStr, time::Duration};
+use tokio::{
+    sync::{mpsc, watch, Mutex},
+    task::LocalSet,
+};
+
+use crate::{
+    flush_queued_balances,
+    history::History,
+    is_reconcile_due, notify,
+    providers::{self, ProviderKind},
+    reconcile_dangling_writes,
+    web::{self, PushedAccount, WebUiConfig},
+    User,
+};
+
+// A user, with the lock held while any of their runs are running, so they can't overlap
+type LockedUser = (Rc<User>, Rc<Mutex<()>>);
+
+// A profile or account of a user's, run on its schedule
+struct Job {
+    user: Rc<User>,
+    // Held while any of the user's jobs are running, so their runs can't overlap
+    user_lock: Rc<Mutex<()>>,
+    target: String,
+    schedule: Schedule,
+    accounts: Vec<String>,
+}
+
+fn get_jobs(users: &[LockedUser]) -> Result<Vec<Job>> {
+    let mut jobs = vec![];
+
+    for (user, user_lock) in users {
+        for (target, spec) in &user.config.schedules {
+            let schedule = Schedule::from_str(spec).map_err(|e| {
+                anyhow!(
+                    "Invalid schedule {:?} for {}'s {}: {}",
+                    spec,
+                    user.name,
+                    target,
+                    e
+                )
+            })?;
+
+            let accounts = match user.config.profiles.get(target) {
+                Some(accounts) => accounts.clone(),
+                None if user.config.accounts.contains_key(target) => vec![target.clone()],
+                None => {
+                    return Err(anyhow!(
+                        "{}'s {} schedule isn't for a configured profile or account",
+                        user.name,
+                        target
+                    ))
+                }
+            };
+
+            if let Some(account) = accounts
+                .iter()
+                .find(|account| !user.config.accounts.contains_key(*account))
+            {
+                return Err(anyhow!(
+                    "{}'s {} profile lists {}, which isn't a configured account",
+                    user.name,
+                    target,
+                    account
+                ));
+            }
+
+            jobs.push(Job {
+                user: user.clone(),
+                user_lock: user_lock.clone(),
+                target: target.clone(),
+                schedule,
+                accounts,
+            });
+        }
+    }
+
+    Ok(jobs)
+}
+
+// Runs the given accounts of the job's, returning `false` if it was cut short by a shutdown
+async fn run_accounts(job: &Job, accounts: &[String], shutdown: &watch::Receiver<bool>) -> bool {
+    let _user_guard = job.user_lock.lock().await;
+
+    info!("Running {}'s {}", job.user.name, job.target);
+
+    if let Err(e) = History::open(&job.user.config.config_path)
+        .and_then(|history| history.set_schedule_ran_at(&job.target, Utc::now()))
+    {
+        warn!(
+            "Failed to record {}'s {} run: {:#}",
+            job.user.name, job.target, e
+        );
+    }
+
+    if let Err(e) = flush_queued_balances(&job.user.config).await {
+        warn!(
+            "Failed to reconcile {}'s queued balances: {:#}",
+            job.user.name, e
+        );
+    }
+
+    notify::flush_held(&job.user.config).await;
+
+    for account in accounts {
+        // The account being updated is left to finish, but no more are started
+        if *shutdown.borrow() {
+            info!(
+                "Shutting down, skipping the rest of {}'s {}",
+                job.user.name, job.target
+            );
+            return false;
+        }
+
+        match is_reconcile_due(&job.user.config, account) {
+            Ok(true) => {}
+            Ok(false) => continue,
+            Err(e) => {
+                error!("Failed to check {}'s {}: {:#}", job.user.name, account, e);
+                continue;
+            }
+        }
+
+        if let Err(e) = providers::update_account(
+            &job.user.config,
+            account,
+            &job.user.config.accounts[account],
+        )
+        .await
+        {
+            error!("Failed to update {}'s {}: {:#}", job.user.name, account, e);
+        }
+    }
+
+    true
+}
+
+// Reconciles each push account as its balance is received, until shutdown
+async fn run_pushed_accounts(
+    users: Vec<LockedUser>,
+    mut pushed: mpsc::UnboundedReceiver<PushedAccount>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        let pushed_account = tokio::select! {
+            pushed_account = pushed.recv() => match pushed_account {
+                Some(pushed_account) => pushed_account,
+                None => return,
+            },
+            _ = shutdown.changed() => return,
+        };
+
+        let (user, user_lock) = match users
+            .iter()
+            .find(|(user, _)| user.name == pushed_account.user)
+        {
+            Some(user) => user,
+            None => continue,
+        };
+
+        let _user_guard = user_lock.lock().await;
+
+        // It was just sent, so it's reconciled whether or not the account is due
+        if let Err(e) = providers::update_account(
+            &user.config,
+            &pushed_account.account,
+            &user.config.accounts[&pushed_account.account],
+        )
+        .await
+        {
+            error!(
+                "Failed to update {}'s {}: {:#}",
+                user.name, pushed_account.account, e
+            );
+        }
+    }
+}
+
+// The scheduled run that was due since the job last ran, if the daemon wasn't running then, e.g.
+// the machine was asleep or off. A job that's never run is counted from now.
+fn missed_run(job: &Job) -> Result<Option<DateTime<Local>>> {
+    let history = History::open(&job.user.config.config_path)?;
+
+    let ran_at = match history.get_schedule_ran_at(&job.target)? {
+        Some(ran_at) => ran_at,
+        None => {
+            history.set_schedule_ran_at(&job.target, Utc::now())?;
+            return Ok(None);
+        }
+    };
+
+    Ok(job
+        .schedule
+        .after(&ran_at.with_timezone(&Local))
+        .next()
+        .filter(|missed| *missed < Local::now()))
+}
+
+async fn run_job(job: Job, mut shutdown: watch::Receiver<bool>) {
+    // Like systemd's `Persistent=true`, but only for the accounts that set CATCH_UP_MISSED_RUNS
+    match missed_run(&job) {
+        Ok(Some(missed)) => {
+            let accounts = job
+                .accounts
+                .iter()
+                .filter(|account| job.user.config.accounts[*account].catch_up_missed_runs)
+                .cloned()
+                .collect::<Vec<_>>();
+
+            if !accounts.is_empty() {
+                info!(
+                    "Missed {}'s {} at {}, catching up",
+                    job.user.name, job.target, missed
+                );
+
+                if !run_accounts(&job, &accounts, &shutdown).await {
+                    return;
+                }
+            }
+        }
+        Ok(None) => {}
+        Err(e) => warn!(
+            "Failed to check {}'s {} for a missed run: {:#}",
+            job.user.name, job.target, e
+        ),
+    }
+
+    loop {
+        let next_run = match job.schedule.upcoming(Local).next() {
+            Some(next_run) => next_run,
+            None => {
+                info!(
+                    "{}'s {} has no more scheduled runs",
+                    job.user.name, job.target
+                );
+                return;
+            }
+        };
+
+        let delay = (next_run - Local::now()).to_std().unwrap_or_default();
+
+        tokio::select! {
+            _ = tokio::time::sleep(delay) => {}
+            _ = shutdown.changed() => return,
+        }
+
+        if !run_accounts(&job, &job.accounts, &shutdown).await {
+            return;
+        }
+    }
+}
+
+#[cfg(unix)]
+async fn wait_for_shutdown_signal() -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate())?;
+
+    tokio::select! {
+        _ = sigterm.recv() => info!("Received SIGTERM"),
+        result = tokio::signal::ctrl_c() => {
+            result?;
+            info!("Received SIGINT");
+        }
+    }
+
+    Ok(())
+}
+
+// Without SIGTERM, e.g. on Windows, where a service is stopped with Ctrl+C's event
+#[cfg(not(unix))]
+async fn wait_for_shutdown_signal() -> Result<()> {
+    tokio::signal::ctrl_c().await?;
+    info!("Received Ctrl+C");
+
+    Ok(())
+}
+
+#[derive(Clone, Copy, Debug)]
+enum ServiceState {
+    Ready,
+    Stopping,
+}
+
+// A no-op when not run by systemd
+#[cfg(all(target_os = "linux", feature = "systemd"))]
+fn notify_systemd(state: ServiceState) {
+    let state = match state {
+        ServiceState::Ready => sd_notify::NotifyState::Ready,
+        ServiceState::Stopping => sd_notify::NotifyState::Stopping,
+    };
+
+    if let Err(e) = sd_notify::notify(false, &[state]) {
+        warn!("Failed to notify systemd: {:#?}", e);
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "systemd")))]
+fn notify_systemd(_state: ServiceState) {}
+
+// Runs every user's scheduled profiles & accounts, and the web UI if it's configured, until
+// SIGTERM/SIGINT, then waits up to `shutdown_timeout` for in-flight runs to finish. History &
+// token writes are synced as they're made, so once the runs finish there's nothing left to
+// flush. A run cut off by the timeout is recovered by the account's next run, the same as after
+// a crash.
+pub async fn run(
+    users: Vec<User>,
+    web_ui: Option<WebUiConfig>,
+    shutdown_timeout: Duration,
+) -> Result<()> {
+    let locked_users = users
+        .iter()
+        .map(|user| (Rc::new(user.clone()), Rc::new(Mutex::new(()))))
+        .collect::<Vec<_>>();
+
+    let jobs = get_jobs(&locked_users)?;
+
+    // Push accounts are run as their balances are received, without a schedule
+    let has_push_accounts = web_ui.is_some()
+        && users.iter().any(|user| {
+            user.config
+                .accounts
+                .values()
+                .any(|account_config| account_config.provider == ProviderKind::Push)
+        });
+
+    if jobs.is_empty() && !has_push_accounts {
+        return Err(anyhow!("No schedules are configured"));
+    }
+
+    for user in &users {
+        if let Err(e) = reconcile_dangling_writes(&user.config).await {
+            warn!(
+                "Failed to check {}'s interrupted writes: {:#}",
+                user.name, e
+            );
+        }
+    }
+
+    // Providers' futures aren't required to be `Send`
+    let local = LocalSet::new();
+
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+
+    let mut handles = jobs
+        .into_iter()
+        .map(|job| {
+            info!("Scheduled {}'s {}", job.user.name, job.target);
+            local.spawn_local(run_job(job, shutdown_rx.clone()))
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(web_ui) = web_ui {
+        let (pushed_tx, pushed_rx) = mpsc::unbounded_channel();
+
+        handles.push(local.spawn_local(run_pushed_accounts(
+            locked_users,
+            pushed_rx,
+            shutdown_rx.clone(),
+        )));
+
+        let shutdown_rx = shutdown_rx.clone();
+        handles.push(local.spawn_local(async move {
+            if let Err(e) = web::serve(web_ui, users, pushed_tx, shutdown_rx).await {
+                error!("Web UI failed: {:#?}", e);
+            }
+        }));
+    }
+
+    notify_systemd(ServiceState::Ready);
+
+    local
+        .run_until(async move {
+            wait_for_shutdown_signal().await?;
+
+            notify_systemd(ServiceState::Stopping);
+
+            info!(
+                "Shutting down, waiting up to {}s for in-flight runs",
+                shutdown_timeout.as_secs()
+            );
+
+            let _ = shutdown_tx.send(true);
+
+            let in_flight = async {
+                for handle in handles {
+                    if let Err(e) = handle.await {
+                        error!("Scheduled run panicked: {:#?}", e);
+                    }
+                }
+            };
+
+            if tokio::time::timeout(shutdown_timeout, in_flight)
+                .await
+                .is_err()
+            {
+                warn!("Timed out waiting for in-flight runs, exiting anyway");
+            }
+
+            Ok(())
+        })
+        .await
+}
@@ -0,0 +1,200 @@
+use anyhow::Result;
+use chrono::prelude::*;
+use lettre::{
+    message::header::ContentType, transport::smtp::authentication::Credentials, AsyncSmtpTransport,
+    AsyncTransport, Message, Tokio1Executor,
+};
+use log::info;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::{currency, permissions, ynab::CurrencyFormat, Config};
+
+static DIGEST_FILENAME: &str = "digest.json";
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum DigestMode {
+    #[default]
+    OnFailure,
+    Daily,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct SmtpConfig {
+    pub host: String,
+    pub port: Option<u16>,
+    pub username: String,
+    pub password: SecretString,
+    pub from: String,
+    pub to: String,
+    #[serde(default)]
+    pub mode: DigestMode,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct DigestEntry {
+    pub time: DateTime<Local>,
+    pub provider: String,
+    pub account: Option<String>,
+    pub real_balance: Option<f32>,
+    pub ynab_balance: Option<f32>,
+    pub adjustment: Option<f32>,
+    // Posted apart from the adjustment, with SPLIT_CONTRIBUTIONS
+    #[serde(default)]
+    pub contributions: Option<f32>,
+    // Dividends & interest posted apart from the adjustment, with POST_INCOME
+    #[serde(default)]
+    pub income: Option<f32>,
+    pub error: Option<String>,
+    #[serde(default)]
+    pub warning: Option<String>,
+    // The YNAB budget's, to show the amounts in
+    #[serde(default)]
+    pub currency: Option<CurrencyFormat>,
+    // What a large adjustment changed, with PREVIEW_ADJUSTMENTS_OVER
+    #[serde(default)]
+    pub preview: Option<String>,
+    // How its GOAL_CATEGORY_ID's goal is going
+    #[serde(default)]
+    pub goal_progress: Option<String>,
+}
+
+impl DigestEntry {
+    pub fn new(provider: String) -> Self {
+        DigestEntry {
+            time: Local::now(),
+            provider,
+            account: None,
+            real_balance: None,
+            ynab_balance: None,
+            adjustment: None,
+            contributions: None,
+            income: None,
+            error: None,
+            warning: None,
+            currency: None,
+            preview: None,
+            goal_progress: None,
+        }
+    }
+}
+
+pub async fn record(config: &Config, entry: DigestEntry) -> Result<()> {
+    let smtp = match &config.smtp {
+        Some(smtp) => smtp,
+        None => return Ok(()),
+    };
+
+    match smtp.mode {
+        DigestMode::OnFailure => {
+            if entry.error.is_some() || entry.warning.is_some() {
+                send(smtp, &[entry]).await?;
+            }
+            Ok(())
+        }
+        DigestMode::Daily => {
+            let digest_path = format!("{}/{}", config.config_path, DIGEST_FILENAME);
+
+            let mut entries = match std::fs::read(&digest_path) {
+                Ok(digest_file) => serde_json::from_slice::<Vec<DigestEntry>>(&digest_file)?,
+                Err(_) => vec![],
+            };
+
+            let today = entry.time.date_naive();
+
+            // Entries from previous days are only cleared once their digest has been delivered,
+            // so a failed send is retried on the next run
+            if entries.iter().any(|e| e.time.date_naive() < today) {
+                send(smtp, &entries).await?;
+                entries.clear();
+            }
+
+            entries.push(entry);
+
+            permissions::write_private(digest_path, serde_json::to_string(&entries)?)?;
+
+            Ok(())
+        }
+    }
+}
+
+async fn send(smtp: &SmtpConfig, entries: &[DigestEntry]) -> Result<()> {
+    let errors = entries.iter().filter(|e| e.error.is_some()).count();
+
+    let subject = if errors == 0 {
+        format!("YNAB updater: {} runs", entries.len())
+    } else {
+        format!("YNAB updater: {} runs, {} failed", entries.len(), errors)
+    };
+
+    let email = Message::builder()
+        .from(smtp.from.parse()?)
+        .to(smtp.to.parse()?)
+        .subject(subject)
+        .header(ContentType::TEXT_HTML)
+        .body(render_html(entries))?;
+
+    let mut transport =
+        AsyncSmtpTransport::<Tokio1Executor>::relay(&smtp.host)?.credentials(Credentials::new(
+            smtp.username.clone(),
+            smtp.password.expose_secret().to_owned(),
+        ));
+    if let Some(port) = smtp.port {
+        transport = transport.port(port);
+    }
+
+    let response = transport.build().send(email).await?;
+
+    info!("SMTP response {:#?}", response.code());
+
+    Ok(())
+}
+
+fn render_html(entries: &[DigestEntry]) -> String {
+    let rows = entries
+        .iter()
+        .map(|e| {
+            let fmt_amount = |a: Option<f32>| {
+                a.map(|a| currency::format(e.currency.as_ref(), a))
+                    .unwrap_or_default()
+            };
+
+            format!(
+                r#"<tr{}><td>{}</td><td>{}</td><td>{}</td><td align="right">{}</td><td align="right">{}</td><td align="right">{}</td><td>{}</td></tr>"#,
+                if e.error.is_some() {
+                    r#" style="background-color:#fdecea""#
+                } else if e.warning.is_some() {
+                    r#" style="background-color:#fff8e1""#
+                } else {
+                    ""
+                },
+                e.time.format("%Y-%m-%d %H:%M"),
+                escape(&e.provider),
+                escape(e.account.as_deref().unwrap_or_default()),
+                fmt_amount(e.real_balance),
+                fmt_amount(e.ynab_balance),
+                fmt_amount(e.adjustment),
+                escape(
+                    e.error
+                        .as_deref()
+                        .or(e.warning.as_deref())
+                        .unwrap_or_default()
+                ),
+            )
+        })
+        .collect::<String>();
+
+    format!(
+        r#"<html><body style="font-family:sans-serif"><table cellpadding="6" style="border-collapse:collapse"><thead><tr style="background-color:#eeeeee"><th>Time</th><th>Provider</th><th>Account</th><th>Real Balance</th><th>YNAB Balance</th><th>Adjustment</th><th>Notes</th></tr></thead><tbody>{}</tbody></table></body></html>"#,
+        rows
+    )
+}
+
+pub(crate) fn escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
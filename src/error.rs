@@ -0,0 +1,85 @@
+use anyhow::Result;
+use reqwest::{Response, StatusCode};
+use std::fmt;
+
+use crate::ynab::{ErrorDetail, ErrorResponse};
+
+#[derive(Clone, Debug)]
+pub struct YnabError {
+    pub status: StatusCode,
+    pub url: String,
+    // `None` when the body wasn't a YNAB error, e.g. from a proxy in front of the API
+    pub error: Option<ErrorDetail>,
+}
+
+impl fmt::Display for YnabError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.error {
+            Some(error) => write!(
+                f,
+                "YNAB API error {} ({}): {}",
+                self.status, error.name, error.detail
+            ),
+            None => write!(f, "YNAB API error {} for {}", self.status, self.url),
+        }
+    }
+}
+
+impl std::error::Error for YnabError {}
+
+// Whether YNAB couldn't be reached at all or is failing, as opposed to rejecting the request
+pub fn is_unreachable(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause
+            .downcast_ref::<reqwest::Error>()
+            .is_some_and(|e| e.is_connect() || e.is_timeout())
+            || cause
+                .downcast_ref::<YnabError>()
+                .is_some_and(|e| e.status.is_server_error())
+    })
+}
+
+// Whether the error came from YNAB, rather than a provider, e.g. for the process's exit code
+pub fn is_ynab_error(e: &anyhow::Error) -> bool {
+    e.chain().any(|cause| {
+        cause.is::<YnabError>()
+            || cause
+                .downcast_ref::<reqwest::Error>()
+                .and_then(|e| e.url())
+                .is_some_and(|url| url.host_str() == Some("api.ynab.com"))
+    })
+}
+
+// Attached to an error caused by the config, e.g. an account missing one of its provider's
+// settings
+#[derive(Clone, Copy, Debug)]
+pub struct ConfigInvalid;
+
+impl fmt::Display for ConfigInvalid {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Invalid config")
+    }
+}
+
+pub trait YnabResponseExt: Sized {
+    // Like `error_for_status`, but keeps the YNAB error body's detail
+    async fn ynab_error_for_status(self) -> Result<Self>;
+}
+
+impl YnabResponseExt for Response {
+    async fn ynab_error_for_status(self) -> Result<Self> {
+        if self.status().is_success() {
+            return Ok(self);
+        }
+
+        let status = self.status();
+        let url = self.url().to_string();
+        let error = self
+            .json::<ErrorResponse>()
+            .await
+            .ok()
+            .map(|response| response.error);
+
+        Err(YnabError { status, url, error }.into())
+    }
+}
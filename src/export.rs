@@ -0,0 +1,136 @@
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use log::info;
+use serde::Deserialize;
+use std::{fs::OpenOptions, io::Write, path::Path};
+
+use crate::{report::RunReport, ynab::CurrencyFormat, Config};
+
+// Each reconciliation appended to a file, for plain text accounting alongside YNAB, e.g.
+// `[export] PATH = "/home/me/finance/ynab.journal"`. Accounts are posted to their
+// `EXPORT_ACCOUNT`, `Assets:<account>` by default, against `COUNTERPARTY`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct ExportConfig {
+    pub path: String,
+    #[serde(default)]
+    pub format: ExportFormat,
+    #[serde(default = "default_counterparty")]
+    pub counterparty: String,
+}
+
+fn default_counterparty() -> String {
+    "Equity:Reconciliation".to_owned()
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ExportFormat {
+    // A ledger-cli/hledger journal entry
+    #[default]
+    Ledger,
+    // A row for GnuCash's CSV transaction import, mapping its columns by their headings
+    Csv,
+}
+
+static CSV_HEADER: &str = "Date,Description,Account,Transfer Account,Amount,Notes\n";
+
+fn csv_field(field: &str) -> String {
+    if field.contains([',', '"', '\n']) {
+        format!("\"{}\"", field.replace('"', "\"\""))
+    } else {
+        field.to_owned()
+    }
+}
+
+fn entry(
+    config: &ExportConfig,
+    account: &str,
+    report: &RunReport,
+    adjustment: f32,
+    currency: Option<&CurrencyFormat>,
+) -> String {
+    let date = Local::now().date_naive();
+    let description = format!("Reconciliation of {}", report.account);
+    let notes = report
+        .run_id
+        .as_ref()
+        .map(|run_id| format!("run {}", run_id))
+        .unwrap_or_default();
+
+    match config.format {
+        ExportFormat::Ledger => {
+            let amount = match currency {
+                Some(currency) => format!("{:.2} {}", adjustment, currency.iso_code),
+                None => format!("{:.2}", adjustment),
+            };
+            let comment = match notes.as_str() {
+                "" => String::new(),
+                notes => format!("  ; {}", notes),
+            };
+
+            format!(
+                "\n{} * {}{}\n    {}  {}\n    {}\n",
+                date, description, comment, account, amount, config.counterparty
+            )
+        }
+        ExportFormat::Csv => format!(
+            "{},{},{},{},{:.2},{}\n",
+            date,
+            csv_field(&description),
+            csv_field(account),
+            csv_field(&config.counterparty),
+            adjustment,
+            csv_field(&notes)
+        ),
+    }
+}
+
+fn append(
+    config: &ExportConfig,
+    account: &str,
+    report: &RunReport,
+    adjustment: f32,
+    currency: Option<&CurrencyFormat>,
+) -> Result<()> {
+    let is_new = !Path::new(&config.path).exists();
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&config.path)?;
+
+    let mut contents = String::new();
+    if is_new && config.format == ExportFormat::Csv {
+        contents.push_str(CSV_HEADER);
+    }
+    contents.push_str(&entry(config, account, report, adjustment, currency));
+
+    // In one write, so entries from concurrent runs can't interleave
+    file.write_all(contents.as_bytes())?;
+    file.sync_all()?;
+
+    info!("Exported the {} adjustment to {}", adjustment, config.path);
+
+    Ok(())
+}
+
+pub fn export(
+    config: &Config,
+    report: &RunReport,
+    adjustment: f32,
+    currency: Option<&CurrencyFormat>,
+) -> Result<()> {
+    let export = config
+        .export
+        .as_ref()
+        .ok_or_else(|| anyhow!("[export] isn't set"))?;
+
+    let account = config
+        .accounts
+        .get(&report.account)
+        .and_then(|account_config| account_config.export_account.clone())
+        .unwrap_or_else(|| format!("Assets:{}", report.account));
+
+    append(export, &account, report, adjustment, currency)
+}
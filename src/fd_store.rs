@@ -0,0 +1,444 @@
+// With `SECRETS = "fd_store"` the token caches & the callback's key never touch the disk. Each is
+// kept in a memfd that's handed to systemd's fd store, which gives them back to the daemon when it
+// restarts, so they last until the machine reboots. Only the daemon can use it, run by systemd with
+// `FileDescriptorStoreMax=` set, e.g. to 64.
+
+use anyhow::{anyhow, Result};
+use chrono::prelude::*;
+use serde::Deserialize;
+use std::sync::OnceLock;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretsStorage {
+    // In the config directories
+    #[default]
+    Files,
+    FdStore,
+}
+
+static STORAGE: OnceLock<SecretsStorage> = OnceLock::new();
+
+// Set once, from the config, before any secret's read. The stored fds are taken straight away, so
+// they're closed on exec rather than inherited by e.g. an `exec` provider's command.
+pub fn init(storage: SecretsStorage) {
+    let _ = STORAGE.set(storage);
+
+    if storage == SecretsStorage::FdStore {
+        store::preload();
+    }
+}
+
+pub fn is_enabled() -> bool {
+    STORAGE.get() == Some(&SecretsStorage::FdStore)
+}
+
+// e.g. `users/alice/ynab_token.json`, from its path in the config directory. systemd only allows
+// printable ASCII other than `:`, up to 255 characters, some of which are the generation's.
+fn fd_name(path: &str) -> Result<String> {
+    let config_path = std::env::var("YNAB_CONFIG_PATH")?;
+    let name = path
+        .strip_prefix(&config_path)
+        .unwrap_or(path)
+        .trim_start_matches('/');
+
+    if name.len() > 255 - "#18446744073709551615".len()
+        || !name.chars().all(|c| c.is_ascii_graphic() && c != ':')
+    {
+        return Err(anyhow!("{} can't be named in systemd's fd store", name));
+    }
+
+    Ok(name.to_owned())
+}
+
+#[cfg(all(target_os = "linux", feature = "systemd"))]
+mod store {
+    use anyhow::{anyhow, Context, Result};
+    use chrono::prelude::*;
+    use log::{info, warn};
+    use sd_notify::NotifyState;
+    use std::{
+        collections::HashMap,
+        ffi::CString,
+        fs::File,
+        io::{Read, Seek, SeekFrom, Write},
+        os::fd::{AsFd, FromRawFd},
+        sync::{Mutex, MutexGuard, OnceLock},
+    };
+
+    // A secret's memfd, stored under its name & generation, e.g. `ynab_token.json#3`. systemd
+    // removes every fd with a name, so each write's is a new generation that's handed over before
+    // the old one's removed.
+    struct Stored {
+        generation: u64,
+        file: File,
+    }
+
+    fn stored_name(name: &str, generation: u64) -> String {
+        format!("{}#{}", name, generation)
+    }
+
+    // The latest generation of each secret, & the stored names of older ones a write didn't get
+    // to remove. One without a generation is the first.
+    fn latest(fds: impl Iterator<Item = (String, File)>) -> (HashMap<String, Stored>, Vec<String>) {
+        let mut latest = HashMap::<String, Stored>::new();
+        let mut stale = vec![];
+
+        for (stored, file) in fds {
+            let (name, generation) = stored
+                .rsplit_once('#')
+                .and_then(|(name, generation)| Some((name, generation.parse().ok()?)))
+                .unwrap_or((stored.as_str(), 0));
+
+            match latest.get(name) {
+                Some(newer) if newer.generation > generation => stale.push(stored),
+                _ => {
+                    if let Some(older) = latest.insert(name.to_owned(), Stored { generation, file })
+                    {
+                        stale.push(stored_name(name, older.generation));
+                    }
+                }
+            }
+        }
+
+        (latest, stale)
+    }
+
+    // The memfds systemd handed over when the daemon started, & those written since
+    fn fds() -> Result<MutexGuard<'static, HashMap<String, Stored>>> {
+        static FDS: OnceLock<Mutex<HashMap<String, Stored>>> = OnceLock::new();
+
+        if std::env::var_os("NOTIFY_SOCKET").is_none() {
+            return Err(anyhow!(
+                "SECRETS = \"fd_store\" only works in the daemon, run by systemd with FileDescriptorStoreMax set"
+            ));
+        }
+
+        FDS.get_or_init(|| {
+            let (fds, stale) = sd_notify::listen_fds_with_names(true)
+                .map(|fds| {
+                    latest(fds.map(|(fd, name)| {
+                        // SAFETY: systemd passes each fd to this process alone
+                        (name, unsafe { File::from_raw_fd(fd) })
+                    }))
+                })
+                .unwrap_or_default();
+
+            if !fds.is_empty() {
+                info!("Restored {} secrets from systemd's fd store", fds.len());
+            }
+
+            for stored in stale {
+                if let Err(e) = remove_stored(&stored) {
+                    warn!("{:#}", e);
+                }
+            }
+
+            Mutex::new(fds)
+        })
+        .lock()
+        .map_err(|_| anyhow!("The fd store's lock is poisoned"))
+    }
+
+    pub fn preload() {
+        drop(fds());
+    }
+
+    // Each memfd holds when it was written, on its first line, then the secret
+    pub fn read(name: &str) -> Result<Option<(Vec<u8>, DateTime<Utc>)>> {
+        let mut fds = fds()?;
+
+        let Some(Stored { file, .. }) = fds.get_mut(name) else {
+            return Ok(None);
+        };
+
+        let mut contents = vec![];
+        file.seek(SeekFrom::Start(0))?;
+        file.read_to_end(&mut contents)?;
+
+        let newline = contents
+            .iter()
+            .position(|byte| *byte == b'\n')
+            .ok_or_else(|| anyhow!("{} in the fd store has no written time", name))?;
+        let written_at =
+            DateTime::parse_from_rfc3339(std::str::from_utf8(&contents[..newline])?)?.into();
+
+        Ok(Some((contents[newline + 1..].to_vec(), written_at)))
+    }
+
+    fn remove_stored(stored: &str) -> Result<()> {
+        sd_notify::notify(
+            false,
+            &[NotifyState::FdStoreRemove, NotifyState::FdName(stored)],
+        )
+        .with_context(|| format!("Failed to remove {} from systemd's fd store", stored))
+    }
+
+    // The old secret's only removed once the new one's stored, so a failed write leaves it in
+    // place
+    pub fn write(name: &str, secret: &[u8]) -> Result<()> {
+        let memfd_name = CString::new(name)?;
+        // SAFETY: the name is a valid C string
+        let fd = unsafe { libc::memfd_create(memfd_name.as_ptr(), libc::MFD_CLOEXEC) };
+        if fd < 0 {
+            return Err(std::io::Error::last_os_error()).context("Failed to create a memfd");
+        }
+        // SAFETY: memfd_create returned a new fd
+        let mut file = unsafe { File::from_raw_fd(fd) };
+
+        writeln!(file, "{}", Utc::now().to_rfc3339())?;
+        file.write_all(secret)?;
+
+        let mut fds = fds()?;
+
+        let generation = fds.get(name).map_or(0, |old| old.generation + 1);
+
+        sd_notify::notify_with_fds(
+            false,
+            &[
+                NotifyState::FdStore,
+                NotifyState::FdName(&stored_name(name, generation)),
+            ],
+            &[file.as_fd()],
+        )
+        .context("Failed to hand a secret to systemd's fd store")?;
+
+        // The new one's read from now on, & an old one that isn't removed is when the daemon next
+        // starts
+        if let Some(old) = fds.insert(name.to_owned(), Stored { generation, file }) {
+            if let Err(e) = remove_stored(&stored_name(name, old.generation)) {
+                warn!("{:#}", e);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub fn remove(name: &str) -> Result<()> {
+        if let Some(old) = fds()?.remove(name) {
+            remove_stored(&stored_name(name, old.generation))?;
+        }
+
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use std::{
+            mem,
+            os::{
+                fd::{AsRawFd, RawFd},
+                unix::net::UnixDatagram,
+            },
+            path::PathBuf,
+        };
+
+        // NOTIFY_SOCKET is the process's, so only one test points it at its socket at a time
+        static NOTIFY_SOCKET: Mutex<()> = Mutex::new(());
+
+        // Stands in for systemd's notify socket
+        struct FakeNotifySocket {
+            path: PathBuf,
+            socket: UnixDatagram,
+        }
+
+        impl FakeNotifySocket {
+            fn bind(name: &str) -> Self {
+                let path = std::env::temp_dir().join(format!(
+                    "ynab-updater-notify-{}-{}",
+                    std::process::id(),
+                    name
+                ));
+                let _ = std::fs::remove_file(&path);
+
+                let socket = UnixDatagram::bind(&path).unwrap();
+                socket.set_nonblocking(true).unwrap();
+                std::env::set_var("NOTIFY_SOCKET", &path);
+
+                FakeNotifySocket { path, socket }
+            }
+
+            // The next message's text & the fds sent with it, with SCM_RIGHTS
+            fn receive(&self) -> Option<(String, Vec<File>)> {
+                let mut buf = [0u8; 1024];
+                let mut control = [0u8; 64];
+                let mut iov = libc::iovec {
+                    iov_base: buf.as_mut_ptr().cast(),
+                    iov_len: buf.len(),
+                };
+                // SAFETY: a zeroed msghdr is valid, & its buffers outlive the recvmsg
+                let mut message: libc::msghdr = unsafe { mem::zeroed() };
+                message.msg_iov = &mut iov;
+                message.msg_iovlen = 1;
+                message.msg_control = control.as_mut_ptr().cast();
+                message.msg_controllen = control.len() as _;
+
+                // SAFETY: the socket's open & the msghdr's buffers are valid
+                let len = unsafe { libc::recvmsg(self.socket.as_raw_fd(), &mut message, 0) };
+                if len < 0 {
+                    return None;
+                }
+
+                let mut files = vec![];
+                // SAFETY: the control messages are within `control`, as recvmsg filled it
+                unsafe {
+                    let mut header = libc::CMSG_FIRSTHDR(&message);
+                    while !header.is_null() {
+                        if (*header).cmsg_level == libc::SOL_SOCKET
+                            && (*header).cmsg_type == libc::SCM_RIGHTS
+                        {
+                            let data = libc::CMSG_DATA(header) as *const RawFd;
+                            let count = ((*header).cmsg_len as usize - libc::CMSG_LEN(0) as usize)
+                                / mem::size_of::<RawFd>();
+                            for i in 0..count {
+                                files.push(File::from_raw_fd(data.add(i).read_unaligned()));
+                            }
+                        }
+                        header = libc::CMSG_NXTHDR(&message, header);
+                    }
+                }
+
+                Some((
+                    String::from_utf8(buf[..len as usize].to_vec()).unwrap(),
+                    files,
+                ))
+            }
+        }
+
+        impl Drop for FakeNotifySocket {
+            fn drop(&mut self) {
+                let _ = std::fs::remove_file(&self.path);
+            }
+        }
+
+        fn contents(mut file: &File) -> String {
+            let mut contents = String::new();
+            file.seek(SeekFrom::Start(0)).unwrap();
+            file.read_to_string(&mut contents).unwrap();
+            contents
+        }
+
+        #[test]
+        fn stores_a_new_secret_before_removing_the_old() {
+            let _lock = NOTIFY_SOCKET.lock().unwrap();
+            let systemd = FakeNotifySocket::bind("replace");
+
+            write("test/replace.json", b"first").unwrap();
+
+            let (message, files) = systemd.receive().unwrap();
+            assert_eq!(message, "FDSTORE=1\nFDNAME=test/replace.json#0\n");
+            assert_eq!(files.len(), 1);
+            assert!(contents(&files[0]).ends_with("\nfirst"));
+            assert!(systemd.receive().is_none());
+
+            write("test/replace.json", b"second").unwrap();
+
+            let (message, files) = systemd.receive().unwrap();
+            assert_eq!(message, "FDSTORE=1\nFDNAME=test/replace.json#1\n");
+            assert_eq!(files.len(), 1);
+            assert!(contents(&files[0]).ends_with("\nsecond"));
+
+            let (message, files) = systemd.receive().unwrap();
+            assert_eq!(message, "FDSTOREREMOVE=1\nFDNAME=test/replace.json#0\n");
+            assert!(files.is_empty());
+            assert!(systemd.receive().is_none());
+
+            assert_eq!(read("test/replace.json").unwrap().unwrap().0, b"second");
+
+            remove("test/replace.json").unwrap();
+
+            let (message, _) = systemd.receive().unwrap();
+            assert_eq!(message, "FDSTOREREMOVE=1\nFDNAME=test/replace.json#1\n");
+            assert!(read("test/replace.json").unwrap().is_none());
+        }
+
+        #[test]
+        fn a_failed_store_keeps_the_old_secret() {
+            let _lock = NOTIFY_SOCKET.lock().unwrap();
+            let systemd = FakeNotifySocket::bind("failed");
+
+            write("test/failed.json", b"first").unwrap();
+            systemd.receive().unwrap();
+
+            // systemd's gone, so the new one can't be handed over
+            drop(systemd);
+            assert!(write("test/failed.json", b"second").is_err());
+
+            let systemd = FakeNotifySocket::bind("failed");
+            assert!(systemd.receive().is_none());
+            assert_eq!(read("test/failed.json").unwrap().unwrap().0, b"first");
+
+            // The next write's the generation after the one that's stored
+            write("test/failed.json", b"third").unwrap();
+            let (message, _) = systemd.receive().unwrap();
+            assert_eq!(message, "FDSTORE=1\nFDNAME=test/failed.json#1\n");
+            let (message, _) = systemd.receive().unwrap();
+            assert_eq!(message, "FDSTOREREMOVE=1\nFDNAME=test/failed.json#0\n");
+        }
+
+        #[test]
+        fn restores_the_latest_generation_of_each_secret() {
+            let fd = || File::open("/dev/null").unwrap();
+
+            let (latest, mut stale) = latest(
+                [
+                    ("a.json#1", fd()),
+                    ("a.json#0", fd()),
+                    ("b.json", fd()),
+                    ("c.json#2", fd()),
+                    ("c.json#3", fd()),
+                ]
+                .into_iter()
+                .map(|(name, file)| (name.to_owned(), file)),
+            );
+
+            let mut generations = latest
+                .iter()
+                .map(|(name, stored)| (name.as_str(), stored.generation))
+                .collect::<Vec<_>>();
+            generations.sort();
+            assert_eq!(generations, [("a.json", 1), ("b.json", 0), ("c.json", 3)]);
+
+            stale.sort();
+            assert_eq!(stale, ["a.json#0", "c.json#2"]);
+        }
+    }
+}
+
+#[cfg(not(all(target_os = "linux", feature = "systemd")))]
+mod store {
+    use anyhow::{anyhow, Result};
+    use chrono::prelude::*;
+
+    pub fn preload() {}
+
+    fn unsupported() -> anyhow::Error {
+        anyhow!("SECRETS = \"fd_store\" needs Linux & the systemd feature")
+    }
+
+    pub fn read(_name: &str) -> Result<Option<(Vec<u8>, DateTime<Utc>)>> {
+        Err(unsupported())
+    }
+
+    pub fn write(_name: &str, _secret: &[u8]) -> Result<()> {
+        Err(unsupported())
+    }
+
+    pub fn remove(_name: &str) -> Result<()> {
+        Err(unsupported())
+    }
+}
+
+// The secret at `path` in the config directory, along with when it was written
+pub fn read(path: &str) -> Result<Option<(Vec<u8>, DateTime<Utc>)>> {
+    store::read(&fd_name(path)?)
+}
+
+pub fn write(path: &str, secret: &[u8]) -> Result<()> {
+    store::write(&fd_name(path)?, secret)
+}
+
+pub fn remove(path: &str) -> Result<()> {
+    store::remove(&fd_name(path)?)
+}
@@ -0,0 +1,140 @@
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use log::info;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::{http, report::RunReport, Config};
+
+// Each reconciliation mirrored into a Firefly III instance, for running it alongside YNAB or
+// moving between them, e.g. `[firefly] URL = "https://firefly.example.com"` with a personal
+// access token as `TOKEN`. Accounts are mirrored to the Firefly asset account given by their
+// `FIREFLY_ACCOUNT_ID`, as a deposit or withdrawal of the adjustment, so Firefly's balance moves
+// with YNAB's. Nothing else is imported, so there's nothing else to mirror.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct FireflyConfig {
+    pub url: String,
+    pub token: SecretString,
+    // The other side of each adjustment, created by Firefly if it doesn't exist
+    #[serde(default = "default_counterparty")]
+    pub counterparty: String,
+}
+
+fn default_counterparty() -> String {
+    "Reconciliation Balance Adjustment".to_owned()
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct TransactionSplit {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    date: String,
+    // Always positive, the type says which way it went
+    amount: String,
+    description: String,
+    source_id: Option<String>,
+    source_name: Option<String>,
+    destination_id: Option<String>,
+    destination_name: Option<String>,
+    // The YNAB transaction, so the two can be matched up
+    external_id: Option<String>,
+    notes: Option<String>,
+}
+
+#[derive(Clone, Debug, Serialize)]
+struct StoreTransaction {
+    // So a transaction identical to one Firefly already has is refused rather than doubled
+    error_if_duplicate_hash: bool,
+    transactions: Vec<TransactionSplit>,
+}
+
+async fn mirror_adjustment(
+    config: &Config,
+    firefly: &FireflyConfig,
+    firefly_account_id: &str,
+    report: &RunReport,
+    adjustment: f32,
+) -> Result<()> {
+    let (kind, source_id, source_name, destination_id, destination_name) = if adjustment >= 0.0 {
+        (
+            "deposit",
+            None,
+            Some(firefly.counterparty.clone()),
+            Some(firefly_account_id.to_owned()),
+            None,
+        )
+    } else {
+        (
+            "withdrawal",
+            Some(firefly_account_id.to_owned()),
+            None,
+            None,
+            Some(firefly.counterparty.clone()),
+        )
+    };
+
+    let transaction = StoreTransaction {
+        error_if_duplicate_hash: true,
+        transactions: vec![TransactionSplit {
+            kind,
+            date: Local::now().date_naive().to_string(),
+            amount: format!("{:.2}", adjustment.abs()),
+            description: format!("Reconciliation of {}", report.account),
+            source_id,
+            source_name,
+            destination_id,
+            destination_name,
+            external_id: report.transaction_id.clone(),
+            notes: report
+                .run_id
+                .as_ref()
+                .map(|run_id| format!("Run {}", run_id)),
+        }],
+    };
+
+    let client = http::client(&config.http)?;
+
+    let response = http::send(
+        &client,
+        client
+            .post(format!(
+                "{}/api/v1/transactions",
+                firefly.url.trim_end_matches('/')
+            ))
+            .bearer_auth(firefly.token.expose_secret())
+            .header(reqwest::header::ACCEPT, "application/vnd.api+json")
+            .json(&transaction),
+    )
+    .await?;
+
+    if !response.status().is_success() {
+        return Err(anyhow!(
+            "Firefly III responded {}: {}",
+            response.status(),
+            response.text().await?
+        ));
+    }
+
+    info!(
+        "Mirrored the {} adjustment to Firefly III",
+        report.adjustment.unwrap_or_default()
+    );
+
+    Ok(())
+}
+
+pub async fn mirror(config: &Config, report: &RunReport, adjustment: f32) -> Result<()> {
+    let firefly = config
+        .firefly
+        .as_ref()
+        .ok_or_else(|| anyhow!("[firefly] isn't set"))?;
+
+    let firefly_account_id = config
+        .accounts
+        .get(&report.account)
+        .and_then(|account_config| account_config.firefly_account_id.as_deref())
+        .ok_or_else(|| anyhow!("{} has no FIREFLY_ACCOUNT_ID", report.account))?;
+
+    mirror_adjustment(config, firefly, firefly_account_id, report, adjustment).await
+}
@@ -0,0 +1,171 @@
+use anyhow::{anyhow, Result};
+use chrono::{prelude::*, Duration};
+use log::{info, warn};
+use serde::Deserialize;
+use serde_json::Value;
+use std::fmt;
+
+use crate::{history::History, http, ynab::CurrencyFormat, Config};
+
+// Rates are looked up with `{from}` & `{to}` replaced by the currencies' ISO codes, e.g. `USD` &
+// `GBP`, and read from the response at `RATE_JSON_POINTER`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct FxConfig {
+    #[serde(default = "default_rates_url")]
+    pub rates_url: String,
+    #[serde(default = "default_rate_json_pointer")]
+    pub rate_json_pointer: String,
+    // A cached rate is used as it is for this long
+    #[serde(default = "default_ttl_hours")]
+    pub ttl_hours: i64,
+    // And when the rates can't be fetched, until it's this old. After that the balance isn't
+    // converted at all, since reconciling with an ancient rate would look right & be wrong.
+    #[serde(default = "default_max_age_days")]
+    pub max_age_days: i64,
+}
+
+impl Default for FxConfig {
+    fn default() -> Self {
+        FxConfig {
+            rates_url: default_rates_url(),
+            rate_json_pointer: default_rate_json_pointer(),
+            ttl_hours: default_ttl_hours(),
+            max_age_days: default_max_age_days(),
+        }
+    }
+}
+
+fn default_rates_url() -> String {
+    "https://api.frankfurter.app/latest?from={from}&to={to}".to_owned()
+}
+
+fn default_rate_json_pointer() -> String {
+    "/rates/{to}".to_owned()
+}
+
+fn default_ttl_hours() -> i64 {
+    12
+}
+
+fn default_max_age_days() -> i64 {
+    7
+}
+
+// Returned when there's no rate recent enough to convert with, which skips the run with a warning
+#[derive(Clone, Debug)]
+pub struct FxRateStale {
+    pub from: String,
+    pub to: String,
+    pub fetched_at: Option<DateTime<Utc>>,
+}
+
+impl fmt::Display for FxRateStale {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.fetched_at {
+            Some(fetched_at) => write!(
+                f,
+                "Failed to fetch the {} to {} rate, and the last one is from {}, skipping",
+                self.from, self.to, fetched_at
+            ),
+            None => write!(
+                f,
+                "Failed to fetch the {} to {} rate, skipping",
+                self.from, self.to
+            ),
+        }
+    }
+}
+
+impl std::error::Error for FxRateStale {}
+
+fn fill(template: &str, from: &str, to: &str) -> String {
+    template.replace("{from}", from).replace("{to}", to)
+}
+
+async fn fetch_rate(config: &Config, from: &str, to: &str) -> Result<f64> {
+    let fx = &config.fx;
+
+    let client = http::client(&config.http)?;
+
+    let response = http::send(&client, client.get(fill(&fx.rates_url, from, to)))
+        .await?
+        .error_for_status()?
+        .json::<Value>()
+        .await?;
+
+    let json_pointer = fill(&fx.rate_json_pointer, from, to);
+
+    response
+        .pointer(&json_pointer)
+        .and_then(|rate| rate.as_f64())
+        .ok_or_else(|| anyhow!("The rates have no number at {}", json_pointer))
+}
+
+// The amount in `from` converted to `to`, with a cached rate if it's recent enough
+// An amount in an account's CURRENCY, if it has one, converted to the budget's
+pub async fn to_budget_currency(
+    config: &Config,
+    amount: f32,
+    from: Option<&str>,
+    currency: Option<&CurrencyFormat>,
+) -> Result<f32> {
+    match from {
+        Some(from) => {
+            let to = currency
+                .map(|c| c.iso_code.as_str())
+                .ok_or_else(|| anyhow!("The YNAB budget has no currency to convert {} to", from))?;
+
+            convert(config, amount, from, to).await
+        }
+        None => Ok(amount),
+    }
+}
+
+pub async fn convert(config: &Config, amount: f32, from: &str, to: &str) -> Result<f32> {
+    if from.eq_ignore_ascii_case(to) {
+        return Ok(amount);
+    }
+
+    let history = History::open(&config.config_path)?;
+
+    let cached = history.get_fx_rate(from, to)?;
+
+    let rate = match cached {
+        Some((rate, fetched_at))
+            if Utc::now() - fetched_at < Duration::hours(config.fx.ttl_hours) =>
+        {
+            rate
+        }
+        _ => match fetch_rate(config, from, to).await {
+            Ok(rate) => {
+                history.set_fx_rate(from, to, rate)?;
+                rate
+            }
+            Err(e) => {
+                warn!("Failed to fetch the {} to {} rate: {:#}", from, to, e);
+
+                match cached {
+                    Some((rate, fetched_at))
+                        if Utc::now() - fetched_at < Duration::days(config.fx.max_age_days) =>
+                    {
+                        warn!("Using the rate from {}", fetched_at);
+                        rate
+                    }
+                    _ => {
+                        return Err(FxRateStale {
+                            from: from.to_owned(),
+                            to: to.to_owned(),
+                            fetched_at: cached.map(|(_, fetched_at)| fetched_at),
+                        }
+                        .into())
+                    }
+                }
+            }
+        },
+    };
+
+    info!("Converting {} {} at {} to {}", amount, from, rate, to);
+
+    Ok((amount as f64 * rate) as f32)
+}
@@ -0,0 +1,60 @@
+use anyhow::{anyhow, Result};
+use log::{info, warn};
+
+use crate::{
+    budget::Budget,
+    currency,
+    ynab::{Category, CurrencyFormat},
+    Config,
+};
+
+// How the category's goal is going, e.g. `House deposit: 64% of £20,000.00`, or its balance when
+// it hasn't a goal
+fn describe(category: &Category, currency: Option<&CurrencyFormat>) -> String {
+    let balance = currency::format(currency, category.balance as f32 / 1000.0);
+
+    match (
+        &category.goal_type,
+        category.goal_percentage_complete,
+        category.goal_target,
+    ) {
+        (Some(_), Some(percent), Some(target)) if target > 0 => format!(
+            "{}: {}% of {}",
+            category.name,
+            percent,
+            currency::format(currency, target as f32 / 1000.0)
+        ),
+        (Some(_), Some(percent), _) => format!("{}: {}% funded", category.name, percent),
+        _ => format!("{}: {}", category.name, balance),
+    }
+}
+
+async fn read(budget: &Budget, category_id: &str) -> Result<Category> {
+    match budget {
+        Budget::Ynab(ynab) => ynab.get_category(category_id).await,
+        Budget::Actual(_) => Err(anyhow!("Actual budgets have no goals to read")),
+    }
+}
+
+// The progress of the account's GOAL_CATEGORY_ID, for the run's notification. It's only a nudge,
+// so failing to read it is only warned about.
+pub async fn progress(
+    config: &Config,
+    budget: &Budget,
+    account: &str,
+    currency: Option<&CurrencyFormat>,
+) -> Option<String> {
+    let category_id = config.accounts.get(account)?.goal_category_id.as_deref()?;
+
+    match read(budget, category_id).await {
+        Ok(category) => {
+            let progress = describe(&category, currency);
+            info!("Goal progress: {}", progress);
+            Some(progress)
+        }
+        Err(e) => {
+            warn!("Failed to read {}'s goal: {:#}", account, e);
+            None
+        }
+    }
+}
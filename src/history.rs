@@ -0,0 +1,1336 @@
+use anyhow::{anyhow, Result};
+use chrono::prelude::*;
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{fmt, path::Path, str::FromStr};
+
+use crate::{permissions, report::RunReport};
+
+static HISTORY_FILENAME: &str = "history.db";
+
+static MIGRATIONS: &[&str] = &["ALTER TABLE last_run ADD COLUMN run_id TEXT"];
+
+static SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS pending_auth (
+    provider TEXT PRIMARY KEY,
+    state TEXT NOT NULL,
+    login_uri TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS account_status (
+    ynab_account_id TEXT PRIMARY KEY,
+    needs_reconfiguration_reason TEXT,
+    updated_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pending_manual_login (
+    provider TEXT PRIMARY KEY,
+    state TEXT NOT NULL,
+    title TEXT NOT NULL,
+    login_uri TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pending_otp (
+    provider TEXT PRIMARY KEY,
+    state TEXT NOT NULL,
+    title TEXT NOT NULL,
+    prompt TEXT NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS provider_pause (
+    provider TEXT PRIMARY KEY,
+    reason TEXT NOT NULL,
+    until TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS provider_failures (
+    provider TEXT PRIMARY KEY,
+    consecutive_failures INTEGER NOT NULL,
+    updated_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS login_attempts (
+    provider TEXT NOT NULL,
+    attempted_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS last_balance (
+    provider TEXT PRIMARY KEY,
+    balance REAL NOT NULL,
+    recorded_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pending_approval (
+    provider TEXT PRIMARY KEY,
+    state TEXT NOT NULL,
+    previous_balance REAL NOT NULL,
+    balance REAL NOT NULL,
+    created_at TEXT NOT NULL,
+    approved INTEGER
+);
+CREATE TABLE IF NOT EXISTS last_adjustment (
+    provider TEXT PRIMARY KEY,
+    transaction_id TEXT NOT NULL,
+    previous_amount INTEGER,
+    previous_date TEXT,
+    amount INTEGER NOT NULL,
+    created_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS write_intent (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    provider TEXT NOT NULL,
+    ynab_account_id TEXT NOT NULL,
+    transaction_id TEXT,
+    import_id TEXT,
+    amount INTEGER NOT NULL,
+    date TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    committed INTEGER
+);
+CREATE TABLE IF NOT EXISTS queued_balance (
+    provider TEXT PRIMARY KEY,
+    ynab_account_id TEXT NOT NULL,
+    balance REAL NOT NULL,
+    fetched_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS expiry_warning (
+    provider TEXT PRIMARY KEY,
+    warned_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS consent (
+    provider TEXT PRIMARY KEY,
+    started_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS manual_value (
+    provider TEXT PRIMARY KEY,
+    value REAL NOT NULL,
+    set_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS fx_rate (
+    from_currency TEXT NOT NULL,
+    to_currency TEXT NOT NULL,
+    rate REAL NOT NULL,
+    fetched_at TEXT NOT NULL,
+    PRIMARY KEY (from_currency, to_currency)
+);
+CREATE TABLE IF NOT EXISTS last_success (
+    provider TEXT PRIMARY KEY,
+    succeeded_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS schedule_run (
+    target TEXT PRIMARY KEY,
+    ran_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS last_run (
+    provider TEXT PRIMARY KEY,
+    report TEXT,
+    error TEXT,
+    run_id TEXT,
+    recorded_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS fetch (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    provider TEXT NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    succeeded INTEGER NOT NULL,
+    fetched_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pushed_balance (
+    provider TEXT PRIMARY KEY,
+    balance REAL NOT NULL,
+    currency TEXT,
+    as_of TEXT,
+    received_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS run_state (
+    provider TEXT PRIMARY KEY,
+    state TEXT NOT NULL,
+    import_id TEXT,
+    updated_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS cashflows_booked (
+    provider TEXT PRIMARY KEY,
+    until TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS income_booked (
+    provider TEXT PRIMARY KEY,
+    until TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS held_notification (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    backend TEXT NOT NULL,
+    event TEXT NOT NULL,
+    title TEXT NOT NULL,
+    message TEXT NOT NULL,
+    url TEXT,
+    held_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS notification_sent (
+    backend TEXT PRIMARY KEY,
+    sent_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pushover_receipt (
+    url TEXT PRIMARY KEY,
+    receipt TEXT NOT NULL
+);
+";
+
+// Idle → FetchingBalance → AwaitingAuth → Reconciling → Done/Failed
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RunState {
+    Idle,
+    FetchingBalance,
+    AwaitingAuth,
+    Reconciling,
+    Done,
+    Failed,
+}
+
+impl fmt::Display for RunState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            RunState::Idle => "idle",
+            RunState::FetchingBalance => "fetching_balance",
+            RunState::AwaitingAuth => "awaiting_auth",
+            RunState::Reconciling => "reconciling",
+            RunState::Done => "done",
+            RunState::Failed => "failed",
+        };
+        write!(f, "{}", state)
+    }
+}
+
+impl FromStr for RunState {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "idle" => Ok(RunState::Idle),
+            "fetching_balance" => Ok(RunState::FetchingBalance),
+            "awaiting_auth" => Ok(RunState::AwaitingAuth),
+            "reconciling" => Ok(RunState::Reconciling),
+            "done" => Ok(RunState::Done),
+            "failed" => Ok(RunState::Failed),
+            _ => Err(anyhow!("Unknown run state: {}", s)),
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct RunStateRecord {
+    pub provider: String,
+    pub state: RunState,
+    // The import_id of the YNAB transaction being written while `Reconciling`
+    pub import_id: Option<String>,
+    pub updated_at: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug)]
+pub struct PendingAuth {
+    pub provider: String,
+    pub state: String,
+    pub login_uri: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// A login that has to be finished by hand through the web UI, e.g. because of a captcha.
+// `login_uri` is the provider's own login page.
+#[derive(Clone, Debug)]
+pub struct PendingManualLogin {
+    pub provider: String,
+    pub state: String,
+    pub title: String,
+    pub login_uri: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// A login waiting on a one-time code the provider sent the user, e.g. by SMS, to be entered
+// through the web UI. `prompt` says where to find it.
+#[derive(Clone, Debug)]
+pub struct PendingOtp {
+    pub provider: String,
+    pub state: String,
+    pub title: String,
+    pub prompt: String,
+    pub created_at: DateTime<Utc>,
+}
+
+// A provider that isn't run until `until`, e.g. after being blocked by a bot challenge
+#[derive(Clone, Debug)]
+pub struct ProviderPause {
+    pub provider: String,
+    pub reason: String,
+    pub until: DateTime<Utc>,
+}
+
+// A balance that moved too far since the last one, waiting to be approved or rejected through
+// the web UI. `approved` is `None` until it's been answered.
+#[derive(Clone, Debug)]
+pub struct PendingApproval {
+    pub provider: String,
+    pub state: String,
+    pub previous_balance: f32,
+    pub balance: f32,
+    pub created_at: DateTime<Utc>,
+    pub approved: Option<bool>,
+}
+
+// The last transaction a provider's reconciliation wrote, to be undone. `previous_amount` &
+// `previous_date` are what an updated reconciliation was before, & are `None` for a created one.
+#[derive(Clone, Debug)]
+pub struct Adjustment {
+    pub provider: String,
+    pub transaction_id: String,
+    pub previous_amount: Option<i64>,
+    pub previous_date: Option<NaiveDate>,
+    pub amount: i64,
+    pub created_at: DateTime<Utc>,
+}
+
+// A YNAB write, recorded before it's sent & marked committed once it's succeeded, so a crash in
+// between can be checked against YNAB. An update has the `transaction_id` it's writing to, a
+// create the `import_id` it's posted with. `committed` is `None` until it's known whether it
+// landed.
+#[derive(Clone, Debug)]
+pub struct WriteIntent {
+    pub id: i64,
+    pub provider: String,
+    pub ynab_account_id: String,
+    pub transaction_id: Option<String>,
+    pub import_id: Option<String>,
+    pub amount: i64,
+    pub date: NaiveDate,
+    pub created_at: DateTime<Utc>,
+    pub committed: Option<bool>,
+}
+
+// A balance fetched while YNAB was unreachable, waiting to be reconciled. Only a provider's latest
+// is kept.
+#[derive(Clone, Debug)]
+pub struct QueuedBalance {
+    pub provider: String,
+    pub ynab_account_id: String,
+    pub balance: f32,
+    pub fetched_at: DateTime<Utc>,
+}
+
+// How a provider's last run ended, either with its report or the error it failed with
+#[derive(Clone, Debug)]
+pub struct LastRun {
+    pub provider: String,
+    pub report: Option<RunReport>,
+    pub error: Option<String>,
+    pub run_id: Option<String>,
+    pub recorded_at: DateTime<Utc>,
+}
+
+// How long fetching a provider's balance took, and whether it succeeded
+#[derive(Clone, Debug)]
+pub struct Fetch {
+    pub provider: String,
+    pub duration_ms: i64,
+    pub succeeded: bool,
+    pub fetched_at: DateTime<Utc>,
+}
+
+// A push account's latest balance, as it was sent to the web UI
+#[derive(Clone, Debug)]
+pub struct PushedBalance {
+    pub provider: String,
+    pub balance: f32,
+    pub currency: Option<String>,
+    pub as_of: Option<DateTime<Utc>>,
+    pub received_at: DateTime<Utc>,
+}
+
+// A notification held back by a backend's quiet hours or batching, to be sent with the others
+#[derive(Clone, Debug)]
+pub struct HeldNotification {
+    pub id: i64,
+    pub event: String,
+    pub title: String,
+    pub message: String,
+    pub url: Option<String>,
+    pub held_at: DateTime<Utc>,
+}
+
+// Connections are opened per call rather than held, so a `History` can be kept across awaits
+#[derive(Clone, Debug)]
+pub struct History {
+    path: String,
+}
+
+impl History {
+    pub fn open(config_path: &str) -> Result<Self> {
+        let history = History {
+            path: format!("{}/{}", config_path, HISTORY_FILENAME),
+        };
+        history.connect()?;
+        Ok(history)
+    }
+
+    fn connect(&self) -> Result<Connection> {
+        // SQLite would create it with the umask's mode
+        if !Path::new(&self.path).exists() {
+            permissions::create_private(Path::new(&self.path))?;
+        }
+
+        let connection = Connection::open(&self.path)?;
+        connection.execute_batch(SCHEMA)?;
+
+        // Columns added since their table was, which are already there in a new history
+        for migration in MIGRATIONS {
+            match connection.execute(migration, []) {
+                Ok(_) => {}
+                Err(e) if e.to_string().contains("duplicate column name") => {}
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(connection)
+    }
+
+    pub fn get_pending_auth(&self, provider: &str) -> Result<Option<PendingAuth>> {
+        let pending_auth = self
+            .connect()?
+            .query_row(
+                "SELECT provider, state, login_uri, created_at FROM pending_auth WHERE provider = ?1",
+                params![provider],
+                |row| {
+                    Ok(PendingAuth {
+                        provider: row.get(0)?,
+                        state: row.get(1)?,
+                        login_uri: row.get(2)?,
+                        created_at: row.get(3)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(pending_auth)
+    }
+
+    pub fn set_pending_auth(&self, pending_auth: &PendingAuth) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO pending_auth (provider, state, login_uri, created_at) VALUES (?1, ?2, ?3, ?4)",
+            params![
+                pending_auth.provider,
+                pending_auth.state,
+                pending_auth.login_uri,
+                pending_auth.created_at
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn clear_pending_auth(&self, provider: &str) -> Result<()> {
+        self.connect()?.execute(
+            "DELETE FROM pending_auth WHERE provider = ?1",
+            params![provider],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_pending_manual_login(&self, provider: &str) -> Result<Option<PendingManualLogin>> {
+        self.query_pending_manual_login("provider", provider)
+    }
+
+    pub fn find_pending_manual_login(&self, state: &str) -> Result<Option<PendingManualLogin>> {
+        self.query_pending_manual_login("state", state)
+    }
+
+    fn query_pending_manual_login(
+        &self,
+        column: &str,
+        value: &str,
+    ) -> Result<Option<PendingManualLogin>> {
+        let pending_manual_login = self
+            .connect()?
+            .query_row(
+                &format!(
+                    "SELECT provider, state, title, login_uri, created_at FROM pending_manual_login WHERE {} = ?1",
+                    column
+                ),
+                params![value],
+                |row| {
+                    Ok(PendingManualLogin {
+                        provider: row.get(0)?,
+                        state: row.get(1)?,
+                        title: row.get(2)?,
+                        login_uri: row.get(3)?,
+                        created_at: row.get(4)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(pending_manual_login)
+    }
+
+    pub fn set_pending_manual_login(
+        &self,
+        pending_manual_login: &PendingManualLogin,
+    ) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO pending_manual_login (provider, state, title, login_uri, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                pending_manual_login.provider,
+                pending_manual_login.state,
+                pending_manual_login.title,
+                pending_manual_login.login_uri,
+                pending_manual_login.created_at
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn clear_pending_manual_login(&self, provider: &str) -> Result<()> {
+        self.connect()?.execute(
+            "DELETE FROM pending_manual_login WHERE provider = ?1",
+            params![provider],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn find_pending_otp(&self, state: &str) -> Result<Option<PendingOtp>> {
+        let pending_otp = self
+            .connect()?
+            .query_row(
+                "SELECT provider, state, title, prompt, created_at FROM pending_otp WHERE state = ?1",
+                params![state],
+                |row| {
+                    Ok(PendingOtp {
+                        provider: row.get(0)?,
+                        state: row.get(1)?,
+                        title: row.get(2)?,
+                        prompt: row.get(3)?,
+                        created_at: row.get(4)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(pending_otp)
+    }
+
+    pub fn set_pending_otp(&self, pending_otp: &PendingOtp) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO pending_otp (provider, state, title, prompt, created_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                pending_otp.provider,
+                pending_otp.state,
+                pending_otp.title,
+                pending_otp.prompt,
+                pending_otp.created_at
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn clear_pending_otp(&self, provider: &str) -> Result<()> {
+        self.connect()?.execute(
+            "DELETE FROM pending_otp WHERE provider = ?1",
+            params![provider],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_run_state(&self, provider: &str) -> Result<Option<RunStateRecord>> {
+        let record = self
+            .connect()?
+            .query_row(
+                "SELECT provider, state, import_id, updated_at FROM run_state WHERE provider = ?1",
+                params![provider],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, DateTime<Utc>>(3)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        record
+            .map(|(provider, state, import_id, updated_at)| {
+                Ok(RunStateRecord {
+                    provider,
+                    state: state.parse()?,
+                    import_id,
+                    updated_at,
+                })
+            })
+            .transpose()
+    }
+
+    pub fn set_run_state(
+        &self,
+        provider: &str,
+        state: RunState,
+        import_id: Option<&str>,
+    ) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO run_state (provider, state, import_id, updated_at) VALUES (?1, ?2, ?3, ?4)",
+            params![provider, state.to_string(), import_id, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    // The reason (e.g. "closed") the YNAB account needs reconfiguring, if it does
+    pub fn get_needs_reconfiguration(&self, ynab_account_id: &str) -> Result<Option<String>> {
+        let reason = self
+            .connect()?
+            .query_row(
+                "SELECT needs_reconfiguration_reason FROM account_status WHERE ynab_account_id = ?1",
+                params![ynab_account_id],
+                |row| row.get::<_, Option<String>>(0),
+            )
+            .optional()?
+            .flatten();
+
+        Ok(reason)
+    }
+
+    pub fn set_needs_reconfiguration(
+        &self,
+        ynab_account_id: &str,
+        reason: Option<&str>,
+    ) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO account_status (ynab_account_id, needs_reconfiguration_reason, updated_at) VALUES (?1, ?2, ?3)",
+            params![ynab_account_id, reason, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_pause(&self, provider: &str) -> Result<Option<ProviderPause>> {
+        let pause = self
+            .connect()?
+            .query_row(
+                "SELECT provider, reason, until FROM provider_pause WHERE provider = ?1",
+                params![provider],
+                |row| {
+                    Ok(ProviderPause {
+                        provider: row.get(0)?,
+                        reason: row.get(1)?,
+                        until: row.get(2)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(pause)
+    }
+
+    pub fn set_pause(&self, pause: &ProviderPause) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO provider_pause (provider, reason, until) VALUES (?1, ?2, ?3)",
+            params![pause.provider, pause.reason, pause.until],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn clear_pause(&self, provider: &str) -> Result<()> {
+        self.connect()?.execute(
+            "DELETE FROM provider_pause WHERE provider = ?1",
+            params![provider],
+        )?;
+
+        Ok(())
+    }
+
+    // Returns the count including this failure
+    pub fn increment_consecutive_failures(&self, provider: &str) -> Result<u32> {
+        let failures = self.connect()?.query_row(
+            "INSERT INTO provider_failures (provider, consecutive_failures, updated_at) VALUES (?1, 1, ?2)
+             ON CONFLICT (provider) DO UPDATE SET consecutive_failures = consecutive_failures + 1, updated_at = ?2
+             RETURNING consecutive_failures",
+            params![provider, Utc::now()],
+            |row| row.get(0),
+        )?;
+
+        Ok(failures)
+    }
+
+    pub fn reset_consecutive_failures(&self, provider: &str) -> Result<()> {
+        self.connect()?.execute(
+            "DELETE FROM provider_failures WHERE provider = ?1",
+            params![provider],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn count_login_attempts_since(&self, provider: &str, since: DateTime<Utc>) -> Result<u32> {
+        let attempts = self.connect()?.query_row(
+            "SELECT COUNT(*) FROM login_attempts WHERE provider = ?1 AND attempted_at > ?2",
+            params![provider, since],
+            |row| row.get(0),
+        )?;
+
+        Ok(attempts)
+    }
+
+    // Attempts older than `prune_before` are no longer counted, so they're dropped
+    pub fn record_login_attempt(&self, provider: &str, prune_before: DateTime<Utc>) -> Result<()> {
+        let connection = self.connect()?;
+
+        connection.execute(
+            "DELETE FROM login_attempts WHERE provider = ?1 AND attempted_at <= ?2",
+            params![provider, prune_before],
+        )?;
+
+        connection.execute(
+            "INSERT INTO login_attempts (provider, attempted_at) VALUES (?1, ?2)",
+            params![provider, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_last_balance(&self, provider: &str) -> Result<Option<f32>> {
+        let balance = self
+            .connect()?
+            .query_row(
+                "SELECT balance FROM last_balance WHERE provider = ?1",
+                params![provider],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        Ok(balance)
+    }
+
+    pub fn set_last_balance(&self, provider: &str, balance: f32) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO last_balance (provider, balance, recorded_at) VALUES (?1, ?2, ?3)",
+            params![provider, balance, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    // When the provider's balance was last reconciled, whether or not it needed adjusting
+    pub fn get_last_success(&self, provider: &str) -> Result<Option<DateTime<Utc>>> {
+        let succeeded_at = self
+            .connect()?
+            .query_row(
+                "SELECT succeeded_at FROM last_success WHERE provider = ?1",
+                params![provider],
+                |row| row.get::<_, DateTime<Utc>>(0),
+            )
+            .optional()?;
+
+        Ok(succeeded_at)
+    }
+
+    pub fn set_last_success(&self, provider: &str) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO last_success (provider, succeeded_at) VALUES (?1, ?2)",
+            params![provider, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    // The day the account's cash flows have been posted up to, but not including
+    pub fn get_cashflows_booked_until(&self, provider: &str) -> Result<Option<NaiveDate>> {
+        let until = self
+            .connect()?
+            .query_row(
+                "SELECT until FROM cashflows_booked WHERE provider = ?1",
+                params![provider],
+                |row| row.get::<_, NaiveDate>(0),
+            )
+            .optional()?;
+
+        Ok(until)
+    }
+
+    pub fn set_cashflows_booked_until(&self, provider: &str, until: NaiveDate) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO cashflows_booked (provider, until) VALUES (?1, ?2)",
+            params![provider, until],
+        )?;
+
+        Ok(())
+    }
+
+    // The day the account's dividends & interest have been posted up to, but not including
+    pub fn get_income_booked_until(&self, provider: &str) -> Result<Option<NaiveDate>> {
+        let until = self
+            .connect()?
+            .query_row(
+                "SELECT until FROM income_booked WHERE provider = ?1",
+                params![provider],
+                |row| row.get::<_, NaiveDate>(0),
+            )
+            .optional()?;
+
+        Ok(until)
+    }
+
+    pub fn set_income_booked_until(&self, provider: &str, until: NaiveDate) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO income_booked (provider, until) VALUES (?1, ?2)",
+            params![provider, until],
+        )?;
+
+        Ok(())
+    }
+
+    // When the daemon last ran the profile or account's schedule
+    pub fn get_schedule_ran_at(&self, target: &str) -> Result<Option<DateTime<Utc>>> {
+        let ran_at = self
+            .connect()?
+            .query_row(
+                "SELECT ran_at FROM schedule_run WHERE target = ?1",
+                params![target],
+                |row| row.get::<_, DateTime<Utc>>(0),
+            )
+            .optional()?;
+
+        Ok(ran_at)
+    }
+
+    pub fn set_schedule_ran_at(&self, target: &str, ran_at: DateTime<Utc>) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO schedule_run (target, ran_at) VALUES (?1, ?2)",
+            params![target, ran_at],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_last_run(&self, provider: &str) -> Result<Option<LastRun>> {
+        let last_run = self
+            .connect()?
+            .query_row(
+                "SELECT provider, report, error, run_id, recorded_at FROM last_run WHERE provider = ?1",
+                params![provider],
+                |row| {
+                    Ok((
+                        row.get::<_, String>(0)?,
+                        row.get::<_, Option<String>>(1)?,
+                        row.get::<_, Option<String>>(2)?,
+                        row.get::<_, Option<String>>(3)?,
+                        row.get::<_, DateTime<Utc>>(4)?,
+                    ))
+                },
+            )
+            .optional()?;
+
+        last_run
+            .map(|(provider, report, error, run_id, recorded_at)| {
+                Ok(LastRun {
+                    provider,
+                    report: report
+                        .map(|report| serde_json::from_str(&report))
+                        .transpose()?,
+                    error,
+                    run_id,
+                    recorded_at,
+                })
+            })
+            .transpose()
+    }
+
+    pub fn set_last_run(
+        &self,
+        provider: &str,
+        run_id: Option<&str>,
+        result: &Result<RunReport>,
+    ) -> Result<()> {
+        let (report, error) = match result {
+            Ok(report) => (Some(serde_json::to_string(report)?), None),
+            Err(e) => (None, Some(format!("{:#}", e))),
+        };
+
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO last_run (provider, report, error, run_id, recorded_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![provider, report, error, run_id, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_pending_approval(&self, provider: &str) -> Result<Option<PendingApproval>> {
+        self.query_pending_approval("provider", provider)
+    }
+
+    pub fn find_pending_approval(&self, state: &str) -> Result<Option<PendingApproval>> {
+        self.query_pending_approval("state", state)
+    }
+
+    fn query_pending_approval(&self, column: &str, value: &str) -> Result<Option<PendingApproval>> {
+        let pending_approval = self
+            .connect()?
+            .query_row(
+                &format!(
+                    "SELECT provider, state, previous_balance, balance, created_at, approved FROM pending_approval WHERE {} = ?1",
+                    column
+                ),
+                params![value],
+                |row| {
+                    Ok(PendingApproval {
+                        provider: row.get(0)?,
+                        state: row.get(1)?,
+                        previous_balance: row.get(2)?,
+                        balance: row.get(3)?,
+                        created_at: row.get(4)?,
+                        approved: row.get(5)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(pending_approval)
+    }
+
+    pub fn set_pending_approval(&self, pending_approval: &PendingApproval) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO pending_approval (provider, state, previous_balance, balance, created_at, approved) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                pending_approval.provider,
+                pending_approval.state,
+                pending_approval.previous_balance,
+                pending_approval.balance,
+                pending_approval.created_at,
+                pending_approval.approved
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn clear_pending_approval(&self, provider: &str) -> Result<()> {
+        self.connect()?.execute(
+            "DELETE FROM pending_approval WHERE provider = ?1",
+            params![provider],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_last_adjustment(&self, provider: &str) -> Result<Option<Adjustment>> {
+        let adjustment = self
+            .connect()?
+            .query_row(
+                "SELECT provider, transaction_id, previous_amount, previous_date, amount, created_at FROM last_adjustment WHERE provider = ?1",
+                params![provider],
+                |row| {
+                    Ok(Adjustment {
+                        provider: row.get(0)?,
+                        transaction_id: row.get(1)?,
+                        previous_amount: row.get(2)?,
+                        previous_date: row.get(3)?,
+                        amount: row.get(4)?,
+                        created_at: row.get(5)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(adjustment)
+    }
+
+    pub fn set_last_adjustment(&self, adjustment: &Adjustment) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO last_adjustment (provider, transaction_id, previous_amount, previous_date, amount, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![
+                adjustment.provider,
+                adjustment.transaction_id,
+                adjustment.previous_amount,
+                adjustment.previous_date,
+                adjustment.amount,
+                adjustment.created_at
+            ],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn clear_last_adjustment(&self, provider: &str) -> Result<()> {
+        self.connect()?.execute(
+            "DELETE FROM last_adjustment WHERE provider = ?1",
+            params![provider],
+        )?;
+
+        Ok(())
+    }
+
+    // Returns the intent's id, to commit it by
+    pub fn begin_write(
+        &self,
+        provider: &str,
+        ynab_account_id: &str,
+        transaction_id: Option<&str>,
+        import_id: Option<&str>,
+        amount: i64,
+        date: NaiveDate,
+    ) -> Result<i64> {
+        let connection = self.connect()?;
+
+        connection.execute(
+            "INSERT INTO write_intent (provider, ynab_account_id, transaction_id, import_id, amount, date, created_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            params![
+                provider,
+                ynab_account_id,
+                transaction_id,
+                import_id,
+                amount,
+                date,
+                Utc::now()
+            ],
+        )?;
+
+        Ok(connection.last_insert_rowid())
+    }
+
+    pub fn set_write_committed(&self, id: i64, committed: bool) -> Result<()> {
+        self.connect()?.execute(
+            "UPDATE write_intent SET committed = ?2 WHERE id = ?1",
+            params![id, committed],
+        )?;
+
+        Ok(())
+    }
+
+    // Intents whose writes were never committed, i.e. the process died while they were in flight
+    pub fn get_dangling_writes(&self) -> Result<Vec<WriteIntent>> {
+        let connection = self.connect()?;
+
+        let mut statement = connection.prepare(
+            "SELECT id, provider, ynab_account_id, transaction_id, import_id, amount, date, created_at, committed FROM write_intent WHERE committed IS NULL ORDER BY id",
+        )?;
+
+        let intents = statement
+            .query_map([], |row| {
+                Ok(WriteIntent {
+                    id: row.get(0)?,
+                    provider: row.get(1)?,
+                    ynab_account_id: row.get(2)?,
+                    transaction_id: row.get(3)?,
+                    import_id: row.get(4)?,
+                    amount: row.get(5)?,
+                    date: row.get(6)?,
+                    created_at: row.get(7)?,
+                    committed: row.get(8)?,
+                })
+            })?
+            .try_collect::<Vec<_>>()?;
+
+        Ok(intents)
+    }
+
+    pub fn get_queued_balances(&self) -> Result<Vec<QueuedBalance>> {
+        let connection = self.connect()?;
+
+        let mut statement = connection.prepare(
+            "SELECT provider, ynab_account_id, balance, fetched_at FROM queued_balance ORDER BY fetched_at",
+        )?;
+
+        let queued_balances = statement
+            .query_map([], |row| {
+                Ok(QueuedBalance {
+                    provider: row.get(0)?,
+                    ynab_account_id: row.get(1)?,
+                    balance: row.get(2)?,
+                    fetched_at: row.get(3)?,
+                })
+            })?
+            .try_collect::<Vec<_>>()?;
+
+        Ok(queued_balances)
+    }
+
+    pub fn set_queued_balance(
+        &self,
+        provider: &str,
+        ynab_account_id: &str,
+        balance: f32,
+    ) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO queued_balance (provider, ynab_account_id, balance, fetched_at) VALUES (?1, ?2, ?3, ?4)",
+            params![provider, ynab_account_id, balance, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn clear_queued_balance(&self, provider: &str) -> Result<()> {
+        self.connect()?.execute(
+            "DELETE FROM queued_balance WHERE provider = ?1",
+            params![provider],
+        )?;
+
+        Ok(())
+    }
+
+    // When the user was last asked to renew the provider's expiring login
+    pub fn get_expiry_warned_at(&self, provider: &str) -> Result<Option<DateTime<Utc>>> {
+        let warned_at = self
+            .connect()?
+            .query_row(
+                "SELECT warned_at FROM expiry_warning WHERE provider = ?1",
+                params![provider],
+                |row| row.get::<_, DateTime<Utc>>(0),
+            )
+            .optional()?;
+
+        Ok(warned_at)
+    }
+
+    pub fn set_expiry_warned_at(&self, provider: &str) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO expiry_warning (provider, warned_at) VALUES (?1, ?2)",
+            params![provider, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    // When the user last logged in to the provider, which its consent runs from
+    pub fn get_consent_started_at(&self, provider: &str) -> Result<Option<DateTime<Utc>>> {
+        let started_at = self
+            .connect()?
+            .query_row(
+                "SELECT started_at FROM consent WHERE provider = ?1",
+                params![provider],
+                |row| row.get::<_, DateTime<Utc>>(0),
+            )
+            .optional()?;
+
+        Ok(started_at)
+    }
+
+    pub fn set_consent_started_at(&self, provider: &str) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO consent (provider, started_at) VALUES (?1, ?2)",
+            params![provider, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    // A value set by hand, with when it was set
+    pub fn get_manual_value(&self, provider: &str) -> Result<Option<(f32, DateTime<Utc>)>> {
+        let manual_value = self
+            .connect()?
+            .query_row(
+                "SELECT value, set_at FROM manual_value WHERE provider = ?1",
+                params![provider],
+                |row| Ok((row.get::<_, f32>(0)?, row.get::<_, DateTime<Utc>>(1)?)),
+            )
+            .optional()?;
+
+        Ok(manual_value)
+    }
+
+    pub fn set_manual_value(&self, provider: &str, value: Option<f32>) -> Result<()> {
+        let connection = self.connect()?;
+
+        match value {
+            Some(value) => connection.execute(
+                "INSERT OR REPLACE INTO manual_value (provider, value, set_at) VALUES (?1, ?2, ?3)",
+                params![provider, value, Utc::now()],
+            )?,
+            None => connection.execute(
+                "DELETE FROM manual_value WHERE provider = ?1",
+                params![provider],
+            )?,
+        };
+
+        Ok(())
+    }
+
+    pub fn get_fx_rate(&self, from: &str, to: &str) -> Result<Option<(f64, DateTime<Utc>)>> {
+        let fx_rate = self
+            .connect()?
+            .query_row(
+                "SELECT rate, fetched_at FROM fx_rate WHERE from_currency = ?1 AND to_currency = ?2",
+                params![from, to],
+                |row| Ok((row.get::<_, f64>(0)?, row.get::<_, DateTime<Utc>>(1)?)),
+            )
+            .optional()?;
+
+        Ok(fx_rate)
+    }
+
+    pub fn set_fx_rate(&self, from: &str, to: &str, rate: f64) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO fx_rate (from_currency, to_currency, rate, fetched_at) VALUES (?1, ?2, ?3, ?4)",
+            params![from, to, rate, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn add_fetch(&self, provider: &str, duration_ms: i64, succeeded: bool) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT INTO fetch (provider, duration_ms, succeeded, fetched_at) VALUES (?1, ?2, ?3, ?4)",
+            params![provider, duration_ms, succeeded, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn get_fetches(&self, provider: &str, since: DateTime<Utc>) -> Result<Vec<Fetch>> {
+        let connection = self.connect()?;
+
+        let mut statement = connection.prepare(
+            "SELECT provider, duration_ms, succeeded, fetched_at FROM fetch WHERE provider = ?1 AND fetched_at >= ?2 ORDER BY fetched_at",
+        )?;
+
+        let fetches = statement
+            .query_map(params![provider, since], |row| {
+                Ok(Fetch {
+                    provider: row.get(0)?,
+                    duration_ms: row.get(1)?,
+                    succeeded: row.get(2)?,
+                    fetched_at: row.get(3)?,
+                })
+            })?
+            .try_collect::<Vec<_>>()?;
+
+        Ok(fetches)
+    }
+
+    pub fn hold_notification(
+        &self,
+        backend: &str,
+        event: &str,
+        title: &str,
+        message: &str,
+        url: Option<&str>,
+    ) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT INTO held_notification (backend, event, title, message, url, held_at) VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+            params![backend, event, title, message, url, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    // Oldest first
+    pub fn get_held_notifications(&self, backend: &str) -> Result<Vec<HeldNotification>> {
+        let connection = self.connect()?;
+        let mut statement = connection.prepare(
+            "SELECT id, event, title, message, url, held_at FROM held_notification WHERE backend = ?1 ORDER BY id",
+        )?;
+
+        let held = statement
+            .query_map(params![backend], |row| {
+                Ok(HeldNotification {
+                    id: row.get(0)?,
+                    event: row.get(1)?,
+                    title: row.get(2)?,
+                    message: row.get(3)?,
+                    url: row.get(4)?,
+                    held_at: row.get(5)?,
+                })
+            })?
+            .try_collect::<Vec<_>>()?;
+
+        Ok(held)
+    }
+
+    // Those up to the last one that was sent, leaving any held since
+    pub fn clear_held_notifications(&self, backend: &str, up_to_id: i64) -> Result<()> {
+        self.connect()?.execute(
+            "DELETE FROM held_notification WHERE backend = ?1 AND id <= ?2",
+            params![backend, up_to_id],
+        )?;
+
+        Ok(())
+    }
+
+    // When the backend last sent a notification that could have been batched
+    pub fn get_notification_sent_at(&self, backend: &str) -> Result<Option<DateTime<Utc>>> {
+        let sent_at = self
+            .connect()?
+            .query_row(
+                "SELECT sent_at FROM notification_sent WHERE backend = ?1",
+                params![backend],
+                |row| row.get::<_, DateTime<Utc>>(0),
+            )
+            .optional()?;
+
+        Ok(sent_at)
+    }
+
+    pub fn set_notification_sent_at(&self, backend: &str) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO notification_sent (backend, sent_at) VALUES (?1, ?2)",
+            params![backend, Utc::now()],
+        )?;
+
+        Ok(())
+    }
+
+    // The receipt of the emergency priority prompt linking to the url, to cancel once it's answered
+    pub fn set_pushover_receipt(&self, url: &str, receipt: &str) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO pushover_receipt (url, receipt) VALUES (?1, ?2)",
+            params![url, receipt],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn take_pushover_receipt(&self, url: &str) -> Result<Option<String>> {
+        let connection = self.connect()?;
+
+        let receipt = connection
+            .query_row(
+                "SELECT receipt FROM pushover_receipt WHERE url = ?1",
+                params![url],
+                |row| row.get::<_, String>(0),
+            )
+            .optional()?;
+
+        connection.execute("DELETE FROM pushover_receipt WHERE url = ?1", params![url])?;
+
+        Ok(receipt)
+    }
+
+    pub fn get_pushed_balance(&self, provider: &str) -> Result<Option<PushedBalance>> {
+        let pushed_balance = self
+            .connect()?
+            .query_row(
+                "SELECT provider, balance, currency, as_of, received_at FROM pushed_balance WHERE provider = ?1",
+                params![provider],
+                |row| {
+                    Ok(PushedBalance {
+                        provider: row.get(0)?,
+                        balance: row.get(1)?,
+                        currency: row.get(2)?,
+                        as_of: row.get(3)?,
+                        received_at: row.get(4)?,
+                    })
+                },
+            )
+            .optional()?;
+
+        Ok(pushed_balance)
+    }
+
+    pub fn set_pushed_balance(&self, pushed_balance: &PushedBalance) -> Result<()> {
+        self.connect()?.execute(
+            "INSERT OR REPLACE INTO pushed_balance (provider, balance, currency, as_of, received_at) VALUES (?1, ?2, ?3, ?4, ?5)",
+            params![
+                pushed_balance.provider,
+                pushed_balance.balance,
+                pushed_balance.currency,
+                pushed_balance.as_of,
+                pushed_balance.received_at
+            ],
+        )?;
+
+        Ok(())
+    }
+}
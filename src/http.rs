@@ -0,0 +1,105 @@
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Mutex, OnceLock},
+};
+
+static DEFAULT_USER_AGENT: &str = concat!("ynab-updater/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Clone, Debug, Default, Deserialize, PartialEq, Eq, Hash)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct HttpConfig {
+    // e.g. `http://proxy:3128` or `socks5h://proxy:1080`
+    pub proxy: Option<String>,
+    // Paths to PEM certificates trusted alongside the system's, e.g. a proxy's MITM CA
+    #[serde(default)]
+    pub root_certificates: Vec<String>,
+    // Sent to YNAB, the API providers & the notifiers. The scrapers send their browser's instead.
+    pub user_agent: Option<String>,
+}
+
+// Every outbound client is built from here, so the proxy, root certificates & user agent apply to
+// YNAB, the providers & the notifiers alike
+pub fn client_builder(config: &HttpConfig) -> Result<reqwest::ClientBuilder> {
+    let mut builder = reqwest::Client::builder()
+        .user_agent(config.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT));
+
+    if let Some(proxy) = &config.proxy {
+        builder = builder.proxy(reqwest::Proxy::all(proxy)?);
+    }
+
+    for path in &config.root_certificates {
+        let pem = std::fs::read(path)
+            .map_err(|e| anyhow!("Failed to read root certificate {}: {}", path, e))?;
+        builder = builder.add_root_certificate(reqwest::Certificate::from_pem(&pem)?);
+    }
+
+    Ok(builder)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+enum Redirects {
+    Follow,
+    // For OAuth, whose redirects carry the code rather than lead anywhere
+    Stop,
+}
+
+// Clients are built once per config & shared, so a run's requests reuse connections rather than
+// each doing its own TLS handshake. The scrapers' clients keep their own cookies, so aren't shared.
+fn shared_client(config: &HttpConfig, redirects: Redirects) -> Result<reqwest::Client> {
+    static CLIENTS: OnceLock<Mutex<HashMap<(HttpConfig, Redirects), reqwest::Client>>> =
+        OnceLock::new();
+
+    let mut clients = CLIENTS
+        .get_or_init(Default::default)
+        .lock()
+        .map_err(|_| anyhow!("The HTTP clients' lock is poisoned"))?;
+
+    if let Some(client) = clients.get(&(config.clone(), redirects)) {
+        return Ok(client.clone());
+    }
+
+    let builder = match redirects {
+        Redirects::Follow => client_builder(config)?,
+        Redirects::Stop => client_builder(config)?.redirect(reqwest::redirect::Policy::none()),
+    };
+    let client = builder.build()?;
+
+    clients.insert((config.clone(), redirects), client.clone());
+
+    Ok(client)
+}
+
+pub fn client(config: &HttpConfig) -> Result<reqwest::Client> {
+    shared_client(config, Redirects::Follow)
+}
+
+pub fn client_without_redirects(config: &HttpConfig) -> Result<reqwest::Client> {
+    shared_client(config, Redirects::Stop)
+}
+
+// Sends the request for YNAB & the API providers. With the `vcr` feature the exchange is recorded
+// to, or replayed from, the cassette named by YNAB_CASSETTE, so real response shapes can be
+// replayed offline with their secrets scrubbed.
+pub async fn send(
+    client: &reqwest::Client,
+    request: reqwest::RequestBuilder,
+) -> Result<reqwest::Response> {
+    let request = request.build()?;
+
+    #[cfg(feature = "vcr")]
+    {
+        if let Some(response) = crate::vcr::replay(&request)? {
+            return Ok(response);
+        }
+
+        let recorded = (request.method().to_string(), request.url().clone());
+        let response = client.execute(request).await?;
+
+        crate::vcr::record(recorded, response).await
+    }
+
+    #[cfg(not(feature = "vcr"))]
+    Ok(client.execute(request).await?)
+}
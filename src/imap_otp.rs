@@ -0,0 +1,408 @@
+// Reads a login's one-time code from the email the provider sent it in, so a login with email 2FA
+// can run unattended. Just enough IMAP for that, over TLS: the mailbox is only examined & its
+// messages only peeked at, so they're left unread.
+//
+// ```toml
+// [imap]
+// HOST = "imap.fastmail.com"
+// USERNAME = "me@example.com"
+// PASSWORD = "credential:imap"
+//
+// [accounts.bank]
+// OTP_EMAIL = { FROM = "security@bank.example", REGEX = 'code is (\d{6})' }
+// ```
+
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Duration, FixedOffset, Utc};
+use log::{debug, info};
+use regex::Regex;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use std::time::{Duration as StdDuration, Instant};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_native_tls::{native_tls, TlsConnector, TlsStream};
+
+use crate::{error::ConfigInvalid, AccountConfig, Config};
+
+// Long enough for a slow email, short enough that the code's still good if it's asked for instead
+static OTP_EMAIL_TIMEOUT_SECS: u64 = 3 * 60;
+
+static OTP_EMAIL_POLL_SECS: u64 = 10;
+
+// The provider's clock, & the mail server's, may be a little behind ours, & the code may have been
+// sent before it was asked for
+static OTP_EMAIL_MAX_AGE_MINS: i64 = 5;
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct ImapConfig {
+    pub host: String,
+    #[serde(default = "default_port")]
+    pub port: u16,
+    pub username: String,
+    pub password: SecretString,
+    #[serde(default = "default_folder")]
+    pub folder: String,
+}
+
+fn default_port() -> u16 {
+    993
+}
+
+fn default_folder() -> String {
+    "INBOX".to_owned()
+}
+
+// Which emails have an account's codes, & where the code is in them
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct OtpEmailConfig {
+    // Matched by the server against the sender & subject, e.g. `bank.example`
+    pub from: Option<String>,
+    pub subject: Option<String>,
+    // The code's its first group, otherwise the whole match
+    #[serde(default = "default_regex")]
+    pub regex: String,
+}
+
+fn default_regex() -> String {
+    r"\b(\d{6})\b".to_owned()
+}
+
+// An untagged response, with any literals it carried, e.g. a message's body
+struct Response {
+    line: String,
+    literals: Vec<Vec<u8>>,
+}
+
+struct Session {
+    stream: BufReader<TlsStream<TcpStream>>,
+    tag: u32,
+}
+
+// As an IMAP quoted string
+fn quote(s: &str) -> String {
+    format!("\"{}\"", s.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl Session {
+    async fn connect(imap: &ImapConfig) -> Result<Self> {
+        let tcp_stream = TcpStream::connect((imap.host.as_str(), imap.port)).await?;
+        let tls_stream = TlsConnector::from(native_tls::TlsConnector::new()?)
+            .connect(&imap.host, tcp_stream)
+            .await?;
+
+        let mut session = Session {
+            stream: BufReader::new(tls_stream),
+            tag: 0,
+        };
+
+        let greeting = session.read_line().await?;
+        if !greeting.starts_with("* OK") {
+            return Err(anyhow!(
+                "{} didn't greet with OK: {}",
+                imap.host,
+                greeting.trim()
+            ));
+        }
+
+        // Sent apart from `command`, so the password's never logged
+        session
+            .command_unlogged(&format!(
+                "LOGIN {} {}",
+                quote(&imap.username),
+                quote(imap.password.expose_secret())
+            ))
+            .await
+            .map_err(|_| anyhow!("{} rejected the IMAP login", imap.host))?;
+
+        session
+            .command(&format!("EXAMINE {}", quote(&imap.folder)))
+            .await?;
+
+        Ok(session)
+    }
+
+    async fn read_line(&mut self) -> Result<String> {
+        let mut line = vec![];
+        if self.stream.read_until(b'\n', &mut line).await? == 0 {
+            return Err(anyhow!("The IMAP server closed the connection"));
+        }
+
+        Ok(String::from_utf8_lossy(&line).into_owned())
+    }
+
+    async fn command(&mut self, command: &str) -> Result<Vec<Response>> {
+        debug!("IMAP: {}", command);
+        self.command_unlogged(command).await
+    }
+
+    async fn command_unlogged(&mut self, command: &str) -> Result<Vec<Response>> {
+        self.tag += 1;
+        let tag = format!("a{}", self.tag);
+
+        self.stream
+            .get_mut()
+            .write_all(format!("{} {}\r\n", tag, command).as_bytes())
+            .await?;
+
+        let mut responses = vec![];
+        let literal_regex = Regex::new(r"\{(\d+)\}\r\n$")?;
+
+        loop {
+            let line = self.read_line().await?;
+
+            if let Some(status) = line.strip_prefix(&format!("{} ", tag)) {
+                return match status.starts_with("OK") {
+                    true => Ok(responses),
+                    false => Err(anyhow!("IMAP command failed: {}", status.trim())),
+                };
+            }
+
+            // A literal's bytes follow its line, then the rest of the response
+            let mut response = Response {
+                line: line.clone(),
+                literals: vec![],
+            };
+            let mut last_line = line;
+            while let Some(length) = literal_regex
+                .captures(&last_line)
+                .and_then(|captures| captures[1].parse::<usize>().ok())
+            {
+                let mut literal = vec![0; length];
+                self.stream.read_exact(&mut literal).await?;
+                response.literals.push(literal);
+
+                last_line = self.read_line().await?;
+                response.line.push_str(&last_line);
+            }
+
+            responses.push(response);
+        }
+    }
+
+    // The UIDs of the messages that could be the code's, newest first
+    async fn search(
+        &mut self,
+        otp_email: &OtpEmailConfig,
+        since: DateTime<Utc>,
+    ) -> Result<Vec<u32>> {
+        let mut criteria = vec![format!("SINCE {}", since.format("%-d-%b-%Y"))];
+        if let Some(from) = &otp_email.from {
+            criteria.push(format!("FROM {}", quote(from)));
+        }
+        if let Some(subject) = &otp_email.subject {
+            criteria.push(format!("SUBJECT {}", quote(subject)));
+        }
+
+        let mut uids = self
+            .command(&format!("UID SEARCH {}", criteria.join(" ")))
+            .await?
+            .iter()
+            .filter_map(|response| response.line.strip_prefix("* SEARCH"))
+            .flat_map(|uids| uids.split_whitespace().filter_map(|uid| uid.parse().ok()))
+            .collect::<Vec<u32>>();
+
+        uids.sort_unstable_by(|a, b| b.cmp(a));
+
+        Ok(uids)
+    }
+
+    // When the message arrived, & the message itself
+    async fn fetch(&mut self, uid: u32) -> Result<Option<(DateTime<FixedOffset>, Vec<u8>)>> {
+        let internal_date_regex = Regex::new(r#"INTERNALDATE "([^"]+)""#)?;
+
+        let responses = self
+            .command(&format!("UID FETCH {} (INTERNALDATE BODY.PEEK[])", uid))
+            .await?;
+
+        for mut response in responses {
+            let internal_date = match internal_date_regex.captures(&response.line) {
+                Some(captures) => {
+                    DateTime::parse_from_str(captures[1].trim(), "%d-%b-%Y %H:%M:%S %z")?
+                }
+                None => continue,
+            };
+
+            if let Some(message) = response.literals.pop() {
+                return Ok(Some((internal_date, message)));
+            }
+        }
+
+        Ok(None)
+    }
+
+    async fn logout(mut self) {
+        let _ = self.command("LOGOUT").await;
+    }
+}
+
+// The message's text to search, with its parts' quoted-printable or base64 decoded & their HTML
+// tags dropped, so e.g. a colour's hex code can't be taken for the code
+fn message_text(message: &[u8]) -> Result<String> {
+    let message = String::from_utf8_lossy(message);
+
+    let mut text = String::new();
+    let mut in_headers = true;
+    let mut encoding = String::new();
+    let mut base64 = String::new();
+
+    for line in message.lines() {
+        if line.starts_with("--") {
+            end_part(&mut text, &mut base64);
+            in_headers = true;
+            encoding.clear();
+            continue;
+        }
+
+        if in_headers {
+            if line.is_empty() {
+                in_headers = false;
+            } else if let Some((name, value)) = line.split_once(':') {
+                if name.eq_ignore_ascii_case("content-transfer-encoding") {
+                    encoding = value.trim().to_ascii_lowercase();
+                }
+            }
+            // Headers are searched too, as the code may be in the subject
+            text.push_str(line);
+            text.push('\n');
+            continue;
+        }
+
+        match encoding.as_str() {
+            "base64" => base64.push_str(line.trim()),
+            "quoted-printable" => match line.strip_suffix('=') {
+                Some(line) => text.push_str(&decode_quoted_printable(line)),
+                None => {
+                    text.push_str(&decode_quoted_printable(line));
+                    text.push('\n');
+                }
+            },
+            _ => {
+                text.push_str(line);
+                text.push('\n');
+            }
+        }
+    }
+    end_part(&mut text, &mut base64);
+
+    let text = Regex::new(r"(?is)<(style|script|head)\b.*?</(style|script|head)>")?
+        .replace_all(&text, " ");
+    let text = Regex::new(r"(?s)<[^>]*>")?.replace_all(&text, " ");
+
+    Ok(text.into_owned())
+}
+
+// A base64 part's text, once all its lines are in
+fn end_part(text: &mut String, base64: &mut String) {
+    if let Ok(decoded) = STANDARD.decode(base64.as_bytes()) {
+        text.push_str(&String::from_utf8_lossy(&decoded));
+    }
+    base64.clear();
+}
+
+fn decode_quoted_printable(line: &str) -> String {
+    let bytes = line.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match (bytes[i], bytes.get(i + 1..i + 3)) {
+            (b'=', Some(hex)) => match u8::from_str_radix(&String::from_utf8_lossy(hex), 16) {
+                Ok(byte) => {
+                    decoded.push(byte);
+                    i += 3;
+                }
+                Err(_) => {
+                    decoded.push(b'=');
+                    i += 1;
+                }
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            }
+        }
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+fn find_code(regex: &Regex, text: &str) -> Option<SecretString> {
+    regex.captures(text).map(|captures| {
+        captures
+            .get(1)
+            .unwrap_or_else(|| captures.get(0).unwrap())
+            .as_str()
+            .to_owned()
+            .into()
+    })
+}
+
+// The account's emails to read its codes from, if it has any, which needs the mailbox configured
+pub fn otp_email<'a>(
+    config: &'a Config,
+    account_config: &'a AccountConfig,
+) -> Result<Option<(&'a ImapConfig, &'a OtpEmailConfig)>> {
+    match (&config.imap, &account_config.otp_email) {
+        (Some(imap), Some(otp_email)) => Ok(Some((imap, otp_email))),
+        (None, Some(_)) => {
+            Err(anyhow!("OTP_EMAIL needs [imap] to be configured").context(ConfigInvalid))
+        }
+        (_, None) => Ok(None),
+    }
+}
+
+// Polls the mailbox for an email with the code that's arrived since it was asked for, or `None` if
+// none does in time
+pub async fn wait_for_code(
+    imap: &ImapConfig,
+    otp_email: &OtpEmailConfig,
+    requested_at: DateTime<Utc>,
+) -> Result<Option<SecretString>> {
+    let regex = Regex::new(&otp_email.regex).context(ConfigInvalid)?;
+    let not_before = requested_at - Duration::minutes(OTP_EMAIL_MAX_AGE_MINS);
+
+    let mut session = Session::connect(imap).await?;
+
+    info!("Waiting for the one-time code's email in {}", imap.folder);
+
+    let deadline = Instant::now() + StdDuration::from_secs(OTP_EMAIL_TIMEOUT_SECS);
+
+    let code = loop {
+        // Has the server tell the session about new messages
+        session.command("NOOP").await?;
+
+        let mut code = None;
+        for uid in session.search(otp_email, not_before).await? {
+            let (internal_date, message) = match session.fetch(uid).await? {
+                Some(fetched) => fetched,
+                None => continue,
+            };
+
+            // Newest first, so the rest are older still
+            if internal_date < not_before {
+                break;
+            }
+
+            if let Some(found) = find_code(&regex, &message_text(&message)?) {
+                code = Some(found);
+                break;
+            }
+        }
+
+        if code.is_some() || Instant::now() >= deadline {
+            break code;
+        }
+
+        tokio::time::sleep(StdDuration::from_secs(OTP_EMAIL_POLL_SECS)).await;
+    };
+
+    session.logout().await;
+
+    Ok(code)
+}
@@ -0,0 +1,152 @@
+// Dividends & interest posted as transactions of their own, to the security's name as payee &
+// categorized, rather than lost in the reconciliation's adjustment. With `POST_INCOME = true`,
+// those since the last run are posted before reconciling, to `DIVIDEND_CATEGORY_ID` or
+// `INTEREST_CATEGORY_ID` if they're set. They're counted from the first run with it set.
+
+use anyhow::Result;
+use chrono::{Local, NaiveDate};
+use log::info;
+use std::collections::HashSet;
+
+use crate::{
+    budget::{Budget, BudgetSink},
+    currency, fx,
+    history::History,
+    providers,
+    reconcile::Milliunits,
+    ynab::{Account, CurrencyFormat, NewTransaction, TransactionClearedStatus},
+    AccountConfig, Config,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum IncomeKind {
+    Dividend,
+    Interest,
+}
+
+#[derive(Clone, Debug)]
+pub struct Income {
+    // The provider's, which the transaction's import_id is made from
+    pub id: String,
+    pub date: NaiveDate,
+    pub kind: IncomeKind,
+    // The security it was paid on, or the institution's for interest
+    pub name: String,
+    pub amount: f32,
+}
+
+pub trait GetIncome {
+    // What was paid from `from` up to but not including `until`
+    async fn get_income(&self, from: NaiveDate, until: NaiveDate) -> Result<Vec<Income>>;
+}
+
+pub struct Booked {
+    // In the budget's currency
+    pub income: f32,
+    // What the account's balance in YNAB went up by, less what had been posted already
+    pub posted: Milliunits,
+}
+
+// FNV-1a, which unlike std's hasher is stable across releases, so an import_id is too
+fn fnv1a(text: &str) -> u64 {
+    text.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ byte as u64).wrapping_mul(0x100000001b3)
+    })
+}
+
+fn import_id(income: &Income) -> String {
+    format!("YNAB-UPDATER:{:016x}", fnv1a(&income.id))
+}
+
+// Posts the income since it was last posted, `None` if there's no window to post yet
+pub async fn book(
+    config: &Config,
+    history: &History,
+    budget: &Budget,
+    account: &Account,
+    provider: &str,
+    account_config: &AccountConfig,
+    currency: Option<&CurrencyFormat>,
+) -> Result<Option<Booked>> {
+    let today = Local::now().date_naive();
+
+    let from = match history.get_income_booked_until(provider)? {
+        Some(from) if from < today => from,
+        Some(_) => return Ok(None),
+        None => {
+            history.set_income_booked_until(provider, today)?;
+            return Ok(None);
+        }
+    };
+
+    let incomes = providers::income(config, provider, account_config, from, today).await?;
+
+    // An interrupted run's are found by their import_id, so they can't be posted twice
+    let already_posted = if incomes.is_empty() {
+        HashSet::new()
+    } else {
+        budget
+            .get_transactions(&account.id, Some(from))
+            .await?
+            .into_iter()
+            .filter_map(|t| t.import_id)
+            .collect::<HashSet<_>>()
+    };
+
+    let mut booked = Booked {
+        income: 0.0,
+        posted: 0,
+    };
+
+    for income in incomes {
+        let amount = fx::to_budget_currency(
+            config,
+            income.amount,
+            account_config.currency.as_deref(),
+            currency,
+        )
+        .await?;
+        let milliunits = currency::to_milliunits(currency, amount);
+        let import_id = import_id(&income);
+
+        booked.income += amount;
+
+        if milliunits == 0 || already_posted.contains(&import_id) {
+            continue;
+        }
+
+        let (kind, category_id) = match income.kind {
+            IncomeKind::Dividend => ("Dividend", &account_config.dividend_category_id),
+            IncomeKind::Interest => ("Interest", &account_config.interest_category_id),
+        };
+
+        info!(
+            "Posting {} {} from {} on {}",
+            kind.to_lowercase(),
+            currency::format(currency, amount),
+            income.name,
+            income.date
+        );
+
+        budget
+            .create_transaction(&NewTransaction {
+                account_id: Some(account.id.clone()),
+                date: Some(income.date),
+                amount: Some(milliunits),
+                payee_name: Some(income.name.clone()),
+                category_id: category_id.clone(),
+                memo: Some(kind.to_owned()),
+                cleared: Some(TransactionClearedStatus::Cleared),
+                approved: Some(category_id.is_some()),
+                import_id: Some(import_id),
+                ..Default::default()
+            })
+            .await?;
+
+        booked.posted += milliunits;
+    }
+
+    history.set_income_booked_until(provider, today)?;
+
+    Ok(Some(booked))
+}
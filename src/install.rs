@@ -0,0 +1,464 @@
+// Each user's schedules registered with the OS's own scheduler, for machines that don't run the
+// daemon or systemd, e.g. a Windows home server or a Mac. Every profile or account with a `schedule` gets a
+// task running `ynab-updater run` for it at the schedule's times, with YNAB_CONFIG_PATH set & its
+// output appended to `ynab-updater.log` in the config directory. A schedule the scheduler can't
+// express, e.g. every second, is an error rather than run at the wrong times.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Weekday;
+use cron::{Schedule, TimeUnitSpec};
+use log::info;
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+    process::Command,
+    str::FromStr,
+};
+
+use crate::User;
+
+static LOG_FILENAME: &str = "ynab-updater.log";
+// Prefixed to each launchd agent's label & file name
+static LAUNCHD_LABEL: &str = "ynab-updater";
+// Task Scheduler's limit for a task's triggers
+static MAX_TRIGGERS: usize = 48;
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Platform {
+    // Task Scheduler, with schtasks
+    Windows,
+    // A launchd agent for each, in ~/Library/LaunchAgents
+    Macos,
+}
+
+// When a schedule runs, in the local time the daemon would run it in
+#[derive(Clone, Debug, PartialEq)]
+pub struct Calendar {
+    // Each hour & minute of the day it runs at
+    pub times: Vec<(u32, u32)>,
+    // Every day unless they're given
+    pub days_of_week: Option<Vec<Weekday>>,
+    pub days_of_month: Option<Vec<u32>>,
+    pub months: Option<Vec<u32>>,
+}
+
+impl Calendar {
+    pub fn from_cron(spec: &str) -> Result<Self> {
+        let schedule =
+            Schedule::from_str(spec).map_err(|e| anyhow!("Invalid schedule {:?}: {}", spec, e))?;
+
+        if schedule.seconds().count() != 1 {
+            return Err(anyhow!(
+                "{:?} runs more than once a minute, which only the daemon can do",
+                spec
+            ));
+        }
+        if !schedule.years().is_all() {
+            return Err(anyhow!("{:?} is only for some years", spec));
+        }
+
+        let times = schedule
+            .hours()
+            .iter()
+            .flat_map(|hour| schedule.minutes().iter().map(move |minute| (hour, minute)))
+            .collect::<Vec<_>>();
+
+        let days_of_week = (!schedule.days_of_week().is_all()).then(|| {
+            schedule
+                .days_of_week()
+                .iter()
+                // cron's are numbered from Sunday, as 1
+                .map(|day| (1..day).fold(Weekday::Sun, |weekday, _| weekday.succ()))
+                .collect()
+        });
+        let days_of_month =
+            (!schedule.days_of_month().is_all()).then(|| schedule.days_of_month().iter().collect());
+        let months = (!schedule.months().is_all()).then(|| schedule.months().iter().collect());
+
+        // cron needs both to match, which no scheduler can say
+        if days_of_week.is_some() && (days_of_month.is_some() || months.is_some()) {
+            return Err(anyhow!(
+                "{:?} is for both days of the week & of the month or months",
+                spec
+            ));
+        }
+
+        Ok(Calendar {
+            times,
+            days_of_week,
+            days_of_month,
+            months,
+        })
+    }
+}
+
+// A profile's or account's run, at its schedule's times
+#[derive(Clone, Debug)]
+pub struct ScheduledTask {
+    // Unique across users, e.g. `alice-daily`
+    pub name: String,
+    pub args: Vec<String>,
+    pub calendar: Calendar,
+}
+
+pub fn tasks(users: &[User]) -> Result<Vec<ScheduledTask>> {
+    let mut tasks = vec![];
+
+    for user in users {
+        for (target, spec) in &user.config.schedules {
+            let calendar = Calendar::from_cron(spec)
+                .with_context(|| format!("{}'s {} schedule", user.name, target))?;
+
+            let mut args = vec!["run".to_owned(), "--user".to_owned(), user.name.clone()];
+
+            if user.config.profiles.contains_key(target) {
+                args.extend(["--profile".to_owned(), target.clone()]);
+            } else if user.config.accounts.contains_key(target) {
+                args.push(target.clone());
+            } else {
+                return Err(anyhow!(
+                    "{}'s {} schedule isn't for a configured profile or account",
+                    user.name,
+                    target
+                ));
+            }
+
+            tasks.push(ScheduledTask {
+                name: format!("{}-{}", user.name, target),
+                args,
+                calendar,
+            });
+        }
+    }
+
+    Ok(tasks)
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+static MONTH_NAMES: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+fn weekday_name(day: &Weekday) -> &'static str {
+    match day {
+        Weekday::Mon => "Monday",
+        Weekday::Tue => "Tuesday",
+        Weekday::Wed => "Wednesday",
+        Weekday::Thu => "Thursday",
+        Weekday::Fri => "Friday",
+        Weekday::Sat => "Saturday",
+        Weekday::Sun => "Sunday",
+    }
+}
+
+// One of Task Scheduler's calendar triggers for each time of day
+fn windows_triggers(calendar: &Calendar) -> Result<String> {
+    if calendar.times.len() > MAX_TRIGGERS {
+        return Err(anyhow!(
+            "It runs {} times a day, more than Task Scheduler's {} triggers",
+            calendar.times.len(),
+            MAX_TRIGGERS
+        ));
+    }
+
+    let schedule = match (
+        &calendar.days_of_week,
+        &calendar.days_of_month,
+        &calendar.months,
+    ) {
+        (Some(days), _, _) => format!(
+            "<ScheduleByWeek><WeeksInterval>1</WeeksInterval><DaysOfWeek>{}</DaysOfWeek></ScheduleByWeek>",
+            days.iter()
+                .map(|day| format!("<{}/>", weekday_name(day)))
+                .collect::<String>()
+        ),
+        (None, None, None) => "<ScheduleByDay><DaysInterval>1</DaysInterval></ScheduleByDay>".to_owned(),
+        (None, days, months) => format!(
+            "<ScheduleByMonth><DaysOfMonth>{}</DaysOfMonth><Months>{}</Months></ScheduleByMonth>",
+            days.clone()
+                .unwrap_or_else(|| (1..=31).collect())
+                .iter()
+                .map(|day| format!("<Day>{}</Day>", day))
+                .collect::<String>(),
+            months
+                .clone()
+                .unwrap_or_else(|| (1..=12).collect())
+                .iter()
+                .filter_map(|month| MONTH_NAMES.get(*month as usize - 1))
+                .map(|month| format!("<{}/>", month))
+                .collect::<String>()
+        ),
+    };
+
+    Ok(calendar
+        .times
+        .iter()
+        .map(|(hour, minute)| {
+            format!(
+                "    <CalendarTrigger>\n      <StartBoundary>2000-01-01T{:02}:{:02}:00</StartBoundary>\n      {}\n    </CalendarTrigger>\n",
+                hour, minute, schedule
+            )
+        })
+        .collect())
+}
+
+// Run through cmd, since a task can't set its own environment
+fn windows_task_xml(task: &ScheduledTask, exe: &str, config_path: &Path) -> Result<String> {
+    let command = format!(
+        "/c set \"YNAB_CONFIG_PATH={}\" && set \"RUST_LOG=info\" && \"{}\" {} >> \"{}\" 2>&1",
+        config_path.display(),
+        exe,
+        task.args.join(" "),
+        config_path.join(LOG_FILENAME).display()
+    );
+
+    Ok(format!(
+        r#"<?xml version="1.0" encoding="UTF-16"?>
+<Task version="1.2" xmlns="http://schemas.microsoft.com/windows/2004/02/mit/task">
+  <RegistrationInfo>
+    <Description>ynab-updater {args}</Description>
+  </RegistrationInfo>
+  <Triggers>
+{triggers}  </Triggers>
+  <Settings>
+    <MultipleInstancesPolicy>IgnoreNew</MultipleInstancesPolicy>
+    <StartWhenAvailable>true</StartWhenAvailable>
+    <RunOnlyIfNetworkAvailable>true</RunOnlyIfNetworkAvailable>
+    <ExecutionTimeLimit>PT1H</ExecutionTimeLimit>
+  </Settings>
+  <Actions>
+    <Exec>
+      <Command>cmd.exe</Command>
+      <Arguments>{command}</Arguments>
+    </Exec>
+  </Actions>
+</Task>
+"#,
+        args = escape_xml(&task.args.join(" ")),
+        triggers = windows_triggers(&task.calendar)?,
+        command = escape_xml(&command),
+    ))
+}
+
+fn install_windows(
+    tasks: &[ScheduledTask],
+    exe: &str,
+    config_path: &Path,
+    dry_run: bool,
+) -> Result<()> {
+    for task in tasks {
+        let name = format!("ynab-updater\\{}", task.name);
+        let xml = windows_task_xml(task, exe, config_path)?;
+
+        if dry_run {
+            println!("{}:\n{}", name, xml);
+            continue;
+        }
+
+        // schtasks only reads UTF-16
+        let path = env::temp_dir().join(format!("ynab-updater-{}.xml", task.name));
+        let bytes = [0xFF, 0xFE]
+            .into_iter()
+            .chain(xml.encode_utf16().flat_map(u16::to_le_bytes))
+            .collect::<Vec<u8>>();
+        fs::write(&path, bytes)?;
+
+        let output = Command::new("schtasks")
+            .args(["/Create", "/F", "/TN", &name, "/XML"])
+            .arg(&path)
+            .output()
+            .context("Failed to run schtasks");
+
+        let _ = fs::remove_file(&path);
+
+        let output = output?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "schtasks failed to register {}: {}",
+                name,
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        info!("Registered {}", name);
+    }
+
+    Ok(())
+}
+
+// One of StartCalendarInterval's dicts for each time on each day, since each only has one value of
+// each field
+fn launchd_intervals(calendar: &Calendar) -> String {
+    let days = match (
+        &calendar.days_of_week,
+        &calendar.days_of_month,
+        &calendar.months,
+    ) {
+        // launchd's are numbered from Sunday, as 0
+        (Some(days), _, _) => days
+            .iter()
+            .map(|day| vec![("Weekday", day.num_days_from_sunday())])
+            .collect(),
+        (None, days, months) => {
+            let days = days
+                .clone()
+                .map_or(vec![None], |d| d.into_iter().map(Some).collect());
+            let months = months
+                .clone()
+                .map_or(vec![None], |m| m.into_iter().map(Some).collect());
+
+            months
+                .iter()
+                .flat_map(|month| {
+                    days.iter().map(move |day| {
+                        month
+                            .map(|month| ("Month", month))
+                            .into_iter()
+                            .chain(day.map(|day| ("Day", day)))
+                            .collect::<Vec<_>>()
+                    })
+                })
+                .collect::<Vec<_>>()
+        }
+    };
+
+    days.iter()
+        .flat_map(|day| {
+            calendar.times.iter().map(move |(hour, minute)| {
+                let keys = day
+                    .iter()
+                    .chain([&("Hour", *hour), &("Minute", *minute)])
+                    .map(|(key, value)| {
+                        format!(
+                            "\t\t\t<key>{}</key>\n\t\t\t<integer>{}</integer>\n",
+                            key, value
+                        )
+                    })
+                    .collect::<String>();
+                format!("\t\t<dict>\n{}\t\t</dict>\n", keys)
+            })
+        })
+        .collect()
+}
+
+fn launchd_plist(task: &ScheduledTask, label: &str, exe: &str, config_path: &Path) -> String {
+    let arguments = [exe.to_owned()]
+        .iter()
+        .chain(&task.args)
+        .map(|arg| format!("\t\t<string>{}</string>\n", escape_xml(arg)))
+        .collect::<String>();
+    let log_path = escape_xml(&config_path.join(LOG_FILENAME).display().to_string());
+
+    format!(
+        r#"<?xml version="1.0" encoding="UTF-8"?>
+<!DOCTYPE plist PUBLIC "-//Apple//DTD PLIST 1.0//EN" "http://www.apple.com/DTDs/PropertyList-1.0.dtd">
+<plist version="1.0">
+<dict>
+	<key>Label</key>
+	<string>{label}</string>
+	<key>ProgramArguments</key>
+	<array>
+{arguments}	</array>
+	<key>EnvironmentVariables</key>
+	<dict>
+		<key>YNAB_CONFIG_PATH</key>
+		<string>{config_path}</string>
+		<key>RUST_LOG</key>
+		<string>info</string>
+	</dict>
+	<key>StartCalendarInterval</key>
+	<array>
+{intervals}	</array>
+	<key>StandardOutPath</key>
+	<string>{log_path}</string>
+	<key>StandardErrorPath</key>
+	<string>{log_path}</string>
+</dict>
+</plist>
+"#,
+        label = escape_xml(label),
+        config_path = escape_xml(&config_path.display().to_string()),
+        intervals = launchd_intervals(&task.calendar),
+    )
+}
+
+// Each agent's plist replaced & loaded again, so launchd reads its new schedule
+fn install_macos(
+    tasks: &[ScheduledTask],
+    exe: &str,
+    config_path: &Path,
+    dry_run: bool,
+) -> Result<()> {
+    let agents_dir =
+        PathBuf::from(env::var("HOME").context("HOME isn't set")?).join("Library/LaunchAgents");
+
+    for task in tasks {
+        let label = format!("{}.{}", LAUNCHD_LABEL, task.name);
+        let path = agents_dir.join(format!("{}.plist", label));
+        let plist = launchd_plist(task, &label, exe, config_path);
+
+        if dry_run {
+            println!("{}:\n{}", path.display(), plist);
+            continue;
+        }
+
+        fs::create_dir_all(&agents_dir)?;
+
+        // Fails if it wasn't loaded, e.g. it's the first install
+        let _ = Command::new("launchctl").arg("unload").arg(&path).output();
+
+        fs::write(&path, plist).with_context(|| format!("Failed to write {}", path.display()))?;
+
+        let output = Command::new("launchctl")
+            .args(["load", "-w"])
+            .arg(&path)
+            .output()
+            .context("Failed to run launchctl")?;
+        if !output.status.success() {
+            return Err(anyhow!(
+                "launchctl failed to load {}: {}",
+                path.display(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            ));
+        }
+
+        info!("Loaded {}", label);
+    }
+
+    Ok(())
+}
+
+// Registers a task for each schedule, replacing those registered before, or only prints them
+pub fn install(platform: Platform, users: &[User], dry_run: bool) -> Result<()> {
+    let tasks = tasks(users)?;
+
+    if tasks.is_empty() {
+        return Err(anyhow!("No schedules are configured"));
+    }
+
+    let exe = env::current_exe()?.display().to_string();
+    // Not canonicalised, which on Windows is a `\\?\` path cmd can't run from
+    let config_path = std::path::absolute(env::var("YNAB_CONFIG_PATH")?)?;
+
+    match platform {
+        Platform::Windows => install_windows(&tasks, &exe, &config_path, dry_run),
+        Platform::Macos => install_macos(&tasks, &exe, &config_path, dry_run),
+    }
+}
@@ -1,28 +1,327 @@
-#![feature(async_fn_in_trait)]
+#![feature(async_fn_in_trait, iterator_try_collect)]
 
-use anyhow::Result;
-use chrono::prelude::*;
-use log::info;
-use pushover::requests::message::SendMessage;
-use reqwest::header;
-use serde::{Deserialize, Serialize};
-use serde_json::json;
-use std::env;
+use anyhow::{anyhow, Context, Result};
+use chrono::{prelude::*, Duration};
+use log::{error, info, warn};
+use rand::{distributions::Alphanumeric, Rng};
+use secrecy::SecretString;
+use serde::{de::DeserializeOwned, Deserialize};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    env, fmt,
+    path::Path,
+    time::Instant,
+};
+
+pub mod access;
+pub mod actual;
+pub mod amount;
+pub mod approval;
+pub mod backfill;
+pub mod browser;
+pub mod budget;
+pub mod callback_tls;
+pub mod contributions;
+pub mod credentials;
+pub mod currency;
+pub mod daemon;
+pub mod digest;
+pub mod error;
+pub mod export;
+pub mod fd_store;
+pub mod firefly;
+pub mod fx;
+pub mod goals;
+pub mod history;
+pub mod http;
+pub mod imap_otp;
+pub mod income;
+pub mod install;
+pub mod logging;
+pub mod manual_login;
+pub mod market;
+pub mod metrics;
+pub mod migrate;
+pub mod notify;
+pub mod oauth;
+pub mod otp_relay;
+pub mod permissions;
+pub mod projection;
+pub mod providers;
+pub mod rate_limit;
+pub mod reconcile;
+pub mod report;
+pub mod scaffold;
+pub mod script;
+pub mod sealed;
+pub mod self_update;
+pub mod sinks;
+pub mod split;
+pub mod stats;
+pub mod token_store;
+pub mod totp;
+#[cfg(feature = "vcr")]
+pub mod vcr;
+pub mod web;
+pub mod ynab;
+
+use access::AccessConfig;
+use actual::ActualConfig;
+use approval::{ApprovalPending, BalanceRejected};
+use browser::ChallengeDetected;
+use budget::{Budget, BudgetKind, BudgetSink};
+use digest::{DigestEntry, SmtpConfig};
+use export::ExportConfig;
+use fd_store::SecretsStorage;
+use firefly::FireflyConfig;
+use fx::{FxConfig, FxRateStale};
+use history::{Adjustment, History, ProviderPause, RunState};
+use http::HttpConfig;
+use imap_otp::{ImapConfig, OtpEmailConfig};
+use market::MarketHoursConfig;
+use metrics::MetricsConfig;
+use notify::{Event, Notification, NotifierKind, QuietHours, WebhookConfig};
+use oauth::{OAuthClient, TokenResponse};
+use permissions::FilePermissions;
+use projection::ProjectionConfig;
+use providers::ProviderKind;
+use rate_limit::RateLimitConfig;
+use reconcile::{Decision, Policy, SkipReason};
+use report::{RunAction, RunReport};
+use script::ScriptSkip;
+use sinks::SinkKind;
+use split::SplitPart;
+use token_store::TokenStore;
+use web::WebUiConfig;
+use ynab::{
+    Account, ExistingTransaction, NewTransaction, SaveTransactionWithIdOrImportId,
+    TransactionClearedStatus, TransactionDetail, TransactionFlagColor,
+};
 
 pub static CONFIG_FILENAME: &str = "settings.toml";
 
+// The user a config without `[users.<name>]` sections belongs to
+pub static DEFAULT_USER: &str = "default";
+
+// Each user's history & token caches live under their own directory
+static USERS_DIRNAME: &str = "users";
+
+static YNAB_OAUTH_AUTH_URL: &str = "https://app.ynab.com/oauth/authorize";
+static YNAB_OAUTH_TOKEN_URL: &str = "https://app.ynab.com/oauth/token";
+
+static YNAB_TOKEN_FILENAME: &str = "ynab_token.json";
+
 #[derive(Clone, Debug, Deserialize)]
 #[serde(rename_all = "SCREAMING_SNAKE_CASE")]
 pub struct Config {
-    #[serde(rename = "config_path")]
+    // Set from `YNAB_CONFIG_PATH`, or the user's directory under it
+    #[serde(rename = "config_path", default)]
     pub config_path: String,
 
     pub pushover_user_key: String,
-    pub pushover_api_key: String,
+    pub pushover_api_key: SecretString,
 
-    pub ynab_bearer_token: String,
+    // Either a personal access token or an OAuth app is needed
+    pub ynab_bearer_token: Option<SecretString>,
+    pub ynab_oauth: Option<YnabOAuthConfig>,
+    // Who may reach the OAuth callback listeners. YNAB_OAUTH's REDIRECT_URI has to include any
+    // PATH_SECRET itself.
+    #[serde(default)]
+    pub callback_access: AccessConfig,
     pub ynab_budget_id: String,
     pub ynab_reconciliation_payee_id: String,
+    // YNAB's API, unless it's e.g. a mock of it to benchmark against
+    pub ynab_api_url: Option<String>,
+    // e.g. `YNAB_RATE_LIMIT = { REQUESTS_PER_HOUR = 200, SAFETY_MARGIN_PERCENT = 10 }`
+    #[serde(default)]
+    pub ynab_rate_limit: RateLimitConfig,
+
+    #[serde(default = "default_auth_timeout_secs")]
+    pub auth_timeout_secs: u64,
+
+    // Flags the reconciliations on the 1st, which are kept as monthly snapshots, e.g. `"blue"`
+    pub snapshot_flag_color: Option<TransactionFlagColor>,
+    // Appends the run's ID to each reconciliation's memo, e.g. `Entered automatically by YNAB
+    // (run 3kq9x0ab)`, so an odd adjustment can be traced back to the run's logs
+    #[serde(default)]
+    pub run_id_in_memo: bool,
+    // A Rhai script with hooks into each run, see script.rs. Needs the `scripting` feature.
+    pub script: Option<String>,
+
+    // A balance that's moved by more than this percentage since the last one isn't reconciled
+    // until it's approved through the web UI, in case e.g. a scraper read the wrong element
+    pub anomaly_threshold_percent: Option<f32>,
+    // An adjustment larger than this, in the budget's currency, is notified with what it changes &
+    // whether it's merged into the last reconciliation or posted as a new one
+    pub preview_adjustments_over: Option<f32>,
+
+    // A login expiring within this many days is renewed early, asking the user to log in again
+    // at most once in that many days
+    #[serde(default = "default_token_expiry_warning_days")]
+    pub token_expiry_warning_days: i64,
+
+    // After this many failed runs in a row a provider is paused for the cool-down, rather than
+    // hammering e.g. a bank's login page until the account is locked
+    #[serde(default = "default_circuit_breaker_failures")]
+    pub circuit_breaker_failures: u32,
+    #[serde(default = "default_circuit_breaker_cooldown_hours")]
+    pub circuit_breaker_cooldown_hours: i64,
+
+    // An account that was reconciled less than this long ago is skipped, so e.g. a manual run
+    // straight after the timer's doesn't fold a second adjustment into the first. `run --force`
+    // ignores it.
+    pub min_reconcile_interval_hours: Option<i64>,
+
+    // How many times a day a provider may submit credentials to log in, since a site redesign
+    // can otherwise fail logins until the account is locked. Accounts can set their own.
+    #[serde(default = "default_max_login_attempts_per_day")]
+    pub max_login_attempts_per_day: u32,
+
+    #[serde(default)]
+    pub notifier: NotifierKind,
+    // The notifier's quiet hours & batching, see notify.rs
+    pub notify_quiet_hours: Option<QuietHours>,
+    pub notify_batch_minutes: Option<i64>,
+    pub smtp: Option<SmtpConfig>,
+    #[serde(default)]
+    pub webhooks: Vec<WebhookConfig>,
+
+    #[serde(default)]
+    pub http: HttpConfig,
+    // For accounts in another currency than the budget's
+    #[serde(default)]
+    pub fx: FxConfig,
+    // Taken from the top level when a user doesn't set their own
+    pub web_ui: Option<WebUiConfig>,
+    // Pushes each run's metrics to statsd
+    #[serde(rename = "metrics")]
+    pub metrics: Option<MetricsConfig>,
+    // For accounts reconciled in Actual Budget rather than YNAB
+    #[serde(rename = "actual")]
+    pub actual: Option<ActualConfig>,
+    // Mirrors each reconciliation into Firefly III
+    #[serde(rename = "firefly")]
+    pub firefly: Option<FireflyConfig>,
+    // Appends each reconciliation to a ledger journal or CSV file
+    #[serde(rename = "export")]
+    pub export: Option<ExportConfig>,
+    // The mailbox accounts with `OTP_EMAIL` read their login codes from
+    #[serde(rename = "imap")]
+    pub imap: Option<ImapConfig>,
+
+    #[serde(rename = "accounts", default)]
+    pub accounts: BTreeMap<String, AccountConfig>,
+    // Named groups of accounts that can be run on their own schedule, e.g.
+    // `profile.weekly = ["hl", "saxo"]`
+    #[serde(rename = "profile", default)]
+    pub profiles: BTreeMap<String, Vec<String>>,
+    // When the daemon runs each profile or account, as a cron expression with seconds, e.g.
+    // `schedule.weekly = "0 0 6 * * Sun"`
+    #[serde(rename = "schedule", default)]
+    pub schedules: BTreeMap<String, String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct AccountConfig {
+    pub provider: ProviderKind,
+    pub ynab_account_id: String,
+    pub max_login_attempts_per_day: Option<u32>,
+    // What's owed, e.g. on a credit card, is read as a positive balance but is negative in YNAB
+    #[serde(default)]
+    pub liability: bool,
+    // Reconciles only YNAB's cleared balance to the real one, leaving uncleared transactions
+    // entered by hand, e.g. a cheque that's yet to be paid in, on top of it
+    #[serde(default)]
+    pub reconcile_cleared_only: bool,
+    // After an adjustment, marks the cleared transactions up to it as reconciled, like YNAB's own
+    // reconcile button
+    #[serde(default)]
+    pub mark_reconciled: bool,
+    // Runs straight away when the daemon starts if a scheduled run was missed while it wasn't
+    // running, e.g. the machine was asleep
+    #[serde(default)]
+    pub catch_up_missed_runs: bool,
+    // The currency the provider's balance is in, e.g. `USD`, when it isn't the budget's
+    pub currency: Option<String>,
+    // For pensions, a projection of the value to put in each reconciliation's memo
+    pub projection: Option<ProjectionConfig>,
+    // For investments, the hours it's fetched in, see market.rs
+    pub market_hours: Option<MarketHoursConfig>,
+    // The Firefly III asset account its reconciliations are mirrored to
+    pub firefly_account_id: Option<String>,
+    // The account it's exported as, e.g. `Assets:Investments:HL`
+    pub export_account: Option<String>,
+    // Where its reconciliations are written besides its budget, see sinks.rs
+    pub sinks: Option<Vec<SinkKind>>,
+    // The budget it's reconciled in, YNAB unless it's `"actual"`
+    #[serde(default)]
+    pub budget: BudgetKind,
+    // Posts what was paid in or taken out apart from the reconciliation, see contributions.rs
+    #[serde(default)]
+    pub split_contributions: bool,
+    pub contribution_payee_name: Option<String>,
+    // Posts dividends & interest as transactions of their own, see income.rs
+    #[serde(default)]
+    pub post_income: bool,
+    pub dividend_category_id: Option<String>,
+    pub interest_category_id: Option<String>,
+    // Flags its reconciliations, e.g. `"purple"` to filter them in YNAB, other than the 1st's
+    // snapshots when SNAPSHOT_FLAG_COLOR is set
+    pub flag_color: Option<TransactionFlagColor>,
+    // The YNAB category whose goal it's saving towards, whose progress is added to the run's
+    // notification, e.g. `House deposit: 64% of £20,000.00`
+    pub goal_category_id: Option<String>,
+    // Splits its adjustments between categories, see split.rs
+    pub split: Option<Vec<SplitPart>>,
+    // Reads its login's one-time codes from email, see imap_otp.rs
+    pub otp_email: Option<OtpEmailConfig>,
+    // The provider's own settings, e.g. `HL_USERNAME`
+    #[serde(flatten)]
+    pub settings: serde_json::Map<String, serde_json::Value>,
+}
+
+impl AccountConfig {
+    pub fn provider_config<T: DeserializeOwned>(&self) -> Result<T> {
+        serde_json::from_value(serde_json::Value::Object(self.settings.clone()))
+            .context(error::ConfigInvalid)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct User {
+    pub name: String,
+    pub config: Config,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct YnabOAuthConfig {
+    pub client_id: String,
+    pub client_secret: SecretString,
+    pub redirect_uri: String,
+    // The address the redirect_uri's callback listener binds to
+    pub listen_addr: String,
+}
+
+fn default_auth_timeout_secs() -> u64 {
+    60 * 60
+}
+
+fn default_token_expiry_warning_days() -> i64 {
+    7
+}
+
+fn default_circuit_breaker_failures() -> u32 {
+    3
+}
+
+fn default_circuit_breaker_cooldown_hours() -> i64 {
+    24
+}
+
+fn default_max_login_attempts_per_day() -> u32 {
+    3
 }
 
 #[derive(Clone, Debug)]
@@ -30,6 +329,146 @@ pub struct YnabAccountConfig {
     pub ynab_account_id: String,
 }
 
+// Returned by a provider when it's waiting on the user to complete an interactive login,
+// which isn't a failure of the run so it's not notified
+#[derive(Clone, Debug)]
+pub struct AuthPending {
+    pub provider: String,
+}
+
+impl fmt::Display for AuthPending {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} is waiting for the user to log in", self.provider)
+    }
+}
+
+impl std::error::Error for AuthPending {}
+
+// Returned by a provider when it's used up its `MAX_LOGIN_ATTEMPTS_PER_DAY`, which skips the run
+// with a warning rather than failing it
+#[derive(Clone, Debug)]
+pub struct LoginBudgetExhausted {
+    pub provider: String,
+    pub attempts: u32,
+}
+
+impl fmt::Display for LoginBudgetExhausted {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} skipped logging in, having tried {} times in the last 24h",
+            self.provider, self.attempts
+        )
+    }
+}
+
+impl std::error::Error for LoginBudgetExhausted {}
+
+// Returned when YNAB can't be reached, e.g. during an outage or without a network. The fetched
+// balance is queued to be reconciled once it's back, so the run is skipped with a warning.
+#[derive(Clone, Debug)]
+pub struct YnabUnreachable {
+    pub provider: String,
+    pub balance: f32,
+}
+
+impl fmt::Display for YnabUnreachable {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "YNAB is unreachable, {}'s balance of {:.2} is queued until it's back",
+            self.provider, self.balance
+        )
+    }
+}
+
+impl std::error::Error for YnabUnreachable {}
+
+// Whether a login expiring at `expires_at` should be renewed now, i.e. it's within the window,
+// usually `TOKEN_EXPIRY_WARNING_DAYS`, & the user hasn't been asked in that long. Asking is
+// recorded.
+pub fn is_renewal_due(
+    config: &Config,
+    account: &str,
+    expires_at: DateTime<Utc>,
+    window: Duration,
+) -> Result<bool> {
+    if expires_at - Utc::now() > window {
+        return Ok(false);
+    }
+
+    let history = History::open(&config.config_path)?;
+
+    if history
+        .get_expiry_warned_at(account)?
+        .is_some_and(|warned_at| Utc::now() - warned_at < window)
+    {
+        return Ok(false);
+    }
+
+    history.set_expiry_warned_at(account)?;
+
+    Ok(true)
+}
+
+// Whether the account's been reconciled within MIN_RECONCILE_INTERVAL_HOURS or it's outside its
+// MARKET_HOURS, which is logged
+pub fn is_reconcile_due(config: &Config, account: &str) -> Result<bool> {
+    let market_hours = config
+        .accounts
+        .get(account)
+        .and_then(|account_config| account_config.market_hours.as_ref());
+
+    if let Some(market_hours) = market_hours {
+        if !market::is_fetch_time(account, market_hours, Utc::now()) {
+            return Ok(false);
+        }
+    }
+
+    let min_interval = match config.min_reconcile_interval_hours {
+        Some(hours) => Duration::hours(hours),
+        None => return Ok(true),
+    };
+
+    match History::open(&config.config_path)?.get_last_success(account)? {
+        Some(succeeded_at) if Utc::now() - succeeded_at < min_interval => {
+            info!(
+                "{} was reconciled at {}, within MIN_RECONCILE_INTERVAL_HOURS, skipping",
+                account, succeeded_at
+            );
+            Ok(false)
+        }
+        _ => Ok(true),
+    }
+}
+
+// Called by a provider before each login attempt
+pub fn spend_login_attempt(
+    config: &Config,
+    account: &str,
+    account_config: &AccountConfig,
+) -> Result<()> {
+    let budget = account_config
+        .max_login_attempts_per_day
+        .unwrap_or(config.max_login_attempts_per_day);
+
+    let history = History::open(&config.config_path)?;
+
+    let since = Utc::now() - Duration::hours(24);
+
+    let attempts = history.count_login_attempts_since(account, since)?;
+
+    if attempts >= budget {
+        return Err(LoginBudgetExhausted {
+            provider: account.to_owned(),
+            attempts,
+        }
+        .into());
+    }
+
+    history.record_login_attempt(account, since)
+}
+
 pub trait GetYnabAccountConfig {
     async fn get(&self) -> Result<YnabAccountConfig>;
 }
@@ -38,196 +477,1240 @@ pub trait GetBalance {
     async fn get(&self) -> Result<f32>;
 }
 
-async fn _update_ynab<T>(config: &Config, t: T) -> Result<()>
+async fn get_ynab_bearer_token(config: &Config) -> Result<SecretString> {
+    let ynab_oauth = match (&config.ynab_oauth, &config.ynab_bearer_token) {
+        (Some(ynab_oauth), _) => ynab_oauth,
+        (None, Some(ynab_bearer_token)) => return Ok(ynab_bearer_token.clone()),
+        (None, None) => {
+            return Err(anyhow!(
+                "Either YNAB_BEARER_TOKEN or YNAB_OAUTH must be configured"
+            ))
+        }
+    };
+
+    let oauth_client = OAuthClient {
+        provider: "ynab".to_owned(),
+        title: "YNAB".to_owned(),
+        auth_url: YNAB_OAUTH_AUTH_URL.to_owned(),
+        token_url: YNAB_OAUTH_TOKEN_URL.to_owned(),
+        client_id: ynab_oauth.client_id.clone(),
+        client_secret: ynab_oauth.client_secret.clone(),
+        basic_auth: false,
+        redirect_uri: ynab_oauth.redirect_uri.clone(),
+        scope: None,
+        listen_addr: ynab_oauth.listen_addr.clone(),
+        callback_path: None,
+        access: config.callback_access.clone(),
+        resolve_authorize_redirect: false,
+        tls: false,
+        http: config.http.clone(),
+        auth_params: vec![],
+    };
+
+    let client = http::client(&config.http)?;
+
+    let token_store = TokenStore::new(&config.config_path, YNAB_TOKEN_FILENAME);
+
+    let token_guard = token_store.lock().await?;
+
+    let token = match token_guard.read::<TokenResponse>()? {
+        // Refreshed a minute early so it can't expire mid-run
+        Some((token, written_at))
+            if Utc::now() < written_at + Duration::seconds(token.expires_in as i64 - 60) =>
+        {
+            token
+        }
+        Some((token, _)) => match oauth_client.refresh(&client, &token.refresh_token).await {
+            Ok(token) => {
+                token_guard.write(&token)?;
+                token
+            }
+            Err(e) => {
+                warn!(
+                    "Failed to refresh the YNAB token, logging in again: {:#?}",
+                    e
+                );
+                oauth_client
+                    .login(&client, &config.config_path, &token_guard, Some(config))
+                    .await?
+            }
+        },
+        None => {
+            oauth_client
+                .login(&client, &config.config_path, &token_guard, Some(config))
+                .await?
+        }
+    };
+
+    Ok(token.access_token)
+}
+
+// What the run did, and the reconciliation it created or updated
+async fn _update_ynab<T>(
+    config: &Config,
+    t: T,
+    entry: &mut DigestEntry,
+) -> Result<(RunAction, Option<String>)>
 where
     T: GetBalance + GetYnabAccountConfig,
 {
+    let history = History::open(&config.config_path)?;
+
+    // A run that was interrupted mid-reconciliation is safe to re-run since the adjustment is
+    // recomputed from the latest YNAB balance, and the interrupted write's import_id is reused so
+    // it can't be posted twice
+    let interrupted_import_id = match history.get_run_state(&entry.provider)? {
+        Some(previous_run) if previous_run.state == RunState::Reconciling => {
+            warn!(
+                "Previous run was interrupted while reconciling at {}, recovering",
+                previous_run.updated_at
+            );
+            previous_run.import_id
+        }
+        Some(previous_run) if previous_run.state == RunState::FetchingBalance => {
+            warn!(
+                "Previous run was interrupted while fetching the balance at {}",
+                previous_run.updated_at
+            );
+            None
+        }
+        _ => None,
+    };
+
     let ynab_account_config = GetYnabAccountConfig::get(&t).await?;
 
-    let real_balance = GetBalance::get(&t).await?;
+    let budget = Budget::for_account(config, &entry.provider).await?;
+
+    let account = match budget
+        .get_account(&ynab_account_config.ynab_account_id)
+        .await
+    {
+        Ok(account) => account,
+        // The balance is still fetched, to be reconciled once YNAB is back
+        Err(e) if error::is_unreachable(&e) => {
+            warn!("YNAB is unreachable: {:#}", e);
+
+            let real_balance = get_real_balance(config, &entry.provider, &t).await?;
+
+            info!("Real Balance: {:#?}", real_balance);
+
+            entry.real_balance = Some(real_balance);
+
+            let real_balance = script::on_balance_fetched(config, entry, real_balance)?;
+            entry.real_balance = Some(real_balance);
+
+            check_balance(config, &history, &entry.provider, real_balance).await?;
+
+            history.set_queued_balance(
+                &entry.provider,
+                &ynab_account_config.ynab_account_id,
+                real_balance,
+            )?;
+
+            return Err(YnabUnreachable {
+                provider: entry.provider.clone(),
+                balance: real_balance,
+            }
+            .into());
+        }
+        Err(e) => return Err(e),
+    };
+
+    let previous_reason =
+        history.get_needs_reconfiguration(&ynab_account_config.ynab_account_id)?;
+
+    // Reconciling into a closed/deleted account would either fail or silently hide the
+    // adjustment, so it's skipped until the account is reopened or the config is updated
+    let account = match account {
+        Some(account) if !account.closed && !account.deleted => account,
+        account => {
+            let reason = match &account {
+                Some(account) if !account.deleted => "closed",
+                _ => "deleted",
+            };
+            let account_name = account
+                .map(|a| a.name)
+                .unwrap_or_else(|| ynab_account_config.ynab_account_id.clone());
+            let warning = format!(
+                "YNAB account {} is {}, skipping until it's reconfigured",
+                account_name, reason
+            );
+            warn!("{}", warning);
+
+            entry.account = Some(account_name);
+            // Only warn once, rather than on every run until it's fixed
+            if previous_reason.is_none() {
+                entry.warning = Some(warning);
+            }
+
+            history
+                .set_needs_reconfiguration(&ynab_account_config.ynab_account_id, Some(reason))?;
+
+            return Ok((RunAction::Skipped(report::SkipReason::Closed), None));
+        }
+    };
+
+    if previous_reason.is_some() {
+        info!("YNAB account {} is usable again", account.name);
+        history.set_needs_reconfiguration(&ynab_account_config.ynab_account_id, None)?;
+    }
+
+    info!("YNAB Balance: {:#?}", account.balance as f32 / 1000.0);
+
+    entry.account = Some(account.name.clone());
+    entry.ynab_balance = Some(account.balance as f32 / 1000.0);
+
+    history.set_run_state(&entry.provider, RunState::FetchingBalance, None)?;
+
+    let real_balance = get_real_balance(config, &entry.provider, &t).await?;
 
     info!("Real Balance: {:#?}", real_balance);
 
-    let mut headers = header::HeaderMap::new();
-    headers.insert(
-        "Authorization",
-        format!("Bearer {}", config.ynab_bearer_token).parse()?,
+    entry.real_balance = Some(real_balance);
+
+    let real_balance = script::on_balance_fetched(config, entry, real_balance)?;
+    entry.real_balance = Some(real_balance);
+
+    check_balance(config, &history, &entry.provider, real_balance).await?;
+
+    match reconcile_balance(
+        config,
+        &history,
+        &budget,
+        &account,
+        real_balance,
+        interrupted_import_id,
+        entry,
+    )
+    .await
+    {
+        Err(e) if error::is_unreachable(&e) => {
+            warn!("YNAB is unreachable: {:#}", e);
+
+            history.set_queued_balance(&entry.provider, &account.id, real_balance)?;
+
+            Err(YnabUnreachable {
+                provider: entry.provider.clone(),
+                balance: real_balance,
+            }
+            .into())
+        }
+        Err(e) => Err(e),
+        // A balance queued by an earlier run is superseded by this one
+        Ok(reconciled) => {
+            history.clear_queued_balance(&entry.provider)?;
+            entry.goal_progress =
+                goals::progress(config, &budget, &entry.provider, entry.currency.as_ref()).await;
+            Ok(reconciled)
+        }
+    }
+}
+
+async fn get_real_balance<T: GetBalance>(config: &Config, account: &str, t: &T) -> Result<f32> {
+    let started = Instant::now();
+
+    let balance = t.get().await;
+
+    // For `stats`. Waiting for a login says nothing about how reliable the provider is.
+    if !balance
+        .as_ref()
+        .is_err_and(|e| e.downcast_ref::<AuthPending>().is_some())
+    {
+        if let Err(e) = History::open(&config.config_path).and_then(|history| {
+            history.add_fetch(
+                account,
+                started.elapsed().as_millis() as i64,
+                balance.is_ok(),
+            )
+        }) {
+            warn!("Failed to record the fetch: {:#?}", e);
+        }
+    }
+
+    let balance = balance?;
+
+    let balance = match config.accounts.get(account) {
+        Some(account_config) if account_config.liability => -balance,
+        _ => balance,
+    };
+
+    // For the run's span, see `providers::update_account`
+    tracing::Span::current().record("amount", balance);
+
+    Ok(balance)
+}
+
+// Holds an anomalous balance for approval, then records it as the last one fetched
+async fn check_balance(
+    config: &Config,
+    history: &History,
+    provider: &str,
+    real_balance: f32,
+) -> Result<()> {
+    if let Some(threshold_percent) = config.anomaly_threshold_percent {
+        if let Some(previous) = history.get_last_balance(provider)? {
+            if approval::is_anomalous(previous, real_balance, threshold_percent) {
+                approval::confirm(config, provider, previous, real_balance).await?;
+            }
+        }
+    }
+
+    history.set_last_balance(provider, real_balance)
+}
+
+// Adjusts the YNAB account to the real balance, recomputing the adjustment from the account's
+// current YNAB balance
+async fn reconcile_balance(
+    config: &Config,
+    history: &History,
+    budget: &Budget,
+    account: &Account,
+    real_balance: f32,
+    interrupted_import_id: Option<String>,
+    entry: &mut DigestEntry,
+) -> Result<(RunAction, Option<String>)> {
+    let now = Local::now().date_naive();
+
+    entry.currency = budget.get_budget_settings().await?.currency_format;
+    let currency = entry.currency.as_ref();
+
+    let account_config = config.accounts.get(&entry.provider);
+
+    let real_balance = match account_config.and_then(|a| a.currency.as_deref()) {
+        Some(from) => {
+            let to = currency
+                .map(|c| c.iso_code.as_str())
+                .ok_or_else(|| anyhow!("The YNAB budget has no currency to convert {} to", from))?;
+
+            let real_balance = fx::convert(config, real_balance, from, to).await?;
+            entry.real_balance = Some(real_balance);
+            real_balance
+        }
+        None => real_balance,
+    };
+
+    let mark_reconciled = account_config.is_some_and(|a| a.mark_reconciled);
+
+    let balance = match account_config {
+        Some(account_config) if account_config.reconcile_cleared_only => account.cleared_balance,
+        _ => account.balance,
+    };
+
+    // Posted first, so the reconciliation is only what the account grew by
+    let balance = match account_config {
+        Some(account_config) if account_config.split_contributions => {
+            match contributions::book(
+                config,
+                history,
+                budget,
+                account,
+                &entry.provider,
+                account_config,
+                currency,
+            )
+            .await?
+            {
+                Some(booked) => {
+                    entry.contributions = Some(booked.contributions);
+                    balance + booked.posted
+                }
+                None => balance,
+            }
+        }
+        _ => balance,
+    };
+
+    let balance = match account_config {
+        Some(account_config) if account_config.post_income => {
+            match income::book(
+                config,
+                history,
+                budget,
+                account,
+                &entry.provider,
+                account_config,
+                currency,
+            )
+            .await?
+            {
+                Some(booked) => {
+                    entry.income = Some(booked.income);
+                    balance + booked.posted
+                }
+                None => balance,
+            }
+        }
+        _ => balance,
+    };
+
+    info!(
+        "Reconciling {} (cleared {}, uncleared {}) to {}",
+        currency::format(currency, balance as f32 / 1000.0),
+        currency::format(currency, account.cleared_balance as f32 / 1000.0),
+        currency::format(currency, account.uncleared_balance as f32 / 1000.0),
+        currency::format(currency, real_balance)
     );
-    headers.insert("Content-Type", "application/json".parse()?);
 
-    let client = reqwest::Client::builder()
-        .default_headers(headers)
-        .connection_verbose(true)
-        .build()?;
+    // Deciding only needs the last transaction, which is at the earliest the last adjustment. An
+    // interrupted adjustment, marking transactions reconciled & flagging old snapshots need them
+    // all.
+    let since_date = match history.get_last_adjustment(&entry.provider)? {
+        Some(adjustment)
+            if interrupted_import_id.is_none()
+                && !mark_reconciled
+                && config.snapshot_flag_color.is_none() =>
+        {
+            Some(adjustment.created_at.with_timezone(&Local).date_naive())
+        }
+        _ => None,
+    };
+
+    let transactions = budget.get_transactions(&account.id, since_date).await?;
+
+    let import_id = match interrupted_import_id {
+        Some(import_id)
+            if transactions
+                .iter()
+                .any(|t| t.import_id.as_deref() == Some(import_id.as_str())) =>
+        {
+            info!("Interrupted adjustment {} was posted", import_id);
+            new_import_id(now)
+        }
+        Some(import_id) => {
+            info!("Interrupted adjustment {} was not posted", import_id);
+            import_id
+        }
+        None => new_import_id(now),
+    };
+
+    let real_balance_milli = currency::to_milliunits(currency, real_balance);
+
+    let flag_color = account_config.and_then(|a| a.flag_color);
 
-    #[derive(Clone, Debug, Serialize, Deserialize)]
-    struct Response<T> {
-        data: T,
+    // Otherwise every reconciliation would look like a snapshot & none would be merged into
+    if flag_color.is_some() && flag_color == config.snapshot_flag_color {
+        return Err(anyhow!(
+            "{}'s FLAG_COLOR is the same as SNAPSHOT_FLAG_COLOR",
+            entry.provider
+        )
+        .context(error::ConfigInvalid));
     }
 
-    #[derive(Clone, Debug, Serialize, Deserialize)]
-    struct AccountWrapper {
-        account: Account,
+    let policy = Policy {
+        reconciliation_payee_id: budget.reconciliation_payee_id(config),
+        snapshot_flag_color: config.snapshot_flag_color.filter(|_| budget.has_flags()),
+        flag_color: flag_color.filter(|_| budget.has_flags()),
+        merges: account_config.is_none_or(|a| a.split.is_none()),
+    };
+
+    flag_snapshots(budget, &transactions, &policy).await;
+
+    let projection_memo = account_config
+        .and_then(|account_config| account_config.projection.as_ref())
+        .map(|projection| projection::memo(real_balance, now, projection, currency));
+
+    let run_id = logging::run_id().filter(|_| config.run_id_in_memo);
+    let memo = |memo: Option<String>| match &run_id {
+        Some(run_id) => Some(format!(
+            "{} (run {})",
+            memo.unwrap_or_else(|| "Entered automatically by YNAB".to_owned()),
+            run_id
+        )),
+        None => memo,
+    };
+
+    let decision = reconcile::decide(
+        real_balance_milli,
+        balance,
+        transactions.last(),
+        now,
+        &policy,
+    );
+
+    if let Some(threshold) = config.preview_adjustments_over {
+        let over = match &decision {
+            Decision::Update { adjustment, .. } | Decision::Create { adjustment } => {
+                (*adjustment as f32 / 1000.0).abs() > threshold
+            }
+            Decision::Skip(_) => false,
+        };
+
+        if over {
+            entry.preview = reconcile::preview(
+                &decision,
+                real_balance_milli,
+                balance,
+                transactions.last(),
+                &policy,
+                currency,
+            );
+
+            if let Some(preview) = &entry.preview {
+                info!("Previewing the adjustment:\n{}", preview);
+            }
+        }
     }
 
-    #[derive(Clone, Debug, Serialize, Deserialize)]
-    struct Account {
-        id: String,
-        balance: i32,
-        last_reconciled_at: String,
+    match decision {
+        Decision::Skip(reason @ SkipReason::BalancesEqual) => {
+            info!("Real & YNAB balances are equal");
+            Ok((RunAction::Skipped(reason.into()), None))
+        }
+        Decision::Skip(reason @ SkipReason::AlreadyReconciledOnThe1st) => {
+            info!("There's already a transaction for the 1st");
+            Ok((RunAction::Skipped(reason.into()), None))
+        }
+        Decision::Update {
+            transaction_id,
+            amount,
+            adjustment,
+        } => {
+            info!(
+                "Real & YNAB balances are not equal and the last transaction was a reconciliation"
+            );
+            entry.adjustment = Some(adjustment as f32 / 1000.0);
+            let previous = transactions.iter().find(|t| t.id == transaction_id);
+            let memo =
+                script::before_post(config, entry, RunAction::Updated, memo(projection_memo))?;
+            history.set_run_state(&entry.provider, RunState::Reconciling, None)?;
+            let intent = history.begin_write(
+                &entry.provider,
+                &account.id,
+                Some(&transaction_id),
+                None,
+                amount,
+                now,
+            )?;
+            budget
+                .update_transaction(
+                    &transaction_id,
+                    &ExistingTransaction {
+                        amount: Some(amount),
+                        date: Some(now),
+                        memo,
+                        flag_color: policy.flag_color_on(now),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+            history.set_write_committed(intent, true)?;
+            history.set_last_adjustment(&Adjustment {
+                provider: entry.provider.clone(),
+                transaction_id: transaction_id.clone(),
+                previous_amount: previous.map(|t| t.amount),
+                previous_date: previous.map(|t| t.date),
+                amount,
+                created_at: Utc::now(),
+            })?;
+
+            if mark_reconciled {
+                mark_cleared_reconciled(budget, &transactions, now).await;
+            }
+            Ok((RunAction::Updated, Some(transaction_id)))
+        }
+        Decision::Create { adjustment } => {
+            info!(
+                "Real & YNAB balances are not equal and the last transaction was not a reconciliation or it's the 1st"
+            );
+            entry.adjustment = Some(adjustment as f32 / 1000.0);
+            let subtransactions = account_config
+                .and_then(|a| a.split.as_ref())
+                .map(|parts| {
+                    split::split(adjustment, parts, &policy.reconciliation_payee_id, currency)
+                })
+                .transpose()?;
+            let memo =
+                script::before_post(config, entry, RunAction::Created, memo(projection_memo))?;
+            history.set_run_state(&entry.provider, RunState::Reconciling, Some(&import_id))?;
+            let intent = history.begin_write(
+                &entry.provider,
+                &account.id,
+                None,
+                Some(&import_id),
+                adjustment,
+                now,
+            )?;
+            let transaction_id = budget
+                .create_transaction(&NewTransaction {
+                    account_id: Some(account.id.clone()),
+                    date: Some(now),
+                    amount: Some(adjustment),
+                    payee_id: Some(policy.reconciliation_payee_id.clone()),
+                    payee_name: Some("Reconciliation Balance Adjustment".to_owned()),
+                    category_id: None,
+                    memo: Some(memo.unwrap_or_else(|| "Entered automatically by YNAB".to_owned())),
+                    cleared: Some(TransactionClearedStatus::Reconciled),
+                    approved: Some(true),
+                    flag_color: policy.flag_color_on(now),
+                    import_id: Some(import_id),
+                    subtransactions,
+                    ..Default::default()
+                })
+                .await?;
+            history.set_write_committed(intent, true)?;
+            history.set_last_adjustment(&Adjustment {
+                provider: entry.provider.clone(),
+                transaction_id: transaction_id.clone(),
+                previous_amount: None,
+                previous_date: None,
+                amount: adjustment,
+                created_at: Utc::now(),
+            })?;
+
+            if mark_reconciled {
+                mark_cleared_reconciled(budget, &transactions, now).await;
+            }
+            Ok((RunAction::Created, Some(transaction_id)))
+        }
     }
+}
 
-    let balance = client
-        .get(format!(
-            "https://api.ynab.com/v1/budgets/{}/accounts/{}",
-            config.ynab_budget_id, ynab_account_config.ynab_account_id
-        ))
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<Response<AccountWrapper>>()
-        .await?
-        .data
-        .account
-        .balance;
-
-    info!("YNAB Balance: {:#?}", balance as f32 / 1000.0);
-
-    #[derive(Clone, Debug, Serialize, Deserialize)]
-    struct TransactionWrapper<T> {
-        transaction: T,
-    }
-    #[derive(Clone, Debug, Serialize, Deserialize)]
-    struct Transactions {
-        transactions: Vec<Transaction>,
-    }
-    #[derive(Clone, Debug, Serialize, Deserialize)]
-    struct Transaction {
-        id: String,
-        #[serde(flatten)]
-        transaction: CreateTransaction,
-    }
-    #[derive(Clone, Debug, Serialize, Deserialize)]
-    struct CreateTransaction {
-        date: NaiveDate,
-        amount: i32,
-        payee_id: String,
-        #[serde(flatten)]
-        other: serde_json::Value,
-    }
-
-    let transactions_response = client
-        .get(format!(
-            "https://api.ynab.com/v1/budgets/{}/accounts/{}/transactions",
-            config.ynab_budget_id, ynab_account_config.ynab_account_id
-        ))
-        .send()
-        .await?
-        .error_for_status()?
-        .json::<Response<Transactions>>()
-        .await?;
+// Flags the snapshots from before SNAPSHOT_FLAG_COLOR was set, or whose flag was taken off.
+// They're only ever added to, so it's only warned about if it fails.
+async fn flag_snapshots(budget: &Budget, transactions: &[TransactionDetail], policy: &Policy) {
+    let flag_color = match policy.snapshot_flag_color {
+        Some(flag_color) => flag_color,
+        None => return,
+    };
 
-    let last_transaction = transactions_response
-        .data
-        .transactions
-        .last()
-        .unwrap()
-        .to_owned();
+    let unflagged = transactions
+        .iter()
+        .filter(|t| {
+            policy.is_reconciliation(t) && policy.is_snapshot(t) && t.flag_color != Some(flag_color)
+        })
+        .map(|t| SaveTransactionWithIdOrImportId {
+            id: Some(t.id.clone()),
+            flag_color: Some(flag_color),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
 
-    let real_balance_milli = { real_balance * 1000.0 } as i32;
-    let balance_adjustment = real_balance_milli - balance;
+    if unflagged.is_empty() {
+        return;
+    }
 
-    let now = Local::now().date_naive();
+    info!("Flagging {} snapshots", unflagged.len());
 
-    if balance == real_balance_milli {
-        info!("Real & YNAB balances are equal");
-        Ok(())
-    } else if now.day() == 1 && last_transaction.transaction.date.day() == 1 {
-        info!("There's already a transaction for the 1st");
-        Ok(())
-    } else if last_transaction.transaction.payee_id
-        == config.ynab_reconciliation_payee_id
-        // preserve the adjustment transaction on the 1st to create a record of the account's value over time
-        && last_transaction.transaction.date.day() != 1
-    {
-        info!("Real & YNAB balances are not equal and the last transaction was a reconciliation");
-        let body = TransactionWrapper {
-            transaction: Transaction {
-                transaction: CreateTransaction {
-                    amount: last_transaction.transaction.amount + balance_adjustment,
-                    date: now,
-                    ..last_transaction.transaction.clone()
-                },
-                ..last_transaction.clone()
-            },
+    if let Err(e) = budget.update_transactions(&unflagged).await {
+        warn!("Failed to flag the snapshots: {:#}", e);
+    }
+}
+
+// The adjustment's already been made, so failing to mark the others is only warned about
+async fn mark_cleared_reconciled(
+    budget: &Budget,
+    transactions: &[TransactionDetail],
+    up_to: NaiveDate,
+) {
+    let cleared = transactions
+        .iter()
+        .filter(|t| t.cleared == TransactionClearedStatus::Cleared && t.date <= up_to)
+        .map(|t| SaveTransactionWithIdOrImportId {
+            id: Some(t.id.clone()),
+            cleared: Some(TransactionClearedStatus::Reconciled),
+            ..Default::default()
+        })
+        .collect::<Vec<_>>();
+
+    if cleared.is_empty() {
+        return;
+    }
+
+    info!(
+        "Marking {} cleared transactions as reconciled",
+        cleared.len()
+    );
+
+    if let Err(e) = budget.update_transactions(&cleared).await {
+        warn!(
+            "Failed to mark the cleared transactions as reconciled: {:#}",
+            e
+        );
+    }
+}
+
+// Reconciles the balances that were fetched while YNAB was unreachable, each against the YNAB
+// account's current balance. One that's still unreachable is left queued for the next run.
+pub async fn flush_queued_balances(config: &Config) -> Result<()> {
+    let history = History::open(&config.config_path)?;
+
+    let queued_balances = history.get_queued_balances()?;
+
+    if queued_balances.is_empty() {
+        return Ok(());
+    }
+
+    for queued_balance in queued_balances {
+        let budget = Budget::for_account(config, &queued_balance.provider).await?;
+
+        let account = match budget.get_account(&queued_balance.ynab_account_id).await? {
+            Some(account) if !account.closed && !account.deleted => account,
+            _ => {
+                warn!(
+                    "{}'s YNAB account is closed or deleted, dropping its queued balance",
+                    queued_balance.provider
+                );
+                history.clear_queued_balance(&queued_balance.provider)?;
+                continue;
+            }
         };
-        let response = client
-            .put(format!(
-                "https://api.ynab.com/v1/budgets/{}/transactions/{}",
-                config.ynab_budget_id, last_transaction.id
-            ))
-            .json(&body)
-            .send()
-            .await?
-            .error_for_status()?;
-        info!("PUT response {:#?}", response.status());
-        Ok(())
-    } else {
+
         info!(
-            "Real & YNAB balances are not equal and the last transaction was not a reconciliation or it's the 1st"
+            "Reconciling {}'s balance of {:.2} queued at {}",
+            queued_balance.provider, queued_balance.balance, queued_balance.fetched_at
         );
-        let body = TransactionWrapper {
-            transaction: CreateTransaction {
-                amount: balance_adjustment,
-                date: now,
-                payee_id: config.ynab_reconciliation_payee_id.clone(),
-                other: json!({
-                    "account_id": ynab_account_config.ynab_account_id,
-                    "approved": true,
-                    "category_name": "Uncategorized",
-                    "cleared": "reconciled",
-                    "memo": "Entered automatically by YNAB",
-                    "payee_name": "Reconciliation Balance Adjustment"
-                }),
-            },
+
+        let mut entry = DigestEntry::new(queued_balance.provider.clone());
+        entry.account = Some(account.name.clone());
+        entry.ynab_balance = Some(account.balance as f32 / 1000.0);
+        entry.real_balance = Some(queued_balance.balance);
+
+        reconcile_balance(
+            config,
+            &history,
+            &budget,
+            &account,
+            queued_balance.balance,
+            None,
+            &mut entry,
+        )
+        .await?;
+
+        history.set_run_state(&queued_balance.provider, RunState::Done, None)?;
+        history.clear_queued_balance(&queued_balance.provider)?;
+    }
+
+    Ok(())
+}
+
+// Checks YNAB for the writes that were in flight when a previous run died, marking each as
+// committed if it landed. One that didn't is left to the account's next run to recompute.
+pub async fn reconcile_dangling_writes(config: &Config) -> Result<()> {
+    let history = History::open(&config.config_path)?;
+
+    let intents = history.get_dangling_writes()?;
+
+    if intents.is_empty() {
+        return Ok(());
+    }
+
+    for intent in intents {
+        let budget = Budget::for_account(config, &intent.provider).await?;
+
+        // The write was dated when it was made, so anything older can't be it
+        let transactions = match budget
+            .get_transactions(&intent.ynab_account_id, Some(intent.date))
+            .await
+        {
+            Ok(transactions) => transactions,
+            Err(e) => {
+                warn!(
+                    "Failed to check {}'s interrupted write from {}: {:#}",
+                    intent.provider, intent.created_at, e
+                );
+                continue;
+            }
         };
-        let response = client
-            .post(format!(
-                "https://api.ynab.com/v1/budgets/{}/transactions",
-                config.ynab_budget_id
-            ))
-            .json(&body)
-            .send()
-            .await?
-            .error_for_status()?;
-        info!("POST response {:#?}", response.status());
-        Ok(())
+
+        let landed = match (&intent.transaction_id, &intent.import_id) {
+            (Some(transaction_id), _) => transactions.iter().any(|t| {
+                &t.id == transaction_id && t.amount == intent.amount && t.date == intent.date
+            }),
+            (None, Some(import_id)) => transactions
+                .iter()
+                .any(|t| t.import_id.as_deref() == Some(import_id.as_str())),
+            (None, None) => false,
+        };
+
+        if landed {
+            info!(
+                "{}'s interrupted write of {:.2} on {} was posted",
+                intent.provider,
+                intent.amount as f32 / 1000.0,
+                intent.date
+            );
+        } else {
+            warn!(
+                "{}'s interrupted write of {:.2} on {} was not posted, it'll be recomputed on the next run",
+                intent.provider,
+                intent.amount as f32 / 1000.0,
+                intent.date
+            );
+        }
+
+        history.set_write_committed(intent.id, landed)?;
+    }
+
+    Ok(())
+}
+
+// Reverts the last reconciliation of an account: a created adjustment is deleted & an updated one
+// is put back as it was. Only the last one is kept, so it can only be undone once.
+pub async fn undo_last_adjustment(config: &Config, account: &str) -> Result<()> {
+    let history = History::open(&config.config_path)?;
+
+    let adjustment = history
+        .get_last_adjustment(account)?
+        .ok_or_else(|| anyhow!("{} has no reconciliation to undo", account))?;
+
+    let budget = Budget::for_account(config, account).await?;
+
+    match (adjustment.previous_amount, adjustment.previous_date) {
+        (Some(amount), Some(date)) => {
+            info!(
+                "Reverting {}'s reconciliation {} from {:.2} to {:.2} on {}",
+                account,
+                adjustment.transaction_id,
+                adjustment.amount as f32 / 1000.0,
+                amount as f32 / 1000.0,
+                date
+            );
+            budget
+                .update_transaction(
+                    &adjustment.transaction_id,
+                    &ExistingTransaction {
+                        amount: Some(amount),
+                        date: Some(date),
+                        ..Default::default()
+                    },
+                )
+                .await?;
+        }
+        _ => {
+            info!(
+                "Deleting {}'s reconciliation {} of {:.2}",
+                account,
+                adjustment.transaction_id,
+                adjustment.amount as f32 / 1000.0
+            );
+            budget
+                .delete_transaction(&adjustment.transaction_id)
+                .await?;
+        }
     }
+
+    history.clear_last_adjustment(account)?;
+
+    Ok(())
+}
+
+fn get_settings() -> Result<config::Config> {
+    let config_path = Path::new(&env::var("YNAB_CONFIG_PATH")?).join(CONFIG_FILENAME);
+
+    let settings = config::Config::builder()
+        .add_source(config::File::from_str(
+            &credentials::resolve(&migrate::load(&config_path)?)?,
+            config::FileFormat::Toml,
+        ))
+        .add_source(config::Environment::with_prefix("YNAB"))
+        .build()?;
+
+    Ok(settings)
 }
 
-pub async fn update_ynab<T>(t: T) -> Result<()>
+// The config of a single user, i.e. one without `[users.<name>]` sections
+pub fn get_config() -> Result<Config> {
+    let settings = get_settings()?;
+
+    let mut config = settings.clone().try_deserialize::<Config>()?;
+
+    if config.accounts.is_empty() {
+        config.accounts = get_flat_accounts(&settings)?;
+    }
+
+    Ok(config)
+}
+
+// With `[users.<name>]` sections every user has their own YNAB token, budget, notifier &
+// accounts, and any top level settings are ignored. Otherwise the whole config is the
+// `DEFAULT_USER`'s. Either way their directories are checked to be private first.
+pub fn get_users() -> Result<Vec<User>> {
+    let settings = get_settings()?;
+
+    let users = read_users(&settings)?;
+
+    let policy = match settings.get::<FilePermissions>("FILE_PERMISSIONS") {
+        Ok(policy) => policy,
+        Err(config::ConfigError::NotFound(_)) => FilePermissions::default(),
+        Err(e) => return Err(e.into()),
+    };
+    match settings.get::<SecretsStorage>("SECRETS") {
+        Ok(storage) => fd_store::init(storage),
+        Err(config::ConfigError::NotFound(_)) => fd_store::init(SecretsStorage::default()),
+        Err(e) => return Err(e.into()),
+    }
+
+    let config_path = env::var("YNAB_CONFIG_PATH")?;
+    let dirs = std::iter::once(config_path.as_str())
+        .chain(users.iter().map(|user| user.config.config_path.as_str()))
+        .collect::<BTreeSet<_>>();
+
+    permissions::check(dirs, policy)?;
+
+    Ok(users)
+}
+
+fn read_users(settings: &config::Config) -> Result<Vec<User>> {
+    let names = match settings.get_table("users") {
+        Ok(users) => users.into_keys().collect::<Vec<_>>(),
+        Err(config::ConfigError::NotFound(_)) => {
+            return Ok(vec![User {
+                name: DEFAULT_USER.to_owned(),
+                config: get_config()?,
+            }])
+        }
+        Err(e) => return Err(e.into()),
+    };
+
+    let config_path = env::var("YNAB_CONFIG_PATH")?;
+
+    let web_ui = get_web_ui_config()?;
+
+    let mut users = names
+        .into_iter()
+        .map(|name| -> Result<User> {
+            let mut config = settings.get::<Config>(&format!("users.{}", name))?;
+
+            config.config_path = format!("{}/{}/{}", config_path, USERS_DIRNAME, name);
+            if config.web_ui.is_none() {
+                config.web_ui = web_ui.clone();
+            }
+            permissions::create_private_dir(&config.config_path)?;
+
+            Ok(User { name, config })
+        })
+        .try_collect::<Vec<_>>()?;
+
+    users.sort_by(|a, b| a.name.cmp(&b.name));
+
+    Ok(users)
+}
+
+// The web UI is served by the daemon, so it's configured at the top level even with
+// `[users.<name>]` sections
+pub fn get_web_ui_config() -> Result<Option<WebUiConfig>> {
+    match get_settings()?.get::<WebUiConfig>("WEB_UI") {
+        Ok(web_ui) => Ok(Some(web_ui)),
+        Err(config::ConfigError::NotFound(_)) => Ok(None),
+        Err(e) => Err(e.into()),
+    }
+}
+
+// Before accounts had their own sections each provider's binary read its YNAB account from the
+// flat config, e.g. `YNAB_HL_ACCOUNT_ID`, along with its own settings
+fn get_flat_accounts(settings: &config::Config) -> Result<BTreeMap<String, AccountConfig>> {
+    let flat_settings = settings
+        .clone()
+        .try_deserialize::<serde_json::Map<String, serde_json::Value>>()?;
+
+    let accounts = [
+        ("hl", ProviderKind::Hl, "YNAB_HL_ACCOUNT_ID"),
+        ("saxo", ProviderKind::Saxo, "YNAB_SAXO_ACCOUNT_ID"),
+    ]
+    .into_iter()
+    .filter_map(|(account, provider, key)| match flat_settings.get(key) {
+        Some(serde_json::Value::String(ynab_account_id)) => Some((
+            account.to_owned(),
+            AccountConfig {
+                provider,
+                ynab_account_id: ynab_account_id.clone(),
+                // The flat config's own `MAX_LOGIN_ATTEMPTS_PER_DAY` is the user's
+                max_login_attempts_per_day: None,
+                liability: false,
+                reconcile_cleared_only: false,
+                mark_reconciled: false,
+                catch_up_missed_runs: false,
+                currency: None,
+                projection: None,
+                market_hours: None,
+                firefly_account_id: None,
+                export_account: None,
+                sinks: None,
+                budget: BudgetKind::Ynab,
+                split_contributions: false,
+                contribution_payee_name: None,
+                post_income: false,
+                dividend_category_id: None,
+                interest_category_id: None,
+                flag_color: None,
+                goal_category_id: None,
+                split: None,
+                otp_email: None,
+                settings: flat_settings.clone(),
+            },
+        )),
+        _ => None,
+    })
+    .collect();
+
+    Ok(accounts)
+}
+
+pub async fn update_ynab<T>(config: &Config, account: &str, t: T) -> Result<RunReport>
 where
     T: GetBalance + GetYnabAccountConfig,
 {
-    let config_path = format!("{}/{}", env::var("YNAB_CONFIG_PATH")?, CONFIG_FILENAME);
+    let history = History::open(&config.config_path)?;
 
-    let config = config::Config::builder()
-        .add_source(config::File::with_name(&config_path))
-        .add_source(config::Environment::with_prefix("YNAB"))
-        .build()?
-        .try_deserialize::<Config>()?;
+    if let Some(pause) = history.get_pause(account)? {
+        if Utc::now() < pause.until {
+            info!(
+                "{} is paused until {} ({}), skipping",
+                account, pause.until, pause.reason
+            );
+            return Ok(RunReport::skipped(account, report::SkipReason::Paused));
+        }
+        history.clear_pause(account)?;
+    }
+
+    let started = Instant::now();
+
+    let mut entry = DigestEntry::new(account.to_owned());
+
+    let result = match _update_ynab(config, t, &mut entry).await {
+        Err(e)
+            if e.downcast_ref::<LoginBudgetExhausted>().is_some()
+                || e.downcast_ref::<BalanceRejected>().is_some()
+                || e.downcast_ref::<YnabUnreachable>().is_some()
+                || e.downcast_ref::<FxRateStale>().is_some() =>
+        {
+            warn!("{}", e);
+            entry.warning = Some(e.to_string());
+            Ok((RunAction::Skipped(report::SkipReason::Warning), None))
+        }
+        Err(e) if e.downcast_ref::<ScriptSkip>().is_some() => {
+            info!("{}", e);
+            entry.warning = e
+                .downcast_ref::<ScriptSkip>()
+                .and_then(|skip| skip.warning.clone());
+            Ok((RunAction::Skipped(report::SkipReason::Script), None))
+        }
+        result => result,
+    };
+
+    let run_state = match &result {
+        Ok(_) => RunState::Done,
+        Err(e)
+            if e.downcast_ref::<AuthPending>().is_some()
+                || e.downcast_ref::<ApprovalPending>().is_some() =>
+        {
+            RunState::AwaitingAuth
+        }
+        Err(_) => RunState::Failed,
+    };
+
+    if let Err(e) = finish_run_state(config, &entry.provider, run_state) {
+        warn!("Failed to record run state: {:#?}", e);
+    }
 
-    match _update_ynab(&config, t).await {
-        Ok(()) => Ok(()),
+    if result.is_ok() {
+        if let Err(e) = history.reset_consecutive_failures(account) {
+            warn!("Failed to reset the failure count: {:#?}", e);
+        }
+    }
+
+    // A skipped run, e.g. one that only queued its balance, hasn't reconciled anything
+    if result
+        .as_ref()
+        .is_ok_and(|(action, _)| action.is_reconciled())
+    {
+        if let Err(e) = history.set_last_success(account) {
+            warn!("Failed to record the run's success: {:#?}", e);
+        }
+    }
+
+    if let Err(e) = &result {
+        let reason = if let Some(auth_pending) = e.downcast_ref::<AuthPending>() {
+            info!("{}, exiting until the next run", auth_pending);
+            Some(report::SkipReason::AwaitingAuth)
+        } else if let Some(approval_pending) = e.downcast_ref::<ApprovalPending>() {
+            info!("{}, exiting until the next run", approval_pending);
+            Some(report::SkipReason::AwaitingApproval)
+        } else {
+            None
+        };
+
+        if let Some(reason) = reason {
+            let result = Ok(RunReport::skipped(account, reason));
+            record_last_run(&history, account, &result);
+            metrics::record(config, account, &result, started.elapsed()).await;
+            return result;
+        }
+    }
+
+    let mut result = result.map(|(action, transaction_id)| RunReport {
+        account: account.to_owned(),
+        ynab_account: entry.account.clone(),
+        real_balance: entry.real_balance,
+        ynab_balance: entry.ynab_balance,
+        adjustment: entry.adjustment,
+        contributions: entry.contributions,
+        income: entry.income,
+        action,
+        transaction_id,
+        warning: entry.warning.clone(),
+        run_id: logging::run_id(),
+        sinks: vec![],
+    });
+
+    if let Ok(report) = &mut result {
+        if matches!(report.action, RunAction::Created | RunAction::Updated) {
+            script::after_post(config, &entry, report);
+            report.sinks = sinks::write(config, report, entry.currency.as_ref()).await;
+        }
+    }
+
+    record_last_run(&history, account, &result);
+    metrics::record(config, account, &result, started.elapsed()).await;
+
+    let notification = match &result {
+        Ok(report) => match (&report.warning, report.action) {
+            (Some(warning), _) => Some(Notification {
+                event: Event::Warning,
+                title: entry.provider.clone(),
+                message: warning.clone(),
+                url: None,
+            }),
+            // It was warned about on the first run that found it closed, & isn't up to date
+            (None, RunAction::Skipped(report::SkipReason::Closed)) => None,
+            (None, RunAction::Created | RunAction::Updated) => Some(Notification {
+                event: Event::Update,
+                title: entry.provider.clone(),
+                message: format!(
+                    "Adjusted {} by {} to {}{}",
+                    report.ynab_account.as_deref().unwrap_or_default(),
+                    currency::format(
+                        entry.currency.as_ref(),
+                        report.adjustment.unwrap_or_default()
+                    ),
+                    currency::format(
+                        entry.currency.as_ref(),
+                        report.real_balance.unwrap_or_default()
+                    ),
+                    [&entry.preview, &entry.goal_progress]
+                        .into_iter()
+                        .flatten()
+                        .map(|line| format!("\n{}", line))
+                        .collect::<String>()
+                ),
+                url: None,
+            }),
+            (None, RunAction::Skipped(_)) => Some(Notification {
+                event: Event::Complete,
+                title: entry.provider.clone(),
+                message: format!(
+                    "{} is up to date{}",
+                    report.ynab_account.as_deref().unwrap_or_default(),
+                    entry
+                        .goal_progress
+                        .as_ref()
+                        .map(|progress| format!("\n{}", progress))
+                        .unwrap_or_default()
+                ),
+                url: None,
+            }),
+        },
         Err(e) => {
-            let api = pushover::API::new();
-            let msg = SendMessage::new(
-                config.pushover_api_key,
-                config.pushover_user_key,
-                format!("Failed to update YNAB: {:#?}", e.to_string()),
-            );
-            api.send(&msg).unwrap();
-            Err(e)
+            error!("Failed to update YNAB: {}", e);
+            entry.error = Some(e.to_string());
+
+            let mut message = format!("Failed to update YNAB: {:#?}", e.to_string());
+
+            let pause = match record_failure(config, &history, account) {
+                Ok(pause) => pause,
+                Err(e) => {
+                    warn!("Failed to record the failure: {:#?}", e);
+                    None
+                }
+            };
+
+            // Retrying a blocked scraper every run only makes the site more suspicious
+            let pause = match e.downcast_ref::<ChallengeDetected>() {
+                Some(challenge) => Some(ProviderPause {
+                    provider: account.to_owned(),
+                    reason: challenge.to_string(),
+                    until: Utc::now() + challenge.pause,
+                }),
+                None => pause,
+            };
+
+            if let Some(pause) = pause {
+                match history.set_pause(&pause) {
+                    Ok(()) => message = format!("{}, paused until {}", message, pause.until),
+                    Err(e) => warn!("Failed to pause {}: {:#?}", account, e),
+                }
+            }
+
+            Some(Notification {
+                event: Event::Failure,
+                title: entry.provider.clone(),
+                message,
+                url: None,
+            })
+        }
+    };
+
+    if let Some(notification) = &notification {
+        notify::notify(config, notification).await;
+    }
+
+    if let Err(e) = digest::record(config, entry).await {
+        warn!("Failed to record run digest: {:#?}", e);
+    }
+
+    result
+}
+
+// Kept for e.g. `notify-failure` to say what went wrong
+fn record_last_run(history: &History, account: &str, result: &Result<RunReport>) {
+    if let Err(e) = history.set_last_run(account, logging::run_id().as_deref(), result) {
+        warn!("Failed to record the run: {:#?}", e);
+    }
+}
+
+// Returns the pause once the provider's failed `CIRCUIT_BREAKER_FAILURES` runs in a row. The
+// count isn't reset by the pause, so after the cool-down a single failure trips it again.
+fn record_failure(
+    config: &Config,
+    history: &History,
+    account: &str,
+) -> Result<Option<ProviderPause>> {
+    let failures = history.increment_consecutive_failures(account)?;
+
+    if failures < config.circuit_breaker_failures {
+        return Ok(None);
+    }
+
+    Ok(Some(ProviderPause {
+        provider: account.to_owned(),
+        reason: format!("failed {} runs in a row", failures),
+        until: Utc::now() + Duration::hours(config.circuit_breaker_cooldown_hours),
+    }))
+}
+
+fn finish_run_state(config: &Config, provider: &str, run_state: RunState) -> Result<()> {
+    let history = History::open(&config.config_path)?;
+
+    // A write that failed may still have landed (e.g. the response timed out), so it's left
+    // `Reconciling` for the next run to check
+    if run_state == RunState::Failed {
+        if let Some(previous_run) = history.get_run_state(provider)? {
+            if previous_run.state == RunState::Reconciling {
+                return Ok(());
+            }
         }
     }
+
+    history.set_run_state(provider, run_state, None)
+}
+
+// YNAB limits import_id to 36 characters
+fn new_import_id(date: NaiveDate) -> String {
+    let suffix = rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(char::from)
+        .collect::<String>();
+
+    format!("YNAB-UPDATER:{}:{}", date, suffix)
 }
@@ -0,0 +1,86 @@
+use rand::{distributions::Alphanumeric, Rng};
+use std::{env, io::Write};
+
+tokio::task_local! {
+    // Set for the length of an account's run, see `providers::update_account`
+    static RUN_ID: String;
+}
+
+// Short enough to search the logs for, or to read off a memo in YNAB
+pub fn new_run_id() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(8)
+        .map(char::from)
+        .collect::<String>()
+        .to_lowercase()
+}
+
+// The ID of the run that's logging, if any
+pub fn run_id() -> Option<String> {
+    RUN_ID.try_with(|run_id| run_id.clone()).ok()
+}
+
+pub async fn with_run_id<F: std::future::Future>(run_id: String, f: F) -> F::Output {
+    RUN_ID.scope(run_id, f).await
+}
+
+// Logs to stderr with env_logger, or with YNAB_LOG=journald, straight to journald. There each run's
+// lines carry PROVIDER=, ACCOUNT=, AMOUNT= & RUN_ID= fields, so e.g. `journalctl --user -u
+// ynab-updater-daemon PROVIDER=saxo` shows only Saxo's runs. RUST_LOG filters either way.
+pub fn init() {
+    if env::var("YNAB_LOG").as_deref() == Ok("journald") {
+        if let Err(e) = init_journald() {
+            init_env_logger();
+            log::warn!("Failed to log to journald, logging to stderr: {:#}", e);
+        }
+    } else {
+        init_env_logger();
+    }
+}
+
+// env_logger's own format, with the run's ID after the target, e.g.
+// `[2024-01-01T06:00:00Z INFO  ynab_updater 3kq9x0ab] Real Balance: 12.5`
+fn init_env_logger() {
+    env_logger::Builder::from_default_env()
+        .format(|buf, record| {
+            write!(
+                buf,
+                "[{} {:<5} {}",
+                buf.timestamp(),
+                buf.default_styled_level(record.level()),
+                record.target()
+            )?;
+            if let Some(run_id) = run_id() {
+                write!(buf, " {}", run_id)?;
+            }
+            writeln!(buf, "] {}", record.args())
+        })
+        .init();
+}
+
+#[cfg(all(target_os = "linux", feature = "journald"))]
+fn init_journald() -> anyhow::Result<()> {
+    use tracing_subscriber::{layer::SubscriberExt, EnvFilter};
+
+    // The fields are named as they are, rather than with journald's default `F_` prefix
+    let journald = tracing_journald::layer()?.with_field_prefix(None);
+
+    let subscriber = tracing_subscriber::registry()
+        .with(EnvFilter::from_default_env())
+        .with(journald);
+
+    tracing::subscriber::set_global_default(subscriber)?;
+
+    // Everything else logs with `log`
+    tracing_log::LogTracer::init()?;
+
+    Ok(())
+}
+
+#[cfg(not(all(target_os = "linux", feature = "journald")))]
+fn init_journald() -> anyhow::Result<()> {
+    Err(anyhow::anyhow!(
+        "Built without the journald feature, which is only on Linux"
+    ))
+}
@@ -0,0 +1,939 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Local, NaiveDate, Utc};
+use clap::{CommandFactory, Parser, Subcommand, ValueEnum};
+use clap_complete::Shell;
+use log::{error, info, warn};
+use serde::Serialize;
+#[cfg(feature = "hl")]
+use std::{env, fs};
+use std::{
+    fmt,
+    path::{Path, PathBuf},
+    process::ExitCode,
+    time::Duration,
+};
+#[cfg(feature = "hl")]
+use ynab_updater::providers::hl::{Hl, HlPage, HlSelectors};
+use ynab_updater::{
+    backfill, daemon,
+    error::{self, ConfigInvalid},
+    flush_queued_balances, get_users, get_web_ui_config,
+    history::History,
+    install::{self, Platform},
+    is_reconcile_due,
+    notify::{self, Event, Notification},
+    providers::{self, ProviderKind},
+    reconcile_dangling_writes,
+    report::{RunAction, RunReport, SkipReason},
+    scaffold::{self, Template},
+    self_update, stats, undo_last_adjustment, User,
+};
+
+// So e.g. systemd's `OnFailure=` or a wrapper script can tell what needs doing, like logging in
+// again. A run that updated some accounts but not others exits with its worst outcome's code.
+const EXIT_AUTH_NEEDED: u8 = 2;
+const EXIT_PROVIDER_ERROR: u8 = 3;
+const EXIT_YNAB_ERROR: u8 = 4;
+const EXIT_CONFIG_ERROR: u8 = 5;
+
+#[derive(Debug, Parser)]
+#[command(
+    about = "Updates YNAB account balances from their institutions",
+    version,
+    after_help = "Exit codes: 0 updated or skipped, 2 an account needs logging in, 3 a provider failed, 4 YNAB failed, 5 the config is invalid"
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+enum Output {
+    Text,
+    // The runs' reports on stdout, with the logs left on stderr
+    Json,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+    #[command(about = "Update YNAB from every account, or only the given ones")]
+    Run {
+        #[arg(long, help = "Only update this user's accounts")]
+        user: Option<String>,
+        #[arg(
+            long,
+            conflicts_with = "accounts",
+            help = "Only update the accounts in this profile"
+        )]
+        profile: Option<String>,
+        #[arg(
+            long,
+            help = "Update accounts even within MIN_RECONCILE_INTERVAL_HOURS"
+        )]
+        force: bool,
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = Output::Text,
+            help = "Print each account's run report"
+        )]
+        output: Output,
+        accounts: Vec<String>,
+    },
+    #[command(about = "Run every user's profiles & accounts on their schedules")]
+    Daemon {
+        #[arg(
+            long,
+            default_value_t = 60,
+            help = "How long to wait for in-flight runs when shutting down, in seconds"
+        )]
+        shutdown_timeout_secs: u64,
+    },
+    #[command(about = "Interactively log in to an account's provider")]
+    Auth {
+        #[arg(long, help = "The user the account belongs to")]
+        user: Option<String>,
+        account: String,
+    },
+    #[cfg(feature = "hl")]
+    #[command(
+        about = "Report which of a provider's selectors match a saved page, or its checked-in fixtures"
+    )]
+    ScrapeTest {
+        #[arg(help = "The provider whose selectors to test, only hl is supported")]
+        provider: String,
+        #[arg(
+            long,
+            value_parser = ["login", "secure-number", "accounts"],
+            help = "The page the saved page is of, otherwise every selector is tested"
+        )]
+        page: Option<String>,
+        #[arg(help = "A saved HTML page from the provider")]
+        fixture: Option<PathBuf>,
+    },
+    #[cfg(feature = "hl")]
+    #[command(
+        about = "Log in to an HL account, saving each page scrubbed of personal details as a fixture"
+    )]
+    RecordFixture {
+        #[arg(long, help = "The user the account belongs to")]
+        user: Option<String>,
+        #[arg(long, default_value = "fixtures/hl", help = "Where to save the pages")]
+        dir: PathBuf,
+        account: String,
+    },
+    #[command(about = "Undo an account's last reconciliation in YNAB")]
+    Undo {
+        #[arg(long, help = "The user the account belongs to")]
+        user: Option<String>,
+        account: String,
+    },
+    #[command(about = "List each account's consent to be read, and how long it has left")]
+    Consents {
+        #[arg(long, help = "Only list this user's accounts")]
+        user: Option<String>,
+    },
+    #[command(
+        about = "Notify that a systemd unit failed, with why from its accounts' last runs",
+        long_about = "Notify that a systemd unit failed, with why from its accounts' last runs. Run by the ynab-updater-notify-failure@ unit, which the other units name in OnFailure="
+    )]
+    NotifyFailure {
+        #[arg(help = "The unit that failed, e.g. ynab-updater-profile-daily.service")]
+        unit: String,
+    },
+    #[command(
+        about = "Set the value of a property account by hand, or clear it to go back to its index"
+    )]
+    SetValue {
+        #[arg(long, help = "The user the account belongs to")]
+        user: Option<String>,
+        #[arg(long, conflicts_with = "value", help = "Clear the value set by hand")]
+        clear: bool,
+        account: String,
+        #[arg(required_unless_present = "clear")]
+        value: Option<f32>,
+    },
+    #[command(
+        about = "Create month-end snapshots in YNAB from a provider's history, for a newly added account",
+        long_about = "Create month-end snapshots in YNAB from a provider's history, for a newly added account. Each is dated the 1st of the following month. Supported by saxo, from its performance time series, & property, from its index."
+    )]
+    Backfill {
+        #[arg(long, help = "The user the account belongs to")]
+        user: Option<String>,
+        #[arg(long, value_parser = parse_month, help = "The first month, e.g. 2023-01")]
+        from: NaiveDate,
+        #[arg(long, help = "Only log the snapshots that would be created")]
+        dry_run: bool,
+        account: String,
+    },
+    #[command(about = "Show how reliable & quick each account's provider has been")]
+    Stats {
+        #[arg(long, help = "Only show this user's accounts")]
+        user: Option<String>,
+        #[arg(long, default_value_t = 30, help = "Over this many days")]
+        days: i64,
+    },
+    #[command(
+        about = "Generate a new provider's module from a template, registered in src/providers.rs",
+        long_about = "Generate a new provider's module from a template, registered in src/providers.rs. Run from a checkout, it leaves TODOs where the institution's URLs, settings & responses go."
+    )]
+    NewProvider {
+        #[arg(
+            long,
+            value_enum,
+            default_value_t = Template::Api,
+            help = "The kind of integration it is"
+        )]
+        kind: Template,
+        #[arg(long, default_value = ".", help = "The checkout to add it to")]
+        dir: PathBuf,
+        #[arg(help = "The provider's name, as accounts' PROVIDER, e.g. my_bank")]
+        name: String,
+    },
+    #[command(
+        about = "Register each schedule with the OS's scheduler, to run without the daemon",
+        long_about = "Register each schedule with the OS's scheduler, to run without the daemon. Each profile or account in a user's `schedule` gets a task running it at the schedule's times, logging to ynab-updater.log in YNAB_CONFIG_PATH. Installing again replaces them."
+    )]
+    InstallSchedule {
+        #[arg(long, value_enum, help = "The OS whose scheduler to register with")]
+        platform: Platform,
+        #[arg(long, help = "Only register this user's schedules")]
+        user: Option<String>,
+        #[arg(long, help = "Only print the tasks that would be registered")]
+        dry_run: bool,
+    },
+    #[command(
+        about = "Replace this binary with the latest release's, once its signature is verified",
+        long_about = "Replace this binary with the latest release's, once its signature is verified. Its download uses the first user's HTTP settings, e.g. their PROXY."
+    )]
+    SelfUpdate {
+        #[arg(long, help = "Only report whether there's a newer release")]
+        check: bool,
+        #[arg(long, help = "Install the latest release even if it isn't newer")]
+        force: bool,
+    },
+    #[command(
+        about = "Print a shell's completions for the commands",
+        long_about = "Print a shell's completions for the commands, e.g. `ynab-updater completions bash > /etc/bash_completion.d/ynab-updater`"
+    )]
+    Completions { shell: Shell },
+    #[command(about = "Print the man page, or write one for each command to a directory")]
+    Man {
+        #[arg(long, help = "Write ynab-updater.1 & a page for each command here")]
+        dir: Option<PathBuf>,
+    },
+}
+
+fn parse_month(month: &str) -> Result<NaiveDate> {
+    NaiveDate::parse_from_str(&format!("{}-01", month), "%Y-%m-%d")
+        .map_err(|_| anyhow!("{} isn't a month like 2023-01", month))
+}
+
+fn select_users(user: Option<&str>) -> Result<Vec<User>> {
+    let users = get_users()?;
+
+    match user {
+        Some(user) => {
+            let users = users
+                .into_iter()
+                .filter(|u| u.name == user)
+                .collect::<Vec<_>>();
+            if users.is_empty() {
+                return Err(anyhow!("No user named {} is configured", user));
+            }
+            Ok(users)
+        }
+        None => Ok(users),
+    }
+}
+
+// The accounts named on the command line or in the profile, otherwise all of the user's
+fn select_accounts(user: &User, accounts: &[String], profile: Option<&str>) -> Result<Vec<String>> {
+    match profile {
+        Some(profile) => {
+            let accounts = user
+                .config
+                .profiles
+                .get(profile)
+                .cloned()
+                .unwrap_or_default();
+            if let Some(account) = accounts
+                .iter()
+                .find(|account| !user.config.accounts.contains_key(*account))
+            {
+                return Err(anyhow!(
+                    "{}'s {} profile lists {}, which isn't a configured account",
+                    user.name,
+                    profile,
+                    account
+                ));
+            }
+            Ok(accounts)
+        }
+        None if accounts.is_empty() => Ok(user.config.accounts.keys().cloned().collect()),
+        None => Ok(accounts
+            .iter()
+            .filter(|account| user.config.accounts.contains_key(*account))
+            .cloned()
+            .collect()),
+    }
+}
+
+// Returned when any of a run's accounts failed or need logging in, with the exit code for it
+#[derive(Clone, Debug)]
+struct RunIncomplete {
+    message: String,
+    exit_code: u8,
+}
+
+impl fmt::Display for RunIncomplete {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.message)
+    }
+}
+
+impl std::error::Error for RunIncomplete {}
+
+fn exit_code(e: &anyhow::Error) -> Option<u8> {
+    if let Some(run_incomplete) = e.downcast_ref::<RunIncomplete>() {
+        return Some(run_incomplete.exit_code);
+    }
+
+    if e.downcast_ref::<ConfigInvalid>().is_some()
+        || e.chain().any(|cause| cause.is::<config::ConfigError>())
+    {
+        Some(EXIT_CONFIG_ERROR)
+    } else if error::is_ynab_error(e) {
+        Some(EXIT_YNAB_ERROR)
+    } else {
+        None
+    }
+}
+
+// A run's report, or why it failed, in `--output json`
+#[derive(Debug, Serialize)]
+struct UserReport<'a> {
+    user: &'a str,
+    account: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    report: Option<RunReport>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+// Every user's accounts are updated in turn, each with their own config & history, so one
+// account failing doesn't stop the others from being updated
+async fn run(
+    user: Option<&str>,
+    accounts: &[String],
+    profile: Option<&str>,
+    force: bool,
+    output: Output,
+) -> Result<()> {
+    let users = select_users(user).context(ConfigInvalid)?;
+
+    if let Some(account) = accounts.iter().find(|account| {
+        !users
+            .iter()
+            .any(|u| u.config.accounts.contains_key(*account))
+    }) {
+        return Err(anyhow!("No account named {} is configured", account).context(ConfigInvalid));
+    }
+
+    if let Some(profile) = profile {
+        if !users
+            .iter()
+            .any(|u| u.config.profiles.contains_key(profile))
+        {
+            return Err(
+                anyhow!("No profile named {} is configured", profile).context(ConfigInvalid)
+            );
+        }
+    }
+
+    let selected = users
+        .iter()
+        .map(|user| Ok((user, select_accounts(user, accounts, profile)?)))
+        .collect::<Result<Vec<_>>>()
+        .context(ConfigInvalid)?;
+
+    let mut failed = 0;
+    let mut worst_exit_code = 0;
+    let mut reports = vec![];
+
+    for (user, accounts) in &selected {
+        if let Err(e) = reconcile_dangling_writes(&user.config).await {
+            warn!(
+                "Failed to check {}'s interrupted writes: {:#}",
+                user.name, e
+            );
+        }
+
+        if let Err(e) = flush_queued_balances(&user.config).await {
+            warn!(
+                "Failed to reconcile {}'s queued balances: {:#}",
+                user.name, e
+            );
+        }
+
+        notify::flush_held(&user.config).await;
+
+        for account in accounts {
+            if !force && !is_reconcile_due(&user.config, account)? {
+                reports.push(UserReport {
+                    user: &user.name,
+                    account,
+                    report: Some(RunReport::skipped(account, SkipReason::NotDue)),
+                    error: None,
+                });
+                continue;
+            }
+
+            info!("Updating {}'s {}", user.name, account);
+
+            match providers::update_account(&user.config, account, &user.config.accounts[account])
+                .await
+            {
+                Ok(report) => reports.push(UserReport {
+                    user: &user.name,
+                    account,
+                    report: Some(report),
+                    error: None,
+                }),
+                Err(e) => {
+                    error!("Failed to update {}'s {}: {:#}", user.name, account, e);
+                    failed += 1;
+                    worst_exit_code =
+                        worst_exit_code.max(exit_code(&e).unwrap_or(EXIT_PROVIDER_ERROR));
+                    reports.push(UserReport {
+                        user: &user.name,
+                        account,
+                        report: None,
+                        error: Some(format!("{:#}", e)),
+                    });
+                }
+            }
+        }
+    }
+
+    if let Output::Json = output {
+        println!("{}", serde_json::to_string_pretty(&reports)?);
+    }
+
+    let awaiting_auth = reports
+        .iter()
+        .filter(|r| {
+            r.report
+                .as_ref()
+                .is_some_and(|r| r.action == RunAction::Skipped(SkipReason::AwaitingAuth))
+        })
+        .count();
+
+    match (failed, awaiting_auth) {
+        (0, 0) => Ok(()),
+        (0, _) => Err(RunIncomplete {
+            message: format!("{} account(s) need logging in", awaiting_auth),
+            exit_code: EXIT_AUTH_NEEDED,
+        }
+        .into()),
+        _ => Err(RunIncomplete {
+            message: format!("{} account(s) failed to update", failed),
+            exit_code: worst_exit_code,
+        }
+        .into()),
+    }
+}
+
+async fn auth(user: Option<&str>, account: &str) -> Result<()> {
+    let users = select_users(user)?
+        .into_iter()
+        .filter(|u| u.config.accounts.contains_key(account))
+        .collect::<Vec<_>>();
+
+    match users.as_slice() {
+        [] => Err(anyhow!("No account named {} is configured", account)),
+        [user] => {
+            providers::auth_account(&user.config, account, &user.config.accounts[account]).await
+        }
+        _ => Err(anyhow!(
+            "More than one user has an account named {}, choose one with --user",
+            account
+        )),
+    }
+}
+
+// Selectors overridden in YNAB_CONFIG_PATH's `hl_selectors.toml` are tested in place of the
+// defaults. Without a saved page each checked-in fixture is tested against its page's selectors.
+#[cfg(feature = "hl")]
+fn scrape_test(provider: &str, page: Option<&str>, fixture: Option<&Path>) -> Result<()> {
+    if provider != "hl" {
+        return Err(anyhow!("{} has no selectors to test", provider));
+    }
+
+    let selectors = HlSelectors::load(env::var("YNAB_CONFIG_PATH").ok().as_deref())?;
+
+    let pages = match fixture {
+        Some(fixture) => vec![(
+            page.and_then(HlPage::from_name),
+            fixture.display().to_string(),
+            fs::read_to_string(fixture)?,
+        )],
+        None => HlPage::ALL
+            .into_iter()
+            .map(|page| {
+                (
+                    Some(page),
+                    format!("{} fixture", page.name()),
+                    page.fixture().to_owned(),
+                )
+            })
+            .collect(),
+    };
+
+    let mut missing = 0;
+
+    for (page, name, html) in pages {
+        println!("{}", name);
+
+        for report in providers::hl::scrape_test(&selectors, page, &html)? {
+            match &report.matched {
+                Some(matched) => println!("  ok      {}: {}", report.name, matched),
+                None => {
+                    println!("  MISSING {}: {}", report.name, report.selector);
+                    missing += 1;
+                }
+            }
+        }
+    }
+
+    match missing {
+        0 => Ok(()),
+        _ => Err(anyhow!("{} selector(s) didn't match", missing)),
+    }
+}
+
+#[cfg(feature = "hl")]
+async fn record_fixture(user: Option<&str>, account: &str, dir: &Path) -> Result<()> {
+    let user = select_users(user)?
+        .into_iter()
+        .find(|u| u.config.accounts.contains_key(account))
+        .ok_or_else(|| anyhow!("No account named {} is configured", account))?;
+    let account_config = &user.config.accounts[account];
+
+    if account_config.provider != ProviderKind::Hl {
+        return Err(anyhow!("{} isn't an HL account", account));
+    }
+
+    let pages = Hl::new(&user.config, account, account_config)?
+        .record_fixtures()
+        .await?;
+
+    fs::create_dir_all(dir)?;
+
+    for (page, html) in pages {
+        let path = dir.join(format!("{}.html", page.name()));
+        fs::write(&path, html)?;
+        info!("Saved {}", path.display());
+    }
+
+    Ok(())
+}
+
+async fn undo(user: Option<&str>, account: &str) -> Result<()> {
+    let users = select_users(user)?
+        .into_iter()
+        .filter(|u| u.config.accounts.contains_key(account))
+        .collect::<Vec<_>>();
+
+    match users.as_slice() {
+        [] => Err(anyhow!("No account named {} is configured", account)),
+        [user] => undo_last_adjustment(&user.config, account).await,
+        _ => Err(anyhow!(
+            "More than one user has an account named {}, choose one with --user",
+            account
+        )),
+    }
+}
+
+// The accounts a unit generated by the nix module runs: `ynab-updater-<account>`,
+// `ynab-updater-profile-<profile>` or, for the daemon, all of them
+fn unit_accounts(user: &User, unit: &str) -> Vec<String> {
+    let target = unit.trim_end_matches(".service");
+    let target = target.strip_prefix("ynab-updater-").unwrap_or(target);
+
+    match target.strip_prefix("profile-") {
+        Some(profile) => user
+            .config
+            .profiles
+            .get(profile)
+            .cloned()
+            .unwrap_or_default(),
+        None if target == "daemon" => user.config.accounts.keys().cloned().collect(),
+        None => user
+            .config
+            .accounts
+            .keys()
+            .filter(|account| *account == target)
+            .cloned()
+            .collect(),
+    }
+}
+
+async fn notify_failure(unit: &str) -> Result<()> {
+    let users = get_users()?;
+
+    let mut notified = false;
+
+    for user in &users {
+        let history = History::open(&user.config.config_path)?;
+
+        let mut reasons = vec![];
+
+        for account in unit_accounts(user, unit) {
+            let last_run = match history.get_last_run(&account)? {
+                Some(last_run) => last_run,
+                None => continue,
+            };
+
+            let at = last_run
+                .recorded_at
+                .with_timezone(&Local)
+                .format("%Y-%m-%d %H:%M");
+
+            match (&last_run.error, &last_run.report) {
+                (Some(error), _) => match &last_run.run_id {
+                    Some(run_id) => reasons.push(format!(
+                        "{} failed at {} (run {}): {}",
+                        account, at, run_id, error
+                    )),
+                    None => reasons.push(format!("{} failed at {}: {}", account, at, error)),
+                },
+                (None, Some(report))
+                    if report.action == RunAction::Skipped(SkipReason::AwaitingAuth) =>
+                {
+                    reasons.push(format!("{} needs logging in since {}", account, at))
+                }
+                _ => {}
+            }
+        }
+
+        if reasons.is_empty() {
+            continue;
+        }
+
+        notify::notify(
+            &user.config,
+            &Notification {
+                event: Event::Failure,
+                title: unit.to_owned(),
+                message: reasons.join("\n"),
+                url: None,
+            },
+        )
+        .await;
+
+        notified = true;
+    }
+
+    // e.g. the config failed to load, so none of the accounts ran
+    if !notified {
+        if let Some(user) = users.first() {
+            notify::notify(
+                &user.config,
+                &Notification {
+                    event: Event::Failure,
+                    title: unit.to_owned(),
+                    message: format!(
+                        "{} failed without any of its accounts' runs failing, see its logs",
+                        unit
+                    ),
+                    url: None,
+                },
+            )
+            .await;
+        }
+    }
+
+    Ok(())
+}
+
+async fn consents(user: Option<&str>) -> Result<()> {
+    println!(
+        "{:<12} {:<16} {:<8} {:<12} {:<12} STATUS",
+        "USER", "ACCOUNT", "PROVIDER", "STARTED", "EXPIRES"
+    );
+
+    for user in select_users(user)? {
+        for (account, account_config) in &user.config.accounts {
+            let consent = match providers::consent(&user.config, account, account_config).await? {
+                Some(consent) => consent,
+                None => continue,
+            };
+
+            let format_date = |date: Option<DateTime<Utc>>| {
+                date.map_or("-".to_owned(), |date| {
+                    date.with_timezone(&Local).format("%Y-%m-%d").to_string()
+                })
+            };
+
+            let status = match consent.expires_at {
+                Some(expires_at) if expires_at <= Utc::now() => "expired".to_owned(),
+                Some(expires_at) => {
+                    let remaining = expires_at - Utc::now();
+                    let renewal = if remaining < consent.renewal_window {
+                        ", renewal due"
+                    } else {
+                        ""
+                    };
+                    match remaining.num_days() {
+                        0 => format!("{} hours left{}", remaining.num_hours(), renewal),
+                        days => format!("{} days left{}", days, renewal),
+                    }
+                }
+                None if consent.started_at.is_none() => "not given".to_owned(),
+                None => "doesn't expire".to_owned(),
+            };
+
+            println!(
+                "{:<12} {:<16} {:<8} {:<12} {:<12} {}",
+                user.name,
+                account,
+                format!("{:?}", account_config.provider).to_lowercase(),
+                format_date(consent.started_at),
+                format_date(consent.expires_at),
+                status
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn stats(user: Option<&str>, days: i64) -> Result<()> {
+    println!(
+        "{:<12} {:<16} {:<8} {:>7} {:>8} {:>8} {:>8}",
+        "USER", "ACCOUNT", "PROVIDER", "FETCHES", "SUCCESS", "P50", "P95"
+    );
+
+    let since = Utc::now() - chrono::Duration::days(days);
+
+    let format_ms = |ms: Option<i64>| {
+        ms.map_or("-".to_owned(), |ms| {
+            format!("{:.1}s", Duration::from_millis(ms as u64).as_secs_f32())
+        })
+    };
+
+    for user in select_users(user)? {
+        let history = History::open(&user.config.config_path)?;
+
+        for (account, account_config) in &user.config.accounts {
+            let stats = match stats::summarise(&history.get_fetches(account, since)?) {
+                Some(stats) => stats,
+                None => continue,
+            };
+
+            println!(
+                "{:<12} {:<16} {:<8} {:>7} {:>7.1}% {:>8} {:>8}",
+                user.name,
+                account,
+                account_config.provider.name(),
+                stats.fetches,
+                stats.success_rate * 100.0,
+                format_ms(stats.p50_ms),
+                format_ms(stats.p95_ms)
+            );
+        }
+    }
+
+    Ok(())
+}
+
+fn new_provider(dir: &Path, name: &str, kind: Template) -> Result<()> {
+    let module_path = scaffold::new_provider(dir, name, kind)?;
+
+    println!(
+        "Created {} & registered it in src/providers.rs. Fill in its TODOs, run `cargo fmt`, then try it with an account whose PROVIDER = \"{}\".",
+        module_path.display(),
+        name
+    );
+
+    Ok(())
+}
+
+fn set_value(user: Option<&str>, account: &str, value: Option<f32>) -> Result<()> {
+    let users = select_users(user)?
+        .into_iter()
+        .filter(|u| u.config.accounts.contains_key(account))
+        .collect::<Vec<_>>();
+
+    let user = match users.as_slice() {
+        [] => return Err(anyhow!("No account named {} is configured", account)),
+        [user] => user,
+        _ => {
+            return Err(anyhow!(
+                "More than one user has an account named {}, choose one with --user",
+                account
+            ))
+        }
+    };
+
+    if user.config.accounts[account].provider != ProviderKind::Property {
+        return Err(anyhow!("{} isn't a property account", account));
+    }
+
+    History::open(&user.config.config_path)?.set_manual_value(account, value)?;
+
+    match value {
+        Some(value) => info!("Set {}'s value to {:.2}", account, value),
+        None => info!("Cleared {}'s value", account),
+    }
+
+    Ok(())
+}
+
+async fn backfill(user: Option<&str>, account: &str, from: NaiveDate, dry_run: bool) -> Result<()> {
+    let users = select_users(user)?
+        .into_iter()
+        .filter(|u| u.config.accounts.contains_key(account))
+        .collect::<Vec<_>>();
+
+    let user = match users.as_slice() {
+        [] => return Err(anyhow!("No account named {} is configured", account)),
+        [user] => user,
+        _ => {
+            return Err(anyhow!(
+                "More than one user has an account named {}, choose one with --user",
+                account
+            ))
+        }
+    };
+
+    let snapshots = backfill::backfill(&user.config, account, from, dry_run).await?;
+
+    info!(
+        "{} {} snapshots for {}",
+        if dry_run { "Would create" } else { "Created" },
+        snapshots.len(),
+        account
+    );
+
+    Ok(())
+}
+
+async fn self_update(check: bool, force: bool) -> Result<()> {
+    // Without a config the defaults do, so a broken install can still be updated
+    let http_config = get_users()
+        .ok()
+        .and_then(|users| users.into_iter().next())
+        .map(|user| user.config.http)
+        .unwrap_or_default();
+
+    match self_update::self_update(&http_config, check, force).await? {
+        Some(release) if check => println!("{} is available", release),
+        Some(release) => info!("Updated to {}", release),
+        None => {}
+    }
+
+    Ok(())
+}
+
+fn man(dir: Option<&Path>) -> Result<()> {
+    match dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            clap_mangen::generate_to(Cli::command(), dir)?;
+            info!("Wrote the man pages to {}", dir.display());
+        }
+        None => clap_mangen::Man::new(Cli::command()).render(&mut std::io::stdout())?,
+    }
+
+    Ok(())
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    ynab_updater::logging::init();
+
+    match run_command(Cli::parse().command).await {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            eprintln!("Error: {:?}", e);
+            ExitCode::from(exit_code(&e).unwrap_or(1))
+        }
+    }
+}
+
+async fn run_command(command: Command) -> Result<()> {
+    match command {
+        Command::Run {
+            user,
+            profile,
+            force,
+            output,
+            accounts,
+        } => {
+            run(
+                user.as_deref(),
+                &accounts,
+                profile.as_deref(),
+                force,
+                output,
+            )
+            .await
+        }
+        Command::Daemon {
+            shutdown_timeout_secs,
+        } => {
+            daemon::run(
+                get_users()?,
+                get_web_ui_config()?,
+                Duration::from_secs(shutdown_timeout_secs),
+            )
+            .await
+        }
+        Command::Auth { user, account } => auth(user.as_deref(), &account).await,
+        #[cfg(feature = "hl")]
+        Command::ScrapeTest {
+            provider,
+            page,
+            fixture,
+        } => scrape_test(&provider, page.as_deref(), fixture.as_deref()),
+        #[cfg(feature = "hl")]
+        Command::RecordFixture { user, dir, account } => {
+            record_fixture(user.as_deref(), &account, &dir).await
+        }
+        Command::Undo { user, account } => undo(user.as_deref(), &account).await,
+        Command::Consents { user } => consents(user.as_deref()).await,
+        Command::NotifyFailure { unit } => notify_failure(&unit).await,
+        Command::SetValue {
+            user,
+            clear: _,
+            account,
+            value,
+        } => set_value(user.as_deref(), &account, value),
+        Command::Backfill {
+            user,
+            from,
+            dry_run,
+            account,
+        } => backfill(user.as_deref(), &account, from, dry_run).await,
+        Command::Stats { user, days } => stats(user.as_deref(), days),
+        Command::NewProvider { kind, dir, name } => new_provider(&dir, &name, kind),
+        Command::InstallSchedule {
+            platform,
+            user,
+            dry_run,
+        } => install::install(platform, &select_users(user.as_deref())?, dry_run),
+        Command::SelfUpdate { check, force } => self_update(check, force).await,
+        Command::Completions { shell } => {
+            clap_complete::generate(
+                shell,
+                &mut Cli::command(),
+                "ynab-updater",
+                &mut std::io::stdout(),
+            );
+            Ok(())
+        }
+        Command::Man { dir } => man(dir.as_deref()),
+    }
+}
@@ -0,0 +1,128 @@
+use anyhow::{anyhow, Result};
+use chrono::{Duration, Utc};
+use log::info;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use std::time::{Duration as StdDuration, Instant};
+
+use crate::{
+    history::{History, PendingManualLogin},
+    notify::{self, Event, Notification},
+    token_store::TokenStore,
+    AuthPending, Config,
+};
+
+// Beyond this a pending manual login is abandoned and a fresh link is generated
+static PENDING_MANUAL_LOGIN_MAX_AGE_HOURS: i64 = 24;
+
+static SESSION_POLL_SECS: u64 = 5;
+
+// The session of a login finished by hand, as the `Cookie` header the browser sent
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ManualSession {
+    pub cookies: String,
+}
+
+fn session_store(config_path: &str, account: &str) -> TokenStore {
+    TokenStore::new(config_path, &format!("{}_session.json", account))
+}
+
+// Called by the web UI once the user has handed the session back
+pub async fn save_session(config_path: &str, account: &str, session: &ManualSession) -> Result<()> {
+    let token_guard = session_store(config_path, account).lock().await?;
+
+    token_guard.write(session)
+}
+
+// Sessions are short lived, so one is only used by the next run
+pub async fn take_session(config: &Config, account: &str) -> Result<Option<String>> {
+    let token_guard = session_store(&config.config_path, account).lock().await?;
+
+    let session = token_guard.read::<ManualSession>()?;
+
+    token_guard.clear()?;
+
+    Ok(session.map(|(session, _)| session.cookies))
+}
+
+// Sends the user a link to the web UI, where they log in to the provider by hand & hand the
+// session back. A session that isn't handed back within `AUTH_TIMEOUT_SECS` is left pending, to
+// be picked up by the next run.
+pub async fn request_session(
+    config: &Config,
+    account: &str,
+    title: &str,
+    login_uri: &str,
+) -> Result<String> {
+    let web_ui = config.web_ui.as_ref().ok_or_else(|| {
+        anyhow!(
+            "{} needs logging in to by hand, which needs WEB_UI to be configured",
+            title
+        )
+    })?;
+
+    let history = History::open(&config.config_path)?;
+
+    let pending_manual_login = match history.get_pending_manual_login(account)? {
+        Some(pending_manual_login)
+            if Utc::now() - pending_manual_login.created_at
+                < Duration::hours(PENDING_MANUAL_LOGIN_MAX_AGE_HOURS) =>
+        {
+            pending_manual_login
+        }
+        _ => {
+            let pending_manual_login = PendingManualLogin {
+                provider: account.to_owned(),
+                state: rand::thread_rng()
+                    .sample_iter(&Alphanumeric)
+                    .take(32)
+                    .map(char::from)
+                    .collect::<String>(),
+                title: title.to_owned(),
+                login_uri: login_uri.to_owned(),
+                created_at: Utc::now(),
+            };
+
+            history.set_pending_manual_login(&pending_manual_login)?;
+
+            pending_manual_login
+        }
+    };
+
+    let notification = Notification {
+        event: Event::Login,
+        title: title.to_owned(),
+        message: format!("{} needs logging in to by hand", title),
+        url: Some(format!(
+            "{}{}/login/{}",
+            web_ui.url.trim_end_matches('/'),
+            web_ui.access.path_prefix(),
+            pending_manual_login.state
+        )),
+    };
+
+    notify::notify(config, &notification).await;
+
+    info!("Waiting for {}'s session from the web UI", title);
+
+    let deadline = Instant::now() + StdDuration::from_secs(config.auth_timeout_secs);
+
+    loop {
+        if let Some(cookies) = take_session(config, account).await? {
+            history.clear_pending_manual_login(account)?;
+            if let Some(url) = &notification.url {
+                notify::prompt_answered(config, url).await;
+            }
+            return Ok(cookies);
+        }
+
+        if Instant::now() >= deadline {
+            return Err(AuthPending {
+                provider: account.to_owned(),
+            }
+            .into());
+        }
+
+        tokio::time::sleep(StdDuration::from_secs(SESSION_POLL_SECS)).await;
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::Result;
+use chrono::{prelude::*, Duration};
+use log::info;
+use serde::{Deserialize, Deserializer};
+
+// When an investment account is fetched, so it's reconciled to a close rather than to wherever
+// the market was when it ran, e.g. `MARKET_HOURS = { MARKET = "nyse" }` for after New York's close.
+// Days the market's closed, weekends & its holidays, are skipped, since there's no new close.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct MarketHoursConfig {
+    pub market: Market,
+    // In the market's own time, e.g. `"09:00"`. From its close until midnight by default.
+    #[serde(default, deserialize_with = "deserialize_time")]
+    pub from: Option<NaiveTime>,
+    #[serde(default, deserialize_with = "deserialize_time")]
+    pub until: Option<NaiveTime>,
+    #[serde(default = "default_skip_closed_days")]
+    pub skip_closed_days: bool,
+    // Closures the built-in calendar doesn't know of, e.g. a one-off bank holiday
+    #[serde(default)]
+    pub holidays: Vec<NaiveDate>,
+}
+
+fn default_skip_closed_days() -> bool {
+    true
+}
+
+fn deserialize_time<'de, D>(deserializer: D) -> Result<Option<NaiveTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Option::<String>::deserialize(deserializer)?
+        .map(|time| NaiveTime::parse_from_str(&time, "%H:%M").map_err(serde::de::Error::custom))
+        .transpose()
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Market {
+    Lse,
+    Nyse,
+}
+
+impl Market {
+    fn name(&self) -> &'static str {
+        match self {
+            Market::Lse => "LSE",
+            Market::Nyse => "NYSE",
+        }
+    }
+
+    fn close(&self) -> NaiveTime {
+        match self {
+            Market::Lse => NaiveTime::from_hms_opt(16, 30, 0),
+            Market::Nyse => NaiveTime::from_hms_opt(16, 0, 0),
+        }
+        .unwrap_or_default()
+    }
+
+    // London's or New York's time, from the UK's & US's daylight saving rules
+    fn local_time(&self, now: DateTime<Utc>) -> NaiveDateTime {
+        let year = now.year();
+
+        let (offset, dst_offset, dst_start, dst_end) = match self {
+            // From 01:00 UTC on the last Sunday of March until the last Sunday of October
+            Market::Lse => (
+                0,
+                1,
+                last_weekday(year, 3, Weekday::Sun).and_hms_opt(1, 0, 0),
+                last_weekday(year, 10, Weekday::Sun).and_hms_opt(1, 0, 0),
+            ),
+            // From 02:00 local on the second Sunday of March until the first Sunday of November
+            Market::Nyse => (
+                -5,
+                -4,
+                nth_weekday(year, 3, Weekday::Sun, 2).and_hms_opt(7, 0, 0),
+                nth_weekday(year, 11, Weekday::Sun, 1).and_hms_opt(6, 0, 0),
+            ),
+        };
+
+        let utc = now.naive_utc();
+        let is_dst =
+            dst_start.is_some_and(|start| utc >= start) && dst_end.is_some_and(|end| utc < end);
+
+        utc + Duration::hours(if is_dst { dst_offset } else { offset })
+    }
+
+    fn is_holiday(&self, date: NaiveDate) -> bool {
+        let year = date.year();
+        let easter = easter_sunday(year);
+
+        let mut holidays = match self {
+            // England's bank holidays
+            Market::Lse => vec![
+                substitute_monday(ymd(year, 1, 1)),
+                easter - Duration::days(2),
+                easter + Duration::days(1),
+                nth_weekday(year, 5, Weekday::Mon, 1),
+                last_weekday(year, 5, Weekday::Mon),
+                last_weekday(year, 8, Weekday::Mon),
+                christmas_substitutes(year).0,
+                christmas_substitutes(year).1,
+            ],
+            Market::Nyse => vec![
+                // Not made up on the Friday before when it's a Saturday
+                match ymd(year, 1, 1).weekday() {
+                    Weekday::Sun => ymd(year, 1, 2),
+                    _ => ymd(year, 1, 1),
+                },
+                nth_weekday(year, 1, Weekday::Mon, 3),
+                nth_weekday(year, 2, Weekday::Mon, 3),
+                easter - Duration::days(2),
+                last_weekday(year, 5, Weekday::Mon),
+                nearest_weekday(ymd(year, 7, 4)),
+                nth_weekday(year, 9, Weekday::Mon, 1),
+                nth_weekday(year, 11, Weekday::Thu, 4),
+                nearest_weekday(ymd(year, 12, 25)),
+            ],
+        };
+
+        // Juneteenth, since it was first observed
+        if *self == Market::Nyse && year >= 2022 {
+            holidays.push(nearest_weekday(ymd(year, 6, 19)));
+        }
+
+        holidays.contains(&date)
+    }
+}
+
+fn ymd(year: i32, month: u32, day: u32) -> NaiveDate {
+    NaiveDate::from_ymd_opt(year, month, day).unwrap_or_default()
+}
+
+fn nth_weekday(year: i32, month: u32, weekday: Weekday, n: u8) -> NaiveDate {
+    NaiveDate::from_weekday_of_month_opt(year, month, weekday, n).unwrap_or_default()
+}
+
+fn last_weekday(year: i32, month: u32, weekday: Weekday) -> NaiveDate {
+    let last_day = match month {
+        12 => ymd(year + 1, 1, 1),
+        _ => ymd(year, month + 1, 1),
+    }
+    .pred_opt()
+    .unwrap_or_default();
+
+    let days_back =
+        (7 + last_day.weekday().num_days_from_monday() - weekday.num_days_from_monday()) % 7;
+
+    last_day - Duration::days(days_back as i64)
+}
+
+// A weekend's holiday on the Monday after
+fn substitute_monday(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date + Duration::days(2),
+        Weekday::Sun => date + Duration::days(1),
+        _ => date,
+    }
+}
+
+// A Saturday's holiday on the Friday before, a Sunday's on the Monday after
+fn nearest_weekday(date: NaiveDate) -> NaiveDate {
+    match date.weekday() {
+        Weekday::Sat => date - Duration::days(1),
+        Weekday::Sun => date + Duration::days(1),
+        _ => date,
+    }
+}
+
+// Christmas & Boxing Day, on the next weekdays that aren't already holidays
+fn christmas_substitutes(year: i32) -> (NaiveDate, NaiveDate) {
+    let christmas = ymd(year, 12, 25);
+
+    match christmas.weekday() {
+        Weekday::Fri => (christmas, ymd(year, 12, 28)),
+        Weekday::Sat => (ymd(year, 12, 27), ymd(year, 12, 28)),
+        Weekday::Sun => (ymd(year, 12, 27), ymd(year, 12, 26)),
+        _ => (christmas, ymd(year, 12, 26)),
+    }
+}
+
+// The anonymous Gregorian algorithm
+fn easter_sunday(year: i32) -> NaiveDate {
+    let a = year % 19;
+    let b = year / 100;
+    let c = year % 100;
+    let d = b / 4;
+    let e = b % 4;
+    let f = (b + 8) / 25;
+    let g = (b - f + 1) / 3;
+    let h = (19 * a + b - d - g + 15) % 30;
+    let i = c / 4;
+    let k = c % 4;
+    let l = (32 + 2 * e + 2 * i - h - k) % 7;
+    let m = (a + 11 * h + 22 * l) / 451;
+    let month = (h + l - 7 * m + 114) / 31;
+    let day = (h + l - 7 * m + 114) % 31 + 1;
+
+    ymd(year, month as u32, day as u32)
+}
+
+// Whether it's a time the account's fetched at, which is logged when it isn't
+pub fn is_fetch_time(account: &str, config: &MarketHoursConfig, now: DateTime<Utc>) -> bool {
+    let market = config.market;
+    let local = market.local_time(now);
+    let date = local.date();
+
+    if config.skip_closed_days {
+        let closed = match date.weekday() {
+            Weekday::Sat | Weekday::Sun => Some("a weekend"),
+            _ if market.is_holiday(date) || config.holidays.contains(&date) => Some("a holiday"),
+            _ => None,
+        };
+
+        if let Some(closed) = closed {
+            info!(
+                "It's {} for the {}, skipping {}",
+                closed,
+                market.name(),
+                account
+            );
+            return false;
+        }
+    }
+
+    let from = config.from.unwrap_or_else(|| market.close());
+    let time = local.time();
+
+    let is_within = match config.until {
+        Some(until) if until < from => time >= from || time < until,
+        Some(until) => time >= from && time < until,
+        None => time >= from,
+    };
+
+    if !is_within {
+        info!(
+            "It's {} in the {}'s time, outside {}'s market hours, skipping",
+            time.format("%H:%M"),
+            market.name(),
+            account
+        );
+    }
+
+    is_within
+}
@@ -0,0 +1,119 @@
+use anyhow::Result;
+use log::warn;
+use serde::Deserialize;
+use std::{fmt::Write, time::Duration};
+use tokio::net::UdpSocket;
+
+use crate::{
+    report::{RunAction, RunReport},
+    Config,
+};
+
+// Each run's metrics pushed to a statsd server, e.g. statsd's Graphite backend or Telegraf, for
+// setups without Prometheus. With `[metrics] STATSD_ADDRESS = "127.0.0.1:8125"` an account `hl`'s
+// runs are sent as:
+//
+// - `ynab_updater.hl.runs.<created|updated|skipped|failed>`, a counter
+// - `ynab_updater.hl.duration`, a timer in milliseconds
+// - `ynab_updater.hl.<real_balance|ynab_balance|adjustment>`, gauges, when the run got that far
+//
+// With `[users.<name>]` sections each user's `PREFIX` tells their accounts apart.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct MetricsConfig {
+    pub statsd_address: String,
+    #[serde(default = "default_prefix")]
+    pub prefix: String,
+}
+
+fn default_prefix() -> String {
+    "ynab_updater".to_owned()
+}
+
+// statsd names are dot separated, so anything else in an account's name is replaced
+fn sanitise(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+fn payload(
+    config: &MetricsConfig,
+    account: &str,
+    result: &Result<RunReport>,
+    duration: Duration,
+) -> String {
+    let name = format!("{}.{}", config.prefix, sanitise(account));
+
+    let outcome = match result {
+        Ok(report) => match report.action {
+            RunAction::Created => "created",
+            RunAction::Updated => "updated",
+            RunAction::Skipped(_) => "skipped",
+        },
+        Err(_) => "failed",
+    };
+
+    let mut payload = format!(
+        "{}.runs.{}:1|c\n{}.duration:{}|ms\n",
+        name,
+        outcome,
+        name,
+        duration.as_millis()
+    );
+
+    if let Ok(report) = result {
+        for (gauge, value) in [
+            ("real_balance", report.real_balance),
+            ("ynab_balance", report.ynab_balance),
+            ("adjustment", report.adjustment),
+        ] {
+            if let Some(value) = value {
+                // A signed gauge adjusts the last value rather than setting it, so a negative one
+                // is set by zeroing it first
+                if value < 0.0 {
+                    let _ = writeln!(payload, "{}.{}:0|g", name, gauge);
+                }
+                let _ = writeln!(payload, "{}.{}:{}|g", name, gauge, value);
+            }
+        }
+    }
+
+    payload
+}
+
+async fn send(config: &MetricsConfig, payload: &str) -> Result<()> {
+    let socket = UdpSocket::bind("0.0.0.0:0").await?;
+
+    socket
+        .send_to(payload.as_bytes(), &config.statsd_address)
+        .await?;
+
+    Ok(())
+}
+
+// Metrics are best effort, so a statsd server that's down doesn't fail the run
+pub async fn record(
+    config: &Config,
+    account: &str,
+    result: &Result<RunReport>,
+    duration: Duration,
+) {
+    let metrics = match &config.metrics {
+        Some(metrics) => metrics,
+        None => return,
+    };
+
+    if let Err(e) = send(metrics, &payload(metrics, account, result, duration)).await {
+        warn!(
+            "Failed to send metrics to {}: {:#}",
+            metrics.statsd_address, e
+        );
+    }
+}
@@ -0,0 +1,165 @@
+// The config file upgraded to the current schema when it's loaded, so a redesign doesn't strand
+// configs written for an older one. Its `CONFIG_VERSION` says which migrations it's had, each
+// upgrading it from one version to the next. The original is backed up before it's rewritten, & the
+// upgraded config is used even if it can't be written back, e.g. it's read only.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::Utc;
+use std::{fs, path::Path};
+use toml_edit::{value, DocumentMut, Item, Table};
+
+static VERSION_KEY: &str = "CONFIG_VERSION";
+
+// Each upgrades a config from its index's version to the next, returning what it changed
+type Migration = fn(&mut Table) -> Vec<String>;
+
+static MIGRATIONS: [Migration; 1] = [flat_accounts_to_sections];
+
+pub fn current_version() -> i64 {
+    MIGRATIONS.len() as i64
+}
+
+// The keys each flat config provider read, along with its `YNAB_<PROVIDER>_ACCOUNT_ID`
+static HL_KEYS: [&str; 7] = [
+    "USER_AGENT",
+    "ACCEPT_LANGUAGE",
+    "MIN_REQUEST_DELAY_MILLIS",
+    "MAX_REQUEST_DELAY_MILLIS",
+    "FLARESOLVERR_URL",
+    "CHALLENGE_SOLVER_COMMAND",
+    "CHALLENGE_PAUSE_HOURS",
+];
+static SAXO_KEYS: [&str; 5] = [
+    "TAILSCALE_IP",
+    "CALLBACK_HOST",
+    "CALLBACK_PORT",
+    "CALLBACK_PATH",
+    "CALLBACK_TLS",
+];
+
+// 0 to 1: HL & Saxo's settings moved from the top level into their own `[accounts.<name>]`
+fn flat_accounts_to_sections(config: &mut Table) -> Vec<String> {
+    // Already in sections, or a config written since
+    if config.contains_key("accounts") || config.contains_key("users") {
+        return vec![];
+    }
+
+    let providers: [(&str, &str, &str, &[&str]); 2] = [
+        ("hl", "YNAB_HL_ACCOUNT_ID", "HL_", &HL_KEYS),
+        ("saxo", "YNAB_SAXO_ACCOUNT_ID", "SAXO_", &SAXO_KEYS),
+    ];
+
+    let mut accounts = Table::new();
+    accounts.set_implicit(true);
+
+    let mut changes = vec![];
+
+    for (name, account_id_key, prefix, keys) in providers {
+        let Some(account_id) = config.remove(account_id_key) else {
+            continue;
+        };
+
+        let mut account = Table::new();
+        account.insert("PROVIDER", value(name));
+        account.insert("YNAB_ACCOUNT_ID", account_id);
+        changes.push(format!(
+            "Moved {} to [accounts.{}]'s YNAB_ACCOUNT_ID",
+            account_id_key, name
+        ));
+
+        let moved = config
+            .iter()
+            .map(|(key, _)| key.to_owned())
+            .filter(|key| key.starts_with(prefix) || keys.contains(&key.as_str()))
+            .collect::<Vec<_>>();
+
+        for key in moved {
+            if let Some(item) = config.remove(&key) {
+                account.insert(&key, item);
+                changes.push(format!("Moved {} to [accounts.{}]", key, name));
+            }
+        }
+
+        accounts.insert(name, Item::Table(account));
+    }
+
+    if !accounts.is_empty() {
+        config.insert("accounts", Item::Table(accounts));
+    }
+
+    changes
+}
+
+// The config file's contents, upgraded to the current version. Each change is printed to stderr,
+// whatever RUST_LOG is, without getting into a run's `--output json`.
+pub fn load(path: &Path) -> Result<String> {
+    let original =
+        fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+    let mut document = original
+        .parse::<DocumentMut>()
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+    let version = match document.get(VERSION_KEY) {
+        Some(version) => version
+            .as_integer()
+            .ok_or_else(|| anyhow!("{} isn't a number", VERSION_KEY))?,
+        None => 0,
+    };
+
+    if version > current_version() {
+        return Err(anyhow!(
+            "{} is CONFIG_VERSION {}, which is newer than this updater's {}, so it needs updating",
+            path.display(),
+            version,
+            current_version()
+        ));
+    }
+    if version == current_version() {
+        return Ok(original);
+    }
+
+    let changes = MIGRATIONS[version as usize..]
+        .iter()
+        .flat_map(|migration| migration(document.as_table_mut()))
+        .collect::<Vec<_>>();
+
+    // Left as it is until there's something to upgrade
+    if changes.is_empty() {
+        return Ok(original);
+    }
+
+    document.insert(VERSION_KEY, value(current_version()));
+    let migrated = document.to_string();
+
+    eprintln!(
+        "Upgraded {} from CONFIG_VERSION {} to {}",
+        path.display(),
+        version,
+        current_version()
+    );
+    for change in &changes {
+        eprintln!("  {}", change);
+    }
+
+    // Suffixed with the time, so an earlier backup isn't overwritten
+    let backup_path = path.with_extension(format!(
+        "toml.v{}-{}.bak",
+        version,
+        Utc::now().format("%Y%m%d%H%M%S")
+    ));
+
+    let written = fs::copy(path, &backup_path)
+        .map_err(anyhow::Error::from)
+        .and_then(|_| Ok(fs::write(path, &migrated)?));
+
+    match written {
+        Ok(()) => eprintln!("The original was backed up to {}", backup_path.display()),
+        Err(e) => eprintln!(
+            "Failed to save the upgraded config, it'll be upgraded again each run: {:#}",
+            e
+        ),
+    }
+
+    Ok(migrated)
+}
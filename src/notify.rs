@@ -0,0 +1,486 @@
+use anyhow::{anyhow, Result};
+use chrono::{Local, NaiveTime, Utc};
+use log::{info, warn};
+use secrecy::ExposeSecret;
+use serde::{Deserialize, Deserializer, Serialize};
+use serde_json::json;
+use std::env;
+use std::time::Duration;
+
+use crate::{history::History, http, Config};
+
+static PUSHOVER_MESSAGES_URL: &str = "https://api.pushover.net/1/messages.json";
+static PUSHOVER_RECEIPTS_URL: &str = "https://api.pushover.net/1/receipts";
+
+// Emergency priority messages are repeated every `PUSHOVER_EMERGENCY_RETRY_SECS` until acknowledged,
+// for at most `PUSHOVER_MAX_EXPIRE_SECS` (Pushover's upper limit)
+static PUSHOVER_EMERGENCY_RETRY_SECS: u64 = 60;
+static PUSHOVER_MAX_EXPIRE_SECS: u64 = 10800;
+
+static DELIVERY_ATTEMPTS: u64 = 3;
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Event {
+    Failure,
+    Warning,
+    Update,
+    Complete,
+    Login,
+    // A balance waiting to be approved through the web UI
+    Approval,
+}
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NotifierKind {
+    #[default]
+    Auto,
+    Pushover,
+    Desktop,
+}
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookFormat {
+    Slack,
+    Discord,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct WebhookConfig {
+    pub url: String,
+    pub format: WebhookFormat,
+    #[serde(default = "default_webhook_events")]
+    pub events: Vec<Event>,
+    pub quiet_hours: Option<QuietHours>,
+    pub batch_minutes: Option<i64>,
+}
+
+fn default_webhook_events() -> Vec<Event> {
+    vec![Event::Failure, Event::Warning, Event::Update]
+}
+
+// Hours a backend's notifications are held through, in local time, e.g.
+// `NOTIFY_QUIET_HOURS = { FROM = "23:00", UNTIL = "07:00" }`. Held ones are sent together once
+// they're over. Each backend can also batch, with `NOTIFY_BATCH_MINUTES` or a webhook's
+// `BATCH_MINUTES`: after one's sent, the rest in that many minutes are held & sent as one, so a
+// flaky night is one message rather than ten. Login & approval prompts are never held, since a
+// run is waiting on them.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct QuietHours {
+    #[serde(deserialize_with = "deserialize_time")]
+    pub from: NaiveTime,
+    #[serde(deserialize_with = "deserialize_time")]
+    pub until: NaiveTime,
+}
+
+fn deserialize_time<'de, D>(deserializer: D) -> Result<NaiveTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    NaiveTime::parse_from_str(&String::deserialize(deserializer)?, "%H:%M")
+        .map_err(serde::de::Error::custom)
+}
+
+impl QuietHours {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.from <= self.until {
+            time >= self.from && time < self.until
+        } else {
+            time >= self.from || time < self.until
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Notification {
+    pub event: Event,
+    pub title: String,
+    pub message: String,
+    pub url: Option<String>,
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Backend<'a> {
+    // Pushover or the desktop's
+    Notifier(NotifierKind),
+    Webhook(&'a WebhookConfig),
+}
+
+impl Backend<'_> {
+    // What its held notifications & batches are recorded under
+    fn key(&self) -> String {
+        match self {
+            Backend::Notifier(_) => "notifier".to_owned(),
+            Backend::Webhook(webhook) => format!("webhook:{}", webhook.url),
+        }
+    }
+
+    fn name(&self) -> String {
+        match self {
+            Backend::Notifier(NotifierKind::Desktop) => "desktop notification".to_owned(),
+            Backend::Notifier(_) => "Pushover notification".to_owned(),
+            Backend::Webhook(webhook) => format!("{:?} webhook", webhook.format),
+        }
+    }
+
+    fn sends(&self, event: Event) -> bool {
+        match self {
+            Backend::Notifier(NotifierKind::Desktop) => true,
+            Backend::Notifier(_) => matches!(
+                event,
+                Event::Failure | Event::Warning | Event::Login | Event::Approval
+            ),
+            Backend::Webhook(webhook) => webhook.events.contains(&event),
+        }
+    }
+
+    fn quiet_hours<'a>(&'a self, config: &'a Config) -> Option<&'a QuietHours> {
+        match self {
+            Backend::Notifier(_) => config.notify_quiet_hours.as_ref(),
+            Backend::Webhook(webhook) => webhook.quiet_hours.as_ref(),
+        }
+    }
+
+    fn batch_minutes(&self, config: &Config) -> Option<i64> {
+        match self {
+            Backend::Notifier(_) => config.notify_batch_minutes,
+            Backend::Webhook(webhook) => webhook.batch_minutes,
+        }
+    }
+
+    async fn send(&self, config: &Config, notification: &Notification) -> Result<()> {
+        match self {
+            Backend::Notifier(NotifierKind::Desktop) => send_desktop(notification),
+            Backend::Notifier(_) => send_pushover(config, notification).await,
+            Backend::Webhook(webhook) => send_webhook(config, webhook, notification).await,
+        }
+    }
+}
+
+fn backends(config: &Config) -> Vec<Backend<'_>> {
+    std::iter::once(Backend::Notifier(resolve_notifier(config.notifier)))
+        .chain(config.webhooks.iter().map(Backend::Webhook))
+        .collect()
+}
+
+// Which of several notifications sent as one it's sent as
+fn severity(event: Event) -> u8 {
+    match event {
+        Event::Failure => 4,
+        Event::Warning => 3,
+        Event::Login | Event::Approval => 2,
+        Event::Update => 1,
+        Event::Complete => 0,
+    }
+}
+
+fn combine(mut notifications: Vec<Notification>) -> Option<Notification> {
+    if notifications.len() <= 1 {
+        return notifications.pop();
+    }
+
+    Some(Notification {
+        event: notifications
+            .iter()
+            .map(|n| n.event)
+            .max_by_key(|event| severity(*event))?,
+        title: format!("{} notifications", notifications.len()),
+        message: notifications
+            .iter()
+            .map(|n| format!("{}: {}", n.title, n.message))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        url: None,
+    })
+}
+
+// Sends the notification with any held before it, or holds it. Without a notification, only
+// sends those held if they're due.
+async fn deliver(
+    config: &Config,
+    backend: &Backend<'_>,
+    notification: Option<&Notification>,
+) -> Result<()> {
+    if let Some(notification) = notification {
+        if matches!(notification.event, Event::Login | Event::Approval) {
+            return backend.send(config, notification).await;
+        }
+    }
+
+    let quiet_hours = backend.quiet_hours(config);
+    let batch_minutes = backend.batch_minutes(config);
+
+    if quiet_hours.is_none() && batch_minutes.is_none() {
+        return match notification {
+            Some(notification) => backend.send(config, notification).await,
+            None => Ok(()),
+        };
+    }
+
+    let history = History::open(&config.config_path)?;
+    let key = backend.key();
+
+    let is_quiet = quiet_hours.is_some_and(|quiet_hours| quiet_hours.contains(Local::now().time()));
+    let is_batching = match batch_minutes {
+        Some(batch_minutes) => history
+            .get_notification_sent_at(&key)?
+            .is_some_and(|sent_at| Utc::now() - sent_at < chrono::Duration::minutes(batch_minutes)),
+        None => false,
+    };
+
+    if is_quiet || is_batching {
+        if let Some(notification) = notification {
+            info!(
+                "Holding the {} {:?}, it's {}",
+                backend.name(),
+                notification.title,
+                if is_quiet { "quiet hours" } else { "batching" }
+            );
+            history.hold_notification(
+                &key,
+                serde_json::to_value(notification.event)?
+                    .as_str()
+                    .unwrap_or_default(),
+                &notification.title,
+                &notification.message,
+                notification.url.as_deref(),
+            )?;
+        }
+        return Ok(());
+    }
+
+    let held = history.get_held_notifications(&key)?;
+    let last_held_id = held.last().map(|held| held.id);
+
+    let notifications = held
+        .into_iter()
+        .map(|held| {
+            Ok(Notification {
+                event: serde_json::from_value(serde_json::Value::String(held.event))?,
+                title: held.title,
+                message: held.message,
+                url: held.url,
+            })
+        })
+        .chain(notification.cloned().map(Ok))
+        .collect::<Result<Vec<_>>>()?;
+
+    let notification = match combine(notifications) {
+        Some(notification) => notification,
+        None => return Ok(()),
+    };
+
+    backend.send(config, &notification).await?;
+
+    if let Some(last_held_id) = last_held_id {
+        history.clear_held_notifications(&key, last_held_id)?;
+    }
+    if batch_minutes.is_some() {
+        history.set_notification_sent_at(&key)?;
+    }
+
+    Ok(())
+}
+
+pub async fn notify(config: &Config, notification: &Notification) {
+    for backend in backends(config) {
+        if !backend.sends(notification.event) {
+            continue;
+        }
+
+        if let Err(e) = deliver(config, &backend, Some(notification)).await {
+            warn!("Failed to send {}: {:#?}", backend.name(), e);
+        }
+    }
+}
+
+// Sends the notifications held by quiet hours or batching that are due, e.g. at the start of a run
+pub async fn flush_held(config: &Config) {
+    for backend in backends(config) {
+        if let Err(e) = deliver(config, &backend, None).await {
+            warn!("Failed to send {}: {:#?}", backend.name(), e);
+        }
+    }
+}
+
+// A session bus is only present when running inside a desktop session, so its absence means
+// we're running headless. A systemd service, e.g. the NixOS module's user services on the server,
+// has one anyway, so under systemd (which sets `INVOCATION_ID` & `JOURNAL_STREAM`) nobody's
+// assumed to be watching the desktop. Elsewhere there's no such bus to tell by, & a scheduled task
+// or agent runs in the user's session, so it's the desktop's.
+fn resolve_notifier(kind: NotifierKind) -> NotifierKind {
+    let under_systemd =
+        env::var_os("INVOCATION_ID").is_some() || env::var_os("JOURNAL_STREAM").is_some();
+
+    match kind {
+        NotifierKind::Auto
+            if !cfg!(target_os = "linux")
+                || (env::var_os("DBUS_SESSION_BUS_ADDRESS").is_some() && !under_systemd) =>
+        {
+            NotifierKind::Desktop
+        }
+        NotifierKind::Auto => NotifierKind::Pushover,
+        kind => kind,
+    }
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct PushoverResponse {
+    request: String,
+    receipt: Option<String>,
+}
+
+// Login & approval prompts use emergency priority so they keep alerting until acknowledged,
+// otherwise a missed notification leaves the run waiting until it times out. Their receipts are
+// kept so they're cancelled once the prompt's answered, see `prompt_answered`.
+async fn send_pushover(config: &Config, notification: &Notification) -> Result<()> {
+    let mut params = vec![
+        ("token", config.pushover_api_key.expose_secret().to_owned()),
+        ("user", config.pushover_user_key.clone()),
+        ("title", notification.title.clone()),
+        ("message", notification.message.clone()),
+    ];
+    if let Some(url) = &notification.url {
+        params.push(("url", url.clone()));
+        let url_title = match notification.event {
+            Event::Approval => "Review",
+            _ => "Login link",
+        };
+        params.push(("url_title", url_title.to_owned()));
+    }
+    if matches!(notification.event, Event::Login | Event::Approval) {
+        params.push(("priority", "2".to_owned()));
+        params.push(("retry", PUSHOVER_EMERGENCY_RETRY_SECS.to_string()));
+        params.push((
+            "expire",
+            config
+                .auth_timeout_secs
+                .min(PUSHOVER_MAX_EXPIRE_SECS)
+                .to_string(),
+        ));
+    }
+
+    let client = http::client(&config.http)?;
+
+    for attempt in 1..=DELIVERY_ATTEMPTS {
+        let result = client
+            .post(PUSHOVER_MESSAGES_URL)
+            .form(&params)
+            .send()
+            .await;
+
+        match result {
+            Ok(response) if response.status().is_success() => {
+                let pushover_response = response.json::<PushoverResponse>().await?;
+                info!(
+                    "Pushover request {} accepted, receipt: {:?}",
+                    pushover_response.request, pushover_response.receipt
+                );
+                if let (Some(receipt), Some(url)) = (&pushover_response.receipt, &notification.url)
+                {
+                    History::open(&config.config_path)?.set_pushover_receipt(url, receipt)?;
+                }
+                return Ok(());
+            }
+            // 4xx responses mean the request itself is invalid (e.g. a bad token) so retrying won't help
+            Ok(response) if response.status().is_client_error() => {
+                let status = response.status();
+                return Err(anyhow!(
+                    "Pushover rejected the message ({}): {}",
+                    status,
+                    response.text().await?
+                ));
+            }
+            Ok(response) => {
+                warn!(
+                    "Pushover delivery attempt {} failed: {}",
+                    attempt,
+                    response.status()
+                );
+            }
+            Err(e) => {
+                warn!("Pushover delivery attempt {} failed: {:#?}", attempt, e);
+            }
+        }
+
+        if attempt < DELIVERY_ATTEMPTS {
+            tokio::time::sleep(Duration::from_secs(5 * attempt)).await;
+        }
+    }
+
+    Err(anyhow!(
+        "Failed to deliver Pushover notification after {} attempts",
+        DELIVERY_ATTEMPTS
+    ))
+}
+
+// Stops the emergency priority prompt linking to the url from alerting, now that it's been
+// answered, rather than leaving Pushover to repeat it until it expires
+pub async fn prompt_answered(config: &Config, url: &str) {
+    if let Err(e) = cancel_pushover(config, url).await {
+        warn!("Failed to cancel the Pushover prompt: {:#}", e);
+    }
+}
+
+async fn cancel_pushover(config: &Config, url: &str) -> Result<()> {
+    let receipt = match History::open(&config.config_path)?.take_pushover_receipt(url)? {
+        Some(receipt) => receipt,
+        None => return Ok(()),
+    };
+
+    http::client(&config.http)?
+        .post(format!("{}/{}/cancel.json", PUSHOVER_RECEIPTS_URL, receipt))
+        .form(&[("token", config.pushover_api_key.expose_secret())])
+        .send()
+        .await?
+        .error_for_status()?;
+
+    info!("Cancelled Pushover receipt {}", receipt);
+
+    Ok(())
+}
+
+fn send_desktop(notification: &Notification) -> Result<()> {
+    let body = match &notification.url {
+        Some(url) => format!("{}\n{}", notification.message, url),
+        None => notification.message.clone(),
+    };
+
+    notify_rust::Notification::new()
+        .appname("ynab-updater")
+        .summary(&notification.title)
+        .body(&body)
+        .show()?;
+
+    Ok(())
+}
+
+async fn send_webhook(
+    config: &Config,
+    webhook: &WebhookConfig,
+    notification: &Notification,
+) -> Result<()> {
+    let body = match webhook.format {
+        WebhookFormat::Slack => json!({
+            "text": format!("*{}*\n{}", notification.title, notification.message)
+        }),
+        WebhookFormat::Discord => json!({
+            "content": format!("**{}**\n{}", notification.title, notification.message)
+        }),
+    };
+
+    let response = http::client(&config.http)?
+        .post(&webhook.url)
+        .json(&body)
+        .send()
+        .await?
+        .error_for_status()?;
+
+    info!("Webhook response {:#?}", response.status());
+
+    Ok(())
+}
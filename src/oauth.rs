@@ -0,0 +1,1106 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Local, Utc};
+use log::{info, warn};
+use rand::{distributions::Alphanumeric, Rng};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use std::time::Duration as StdDuration;
+use tokio::{
+    io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+};
+use tokio_rustls::TlsAcceptor;
+
+use crate::{
+    access::AccessConfig,
+    callback_tls,
+    digest::escape,
+    history::{History, PendingAuth},
+    http::{self, HttpConfig},
+    notify::{self, Event, Notification},
+    token_store::{serialize_secret, Expiring, TokenStore, TokenStoreGuard},
+    AuthPending, Config,
+};
+
+// Beyond this a pending login is abandoned and a fresh login link is generated
+static PENDING_AUTH_MAX_AGE_HOURS: i64 = 24;
+
+// A redirect's request line & headers, with a long code, are well under this
+static REQUEST_MAX_BYTES: usize = 16 * 1024;
+static REQUEST_TIMEOUT_SECS: u64 = 10;
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TokenResponse {
+    #[serde(serialize_with = "serialize_secret")]
+    pub access_token: SecretString,
+    pub expires_in: u32,
+    #[serde(serialize_with = "serialize_secret")]
+    pub refresh_token: SecretString,
+    // Not every provider expires its refresh tokens, e.g. YNAB's last until revoked
+    pub refresh_token_expires_in: Option<u32>,
+}
+
+impl Expiring for TokenResponse {
+    fn expires_at(&self, written_at: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        self.refresh_token_expires_in
+            .map(|expires_in| written_at + Duration::seconds(expires_in as i64))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct OAuthClient {
+    pub provider: String,
+    pub title: String,
+    pub auth_url: String,
+    pub token_url: String,
+    pub client_id: String,
+    pub client_secret: SecretString,
+    // Sent to the token endpoint as basic auth, as e.g. Schwab wants, rather than in the form
+    pub basic_auth: bool,
+    pub redirect_uri: String,
+    pub scope: Option<String>,
+    // The address the redirect_uri's callback listener binds to
+    pub listen_addr: String,
+    // The path the callback listener accepts the redirect on, or any path if it isn't given
+    pub callback_path: Option<String>,
+    pub access: AccessConfig,
+    // Saxo's login link is the Location its authorize endpoint redirects to, rather than the
+    // authorize endpoint itself
+    pub resolve_authorize_redirect: bool,
+    // Whether the callback listener serves HTTPS, see `callback_tls`
+    pub tls: bool,
+    pub http: HttpConfig,
+    // Added to the login link, replacing any of its own, e.g. Open Banking's signed `request`
+    // object. A `state` given here is the login's, since it's signed into the request object too.
+    pub auth_params: Vec<(String, String)>,
+}
+
+impl OAuthClient {
+    async fn get_login_uri(&self, state: &str) -> Result<String> {
+        let mut params = vec![
+            ("response_type", "code"),
+            ("client_id", self.client_id.as_str()),
+            ("state", state),
+            ("redirect_uri", self.redirect_uri.as_str()),
+        ];
+        if let Some(scope) = &self.scope {
+            params.push(("scope", scope.as_str()));
+        }
+        for (name, value) in &self.auth_params {
+            params.retain(|(param, _)| param != name);
+            params.push((name.as_str(), value.as_str()));
+        }
+
+        if !self.resolve_authorize_redirect {
+            let url = reqwest::Url::parse_with_params(&self.auth_url, &params)?;
+            return Ok(url.to_string());
+        }
+
+        let client = http::client_without_redirects(&self.http)?;
+
+        let location = http::send(
+            &client,
+            client
+                .get(&self.auth_url)
+                .header("Content-Type", "application/x-www-form-urlencoded")
+                .query(&params),
+        )
+        .await?
+        .headers()
+        .get("location")
+        .ok_or_else(|| anyhow!("Unable to get Location header"))?
+        .to_str()?
+        .to_owned();
+
+        Ok(location)
+    }
+
+    pub async fn exchange_code(
+        &self,
+        client: &reqwest::Client,
+        code: &str,
+    ) -> Result<TokenResponse> {
+        self.request_token(
+            client,
+            vec![
+                ("grant_type", "authorization_code"),
+                ("code", code),
+                ("redirect_uri", self.redirect_uri.as_str()),
+            ],
+        )
+        .await
+    }
+
+    pub async fn refresh(
+        &self,
+        client: &reqwest::Client,
+        refresh_token: &SecretString,
+    ) -> Result<TokenResponse> {
+        self.request_token(
+            client,
+            vec![
+                ("grant_type", "refresh_token"),
+                ("refresh_token", refresh_token.expose_secret()),
+                ("redirect_uri", self.redirect_uri.as_str()),
+            ],
+        )
+        .await
+    }
+
+    async fn request_token(
+        &self,
+        client: &reqwest::Client,
+        mut params: Vec<(&str, &str)>,
+    ) -> Result<TokenResponse> {
+        let mut request = client.post(&self.token_url);
+
+        // An empty secret's left out, for a client that's authenticated by its TLS certificate,
+        // e.g. Open Banking's `tls_client_auth`
+        let client_secret =
+            Some(self.client_secret.expose_secret()).filter(|secret| !secret.is_empty());
+
+        if self.basic_auth {
+            request = request.basic_auth(&self.client_id, client_secret);
+        } else {
+            params.push(("client_id", self.client_id.as_str()));
+            if let Some(client_secret) = client_secret {
+                params.push(("client_secret", client_secret));
+            }
+        }
+
+        let token = http::send(client, request.form(&params))
+            .await?
+            .error_for_status()?
+            .json::<TokenResponse>()
+            .await?;
+
+        Ok(token)
+    }
+
+    // Without a YNAB config the login is interactive: the login link is printed rather than
+    // notified and the auth code is waited for indefinitely. Otherwise a login that isn't
+    // completed within `AUTH_TIMEOUT_SECS` is left pending, to be resumed by the next run.
+    pub async fn login(
+        &self,
+        client: &reqwest::Client,
+        config_path: &str,
+        token_guard: &TokenStoreGuard,
+        ynab_config: Option<&Config>,
+    ) -> Result<TokenResponse> {
+        let message = format!("Login to {}", self.title);
+
+        let token = self
+            .login_with_message(client, config_path, ynab_config, message)
+            .await?;
+
+        self.save_login(config_path, token_guard, &token)?;
+
+        Ok(token)
+    }
+
+    // Logs in before the current login expires, so it's renewed rather than discovered broken by
+    // a failed run. The token store's only locked once the login's done, so other runs carry on
+    // with the current login while it's waited for.
+    pub async fn renew(
+        &self,
+        client: &reqwest::Client,
+        config_path: &str,
+        token_store: &TokenStore,
+        ynab_config: &Config,
+        expires_at: DateTime<Utc>,
+    ) -> Result<TokenResponse> {
+        let message = format!(
+            "{}'s login expires on {}, log in again to renew it",
+            self.title,
+            expires_at.with_timezone(&Local).format("%Y-%m-%d %H:%M")
+        );
+
+        let token = self
+            .login_with_message(client, config_path, Some(ynab_config), message)
+            .await?;
+
+        self.save_login(config_path, &token_store.lock().await?, &token)?;
+
+        Ok(token)
+    }
+
+    async fn login_with_message(
+        &self,
+        client: &reqwest::Client,
+        config_path: &str,
+        ynab_config: Option<&Config>,
+        message: String,
+    ) -> Result<TokenResponse> {
+        let history = History::open(config_path)?;
+
+        let pending_auth = match pending_login(config_path, &self.provider)? {
+            Some(pending_auth) => {
+                info!("Resuming login started at {}", pending_auth.created_at);
+                pending_auth
+            }
+            None => {
+                let state = match self.auth_params.iter().find(|(name, _)| name == "state") {
+                    Some((_, state)) => state.clone(),
+                    None => new_state(),
+                };
+
+                let login_uri = self.get_login_uri(&state).await?;
+
+                let pending_auth = PendingAuth {
+                    provider: self.provider.clone(),
+                    state,
+                    login_uri,
+                    created_at: Utc::now(),
+                };
+
+                history.set_pending_auth(&pending_auth)?;
+
+                pending_auth
+            }
+        };
+
+        self.log_exposure_hint();
+
+        let tls = match self.tls {
+            true => Some(callback_tls::load_or_generate(
+                config_path,
+                &self.callback_hosts(),
+            )?),
+            false => None,
+        };
+
+        // The browser warns about the self-signed certificate, which is to be trusted only if it's
+        // this one
+        let fingerprint_hint = tls
+            .as_ref()
+            .map(|tls| {
+                format!(
+                    "\nIf the browser warns about the callback's certificate, check its SHA-256 fingerprint is {}",
+                    tls.fingerprint
+                )
+            })
+            .unwrap_or_default();
+
+        let listener = CallbackListener {
+            listen_addr: &self.listen_addr,
+            callback_path: self.callback_path.as_deref(),
+            access: &self.access,
+            tls: tls.as_ref().map(|tls| &tls.acceptor),
+            title: &self.title,
+        };
+
+        let auth_code = match ynab_config {
+            Some(ynab_config) => {
+                let notification = Notification {
+                    event: Event::Login,
+                    title: self.title.clone(),
+                    message: format!("{}{}", message, fingerprint_hint),
+                    url: Some(pending_auth.login_uri),
+                };
+
+                notify::notify(ynab_config, &notification).await;
+
+                let auth_code = wait_for_auth_code(
+                    &listener,
+                    Some(ynab_config.auth_timeout_secs),
+                    &pending_auth.state,
+                )
+                .await?;
+
+                if let (Some(_), Some(url)) = (&auth_code, &notification.url) {
+                    notify::prompt_answered(ynab_config, url).await;
+                }
+
+                auth_code
+            }
+            None => {
+                println!(
+                    "Login to {}: {}{}",
+                    self.title, pending_auth.login_uri, fingerprint_hint
+                );
+
+                wait_for_auth_code(&listener, None, &pending_auth.state).await?
+            }
+        };
+
+        let auth_code = match auth_code {
+            Some(auth_code) => auth_code,
+            None => {
+                return Err(AuthPending {
+                    provider: self.provider.clone(),
+                }
+                .into())
+            }
+        };
+
+        self.exchange_code(client, &auth_code).await
+    }
+
+    fn save_login(
+        &self,
+        config_path: &str,
+        token_guard: &TokenStoreGuard,
+        token: &TokenResponse,
+    ) -> Result<()> {
+        token_guard.write(token)?;
+
+        let history = History::open(config_path)?;
+        history.clear_pending_auth(&self.provider)?;
+        history.set_consent_started_at(&self.provider)
+    }
+
+    // The hosts the callback's certificate is for, the listener's & the redirect_uri's
+    fn callback_hosts(&self) -> Vec<&str> {
+        let mut hosts = vec![];
+
+        if let Some((listen_host, _)) = self.listen_addr.rsplit_once(':') {
+            hosts.push(listen_host);
+        }
+
+        if let Some(redirect_host) = self
+            .redirect_uri
+            .split_once("://")
+            .and_then(|(_, rest)| rest.split(['/', ':']).next())
+            .filter(|host| !host.is_empty() && !hosts.contains(host))
+        {
+            hosts.push(redirect_host);
+        }
+
+        hosts
+    }
+
+    // A listener on localhost can't be reached by the redirect unless it's exposed, e.g. by
+    // Tailscale on the redirect_uri's host
+    fn log_exposure_hint(&self) {
+        let is_loopback = |host: &str| {
+            host == "localhost"
+                || host
+                    .parse::<std::net::IpAddr>()
+                    .is_ok_and(|ip| ip.is_loopback())
+        };
+
+        let listen_host = self
+            .listen_addr
+            .rsplit_once(':')
+            .map_or(self.listen_addr.as_str(), |(host, _)| host);
+        let redirect_host = reqwest::Url::parse(&self.redirect_uri)
+            .ok()
+            .and_then(|url| url.host_str().map(str::to_owned))
+            .unwrap_or_default();
+
+        if is_loopback(listen_host) && !is_loopback(&redirect_host) {
+            info!(
+                "The callback listener is on {}, which {} can't reach unless it's exposed, e.g. with `tailscale serve --bg http://{}` (or `tailscale funnel` to reach it from outside the tailnet)",
+                self.listen_addr,
+                self.redirect_uri,
+                self.listen_addr
+            );
+        }
+    }
+}
+
+// A login that's waiting on its redirect & young enough to be resumed, e.g. by the next run
+pub fn pending_login(config_path: &str, provider: &str) -> Result<Option<PendingAuth>> {
+    Ok(History::open(config_path)?
+        .get_pending_auth(provider)?
+        .filter(|pending_auth| {
+            Utc::now() - pending_auth.created_at < Duration::hours(PENDING_AUTH_MAX_AGE_HOURS)
+        }))
+}
+
+// A login's `state`, which its redirect has to carry back
+pub fn new_state() -> String {
+    rand::thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(32)
+        .map(char::from)
+        .collect()
+}
+
+// The redirect_uri for a callback listener on `listen_addr`, & the only path it accepts the
+// redirect on. It's derived from the listener, so they can't drift apart, unless the callback's
+// served from elsewhere & its redirect_uri is configured, when any path is accepted unless
+// `callback_path` is configured too.
+pub fn callback_redirect(
+    listen_addr: &str,
+    tls: bool,
+    access: &AccessConfig,
+    redirect_uri: Option<&str>,
+    callback_path: Option<&str>,
+) -> (String, Option<String>) {
+    match redirect_uri {
+        Some(redirect_uri) => (redirect_uri.to_owned(), callback_path.map(str::to_owned)),
+        None => {
+            let callback_path = callback_path.unwrap_or("/");
+            (
+                format!(
+                    "{}://{}{}{}",
+                    if tls { "https" } else { "http" },
+                    listen_addr,
+                    access.path_prefix(),
+                    callback_path
+                ),
+                Some(callback_path.to_owned()),
+            )
+        }
+    }
+}
+
+struct CallbackListener<'a> {
+    listen_addr: &'a str,
+    callback_path: Option<&'a str>,
+    access: &'a AccessConfig,
+    tls: Option<&'a TlsAcceptor>,
+    // Shown on the page the browser's left on
+    title: &'a str,
+}
+
+// Some browsers, e.g. Brave & Safari on iOS, upgrade the redirect to HTTPS whatever its scheme,
+// which a plain listener can't decode. With `CALLBACK_TLS = true` the listener serves HTTPS, &
+// redirects a plain request to it; without it an HTTPS request is only logged, as a hint to set it.
+//
+// Anything that can reach the listener could otherwise inject a code, so redirects without the
+// login's state are rejected & the listener keeps waiting for the real one.
+async fn wait_for_auth_code(
+    listener: &CallbackListener<'_>,
+    timeout_secs: Option<u64>,
+    state: &str,
+) -> Result<Option<String>> {
+    info!("Waiting for auth code redirect");
+
+    let tcp_listener = TcpListener::bind(listener.listen_addr).await?;
+
+    let accept = async {
+        loop {
+            let (mut stream, addr) = tcp_listener.accept().await?;
+
+            if !listener.access.allows(addr.ip()) {
+                warn!("Rejected a redirect from {}, which isn't allowed", addr);
+                let _ = respond(&mut stream, "403 Forbidden", "forbidden").await;
+                continue;
+            }
+
+            let result = match (is_tls_handshake(&stream).await, listener.tls) {
+                (Ok(true), Some(acceptor)) => {
+                    match with_request_timeout(acceptor.accept(stream)).await {
+                        Ok(stream) => handle_redirect(stream, listener, state).await,
+                        Err(e) => Err(e),
+                    }
+                }
+                (Ok(true), None) => {
+                    warn!(
+                        "The redirect from {} was over HTTPS, which the browser may have upgraded it to. Set CALLBACK_TLS = true to serve it.",
+                        addr
+                    );
+                    continue;
+                }
+                (Ok(false), Some(_)) => {
+                    match redirect_to_https(stream).await {
+                        Ok(()) => info!("Redirected {} to HTTPS", addr),
+                        Err(e) => warn!("Failed to redirect {} to HTTPS: {:#}", addr, e),
+                    }
+                    continue;
+                }
+                (Ok(false), None) => handle_redirect(stream, listener, state).await,
+                (Err(e), _) => Err(e),
+            };
+
+            match result {
+                Ok(Some(code)) => return Ok::<_, anyhow::Error>(code),
+                Ok(None) => warn!("Rejected a redirect from {} that wasn't the login's", addr),
+                Err(e) => warn!("Failed to handle a redirect from {}: {:#}", addr, e),
+            }
+        }
+    };
+
+    match timeout_secs {
+        Some(timeout_secs) => {
+            match tokio::time::timeout(StdDuration::from_secs(timeout_secs), accept).await {
+                Ok(code) => Ok(Some(code?)),
+                Err(_) => {
+                    info!(
+                        "Timed out after {}s waiting for the login redirect",
+                        timeout_secs
+                    );
+                    Ok(None)
+                }
+            }
+        }
+        None => Ok(Some(accept.await?)),
+    }
+}
+
+async fn with_request_timeout<T, E>(
+    future: impl std::future::Future<Output = std::result::Result<T, E>>,
+) -> Result<T>
+where
+    anyhow::Error: From<E>,
+{
+    tokio::time::timeout(StdDuration::from_secs(REQUEST_TIMEOUT_SECS), future)
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "Timed out after {}s waiting for the request",
+                REQUEST_TIMEOUT_SECS
+            )
+        })?
+        .map_err(anyhow::Error::from)
+}
+
+// A TLS connection starts with a handshake record, 0x16, where an HTTP request starts with its
+// method
+async fn is_tls_handshake(stream: &TcpStream) -> Result<bool> {
+    let mut first = [0; 1];
+    let peeked = with_request_timeout(stream.peek(&mut first)).await?;
+
+    Ok(peeked == 1 && first[0] == 0x16)
+}
+
+// The same URL over HTTPS, on the same host & port, so the code & state are kept
+async fn redirect_to_https(mut stream: TcpStream) -> Result<()> {
+    let buffer = read_request(&mut stream).await?;
+
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut req = httparse::Request::new(&mut headers);
+    req.parse(&buffer)?;
+
+    let path = req
+        .path
+        .ok_or_else(|| anyhow!("Unable to parse the request's path"))?;
+    let host = req
+        .headers
+        .iter()
+        .find(|header| header.name.eq_ignore_ascii_case("host"))
+        .map(|header| std::str::from_utf8(header.value))
+        .transpose()?
+        .ok_or_else(|| anyhow!("The request has no Host"))?;
+
+    write_response(
+        &mut stream,
+        "307 Temporary Redirect",
+        &[("Location", &format!("https://{}{}", host, path))],
+        "text/plain; charset=utf-8",
+        "redirecting to https",
+    )
+    .await
+}
+
+// Up to the end of the request's headers, which can arrive in any number of reads. A browser's
+// speculative connection may never send anything, so it's given up on rather than holding up the
+// listener, as is anything too big to be a redirect.
+async fn read_request<S: AsyncRead + Unpin>(stream: &mut S) -> Result<Vec<u8>> {
+    let mut buffer = vec![];
+
+    let read = async {
+        let mut chunk = [0; 4096];
+
+        while !buffer.windows(4).any(|window| window == b"\r\n\r\n") {
+            if buffer.len() > REQUEST_MAX_BYTES {
+                return Err(anyhow!(
+                    "The request's headers are over {} bytes",
+                    REQUEST_MAX_BYTES
+                ));
+            }
+
+            match stream.read(&mut chunk).await? {
+                0 => return Err(anyhow!("The connection closed before the request was sent")),
+                read => buffer.extend_from_slice(&chunk[..read]),
+            }
+        }
+
+        Ok(())
+    };
+
+    with_request_timeout(read).await?;
+
+    Ok(buffer)
+}
+
+// The redirect's code, or `None` when its path or state isn't the login's
+async fn handle_redirect<S: AsyncRead + AsyncWrite + Unpin>(
+    mut stream: S,
+    listener: &CallbackListener<'_>,
+    state: &str,
+) -> Result<Option<String>> {
+    let buffer = read_request(&mut stream).await?;
+
+    let mut headers = [httparse::EMPTY_HEADER; 64];
+    let mut req = httparse::Request::new(&mut headers);
+    req.parse(&buffer)?;
+
+    let path = req
+        .path
+        .ok_or_else(|| anyhow!("Unable to parse the redirect's path"))?;
+    let url = reqwest::Url::parse(&format!("http://_{}", path))?;
+    let query = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+    };
+
+    let path = listener.access.strip_path_secret(url.path());
+
+    if path.is_none()
+        || listener
+            .callback_path
+            .is_some_and(|callback_path| path != Some(callback_path))
+    {
+        respond(&mut stream, "404 Not Found", "not found").await?;
+        return Ok(None);
+    }
+
+    // A hybrid flow's, e.g. Open Banking's, carries the code in the fragment, which the browser
+    // doesn't send, so the page sends it again as the query
+    if url.query().is_none() {
+        write_response(
+            &mut stream,
+            "200 OK",
+            &[],
+            "text/html; charset=utf-8",
+            FRAGMENT_PAGE,
+        )
+        .await?;
+        return Ok(None);
+    }
+
+    if query("state").as_deref() != Some(state) {
+        respond(&mut stream, "400 Bad Request", "invalid state").await?;
+        return Ok(None);
+    }
+
+    // The provider's refusal, e.g. `access_denied` when the user cancels the login
+    if let Some(error) = query("error") {
+        respond(&mut stream, "400 Bad Request", "login refused").await?;
+        return Err(anyhow!(
+            "The login was refused with {}{}",
+            error,
+            query("error_description")
+                .map(|description| format!(": {}", description))
+                .unwrap_or_default()
+        ));
+    }
+
+    let code = match query("code") {
+        Some(code) => code,
+        None => {
+            respond(&mut stream, "400 Bad Request", "missing code").await?;
+            return Err(anyhow!("Unable to parse code from redirect_uri"));
+        }
+    };
+
+    write_response(
+        &mut stream,
+        "200 OK",
+        &[],
+        "text/html; charset=utf-8",
+        &success_page(listener.title),
+    )
+    .await?;
+
+    Ok(Some(code))
+}
+
+static FRAGMENT_PAGE: &str = r#"<!DOCTYPE html>
+<html>
+<head><meta charset="utf-8"><title>Logging in</title></head>
+<body>
+<p>Logging in…</p>
+<script>
+if (location.hash.length > 1) location.replace(location.pathname + "?" + location.hash.slice(1))
+</script>
+</body>
+</html>
+"#;
+
+// Closes itself where the browser allows it, i.e. when the tab was opened by the notification
+fn success_page(title: &str) -> String {
+    format!(
+        r#"<!DOCTYPE html>
+<html>
+<head>
+<meta charset="utf-8">
+<meta name="viewport" content="width=device-width, initial-scale=1">
+<title>Logged in to {title}</title>
+</head>
+<body>
+<p>Logged in to {title}, this tab can be closed.</p>
+<script>window.close()</script>
+</body>
+</html>
+"#,
+        title = escape(title)
+    )
+}
+
+async fn respond<S: AsyncWrite + Unpin>(stream: &mut S, status: &str, body: &str) -> Result<()> {
+    write_response(stream, status, &[], "text/plain; charset=utf-8", body).await
+}
+
+async fn write_response<S: AsyncWrite + Unpin>(
+    stream: &mut S,
+    status: &str,
+    headers: &[(&str, &str)],
+    content_type: &str,
+    body: &str,
+) -> Result<()> {
+    let headers = headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}\r\n", name, value))
+        .collect::<String>();
+
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {}\r\n{}Content-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+                status,
+                headers,
+                content_type,
+                body.len(),
+                body
+            )
+            .as_bytes(),
+        )
+        .await?;
+    // Over TLS, this sends its close_notify, without which a client sees the response as truncated
+    stream.shutdown().await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream};
+
+    static STATE: &str = "the-state";
+
+    fn listener<'a>(access: &'a AccessConfig, listen_addr: &'a str) -> CallbackListener<'a> {
+        CallbackListener {
+            listen_addr,
+            callback_path: Some("/callback"),
+            access,
+            tls: None,
+            title: "Test",
+        }
+    }
+
+    async fn response(mut client: DuplexStream) -> String {
+        let mut response = String::new();
+        client.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    // The redirect's result & what the browser's sent back
+    async fn redirect(path: &str) -> (Result<Option<String>>, String) {
+        let (mut client, server) = duplex(64 * 1024);
+        client
+            .write_all(format!("GET {} HTTP/1.1\r\nHost: localhost\r\n\r\n", path).as_bytes())
+            .await
+            .unwrap();
+
+        let result = handle_redirect(server, &listener(&AccessConfig::default(), ""), STATE).await;
+
+        (result, response(client).await)
+    }
+
+    // A free port, which the listener's bound to before its client connects
+    fn listen_addr() -> String {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("127.0.0.1:{}", listener.local_addr().unwrap().port())
+    }
+
+    async fn send(listen_addr: &str, request: &str) -> String {
+        let mut stream = loop {
+            match TcpStream::connect(listen_addr).await {
+                Ok(stream) => break stream,
+                Err(_) => tokio::time::sleep(StdDuration::from_millis(10)).await,
+            }
+        };
+
+        stream.write_all(request.as_bytes()).await.unwrap();
+
+        let mut response = String::new();
+        stream.read_to_string(&mut response).await.unwrap();
+        response
+    }
+
+    #[tokio::test]
+    async fn reads_a_request_sent_over_several_reads() {
+        let (mut client, mut server) = duplex(64 * 1024);
+
+        let request = "GET /callback?code=abc&state=the-state HTTP/1.1\r\nHost: localhost\r\n\r\n";
+        let send = async {
+            for chunk in request.as_bytes().chunks(7) {
+                client.write_all(chunk).await.unwrap();
+                tokio::task::yield_now().await;
+            }
+        };
+
+        let (buffer, ()) = tokio::join!(read_request(&mut server), send);
+
+        assert_eq!(buffer.unwrap(), request.as_bytes());
+    }
+
+    #[tokio::test]
+    async fn rejects_headers_over_the_cap() {
+        let (mut client, mut server) = duplex(64 * 1024);
+
+        client
+            .write_all(
+                format!(
+                    "GET /callback HTTP/1.1\r\nCookie: {}\r\n",
+                    "a".repeat(REQUEST_MAX_BYTES)
+                )
+                .as_bytes(),
+            )
+            .await
+            .unwrap();
+
+        let e = read_request(&mut server).await.unwrap_err();
+
+        assert!(e.to_string().contains("over 16384 bytes"), "{:#}", e);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn gives_up_on_a_request_that_isnt_sent() {
+        let (mut client, mut server) = duplex(64 * 1024);
+
+        client
+            .write_all(b"GET /callback HTTP/1.1\r\n")
+            .await
+            .unwrap();
+
+        let started = tokio::time::Instant::now();
+        let e = read_request(&mut server).await.unwrap_err();
+
+        assert!(e.to_string().contains("Timed out after 10s"), "{:#}", e);
+        assert_eq!(
+            started.elapsed(),
+            StdDuration::from_secs(REQUEST_TIMEOUT_SECS)
+        );
+    }
+
+    #[tokio::test]
+    async fn takes_the_code_from_the_logins_redirect() {
+        let (result, response) = redirect("/callback?code=abc&state=the-state").await;
+
+        assert_eq!(result.unwrap().as_deref(), Some("abc"));
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+        assert!(response.contains("Logged in to Test"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn rejects_a_redirect_without_the_logins_state() {
+        for path in [
+            "/callback?code=abc&state=another-state",
+            "/callback?code=abc",
+        ] {
+            let (result, response) = redirect(path).await;
+
+            assert!(result.unwrap().is_none(), "{}", path);
+            assert!(
+                response.starts_with("HTTP/1.1 400 Bad Request"),
+                "{}: {}",
+                path,
+                response
+            );
+            assert!(
+                response.ends_with("invalid state"),
+                "{}: {}",
+                path,
+                response
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn fails_a_redirect_without_a_code() {
+        let (result, response) = redirect("/callback?state=the-state").await;
+
+        assert!(result.is_err());
+        assert!(
+            response.starts_with("HTTP/1.1 400 Bad Request"),
+            "{}",
+            response
+        );
+        assert!(response.ends_with("missing code"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn fails_a_refused_login() {
+        let (result, response) =
+            redirect("/callback?error=access_denied&error_description=Cancelled&state=the-state")
+                .await;
+
+        assert_eq!(
+            result.unwrap_err().to_string(),
+            "The login was refused with access_denied: Cancelled"
+        );
+        assert!(
+            response.starts_with("HTTP/1.1 400 Bad Request"),
+            "{}",
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn ignores_another_path() {
+        let (result, response) = redirect("/favicon.ico?code=abc&state=the-state").await;
+
+        assert!(result.unwrap().is_none());
+        assert!(
+            response.starts_with("HTTP/1.1 404 Not Found"),
+            "{}",
+            response
+        );
+    }
+
+    #[tokio::test]
+    async fn asks_for_a_fragments_code_as_the_query() {
+        let (result, response) = redirect("/callback").await;
+
+        assert!(result.unwrap().is_none());
+        assert!(response.contains("location.hash"), "{}", response);
+    }
+
+    #[tokio::test]
+    async fn keeps_waiting_after_a_redirect_with_the_wrong_state() {
+        let access = AccessConfig::default();
+        let listen_addr = listen_addr();
+        let listener = listener(&access, &listen_addr);
+
+        let browser = async {
+            let rejected = send(
+                &listen_addr,
+                "GET /callback?code=injected&state=another-state HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            )
+            .await;
+            let accepted = send(
+                &listen_addr,
+                "GET /callback?code=abc&state=the-state HTTP/1.1\r\nHost: localhost\r\n\r\n",
+            )
+            .await;
+            (rejected, accepted)
+        };
+
+        let (code, (rejected, accepted)) =
+            tokio::join!(wait_for_auth_code(&listener, None, STATE), browser);
+
+        assert_eq!(code.unwrap().as_deref(), Some("abc"));
+        assert!(
+            rejected.starts_with("HTTP/1.1 400 Bad Request"),
+            "{}",
+            rejected
+        );
+        assert!(accepted.starts_with("HTTP/1.1 200 OK"), "{}", accepted);
+    }
+
+    #[tokio::test]
+    async fn redirects_plain_http_to_the_tls_listener() {
+        let config_path =
+            std::env::temp_dir().join(format!("ynab-updater-oauth-{}", std::process::id()));
+        std::fs::create_dir_all(&config_path).unwrap();
+        let tls =
+            callback_tls::load_or_generate(config_path.to_str().unwrap(), &["localhost"]).unwrap();
+
+        let access = AccessConfig::default();
+        let listen_addr = listen_addr();
+        let listener = CallbackListener {
+            tls: Some(&tls.acceptor),
+            ..listener(&access, &listen_addr)
+        };
+
+        let request = format!(
+            "GET /callback?code=abc&state=the-state HTTP/1.1\r\nHost: {}\r\n\r\n",
+            listen_addr
+        );
+
+        let response = tokio::select! {
+            code = wait_for_auth_code(&listener, None, STATE) => panic!("Got {:?}", code),
+            response = send(&listen_addr, &request) => response,
+        };
+
+        assert!(
+            response.starts_with("HTTP/1.1 307 Temporary Redirect"),
+            "{}",
+            response
+        );
+        assert!(
+            response.contains(&format!(
+                "Location: https://{}/callback?code=abc&state=the-state\r\n",
+                listen_addr
+            )),
+            "{}",
+            response
+        );
+
+        std::fs::remove_dir_all(&config_path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn accepts_a_redirect_over_tls() {
+        use std::sync::Arc;
+        use tokio_rustls::{
+            rustls::{
+                crypto::ring,
+                pki_types::{pem::PemObject, CertificateDer, ServerName},
+                ClientConfig, RootCertStore,
+            },
+            TlsConnector,
+        };
+
+        let config_path =
+            std::env::temp_dir().join(format!("ynab-updater-oauth-tls-{}", std::process::id()));
+        std::fs::create_dir_all(&config_path).unwrap();
+        let tls =
+            callback_tls::load_or_generate(config_path.to_str().unwrap(), &["localhost"]).unwrap();
+
+        // A browser that's accepted the self-signed certificate
+        let mut roots = RootCertStore::empty();
+        roots
+            .add(CertificateDer::from_pem_file(config_path.join("callback-cert.pem")).unwrap())
+            .unwrap();
+        let client_config = ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .unwrap()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+
+        let access = AccessConfig::default();
+        let listen_addr = listen_addr();
+        let listener = CallbackListener {
+            tls: Some(&tls.acceptor),
+            ..listener(&access, &listen_addr)
+        };
+
+        let send = async {
+            let stream = loop {
+                match TcpStream::connect(&listen_addr).await {
+                    Ok(stream) => break stream,
+                    Err(_) => tokio::time::sleep(StdDuration::from_millis(10)).await,
+                }
+            };
+            let mut stream = TlsConnector::from(Arc::new(client_config))
+                .connect(ServerName::try_from("localhost").unwrap(), stream)
+                .await
+                .unwrap();
+
+            stream
+                .write_all(
+                    b"GET /callback?code=abc&state=the-state HTTP/1.1\r\nHost: localhost\r\n\r\n",
+                )
+                .await
+                .unwrap();
+
+            let mut response = String::new();
+            stream.read_to_string(&mut response).await.unwrap();
+            response
+        };
+
+        let (code, response) = tokio::join!(wait_for_auth_code(&listener, None, STATE), send);
+
+        assert_eq!(code.unwrap(), Some("abc".to_owned()));
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{}", response);
+
+        std::fs::remove_dir_all(&config_path).unwrap();
+    }
+}
@@ -0,0 +1,162 @@
+use anyhow::{anyhow, Result};
+use chrono::Utc;
+use log::{info, warn};
+use rand::{distributions::Alphanumeric, Rng};
+use secrecy::SecretString;
+use serde::{Deserialize, Serialize};
+use std::{
+    io::{self, IsTerminal, Write},
+    time::{Duration as StdDuration, Instant},
+};
+
+use crate::{
+    history::{History, PendingOtp},
+    imap_otp,
+    notify::{self, Event, Notification},
+    token_store::{serialize_secret, TokenStore},
+    AccountConfig, AuthPending, Config,
+};
+
+// A code sent by SMS or email is usually only good for a few minutes, so there's no waiting on it
+// for the whole of `AUTH_TIMEOUT_SECS`
+static OTP_TIMEOUT_SECS: u64 = 10 * 60;
+
+static OTP_POLL_SECS: u64 = 2;
+
+// A one-time code handed back through the web UI
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct OtpCode {
+    #[serde(serialize_with = "serialize_secret")]
+    pub code: SecretString,
+}
+
+fn code_store(config_path: &str, account: &str) -> TokenStore {
+    TokenStore::new(config_path, &format!("{}_otp.json", account))
+}
+
+// Called by the web UI once the user has handed the code back
+pub async fn save_code(config_path: &str, account: &str, code: &OtpCode) -> Result<()> {
+    let token_guard = code_store(config_path, account).lock().await?;
+
+    token_guard.write(code)
+}
+
+async fn take_code(config_path: &str, account: &str) -> Result<Option<SecretString>> {
+    let token_guard = code_store(config_path, account).lock().await?;
+
+    let code = token_guard.read::<OtpCode>()?;
+
+    token_guard.clear()?;
+
+    Ok(code.map(|(code, _)| code.code))
+}
+
+// Asks the user for a one-time code the provider's sent them, e.g. by SMS, while its login waits,
+// unless it's read from the email it was sent in with `OTP_EMAIL`. On a terminal it's typed in,
+// otherwise the notifier links to the web UI to enter it. The code's only good for this login, so
+// one that isn't entered in time leaves nothing pending for the next run, which logs in & asks
+// again.
+pub async fn request_code(
+    config: &Config,
+    account: &str,
+    account_config: &AccountConfig,
+    title: &str,
+    prompt: &str,
+) -> Result<SecretString> {
+    if let Some((imap, otp_email)) = imap_otp::otp_email(config, account_config)? {
+        match imap_otp::wait_for_code(imap, otp_email, Utc::now()).await {
+            Ok(Some(code)) => return Ok(code),
+            Ok(None) => warn!("{}'s code wasn't emailed in time, asking for it", title),
+            Err(e) => warn!(
+                "Failed to read {}'s code from email, asking for it: {:#}",
+                title, e
+            ),
+        }
+    }
+
+    if io::stdin().is_terminal() {
+        return read_code(title, prompt).await;
+    }
+
+    let web_ui = config.web_ui.as_ref().ok_or_else(|| {
+        anyhow!(
+            "{} needs a one-time code, which needs WEB_UI to be configured, or running on a terminal",
+            title
+        )
+    })?;
+
+    let history = History::open(&config.config_path)?;
+
+    // A code left from an earlier login is no use to this one
+    take_code(&config.config_path, account).await?;
+
+    let pending_otp = PendingOtp {
+        provider: account.to_owned(),
+        state: rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(32)
+            .map(char::from)
+            .collect::<String>(),
+        title: title.to_owned(),
+        prompt: prompt.to_owned(),
+        created_at: Utc::now(),
+    };
+
+    history.set_pending_otp(&pending_otp)?;
+
+    let notification = Notification {
+        event: Event::Login,
+        title: title.to_owned(),
+        message: prompt.to_owned(),
+        url: Some(format!(
+            "{}{}/otp/{}",
+            web_ui.url.trim_end_matches('/'),
+            web_ui.access.path_prefix(),
+            pending_otp.state
+        )),
+    };
+
+    notify::notify(config, &notification).await;
+
+    info!("Waiting for {}'s one-time code from the web UI", title);
+
+    let deadline =
+        Instant::now() + StdDuration::from_secs(config.auth_timeout_secs.min(OTP_TIMEOUT_SECS));
+
+    loop {
+        if let Some(code) = take_code(&config.config_path, account).await? {
+            history.clear_pending_otp(account)?;
+            if let Some(url) = &notification.url {
+                notify::prompt_answered(config, url).await;
+            }
+            return Ok(code);
+        }
+
+        if Instant::now() >= deadline {
+            history.clear_pending_otp(account)?;
+
+            return Err(AuthPending {
+                provider: account.to_owned(),
+            }
+            .into());
+        }
+
+        tokio::time::sleep(StdDuration::from_secs(OTP_POLL_SECS)).await;
+    }
+}
+
+async fn read_code(title: &str, prompt: &str) -> Result<SecretString> {
+    print!("{}\n{}'s code: ", prompt, title);
+    io::stdout().flush()?;
+
+    let code = tokio::task::spawn_blocking(|| {
+        let mut code = String::new();
+        io::stdin().read_line(&mut code).map(|_| code)
+    })
+    .await??;
+
+    match code.trim() {
+        "" => Err(anyhow!("No code was entered for {}", title)),
+        code => Ok(code.to_owned().into()),
+    }
+}
@@ -0,0 +1,137 @@
+// The config directories hold the YNAB token, provider logins & every balance, so they're checked
+// to be only the current user's on startup, & anything secret is created that way rather than with
+// the umask's usual world readable mode. Only checked on Unix, where the modes mean something.
+
+use anyhow::{anyhow, Result};
+use log::warn;
+use serde::Deserialize;
+use std::{fs, io, path::Path};
+
+use crate::error::ConfigInvalid;
+
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum FilePermissions {
+    // Fails to start with any that aren't
+    #[default]
+    Refuse,
+    Warn,
+}
+
+// The files in a config directory that are secret: its settings & their backups, the history,
+// the token & session caches, & the callback's private key
+fn is_secret(filename: &str) -> bool {
+    filename == crate::CONFIG_FILENAME
+        || filename.starts_with("history.db")
+        || filename == "callback-key.pem"
+        || filename.ends_with(".json")
+        || filename.ends_with(".bak")
+}
+
+#[cfg(unix)]
+fn problem(path: &Path, private_mode: u32) -> Result<Option<String>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = fs::metadata(path)?;
+    // SAFETY: getuid can't fail
+    let uid = unsafe { libc::getuid() };
+
+    if metadata.uid() != uid {
+        return Ok(Some(format!(
+            "{} is owned by uid {}, not the current user's {}",
+            path.display(),
+            metadata.uid(),
+            uid
+        )));
+    }
+
+    let mode = metadata.mode() & 0o777;
+    if mode & 0o077 != 0 {
+        return Ok(Some(format!(
+            "{} is mode {:04o}, rather than {:04o} (chmod {:o} {})",
+            path.display(),
+            mode,
+            private_mode,
+            private_mode,
+            path.display()
+        )));
+    }
+
+    Ok(None)
+}
+
+#[cfg(not(unix))]
+fn problem(_path: &Path, _private_mode: u32) -> Result<Option<String>> {
+    Ok(None)
+}
+
+// Each of the directories & their secret files that others could read or the current user doesn't
+// own, refused or warned about as FILE_PERMISSIONS says
+pub fn check<'a>(dirs: impl IntoIterator<Item = &'a str>, policy: FilePermissions) -> Result<()> {
+    let mut problems = vec![];
+
+    for dir in dirs {
+        problems.extend(problem(Path::new(dir), 0o700)?);
+
+        for entry in fs::read_dir(dir)? {
+            let entry = entry?;
+
+            if entry.file_type()?.is_file() && is_secret(&entry.file_name().to_string_lossy()) {
+                problems.extend(problem(&entry.path(), 0o600)?);
+            }
+        }
+    }
+
+    if problems.is_empty() {
+        return Ok(());
+    }
+
+    match policy {
+        FilePermissions::Refuse => Err(anyhow!(
+            "Others could read the config's secrets, set FILE_PERMISSIONS = \"warn\" to run anyway:\n{}",
+            problems.join("\n")
+        )
+        .context(ConfigInvalid)),
+        FilePermissions::Warn => {
+            for problem in problems {
+                warn!("{}", problem);
+            }
+            Ok(())
+        }
+    }
+}
+
+// Written, or replaced, with only the current user able to read it
+pub fn write_private(path: impl AsRef<Path>, contents: impl AsRef<[u8]>) -> io::Result<()> {
+    use std::io::Write;
+
+    create_private(path.as_ref())?.write_all(contents.as_ref())
+}
+
+// Created, or truncated, with only the current user able to read it. An existing file's mode isn't
+// changed, which is what `check` is for.
+pub fn create_private(path: &Path) -> io::Result<fs::File> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    options.open(path)
+}
+
+pub fn create_private_dir(path: &str) -> io::Result<()> {
+    let mut builder = fs::DirBuilder::new();
+    builder.recursive(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::DirBuilderExt;
+        builder.mode(0o700);
+    }
+
+    builder.create(path)
+}
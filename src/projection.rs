@@ -0,0 +1,48 @@
+use chrono::NaiveDate;
+use serde::Deserialize;
+
+use crate::{currency, ynab::CurrencyFormat};
+
+// A pension's value projected to retirement, growing at a fixed rate with a fixed contribution
+// each month, e.g. `PROJECTION = { ANNUAL_GROWTH_PERCENT = 5, MONTHLY_CONTRIBUTION = 400,
+// TARGET_DATE = "2055-06-01" }`. It's only an illustration, but kept in each reconciliation's memo
+// it's a record of how the projection has moved along with the value.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct ProjectionConfig {
+    pub annual_growth_percent: f32,
+    #[serde(default)]
+    pub monthly_contribution: f32,
+    pub target_date: NaiveDate,
+}
+
+// The value at the target date, compounded monthly, with contributions at the end of each month
+pub fn project(value: f32, today: NaiveDate, config: &ProjectionConfig) -> f32 {
+    let months = ((config.target_date - today).num_days().max(0) as f64) / (365.25 / 12.0);
+
+    let rate = (1.0 + config.annual_growth_percent as f64 / 100.0).powf(1.0 / 12.0) - 1.0;
+    let growth = (1.0 + rate).powf(months);
+
+    let contributions = if rate == 0.0 {
+        config.monthly_contribution as f64 * months
+    } else {
+        config.monthly_contribution as f64 * (growth - 1.0) / rate
+    };
+
+    (value as f64 * growth + contributions) as f32
+}
+
+pub fn memo(
+    value: f32,
+    today: NaiveDate,
+    config: &ProjectionConfig,
+    currency: Option<&CurrencyFormat>,
+) -> String {
+    format!(
+        "Projected {} at {} ({}% a year, {} a month)",
+        currency::format(currency, project(value, today, config)),
+        config.target_date,
+        config.annual_growth_percent,
+        currency::format(currency, config.monthly_contribution)
+    )
+}
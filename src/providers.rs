@@ -0,0 +1,510 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, NaiveDate, Utc};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+use tracing::{field, Instrument};
+
+#[cfg(any(feature = "saxo", feature = "property"))]
+use crate::backfill::GetHistory;
+#[cfg(feature = "saxo")]
+use crate::{contributions::GetCashflows, income::GetIncome};
+use crate::{
+    history::History, income::Income, logging, report::RunReport, update_ynab, AccountConfig,
+    Config,
+};
+
+#[cfg(feature = "amex")]
+pub mod amex;
+#[cfg(feature = "degiro")]
+pub mod degiro;
+#[cfg(feature = "exec")]
+pub mod exec;
+#[cfg(feature = "form")]
+pub mod form;
+#[cfg(feature = "halifax")]
+pub mod halifax;
+#[cfg(feature = "hl")]
+pub mod hl;
+pub mod mock;
+#[cfg(feature = "open_banking")]
+pub mod open_banking;
+#[cfg(feature = "property")]
+pub mod property;
+pub mod push;
+#[cfg(feature = "saxo")]
+pub mod saxo;
+#[cfg(feature = "schwab")]
+pub mod schwab;
+#[cfg(feature = "simplefin")]
+pub mod simplefin;
+#[cfg(feature = "starling")]
+pub mod starling;
+#[cfg(feature = "vehicle")]
+pub mod vehicle;
+#[cfg(feature = "wasm")]
+pub mod wasm;
+
+#[cfg(feature = "amex")]
+use amex::Amex;
+#[cfg(feature = "degiro")]
+use degiro::Degiro;
+#[cfg(feature = "exec")]
+use exec::Exec;
+#[cfg(feature = "form")]
+use form::Form;
+#[cfg(feature = "halifax")]
+use halifax::Halifax;
+#[cfg(feature = "hl")]
+use hl::Hl;
+use mock::Mock;
+#[cfg(feature = "open_banking")]
+use open_banking::OpenBanking;
+#[cfg(feature = "property")]
+use property::Property;
+use push::Push;
+#[cfg(feature = "saxo")]
+use saxo::Saxo;
+#[cfg(feature = "schwab")]
+use schwab::Schwab;
+#[cfg(feature = "simplefin")]
+use simplefin::SimpleFin;
+#[cfg(feature = "starling")]
+use starling::Starling;
+#[cfg(feature = "vehicle")]
+use vehicle::Vehicle;
+#[cfg(feature = "wasm")]
+use wasm::Wasm;
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ProviderKind {
+    Hl,
+    Saxo,
+    Starling,
+    // A bank with a simple form login, described by a preset
+    Form,
+    Vehicle,
+    Property,
+    // Any of a SimpleFIN bridge's accounts
+    #[serde(rename = "simplefin")]
+    SimpleFin,
+    // A command the balance is read from, for institutions that aren't supported
+    Exec,
+    // A sandboxed plugin, with the `wasm` feature
+    Wasm,
+    // A balance POSTed to the web UI
+    Push,
+    Schwab,
+    Degiro,
+    Amex,
+    // A mortgage, or another account, under a Halifax online banking login
+    Halifax,
+    // A UK bank's account through Open Banking, with its bank a preset
+    OpenBanking,
+    Mock,
+}
+
+impl ProviderKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ProviderKind::Hl => "hl",
+            ProviderKind::Saxo => "saxo",
+            ProviderKind::Starling => "starling",
+            ProviderKind::Form => "form",
+            ProviderKind::Vehicle => "vehicle",
+            ProviderKind::Property => "property",
+            ProviderKind::SimpleFin => "simplefin",
+            ProviderKind::Exec => "exec",
+            ProviderKind::Wasm => "wasm",
+            ProviderKind::Push => "push",
+            ProviderKind::Schwab => "schwab",
+            ProviderKind::Degiro => "degiro",
+            ProviderKind::Amex => "amex",
+            ProviderKind::Halifax => "halifax",
+            ProviderKind::OpenBanking => "open_banking",
+            ProviderKind::Mock => "mock",
+        }
+    }
+}
+
+// An account whose provider's feature the updater was built without, see Cargo.toml's `full`
+fn not_compiled(account: &str, provider: ProviderKind) -> anyhow::Error {
+    anyhow!(
+        "{} is a {} account, but the updater was built without the {} feature",
+        account,
+        provider.name(),
+        provider.name()
+    )
+}
+
+pub async fn update_account(
+    config: &Config,
+    account: &str,
+    account_config: &AccountConfig,
+) -> Result<RunReport> {
+    let run_id = logging::new_run_id();
+
+    // Every line logged during the run carries these, with the amount once it's fetched
+    let span = tracing::info_span!(
+        "run",
+        provider = account_config.provider.name(),
+        account,
+        amount = field::Empty,
+        run_id = run_id.as_str()
+    );
+
+    logging::with_run_id(
+        run_id.clone(),
+        _update_account(config, account, account_config).instrument(span),
+    )
+    .await
+}
+
+async fn _update_account(
+    config: &Config,
+    account: &str,
+    account_config: &AccountConfig,
+) -> Result<RunReport> {
+    match account_config.provider {
+        #[cfg(feature = "hl")]
+        ProviderKind::Hl => {
+            update_ynab(config, account, Hl::new(config, account, account_config)?).await
+        }
+        #[cfg(feature = "saxo")]
+        ProviderKind::Saxo => {
+            update_ynab(config, account, Saxo::new(config, account, account_config)?).await
+        }
+        #[cfg(feature = "starling")]
+        ProviderKind::Starling => {
+            update_ynab(
+                config,
+                account,
+                Starling::new(config, account, account_config)?,
+            )
+            .await
+        }
+        #[cfg(feature = "form")]
+        ProviderKind::Form => {
+            update_ynab(config, account, Form::new(config, account, account_config)?).await
+        }
+        #[cfg(feature = "vehicle")]
+        ProviderKind::Vehicle => {
+            update_ynab(
+                config,
+                account,
+                Vehicle::new(config, account, account_config)?,
+            )
+            .await
+        }
+        #[cfg(feature = "property")]
+        ProviderKind::Property => {
+            update_ynab(
+                config,
+                account,
+                Property::new(config, account, account_config)?,
+            )
+            .await
+        }
+        #[cfg(feature = "simplefin")]
+        ProviderKind::SimpleFin => {
+            update_ynab(
+                config,
+                account,
+                SimpleFin::new(config, account, account_config)?,
+            )
+            .await
+        }
+        #[cfg(feature = "exec")]
+        ProviderKind::Exec => {
+            update_ynab(config, account, Exec::new(config, account, account_config)?).await
+        }
+        #[cfg(feature = "wasm")]
+        ProviderKind::Wasm => {
+            update_ynab(config, account, Wasm::new(config, account, account_config)?).await
+        }
+        ProviderKind::Push => {
+            update_ynab(config, account, Push::new(config, account, account_config)?).await
+        }
+        #[cfg(feature = "schwab")]
+        ProviderKind::Schwab => {
+            update_ynab(
+                config,
+                account,
+                Schwab::new(config, account, account_config)?,
+            )
+            .await
+        }
+        #[cfg(feature = "degiro")]
+        ProviderKind::Degiro => {
+            update_ynab(
+                config,
+                account,
+                Degiro::new(config, account, account_config)?,
+            )
+            .await
+        }
+        #[cfg(feature = "amex")]
+        ProviderKind::Amex => {
+            update_ynab(config, account, Amex::new(config, account, account_config)?).await
+        }
+        #[cfg(feature = "halifax")]
+        ProviderKind::Halifax => {
+            update_ynab(
+                config,
+                account,
+                Halifax::new(config, account, account_config)?,
+            )
+            .await
+        }
+        #[cfg(feature = "open_banking")]
+        ProviderKind::OpenBanking => {
+            update_ynab(
+                config,
+                account,
+                OpenBanking::new(config, account, account_config)?,
+            )
+            .await
+        }
+        ProviderKind::Mock => update_ynab(config, account, Mock::new(account_config)?).await,
+        // Any provider left out of the build
+        #[allow(unreachable_patterns)]
+        provider => Err(not_compiled(account, provider)),
+    }
+}
+
+// An account's consent to be read by its provider, which lapses unless it's renewed by logging in
+// again, e.g. Saxo's refresh token or a 90 day Open Banking consent
+#[derive(Clone, Debug)]
+pub struct Consent {
+    // `None` if it was given before consents were recorded
+    pub started_at: Option<DateTime<Utc>>,
+    // `None` if there's no consent, or it lasts until it's revoked
+    pub expires_at: Option<DateTime<Utc>>,
+    // How long before it expires it's renewed
+    pub renewal_window: Duration,
+}
+
+// `None` for providers that don't need consent
+#[cfg_attr(
+    not(any(feature = "open_banking", feature = "saxo", feature = "schwab")),
+    allow(unused_variables, unreachable_code)
+)]
+pub async fn consent(
+    config: &Config,
+    account: &str,
+    account_config: &AccountConfig,
+) -> Result<Option<Consent>> {
+    let (expires_at, renewal_window) = match account_config.provider {
+        #[cfg(feature = "saxo")]
+        ProviderKind::Saxo => (
+            Saxo::new(config, account, account_config)?
+                .consent_expires_at()
+                .await?,
+            Duration::days(config.token_expiry_warning_days),
+        ),
+        #[cfg(feature = "schwab")]
+        ProviderKind::Schwab => (
+            Schwab::new(config, account, account_config)?.consent_expires_at()?,
+            schwab::renewal_window(config),
+        ),
+        #[cfg(feature = "open_banking")]
+        ProviderKind::OpenBanking => (
+            OpenBanking::new(config, account, account_config)?.consent_expires_at()?,
+            open_banking::renewal_window(config),
+        ),
+        ProviderKind::Hl
+        | ProviderKind::Starling
+        | ProviderKind::Form
+        | ProviderKind::Vehicle
+        | ProviderKind::Property
+        | ProviderKind::SimpleFin
+        | ProviderKind::Exec
+        | ProviderKind::Wasm
+        | ProviderKind::Push
+        | ProviderKind::Degiro
+        | ProviderKind::Amex
+        | ProviderKind::Halifax
+        | ProviderKind::Mock => return Ok(None),
+        #[allow(unreachable_patterns)]
+        provider => return Err(not_compiled(account, provider)),
+    };
+
+    let started_at = History::open(&config.config_path)?.get_consent_started_at(account)?;
+
+    Ok(Some(Consent {
+        started_at,
+        expires_at,
+        renewal_window,
+    }))
+}
+
+// Interactively logs in to the account's provider, for those that need it
+#[cfg_attr(
+    not(any(feature = "open_banking", feature = "saxo", feature = "schwab")),
+    allow(unused_variables)
+)]
+pub async fn auth_account(
+    config: &Config,
+    account: &str,
+    account_config: &AccountConfig,
+) -> Result<()> {
+    match account_config.provider {
+        #[cfg(feature = "saxo")]
+        ProviderKind::Saxo => Saxo::new(config, account, account_config)?.auth().await,
+        #[cfg(feature = "schwab")]
+        ProviderKind::Schwab => Schwab::new(config, account, account_config)?.auth().await,
+        #[cfg(feature = "open_banking")]
+        ProviderKind::OpenBanking => {
+            OpenBanking::new(config, account, account_config)?
+                .auth()
+                .await
+        }
+        ProviderKind::Hl
+        | ProviderKind::Starling
+        | ProviderKind::Form
+        | ProviderKind::Vehicle
+        | ProviderKind::Property
+        | ProviderKind::SimpleFin
+        | ProviderKind::Exec
+        | ProviderKind::Wasm
+        | ProviderKind::Push
+        | ProviderKind::Degiro
+        | ProviderKind::Amex
+        | ProviderKind::Halifax
+        | ProviderKind::Mock => Err(anyhow!("{} doesn't need logging in to", account)),
+        #[allow(unreachable_patterns)]
+        provider => Err(not_compiled(account, provider)),
+    }
+}
+
+// The account's past values, for providers that keep them
+#[cfg_attr(
+    not(any(feature = "saxo", feature = "property")),
+    allow(unused_variables)
+)]
+pub async fn history(
+    config: &Config,
+    account: &str,
+    account_config: &AccountConfig,
+    from: NaiveDate,
+) -> Result<BTreeMap<NaiveDate, f32>> {
+    match account_config.provider {
+        #[cfg(feature = "saxo")]
+        ProviderKind::Saxo => {
+            Saxo::new(config, account, account_config)?
+                .get_history(from)
+                .await
+        }
+        #[cfg(feature = "property")]
+        ProviderKind::Property => {
+            Property::new(config, account, account_config)?
+                .get_history(from)
+                .await
+        }
+        ProviderKind::Hl
+        | ProviderKind::Starling
+        | ProviderKind::Form
+        | ProviderKind::Vehicle
+        | ProviderKind::SimpleFin
+        | ProviderKind::Exec
+        | ProviderKind::Wasm
+        | ProviderKind::Push
+        | ProviderKind::Schwab
+        | ProviderKind::Degiro
+        | ProviderKind::Amex
+        | ProviderKind::Halifax
+        | ProviderKind::OpenBanking
+        | ProviderKind::Mock => Err(anyhow!(
+            "{}'s provider {} has no history to backfill from",
+            account,
+            account_config.provider.name()
+        )),
+        #[allow(unreachable_patterns)]
+        provider => Err(not_compiled(account, provider)),
+    }
+}
+
+// What was paid in less what was taken out, for providers that say
+#[cfg_attr(not(feature = "saxo"), allow(unused_variables))]
+pub async fn cashflows(
+    config: &Config,
+    account: &str,
+    account_config: &AccountConfig,
+    from: NaiveDate,
+    until: NaiveDate,
+) -> Result<f32> {
+    match account_config.provider {
+        #[cfg(feature = "saxo")]
+        ProviderKind::Saxo => {
+            Saxo::new(config, account, account_config)?
+                .get_cashflows(from, until)
+                .await
+        }
+        ProviderKind::Hl
+        | ProviderKind::Starling
+        | ProviderKind::Form
+        | ProviderKind::Vehicle
+        | ProviderKind::Property
+        | ProviderKind::SimpleFin
+        | ProviderKind::Exec
+        | ProviderKind::Wasm
+        | ProviderKind::Push
+        | ProviderKind::Schwab
+        | ProviderKind::Degiro
+        | ProviderKind::Amex
+        | ProviderKind::Halifax
+        | ProviderKind::OpenBanking
+        | ProviderKind::Mock => Err(anyhow!(
+            "{} has SPLIT_CONTRIBUTIONS, but its provider {} doesn't give its cash flows",
+            account,
+            account_config.provider.name()
+        )),
+        #[allow(unreachable_patterns)]
+        provider => Err(not_compiled(account, provider)),
+    }
+}
+
+// Dividends & interest paid, for providers that say
+#[cfg_attr(not(any(feature = "saxo", feature = "hl")), allow(unused_variables))]
+pub async fn income(
+    config: &Config,
+    account: &str,
+    account_config: &AccountConfig,
+    from: NaiveDate,
+    until: NaiveDate,
+) -> Result<Vec<Income>> {
+    match account_config.provider {
+        #[cfg(feature = "saxo")]
+        ProviderKind::Saxo => {
+            Saxo::new(config, account, account_config)?
+                .get_income(from, until)
+                .await
+        }
+        #[cfg(feature = "hl")]
+        ProviderKind::Hl => {
+            Hl::new(config, account, account_config)?
+                .get_income(from, until)
+                .await
+        }
+        ProviderKind::Starling
+        | ProviderKind::Form
+        | ProviderKind::Vehicle
+        | ProviderKind::Property
+        | ProviderKind::SimpleFin
+        | ProviderKind::Exec
+        | ProviderKind::Wasm
+        | ProviderKind::Push
+        | ProviderKind::Schwab
+        | ProviderKind::Degiro
+        | ProviderKind::Amex
+        | ProviderKind::Halifax
+        | ProviderKind::OpenBanking
+        | ProviderKind::Mock => Err(anyhow!(
+            "{} has POST_INCOME, but its provider {} doesn't give its dividends & interest",
+            account,
+            account_config.provider.name()
+        )),
+        #[allow(unreachable_patterns)]
+        provider => Err(not_compiled(account, provider)),
+    }
+}
@@ -0,0 +1,513 @@
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{de::DeserializeOwned, Deserialize};
+use serde_json::json;
+
+use crate::{
+    browser::{Browser, BrowserConfig, CaptchaDetected},
+    error::ConfigInvalid,
+    manual_login, otp_relay, spend_login_attempt, AccountConfig, Config, GetBalance,
+    GetYnabAccountConfig, YnabAccountConfig,
+};
+
+static AMEX_LOGIN_PAGE_URL: &str = "https://www.americanexpress.com/en-gb/account/login";
+static AMEX_LOGON_URL: &str = "https://global.americanexpress.com/myca/logon/emea/action/login";
+static AMEX_CHALLENGES_URL: &str =
+    "https://functions.americanexpress.com/ReadAuthenticationChallenges.v3";
+static AMEX_OTP_DELIVERY_URL: &str =
+    "https://functions.americanexpress.com/CreateOneTimePasscodeDelivery.v1";
+static AMEX_OTP_VERIFY_URL: &str =
+    "https://functions.americanexpress.com/UpdateAuthenticationTokenWithChallenge.v3";
+static AMEX_MEMBER_URL: &str = "https://global.americanexpress.com/api/servicing/v1/member";
+static AMEX_BALANCES_URL: &str =
+    "https://global.americanexpress.com/api/servicing/v1/financials/balances";
+static AMEX_TRANSACTIONS_URL: &str =
+    "https://global.americanexpress.com/api/servicing/v1/financials/transactions";
+
+static AMEX_JOURNEY: &str = "aexp.global:create:session";
+static AMEX_LOCALE: &str = "en-GB";
+
+// The login's statuses that aren't success
+const STATUS_OTP_NEEDED: i64 = 1;
+const STATUS_BAD_CREDENTIALS: i64 = 2;
+
+// An Amex UK card, read through the JSON API its website uses, which isn't documented & may change
+// under it. Its balance is what's owed, with the pending charges that are yet to be added to it,
+// as a negative balance. A login from somewhere new is sent a one-time code, which is asked for
+// through `otp_relay`. A login that asks for a captcha, or that's set to with AMEX_MANUAL_LOGIN, is
+// finished by hand through the web UI.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct AmexConfig {
+    pub amex_username: String,
+    pub amex_password: SecretString,
+    // The last 5 digits of the card, for a login with more than one
+    pub amex_card_ending: Option<String>,
+    // "SMS" or "EMAIL", where the one-time code's sent
+    #[serde(default = "default_otp_method")]
+    pub amex_otp_method: String,
+    #[serde(default = "default_include_pending")]
+    pub amex_include_pending: bool,
+    // For when the scripted login's refused, since Amex's own is run by its page's JavaScript
+    #[serde(default)]
+    pub amex_manual_login: bool,
+
+    #[serde(flatten)]
+    pub browser: BrowserConfig,
+}
+
+fn default_otp_method() -> String {
+    "SMS".to_owned()
+}
+
+fn default_include_pending() -> bool {
+    true
+}
+
+#[derive(Clone, Debug)]
+pub struct Amex {
+    account: String,
+    account_config: AccountConfig,
+    config: AmexConfig,
+    ynab_config: Config,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LogonResponse {
+    status_code: i64,
+    error_code: Option<String>,
+    reauth: Option<Reauth>,
+}
+
+// What the one-time code's challenge is looked up with
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Reauth {
+    action_id: String,
+    application_id: Option<String>,
+    mfa_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChallengesResponse {
+    #[serde(default)]
+    challenge_questions: Vec<ChallengeQuestion>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChallengeQuestion {
+    #[serde(default)]
+    challenge_options: Vec<ChallengeOption>,
+}
+
+// Somewhere the code can be sent, with its address encrypted
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct ChallengeOption {
+    #[serde(rename = "type")]
+    kind: String,
+    encrypted_value: String,
+    masked_value: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct OtpDelivery {
+    encrypted_channel_value: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Member {
+    accounts: Vec<Card>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Card {
+    account_token: String,
+    account: CardAccount,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct CardAccount {
+    display_account_number: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Balance {
+    total_balance_amount: f64,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Transactions {
+    #[serde(default)]
+    transactions: Vec<Transaction>,
+}
+
+// A charge is positive & a refund negative
+#[derive(Clone, Debug, Deserialize)]
+struct Transaction {
+    amount: f64,
+}
+
+fn parse<T: DeserializeOwned>(text: &str, what: &str) -> Result<T> {
+    serde_json::from_str(text).with_context(|| format!("Failed to read Amex's {}", what))
+}
+
+// The card ending in `card_ending`, otherwise the login's only one
+fn find_card<'a>(cards: &'a [Card], card_ending: Option<&str>) -> Result<&'a Card> {
+    let endings = || {
+        cards
+            .iter()
+            .map(|card| card.account.display_account_number.as_str())
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    match (card_ending, cards) {
+        (Some(card_ending), _) => cards
+            .iter()
+            .find(|card| card.account.display_account_number.ends_with(card_ending))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Amex has no card ending {}, only {}",
+                    card_ending,
+                    endings()
+                )
+                .context(ConfigInvalid)
+            }),
+        (None, [card]) => Ok(card),
+        (None, []) => Err(anyhow!("Amex's login has no cards")),
+        (None, _) => Err(anyhow!(
+            "Amex's login has cards ending {}, so AMEX_CARD_ENDING has to say which",
+            endings()
+        )
+        .context(ConfigInvalid)),
+    }
+}
+
+impl Amex {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        // It'd be negated twice
+        if account_config.liability {
+            return Err(anyhow!(
+                "{}'s Amex balance is already negative for what's owed, so it can't be a LIABILITY",
+                account
+            )
+            .context(ConfigInvalid));
+        }
+
+        Ok(Amex {
+            account: account.to_owned(),
+            account_config: account_config.clone(),
+            config: account_config.provider_config()?,
+            ynab_config: ynab_config.clone(),
+        })
+    }
+
+    async fn login(&self, browser: &mut Browser) -> Result<()> {
+        spend_login_attempt(&self.ynab_config, &self.account, &self.account_config)?;
+
+        // For the cookies the logon expects
+        browser.get(AMEX_LOGIN_PAGE_URL).await?;
+        browser.pause().await;
+
+        let mut logon = self.post_logon(browser).await?;
+
+        if logon.status_code == STATUS_OTP_NEEDED {
+            let reauth = logon
+                .reauth
+                .ok_or_else(|| anyhow!("Amex asked for a one-time code without its challenge"))?;
+
+            self.verify_otp(browser, &reauth).await?;
+
+            browser.pause().await;
+            logon = self.post_logon(browser).await?;
+        }
+
+        match logon.status_code {
+            0 => Ok(()),
+            STATUS_BAD_CREDENTIALS => Err(anyhow!(
+                "Amex rejected {}'s username or password",
+                self.account
+            )),
+            status => Err(anyhow!(
+                "Amex's login failed with status {} ({})",
+                status,
+                logon.error_code.unwrap_or_default()
+            )),
+        }
+    }
+
+    async fn post_logon(&self, browser: &Browser) -> Result<LogonResponse> {
+        let logon = browser
+            .post_form(
+                AMEX_LOGON_URL,
+                &[
+                    ("request_type", "login"),
+                    ("Face", "en_GB"),
+                    ("Logon", "Logon"),
+                    ("version", "4"),
+                    ("channel", "Web"),
+                    ("REMEMBERME", "off"),
+                    ("DestPage", "https://global.americanexpress.com/dashboard"),
+                    ("UserID", &self.config.amex_username),
+                    ("Password", self.config.amex_password.expose_secret()),
+                ],
+            )
+            .await?;
+
+        serde_json::from_str::<LogonResponse>(&logon).context(
+            "Failed to read Amex's login response, it may need JavaScript, \
+            in which case set AMEX_MANUAL_LOGIN = true to log in by hand",
+        )
+    }
+
+    // Has the code sent by AMEX_OTP_METHOD, asks the user for it & hands it back
+    async fn verify_otp(&self, browser: &Browser, reauth: &Reauth) -> Result<()> {
+        let challenges = browser
+            .post_json(
+                AMEX_CHALLENGES_URL,
+                &json!({
+                    "userJourneyIdentifier": AMEX_JOURNEY,
+                    "assessmentToken": reauth.mfa_id,
+                    "meta": {
+                        "authenticationActionId": reauth.action_id,
+                        "applicationId": reauth.application_id,
+                        "locale": AMEX_LOCALE,
+                    },
+                }),
+            )
+            .await?;
+
+        let challenges = parse::<ChallengesResponse>(&challenges, "one-time code's challenge")?;
+
+        let option = challenges
+            .challenge_questions
+            .iter()
+            .flat_map(|question| &question.challenge_options)
+            .find(|option| {
+                option
+                    .kind
+                    .eq_ignore_ascii_case(&self.config.amex_otp_method)
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "Amex can't send {}'s one-time code by {}",
+                    self.account,
+                    self.config.amex_otp_method
+                )
+                .context(ConfigInvalid)
+            })?;
+
+        let delivery = browser
+            .post_json(
+                AMEX_OTP_DELIVERY_URL,
+                &json!({
+                    "userJourneyIdentifier": AMEX_JOURNEY,
+                    "otpDeliveryRequest": {
+                        "deliveryMethod": option.kind,
+                        "encryptedValue": option.encrypted_value,
+                    },
+                    "locale": AMEX_LOCALE,
+                }),
+            )
+            .await?;
+
+        let delivery = parse::<OtpDelivery>(&delivery, "one-time code's delivery")?;
+
+        info!("Amex sent {}'s one-time code", self.account);
+
+        let code = otp_relay::request_code(
+            &self.ynab_config,
+            &self.account,
+            &self.account_config,
+            "Amex",
+            &format!(
+                "Enter the code Amex sent to {} to log in",
+                option.masked_value.as_deref().unwrap_or("you")
+            ),
+        )
+        .await?;
+
+        browser
+            .post_json(
+                AMEX_OTP_VERIFY_URL,
+                &json!({
+                    "userJourneyIdentifier": AMEX_JOURNEY,
+                    "assessmentToken": reauth.mfa_id,
+                    "challengeAnswers": [{
+                        "type": "OTP",
+                        "encryptedValue": delivery.encrypted_channel_value,
+                        "value": code.expose_secret(),
+                    }],
+                }),
+            )
+            .await
+            .context("Amex refused the one-time code")?;
+
+        Ok(())
+    }
+
+    // Takes the session of a login finished by hand in the web UI
+    async fn manual_login(&self, browser: &Browser) -> Result<()> {
+        let cookies = manual_login::request_session(
+            &self.ynab_config,
+            &self.account,
+            "Amex",
+            AMEX_LOGIN_PAGE_URL,
+        )
+        .await?;
+
+        browser.add_cookies(AMEX_MEMBER_URL, &cookies)
+    }
+
+    // What's owed on the card, with its pending charges unless AMEX_INCLUDE_PENDING is false
+    async fn get_owed(&self, browser: &Browser) -> Result<f64> {
+        let member = parse::<Member>(&browser.get_json(AMEX_MEMBER_URL, &[]).await?, "cards")?;
+
+        let card = find_card(&member.accounts, self.config.amex_card_ending.as_deref())?;
+        let headers = [("account_token", card.account_token.as_str())];
+
+        browser.pause().await;
+
+        let balances = browser
+            .get_json(
+                &format!(
+                    "{}?extended_details=deferred,non_deferred,pay_in_full,pay_over_time,early_pay",
+                    AMEX_BALANCES_URL
+                ),
+                &headers,
+            )
+            .await?;
+
+        let balance = parse::<Vec<Balance>>(&balances, "balance")?
+            .first()
+            .map(|balance| balance.total_balance_amount)
+            .ok_or_else(|| anyhow!("Amex has no balance for {}", self.account))?;
+
+        if !self.config.amex_include_pending {
+            return Ok(balance);
+        }
+
+        browser.pause().await;
+
+        let pending = browser
+            .get_json(
+                &format!("{}?limit=1000&status=pending", AMEX_TRANSACTIONS_URL),
+                &headers,
+            )
+            .await?;
+
+        let pending = parse::<Transactions>(&pending, "pending charges")?
+            .transactions
+            .iter()
+            .map(|transaction| transaction.amount)
+            .sum::<f64>();
+
+        info!(
+            "Amex's balance is {:.2} with {:.2} of pending charges",
+            balance, pending
+        );
+
+        Ok(balance + pending)
+    }
+}
+
+impl GetYnabAccountConfig for Amex {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.account_config.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for Amex {
+    async fn get(&self) -> Result<f32> {
+        // A session handed back after the previous run's manual login timed out
+        if let Some(cookies) = manual_login::take_session(&self.ynab_config, &self.account).await? {
+            let browser = Browser::new(&self.config.browser, &self.ynab_config.http)?;
+            browser.add_cookies(AMEX_MEMBER_URL, &cookies)?;
+
+            match self.get_owed(&browser).await {
+                Ok(owed) => return Ok(-owed as f32),
+                Err(e) => info!(
+                    "The session from the manual login has expired, logging in: {:#}",
+                    e
+                ),
+            }
+        }
+
+        let mut browser = Browser::new(&self.config.browser, &self.ynab_config.http)?;
+
+        match self.config.amex_manual_login {
+            true => self.manual_login(&browser).await?,
+            false => match self.login(&mut browser).await {
+                Err(e) if e.downcast_ref::<CaptchaDetected>().is_some() => {
+                    info!("Captcha detected, falling back to a manual login");
+                    self.manual_login(&browser).await?
+                }
+                login => login?,
+            },
+        }
+
+        browser.pause().await;
+
+        Ok(-self.get_owed(&browser).await? as f32)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cards() -> Vec<Card> {
+        parse::<Member>(
+            r#"{
+                "accounts": [
+                    { "account_token": "A", "account": { "display_account_number": "XXXX-XXXXXX-51002" } },
+                    { "account_token": "B", "account": { "display_account_number": "XXXX-XXXXXX-71004" } }
+                ]
+            }"#,
+            "cards",
+        )
+        .unwrap()
+        .accounts
+    }
+
+    #[test]
+    fn finds_the_card_by_its_ending() {
+        assert_eq!(
+            find_card(&cards(), Some("71004")).unwrap().account_token,
+            "B"
+        );
+    }
+
+    #[test]
+    fn needs_the_ending_of_one_of_several_cards() {
+        let e = find_card(&cards(), None).unwrap_err();
+
+        assert!(e.downcast_ref::<ConfigInvalid>().is_some());
+        assert!(format!("{:#}", e).contains("51002"));
+    }
+
+    #[test]
+    fn takes_the_only_card() {
+        assert_eq!(find_card(&cards()[..1], None).unwrap().account_token, "A");
+    }
+
+    #[test]
+    fn an_unknown_ending_is_invalid() {
+        assert!(find_card(&cards(), Some("00000"))
+            .unwrap_err()
+            .downcast_ref::<ConfigInvalid>()
+            .is_some());
+    }
+}
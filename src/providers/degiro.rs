@@ -0,0 +1,277 @@
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    fx, http, spend_login_attempt, totp, AccountConfig, Config, GetBalance, GetYnabAccountConfig,
+    YnabAccountConfig,
+};
+
+static DEGIRO_LOGIN_URL: &str = "https://trader.degiro.nl/login/secure/login";
+static DEGIRO_TOTP_LOGIN_URL: &str = "https://trader.degiro.nl/login/secure/login/totp";
+static DEGIRO_CONFIG_URL: &str = "https://trader.degiro.nl/login/secure/config";
+
+// The login's statuses that aren't success
+const STATUS_BAD_CREDENTIALS: i64 = 3;
+const STATUS_TOTP_NEEDED: i64 = 6;
+
+// A Degiro account, read through the JSON API its web trader uses, which isn't documented & may
+// change under it. Its value is its positions' & its cash, in its base currency. A login with 2FA
+// needs the authenticator app's secret, which can be kept out of the config as a systemd
+// credential, e.g. `DEGIRO_TOTP_SECRET = "credential:degiro-totp"`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct DegiroConfig {
+    pub degiro_username: String,
+    pub degiro_password: SecretString,
+    // The base32 secret shown when the authenticator app was set up, e.g. `JBSW Y3DP EHPK 3PXP`, or
+    // the `otpauth://` URI its QR code holds
+    pub degiro_totp_secret: Option<SecretString>,
+    // What the account's values are in, converted to the account's CURRENCY if it has one,
+    // otherwise taken to be the budget's
+    #[serde(default = "default_base_currency")]
+    pub degiro_base_currency: String,
+}
+
+fn default_base_currency() -> String {
+    "EUR".to_owned()
+}
+
+#[derive(Clone, Debug)]
+pub struct Degiro {
+    account: String,
+    account_config: AccountConfig,
+    config: DegiroConfig,
+    ynab_config: Config,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct LoginResponse {
+    status: i64,
+    status_text: Option<String>,
+    session_id: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct DataResponse<T> {
+    data: T,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SessionConfig {
+    pa_url: String,
+    trading_url: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Client {
+    int_account: i64,
+}
+
+// Each row is a list of `{ "name": …, "value": … }`
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UpdateResponse {
+    portfolio: Section,
+    total_portfolio: Section,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Section {
+    value: Vec<Field>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Field {
+    name: String,
+    #[serde(default)]
+    value: Value,
+}
+
+fn field<'a>(fields: &'a [Field], name: &str) -> Option<&'a Value> {
+    fields
+        .iter()
+        .find(|field| field.name == name)
+        .map(|field| &field.value)
+}
+
+fn number(fields: &[Field], name: &str) -> Result<f64> {
+    field(fields, name)
+        .and_then(Value::as_f64)
+        .ok_or_else(|| anyhow!("Degiro's portfolio has no {} number", name))
+}
+
+impl Degiro {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        Ok(Degiro {
+            account: account.to_owned(),
+            account_config: account_config.clone(),
+            config: account_config.provider_config()?,
+            ynab_config: ynab_config.clone(),
+        })
+    }
+
+    // The session's ID, with a TOTP code if the login asks for one
+    async fn login(&self, client: &reqwest::Client) -> Result<String> {
+        spend_login_attempt(&self.ynab_config, &self.account, &self.account_config)?;
+
+        let mut body = json!({
+            "username": self.config.degiro_username,
+            "password": self.config.degiro_password.expose_secret(),
+            "isPassCodeReset": false,
+            "isRedirectToMobile": false,
+            "queryParams": {},
+        });
+
+        let mut login = self.post_login(client, DEGIRO_LOGIN_URL, &body).await?;
+
+        if login.status == STATUS_TOTP_NEEDED {
+            let totp_secret = self.config.degiro_totp_secret.as_ref().ok_or_else(|| {
+                anyhow!(
+                    "{}'s Degiro login has 2FA, which needs DEGIRO_TOTP_SECRET",
+                    self.account
+                )
+            })?;
+
+            info!("Logging in to Degiro with a TOTP code");
+
+            body["oneTimePassword"] = json!(totp::fresh_code(totp_secret).await?);
+            login = self
+                .post_login(client, DEGIRO_TOTP_LOGIN_URL, &body)
+                .await?;
+        }
+
+        match (login.status, login.session_id) {
+            (0, Some(session_id)) => Ok(session_id),
+            (STATUS_BAD_CREDENTIALS, _) => Err(anyhow!(
+                "Degiro rejected {}'s username or password",
+                self.account
+            )),
+            (status, _) => Err(anyhow!(
+                "Degiro's login failed with status {} ({})",
+                status,
+                login.status_text.unwrap_or_default()
+            )),
+        }
+    }
+
+    // A failed login's an error status with its reason in the body
+    async fn post_login(
+        &self,
+        client: &reqwest::Client,
+        url: &str,
+        body: &Value,
+    ) -> Result<LoginResponse> {
+        http::send(client, client.post(url).json(body))
+            .await?
+            .json::<LoginResponse>()
+            .await
+            .context("Failed to read Degiro's login response")
+    }
+
+    // The portfolio's positions & cash, in its base currency
+    async fn get_values(&self, client: &reqwest::Client, session_id: &str) -> Result<(f64, f64)> {
+        let session_config = http::send(
+            client,
+            client.get(DEGIRO_CONFIG_URL).header(
+                reqwest::header::COOKIE,
+                format!("JSESSIONID={}", session_id),
+            ),
+        )
+        .await?
+        .error_for_status()?
+        .json::<DataResponse<SessionConfig>>()
+        .await?
+        .data;
+
+        let degiro_client = http::send(
+            client,
+            client
+                .get(format!("{}client", session_config.pa_url))
+                .query(&[("sessionId", session_id)]),
+        )
+        .await?
+        .error_for_status()?
+        .json::<DataResponse<Client>>()
+        .await?
+        .data;
+
+        let update = http::send(
+            client,
+            client
+                .get(format!(
+                    "{}v5/update/{};jsessionid={}",
+                    session_config.trading_url, degiro_client.int_account, session_id
+                ))
+                .query(&[("portfolio", "0"), ("totalPortfolio", "0")]),
+        )
+        .await?
+        .error_for_status()?
+        .json::<UpdateResponse>()
+        .await?;
+
+        // Cash is listed as positions too, so only products are counted
+        let positions = update
+            .portfolio
+            .value
+            .iter()
+            .filter_map(|row| row.value.as_array())
+            .map(|row| serde_json::from_value::<Vec<Field>>(Value::Array(row.clone())))
+            .collect::<Result<Vec<_>, _>>()?
+            .iter()
+            .filter(|row| field(row, "positionType").and_then(Value::as_str) == Some("PRODUCT"))
+            .map(|row| number(row, "value"))
+            .sum::<Result<f64>>()?;
+
+        let cash = number(&update.total_portfolio.value, "totalCash")?;
+
+        Ok((positions, cash))
+    }
+}
+
+impl GetYnabAccountConfig for Degiro {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.account_config.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for Degiro {
+    async fn get(&self) -> Result<f32> {
+        let client = http::client(&self.ynab_config.http)?;
+
+        let session_id = self.login(&client).await?;
+
+        let (positions, cash) = self.get_values(&client, &session_id).await?;
+
+        info!(
+            "Degiro's positions are worth {:.2} {} & its cash {:.2}",
+            positions, self.config.degiro_base_currency, cash
+        );
+
+        let balance = (positions + cash) as f32;
+
+        match &self.account_config.currency {
+            Some(to) => {
+                fx::convert(
+                    &self.ynab_config,
+                    balance,
+                    &self.config.degiro_base_currency,
+                    to,
+                )
+                .await
+            }
+            None => Ok(balance),
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::info;
+use serde::Deserialize;
+use std::time::Duration as StdDuration;
+use tokio::process::Command;
+
+use crate::{fx, AccountConfig, Config, GetBalance, GetYnabAccountConfig, YnabAccountConfig};
+
+// A balance from a command, for an institution that isn't supported here, e.g.
+// `COMMAND = ["python3", "/home/me/my_bank.py"]`. It's run with the account's name as its only
+// argument & prints e.g. `{ "balance": 123.45, "currency": "GBP", "as_of": "2024-01-01T06:00:00Z" }`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct ExecConfig {
+    pub command: Vec<String>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+    // An `as_of` older than this fails the run rather than reconciling a stale balance
+    #[serde(default = "default_max_age_hours")]
+    pub max_age_hours: i64,
+}
+
+fn default_timeout_secs() -> u64 {
+    120
+}
+
+fn default_max_age_hours() -> i64 {
+    48
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ExecOutput {
+    balance: f32,
+    currency: Option<String>,
+    as_of: Option<DateTime<Utc>>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Exec {
+    account: String,
+    ynab_account_id: String,
+    // The account's CURRENCY, which a balance in another currency is converted to
+    currency: Option<String>,
+    config: ExecConfig,
+    ynab_config: Config,
+}
+
+impl Exec {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        Ok(Exec {
+            account: account.to_owned(),
+            ynab_account_id: account_config.ynab_account_id.clone(),
+            currency: account_config.currency.clone(),
+            config: account_config.provider_config()?,
+            ynab_config: ynab_config.clone(),
+        })
+    }
+
+    async fn run(&self) -> Result<ExecOutput> {
+        let (program, args) = self
+            .config
+            .command
+            .split_first()
+            .ok_or_else(|| anyhow!("COMMAND is empty"))?;
+
+        info!("Running {:?}", self.config.command);
+
+        let output = tokio::time::timeout(
+            StdDuration::from_secs(self.config.timeout_secs),
+            Command::new(program)
+                .args(args)
+                .arg(&self.account)
+                .kill_on_drop(true)
+                .output(),
+        )
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "{} didn't finish within {}s",
+                program,
+                self.config.timeout_secs
+            )
+        })??;
+
+        if !output.status.success() {
+            return Err(anyhow!(
+                "{} exited with {}: {}",
+                program,
+                output.status,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+
+        serde_json::from_slice::<ExecOutput>(&output.stdout).map_err(|e| {
+            anyhow!(
+                "{} printed {:?}, not a balance: {}",
+                program,
+                String::from_utf8_lossy(&output.stdout),
+                e
+            )
+        })
+    }
+}
+
+impl GetYnabAccountConfig for Exec {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for Exec {
+    async fn get(&self) -> Result<f32> {
+        let output = self.run().await?;
+
+        if let Some(as_of) = output.as_of {
+            if Utc::now() - as_of > Duration::hours(self.config.max_age_hours) {
+                return Err(anyhow!(
+                    "{}'s balance is from {}, older than MAX_AGE_HOURS",
+                    self.account,
+                    as_of
+                ));
+            }
+        }
+
+        // Without the account's CURRENCY there's no telling whether the balance is in the budget's
+        match (output.currency, &self.currency) {
+            (None, _) => Ok(output.balance),
+            (Some(from), Some(to)) => {
+                fx::convert(&self.ynab_config, output.balance, &from, to).await
+            }
+            (Some(from), None) => Err(anyhow!(
+                "{}'s balance is in {}, set the account's CURRENCY for it to be converted to the budget's",
+                self.account,
+                from
+            )),
+        }
+    }
+}
@@ -0,0 +1,405 @@
+use anyhow::{anyhow, Result};
+use config::FileFormat;
+use log::info;
+use regex::Regex;
+use scraper::{Html, Selector};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::{
+    amount,
+    browser::{Browser, BrowserConfig, CaptchaDetected},
+    manual_login, otp_relay, spend_login_attempt, totp, AccountConfig, Config, GetBalance,
+    GetYnabAccountConfig, YnabAccountConfig,
+};
+
+static PRESETS_DIRNAME: &str = "form_presets";
+
+// The presets shipped with the updater, by name
+static SHIPPED_PRESETS: [(&str, &str); 2] = [
+    ("tandem", include_str!("form_presets/tandem.toml")),
+    ("zopa", include_str!("form_presets/zopa.toml")),
+];
+
+// Many small banks' logins are a single form, with the balance in a single element, & only differ
+// in where those are. That's a preset, either one of `SHIPPED_PRESETS` or read from
+// `form_presets/<PRESET>.toml` in the config directory, which overrides a shipped one's settings.
+// The account can override any of them or set them itself.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct FormPreset {
+    pub title: String,
+    pub login_url: String,
+    // Where the form's posted, otherwise its `action`
+    pub login_action_url: Option<String>,
+    pub username_field: String,
+    pub password_field: String,
+    // For a login that asks for an authenticator app's code alongside the password, filled in from
+    // FORM_TOTP_SECRET
+    pub totp_field: Option<String>,
+    // For a login that sends a one-time code, e.g. by SMS, & asks for it on a form of its own once
+    // the password's in, the field it's entered in. The code's asked for through `otp_relay`.
+    pub otp_field: Option<String>,
+    // Where to find the code, e.g. "Enter the code sent to your phone"
+    pub otp_prompt: Option<String>,
+    // Sent along with the form's own hidden fields, e.g. a submit button's value
+    #[serde(default)]
+    pub extra_fields: BTreeMap<String, String>,
+    // The page with the balance, otherwise the one the login lands on
+    pub balance_url: Option<String>,
+    pub balance_selector: String,
+    #[serde(default = "default_balance_regex")]
+    pub balance_regex: String,
+    // `","` for a site that writes `1.234,56`, otherwise it's told from the balance itself
+    pub decimal_separator: Option<char>,
+}
+
+fn default_balance_regex() -> String {
+    r"(\(?-?\d[\d.,'\s]*[.,]\d{2}\)?-?)".to_owned()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct FormConfig {
+    pub form_username: String,
+    pub form_password: SecretString,
+    // See `totp`
+    pub form_totp_secret: Option<SecretString>,
+
+    #[serde(flatten)]
+    pub browser: BrowserConfig,
+}
+
+#[derive(Clone, Debug)]
+pub struct Form {
+    account: String,
+    account_config: AccountConfig,
+    config: FormConfig,
+    preset: FormPreset,
+    ynab_config: Config,
+}
+
+impl FormPreset {
+    pub fn load(config_path: &str, account_config: &AccountConfig) -> Result<Self> {
+        let mut builder = config::Config::builder();
+
+        if let Some(preset) = account_config
+            .settings
+            .get("PRESET")
+            .and_then(|preset| preset.as_str())
+        {
+            let shipped = SHIPPED_PRESETS
+                .iter()
+                .find(|(name, _)| *name == preset)
+                .map(|(_, shipped)| *shipped);
+
+            if let Some(shipped) = shipped {
+                builder = builder.add_source(config::File::from_str(shipped, FileFormat::Toml));
+            }
+
+            builder = builder.add_source(
+                config::File::with_name(&format!(
+                    "{}/{}/{}.toml",
+                    config_path, PRESETS_DIRNAME, preset
+                ))
+                .required(shipped.is_none()),
+            );
+        }
+
+        let builder = builder.add_source(config::File::from_str(
+            &serde_json::to_string(&account_config.settings)?,
+            FileFormat::Json,
+        ));
+
+        Ok(builder.build()?.try_deserialize::<FormPreset>()?)
+    }
+}
+
+impl Form {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        Ok(Form {
+            account: account.to_owned(),
+            account_config: account_config.clone(),
+            config: account_config.provider_config()?,
+            preset: FormPreset::load(&ynab_config.config_path, account_config)?,
+            ynab_config: ynab_config.clone(),
+        })
+    }
+
+    async fn login(&self, browser: &mut Browser) -> Result<String> {
+        let preset = &self.preset;
+
+        spend_login_attempt(&self.ynab_config, &self.account, &self.account_config)?;
+
+        let login_page = browser.get(&preset.login_url).await?;
+
+        let (action, hidden_fields) = find_form(
+            preset,
+            &login_page,
+            &preset.username_field,
+            preset.login_action_url.as_deref(),
+        )?
+        .ok_or_else(|| {
+            anyhow!(
+                "{}'s login page has no form with a {} field",
+                preset.title,
+                preset.username_field
+            )
+        })?;
+
+        let mut params = hidden_fields;
+        params.extend(preset.extra_fields.clone());
+        params.insert(
+            preset.username_field.clone(),
+            self.config.form_username.clone(),
+        );
+        params.insert(
+            preset.password_field.clone(),
+            self.config.form_password.expose_secret().to_owned(),
+        );
+
+        browser.pause().await;
+
+        // After the pause, so the code's still fresh when it's posted
+        if let Some(totp_field) = &preset.totp_field {
+            let totp_secret = self.config.form_totp_secret.as_ref().ok_or_else(|| {
+                anyhow!(
+                    "{}'s login asks for a TOTP code, which needs FORM_TOTP_SECRET",
+                    preset.title
+                )
+            })?;
+
+            params.insert(totp_field.clone(), totp::fresh_code(totp_secret).await?);
+        }
+
+        let mut landing_page = browser
+            .post_form(
+                &action,
+                &params
+                    .iter()
+                    .map(|(name, value)| (name.as_str(), value.as_str()))
+                    .collect::<Vec<_>>(),
+            )
+            .await?;
+
+        // Only asked for when the login lands on the code's form, e.g. not from a trusted device
+        if let Some(otp_field) = &preset.otp_field {
+            if let Some((action, mut params)) = find_form(preset, &landing_page, otp_field, None)? {
+                let code = otp_relay::request_code(
+                    &self.ynab_config,
+                    &self.account,
+                    &self.account_config,
+                    &preset.title,
+                    preset.otp_prompt.as_deref().unwrap_or(&format!(
+                        "Enter the code {} sent you to log in",
+                        preset.title
+                    )),
+                )
+                .await?;
+
+                params.insert(otp_field.clone(), code.expose_secret().to_owned());
+
+                landing_page = browser
+                    .post_form(
+                        &action,
+                        &params
+                            .iter()
+                            .map(|(name, value)| (name.as_str(), value.as_str()))
+                            .collect::<Vec<_>>(),
+                    )
+                    .await?;
+            }
+        }
+
+        match &preset.balance_url {
+            Some(balance_url) => {
+                browser.pause().await;
+                browser.get(balance_url).await
+            }
+            None => Ok(landing_page),
+        }
+    }
+}
+
+impl GetYnabAccountConfig for Form {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.account_config.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for Form {
+    async fn get(&self) -> Result<f32> {
+        let preset = &self.preset;
+
+        let mut browser = Browser::new(&self.config.browser, &self.ynab_config.http)?;
+
+        let balance_page = match self.login(&mut browser).await {
+            // Without a balance page there's nowhere to take a session from a manual login to
+            Err(e) if e.downcast_ref::<CaptchaDetected>().is_some() => {
+                let balance_url = preset.balance_url.as_ref().ok_or(e)?;
+
+                info!("Captcha detected, falling back to a manual login");
+
+                let cookies = manual_login::request_session(
+                    &self.ynab_config,
+                    &self.account,
+                    &preset.title,
+                    &preset.login_url,
+                )
+                .await?;
+
+                browser.add_cookies(balance_url, &cookies)?;
+                browser.get(balance_url).await?
+            }
+            balance_page => balance_page?,
+        };
+
+        find_balance(preset, &balance_page)
+    }
+}
+
+fn parse_selector(selector: &str) -> Result<Selector> {
+    Selector::parse(selector).map_err(|e| anyhow!("Invalid selector {:?}: {:?}", selector, e))
+}
+
+// Where the page's form with `field` is posted to, unless it's `action_url`, and its hidden fields,
+// e.g. a CSRF token. A relative action's taken to be relative to the login page.
+fn find_form(
+    preset: &FormPreset,
+    page: &str,
+    field: &str,
+    action_url: Option<&str>,
+) -> Result<Option<(String, BTreeMap<String, String>)>> {
+    let document = Html::parse_document(page);
+
+    let form_selector = parse_selector("form")?;
+    let field_selector =
+        parse_selector(&format!("input[name=\"{}\"]", field.replace('"', "\\\"")))?;
+    let hidden_selector = parse_selector("input[type=\"hidden\"]")?;
+
+    let form = match document
+        .select(&form_selector)
+        .find(|form| form.select(&field_selector).next().is_some())
+    {
+        Some(form) => form,
+        None => return Ok(None),
+    };
+
+    let hidden_fields = form
+        .select(&hidden_selector)
+        .filter_map(|input| {
+            let name = input.value().attr("name")?;
+            Some((
+                name.to_owned(),
+                input.value().attr("value").unwrap_or_default().to_owned(),
+            ))
+        })
+        .collect();
+
+    let action = match (action_url, form.value().attr("action")) {
+        (Some(action_url), _) => action_url.to_owned(),
+        (None, Some(action)) if !action.is_empty() => reqwest::Url::parse(&preset.login_url)?
+            .join(action)?
+            .to_string(),
+        (None, _) => preset.login_url.clone(),
+    };
+
+    Ok(Some((action, hidden_fields)))
+}
+
+fn find_balance(preset: &FormPreset, balance_page: &str) -> Result<f32> {
+    let document = Html::parse_document(balance_page);
+
+    let selector = parse_selector(&preset.balance_selector)?;
+    let regex = Regex::new(&preset.balance_regex)?;
+
+    let text = document
+        .select(&selector)
+        .next()
+        .ok_or_else(|| {
+            anyhow!(
+                "Failed to match selector {} on {}'s balance page, the login may have failed",
+                preset.balance_selector,
+                preset.title
+            )
+        })?
+        .text()
+        .collect::<String>();
+
+    let balance = regex
+        .captures(&text)
+        .and_then(|captures| captures.get(1))
+        .ok_or_else(|| {
+            anyhow!(
+                "Failed to match {} in {}'s balance {:?}",
+                preset.balance_regex,
+                preset.title,
+                text.trim()
+            )
+        })?
+        .as_str();
+
+    amount::parse_with(balance, preset.decimal_separator)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn account_config(settings: serde_json::Value) -> AccountConfig {
+        let mut account_config = serde_json::json!({
+            "PROVIDER": "form",
+            "YNAB_ACCOUNT_ID": "account",
+        });
+        account_config
+            .as_object_mut()
+            .unwrap()
+            .extend(settings.as_object().unwrap().clone());
+
+        serde_json::from_value(account_config).unwrap()
+    }
+
+    #[test]
+    fn loads_the_shipped_presets() {
+        for (name, _) in SHIPPED_PRESETS {
+            let preset = FormPreset::load(
+                "/nonexistent",
+                &account_config(serde_json::json!({ "PRESET": name })),
+            )
+            .unwrap();
+
+            assert_eq!(preset.title.to_lowercase(), name);
+        }
+    }
+
+    #[test]
+    fn the_account_overrides_a_shipped_preset() {
+        let preset = FormPreset::load(
+            "/nonexistent",
+            &account_config(serde_json::json!({
+                "PRESET": "zopa",
+                "BALANCE_SELECTOR": ".balance",
+            })),
+        )
+        .unwrap();
+
+        assert_eq!(preset.balance_selector, ".balance");
+        assert_eq!(preset.otp_field.as_deref(), Some("code"));
+    }
+
+    #[test]
+    fn an_unknown_preset_needs_a_file() {
+        assert!(FormPreset::load(
+            "/nonexistent",
+            &account_config(serde_json::json!({ "PRESET": "unknown" })),
+        )
+        .is_err());
+    }
+}
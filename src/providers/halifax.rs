@@ -0,0 +1,527 @@
+use anyhow::{anyhow, Context, Result};
+use config::FileFormat;
+use log::info;
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+
+use crate::{
+    amount,
+    browser::{Browser, BrowserConfig, CaptchaDetected},
+    error::ConfigInvalid,
+    manual_login, spend_login_attempt, AccountConfig, Config, GetBalance, GetYnabAccountConfig,
+    YnabAccountConfig,
+};
+
+static HALIFAX_LOGIN_URL: &str = "https://www.halifax-online.co.uk/personal/logon/login.jsp";
+static HALIFAX_OVERVIEW_URL: &str =
+    "https://secure.halifax-online.co.uk/personal/a/account_overview_personal/";
+
+static DEFAULT_SELECTORS: &str = include_str!("halifax_selectors.toml");
+static SELECTORS_FILENAME: &str = "halifax_selectors.toml";
+
+// A Halifax mortgage, or any other of the login's accounts, from its online banking's account
+// overview. What's owed is its balance as a negative one, e.g. for a YNAB loan account run on a
+// monthly `schedule`. Its login's a password & then three characters of the memorable
+// information, over two pages, so it doesn't fit a `form` preset.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct HalifaxConfig {
+    pub halifax_username: String,
+    pub halifax_password: SecretString,
+    pub halifax_memorable_information: SecretString,
+    // The account's name on the overview, or enough of it to pick it out
+    #[serde(default = "default_account")]
+    pub halifax_account: String,
+
+    #[serde(flatten)]
+    pub browser: BrowserConfig,
+}
+
+fn default_account() -> String {
+    "Mortgage".to_owned()
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct HalifaxSelectors {
+    pub username_field: String,
+    pub password_field: String,
+    pub memorable_information_label: String,
+    pub memorable_information_character_regex: String,
+    pub account_rows: String,
+    pub account_name: String,
+    pub account_balance: String,
+    pub balance_regex: String,
+}
+
+impl HalifaxSelectors {
+    // The shipped defaults, overridden by any in the config directory's `halifax_selectors.toml`
+    pub fn load(config_path: Option<&str>) -> Result<Self> {
+        let mut builder = config::Config::builder()
+            .add_source(config::File::from_str(DEFAULT_SELECTORS, FileFormat::Toml));
+
+        if let Some(config_path) = config_path {
+            builder = builder.add_source(
+                config::File::with_name(&format!("{}/{}", config_path, SELECTORS_FILENAME))
+                    .required(false),
+            );
+        }
+
+        Ok(builder.build()?.try_deserialize::<HalifaxSelectors>()?)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Halifax {
+    account: String,
+    account_config: AccountConfig,
+    config: HalifaxConfig,
+    selectors: HalifaxSelectors,
+    ynab_config: Config,
+}
+
+impl Halifax {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        // It'd be negated twice
+        if account_config.liability {
+            return Err(anyhow!(
+                "{}'s Halifax balance is already negative for what's owed, so it can't be a LIABILITY",
+                account
+            )
+            .context(ConfigInvalid));
+        }
+
+        Ok(Halifax {
+            account: account.to_owned(),
+            account_config: account_config.clone(),
+            config: account_config.provider_config()?,
+            selectors: HalifaxSelectors::load(Some(&ynab_config.config_path))?,
+            ynab_config: ynab_config.clone(),
+        })
+    }
+
+    // The account overview the login lands on
+    async fn login(&self, browser: &mut Browser) -> Result<String> {
+        let selectors = &self.selectors;
+
+        spend_login_attempt(&self.ynab_config, &self.account, &self.account_config)?;
+
+        let login_page = browser.get(HALIFAX_LOGIN_URL).await?;
+
+        let (action, mut params) =
+            find_form(&login_page, HALIFAX_LOGIN_URL, &selectors.username_field)?.ok_or_else(
+                || {
+                    anyhow!(
+                        "Halifax's login page has no form with a {} field, check USERNAME_FIELD",
+                        selectors.username_field
+                    )
+                },
+            )?;
+
+        params.push((
+            selectors.username_field.clone(),
+            self.config.halifax_username.clone(),
+        ));
+        params.push((
+            selectors.password_field.clone(),
+            self.config.halifax_password.expose_secret().to_owned(),
+        ));
+
+        browser.pause().await;
+
+        let memorable_information_page = post(browser, &action, &params).await?;
+
+        let (action, params) = answer_memorable_information(
+            selectors,
+            &memorable_information_page,
+            &self.config.halifax_memorable_information,
+        )?;
+
+        browser.pause().await;
+
+        post(browser, &action, &params).await
+    }
+
+    // The overview using a session from a login finished by hand, if it's still logged in
+    async fn get_overview_with_session(
+        &self,
+        browser: &mut Browser,
+        cookies: &str,
+    ) -> Result<Option<String>> {
+        browser.add_cookies(HALIFAX_OVERVIEW_URL, cookies)?;
+
+        let overview = browser.get(HALIFAX_OVERVIEW_URL).await?;
+
+        Ok(has_accounts(&self.selectors, &overview)?.then_some(overview))
+    }
+
+    async fn get_overview(&self) -> Result<String> {
+        let mut browser = Browser::new(&self.config.browser, &self.ynab_config.http)?;
+
+        // A session handed back after the previous run's captcha
+        if let Some(cookies) = manual_login::take_session(&self.ynab_config, &self.account).await? {
+            if let Some(overview) = self
+                .get_overview_with_session(&mut browser, &cookies)
+                .await?
+            {
+                return Ok(overview);
+            }
+            info!("The session from the manual login has expired, logging in");
+        }
+
+        match self.login(&mut browser).await {
+            Err(e) if e.downcast_ref::<CaptchaDetected>().is_some() => {
+                info!("{}, falling back to a manual login", e);
+
+                let cookies = manual_login::request_session(
+                    &self.ynab_config,
+                    &self.account,
+                    "Halifax",
+                    HALIFAX_LOGIN_URL,
+                )
+                .await?;
+
+                self.get_overview_with_session(&mut browser, &cookies)
+                    .await?
+                    .ok_or_else(|| anyhow!("The session from the manual login isn't logged in"))
+            }
+            overview => overview,
+        }
+    }
+}
+
+impl GetYnabAccountConfig for Halifax {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.account_config.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for Halifax {
+    async fn get(&self) -> Result<f32> {
+        let overview = self.get_overview().await?;
+
+        let owed =
+            get_account_balance(&self.selectors, &self.config.halifax_account, &overview)?.abs();
+
+        info!(
+            "{} is owed on Halifax's {}",
+            owed, self.config.halifax_account
+        );
+
+        Ok(-owed)
+    }
+}
+
+// Where a form's posted to, & what with
+type FilledForm = (String, Vec<(String, String)>);
+
+fn parse_selector(selector: &str) -> Result<Selector> {
+    Selector::parse(selector).map_err(|e| anyhow!("Invalid selector {:?}: {:?}", selector, e))
+}
+
+async fn post(browser: &Browser, action: &str, params: &[(String, String)]) -> Result<String> {
+    browser
+        .post_form(
+            action,
+            &params
+                .iter()
+                .map(|(name, value)| (name.as_str(), value.as_str()))
+                .collect::<Vec<_>>(),
+        )
+        .await
+}
+
+// Where the page's form with an element named `field` is posted to, relative to `base_url`, with
+// its hidden fields & its submit button's value, which the site checks was pressed
+fn find_form(page: &str, base_url: &str, field: &str) -> Result<Option<FilledForm>> {
+    let document = Html::parse_document(page);
+
+    let form_selector = parse_selector("form")?;
+    let field_selector = parse_selector(&format!("[name=\"{}\"]", field.replace('"', "\\\"")))?;
+    let sent_selector = parse_selector("input[type=\"hidden\"], input[type=\"submit\"]")?;
+
+    let Some(form) = document
+        .select(&form_selector)
+        .find(|form| form.select(&field_selector).next().is_some())
+    else {
+        return Ok(None);
+    };
+
+    let params = form
+        .select(&sent_selector)
+        .filter_map(|input| {
+            Some((
+                input.value().attr("name")?.to_owned(),
+                input.value().attr("value").unwrap_or_default().to_owned(),
+            ))
+        })
+        .collect();
+
+    let action = match form.value().attr("action") {
+        Some(action) if !action.is_empty() => {
+            reqwest::Url::parse(base_url)?.join(action)?.to_string()
+        }
+        _ => base_url.to_owned(),
+    };
+
+    Ok(Some((action, params)))
+}
+
+// The memorable information form, with each character that's asked for picked in its select. The
+// selects' options are the characters, padded with a non-breaking space that's sent with them.
+fn answer_memorable_information(
+    selectors: &HalifaxSelectors,
+    page: &str,
+    memorable_information: &SecretString,
+) -> Result<FilledForm> {
+    let document = Html::parse_document(page);
+
+    let label_selector = parse_selector(&selectors.memorable_information_label)?;
+    let regex = Regex::new(&selectors.memorable_information_character_regex)?;
+    let option_selector = parse_selector("option")?;
+
+    let characters = memorable_information
+        .expose_secret()
+        .chars()
+        .collect::<Vec<_>>();
+
+    let labels = document.select(&label_selector).collect::<Vec<_>>();
+
+    let first_select = labels
+        .first()
+        .and_then(|label| label.value().attr("for"))
+        .ok_or_else(|| {
+            anyhow!(
+                "Halifax didn't ask for the memorable information, check MEMORABLE_INFORMATION_LABEL, or the login may have failed"
+            )
+        })?;
+
+    // It's on the secure host, like the overview
+    let (action, mut params) =
+        find_form(page, HALIFAX_OVERVIEW_URL, first_select)?.ok_or_else(|| {
+            anyhow!(
+                "Halifax's memorable information page has no form with {}",
+                first_select
+            )
+        })?;
+
+    for label in labels {
+        let text = label.text().collect::<String>();
+
+        let position = regex
+            .captures(&text)
+            .and_then(|captures| captures.get(1))
+            .and_then(|position| position.as_str().parse::<usize>().ok())
+            .ok_or_else(|| {
+                anyhow!(
+                    "Failed to match MEMORABLE_INFORMATION_CHARACTER_REGEX in {:?}",
+                    text.trim()
+                )
+            })?;
+
+        let character = position
+            .checked_sub(1)
+            .and_then(|i| characters.get(i))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Halifax asked for character {} of the memorable information, which is only {} long",
+                    position,
+                    characters.len()
+                )
+                .context(ConfigInvalid)
+            })?;
+
+        let select_id = label.value().attr("for").unwrap_or_default();
+        let select = document
+            .select(&parse_selector(&format!(
+                "select[id=\"{}\"]",
+                select_id.replace('"', "\\\"")
+            ))?)
+            .next()
+            .ok_or_else(|| {
+                anyhow!(
+                    "Halifax's memorable information has no select {}",
+                    select_id
+                )
+            })?;
+
+        let value = select
+            .select(&option_selector)
+            .filter_map(|option| option.value().attr("value"))
+            .find(|value| value.trim().eq_ignore_ascii_case(&character.to_string()))
+            .ok_or_else(|| {
+                anyhow!(
+                    "Halifax's character {} select has no option for the memorable information's",
+                    position
+                )
+            })?;
+
+        params.push((
+            select.value().attr("name").unwrap_or(select_id).to_owned(),
+            value.to_owned(),
+        ));
+    }
+
+    Ok((action, params))
+}
+
+fn has_accounts(selectors: &HalifaxSelectors, overview: &str) -> Result<bool> {
+    let document = Html::parse_document(overview);
+
+    Ok(document
+        .select(&parse_selector(&selectors.account_rows)?)
+        .next()
+        .is_some())
+}
+
+fn account_name(selectors: &HalifaxSelectors, row: ElementRef) -> Result<Option<String>> {
+    Ok(row
+        .select(&parse_selector(&selectors.account_name)?)
+        .next()
+        .map(|name| name.text().collect::<String>().trim().to_owned()))
+}
+
+// The balance of the one account whose name has `account` in it
+fn get_account_balance(selectors: &HalifaxSelectors, account: &str, overview: &str) -> Result<f32> {
+    let document = Html::parse_document(overview);
+
+    let rows_selector = parse_selector(&selectors.account_rows)?;
+    let balance_selector = parse_selector(&selectors.account_balance)?;
+    let regex = Regex::new(&selectors.balance_regex)?;
+
+    let mut names = vec![];
+    let mut matches = vec![];
+
+    for row in document.select(&rows_selector) {
+        let Some(name) = account_name(selectors, row)? else {
+            continue;
+        };
+
+        if name.to_lowercase().contains(&account.to_lowercase()) {
+            matches.push(row);
+        }
+        names.push(name);
+    }
+
+    let row = match matches.as_slice() {
+        [row] => row,
+        [] if names.is_empty() => {
+            return Err(anyhow!(
+                "Failed to match ACCOUNT_ROWS & ACCOUNT_NAME on Halifax's overview, the login may have failed"
+            ))
+        }
+        [] => {
+            return Err(anyhow!(
+                "Halifax has no account named like {}, only {}",
+                account,
+                names.join(", ")
+            )
+            .context(ConfigInvalid))
+        }
+        _ => {
+            return Err(anyhow!(
+                "More than one of Halifax's accounts is named like {}, of {}, so HALIFAX_ACCOUNT has to say which",
+                account,
+                names.join(", ")
+            )
+            .context(ConfigInvalid))
+        }
+    };
+
+    let text = row
+        .select(&balance_selector)
+        .next()
+        .map(|balance| balance.text().collect::<String>())
+        .ok_or_else(|| anyhow!("Failed to match ACCOUNT_BALANCE in Halifax's {}", account))?;
+
+    let balance = regex
+        .captures(&text)
+        .and_then(|captures| captures.get(1))
+        .ok_or_else(|| anyhow!("Failed to match BALANCE_REGEX in {:?}", text.trim()))?;
+
+    amount::parse(balance.as_str())
+        .with_context(|| format!("Failed to read Halifax's {} balance", account))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    static MEMORABLE_INFORMATION: &str =
+        include_str!("../../fixtures/halifax/memorable-information.html");
+    static OVERVIEW: &str = include_str!("../../fixtures/halifax/overview.html");
+
+    fn selectors() -> HalifaxSelectors {
+        HalifaxSelectors::load(None).unwrap()
+    }
+
+    #[test]
+    fn picks_the_asked_for_characters() {
+        let (action, params) = answer_memorable_information(
+            &selectors(),
+            MEMORABLE_INFORMATION,
+            &"bristol".to_owned().into(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            action,
+            "https://secure.halifax-online.co.uk/personal/a/logon/entermemorableinformation.jsp"
+        );
+
+        // Characters 2, 5 & 7, after the form's own fields
+        let picked = params
+            .iter()
+            .filter(|(name, _)| name.contains("memInfo"))
+            .map(|(_, value)| value.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(picked, ["\u{a0}r", "\u{a0}t", "\u{a0}l"]);
+        assert!(params.iter().any(|(name, _)| name == "submitToken"));
+    }
+
+    #[test]
+    fn short_memorable_information_is_invalid() {
+        assert!(answer_memorable_information(
+            &selectors(),
+            MEMORABLE_INFORMATION,
+            &"brist".to_owned().into(),
+        )
+        .unwrap_err()
+        .downcast_ref::<ConfigInvalid>()
+        .is_some());
+    }
+
+    #[test]
+    fn reads_the_mortgage_balance() {
+        assert_eq!(
+            get_account_balance(&selectors(), "Mortgage", OVERVIEW).unwrap(),
+            123456.78
+        );
+    }
+
+    #[test]
+    fn an_unknown_account_lists_the_accounts() {
+        let e = get_account_balance(&selectors(), "Savings", OVERVIEW).unwrap_err();
+
+        assert!(format!("{:#}", e).contains("Reward Current Account, Mortgage"));
+    }
+
+    #[test]
+    fn a_page_without_accounts_failed_to_log_in() {
+        assert!(
+            get_account_balance(&selectors(), "Mortgage", MEMORABLE_INFORMATION)
+                .unwrap_err()
+                .to_string()
+                .contains("the login may have failed")
+        );
+    }
+}
@@ -0,0 +1,1166 @@
+use anyhow::{anyhow, Context, Result};
+use chrono::NaiveDate;
+use config::FileFormat;
+use log::{info, warn};
+use regex::Regex;
+use scraper::{ElementRef, Html, Selector};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant},
+};
+use tokio::sync::{OwnedMappedMutexGuard, OwnedMutexGuard};
+
+use crate::{
+    amount,
+    browser::{Browser, BrowserConfig, CaptchaDetected},
+    income::{GetIncome, Income, IncomeKind},
+    manual_login,
+    sealed::Sealed,
+    spend_login_attempt,
+    token_store::{TokenStore, TokenStoreGuard},
+    AccountConfig, Config, GetBalance, GetYnabAccountConfig, YnabAccountConfig,
+};
+
+static HL_LOGIN_URL: &str = "https://online.hl.co.uk/my-accounts/login-step-one";
+static HL_ACCOUNTS_URL: &str = "https://online.hl.co.uk/my-accounts";
+
+static DEFAULT_SELECTORS: &str = include_str!("hl_selectors.toml");
+static SELECTORS_FILENAME: &str = "hl_selectors.toml";
+
+// How long a login's session is shared for, long enough for a run's accounts
+static SESSION_TTL: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct HlConfig {
+    pub hl_username: String,
+    pub hl_date_of_birth: String,
+    pub hl_password: SecretString,
+    pub hl_secure_numbers: [SecretString; 6],
+    // Which of the login's accounts it is, by its name on the accounts page, e.g.
+    // `Stocks & Shares ISA`, rather than all of them
+    pub hl_account: Option<String>,
+    // Ticks HL's "remember this device" & keeps the cookie it sets, sealed with the login, so
+    // later logins skip the secure number until HL stops accepting it
+    #[serde(default)]
+    pub hl_remember_device: bool,
+
+    #[serde(flatten)]
+    pub browser: BrowserConfig,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct HlSelectors {
+    pub hl_vt: String,
+    pub secure_number: String,
+    pub secure_number_digit_regex: String,
+    pub remember_device_field: String,
+    pub remember_device_cookie: String,
+    pub total: String,
+    pub total_columns: Vec<usize>,
+    pub total_regex: String,
+    pub account_rows: String,
+    pub account_name: String,
+    pub account_column: String,
+    pub account_link: String,
+    pub account_id_regex: String,
+    pub capital_transactions_url: String,
+    pub transaction_rows: String,
+    pub transaction_date: String,
+    pub transaction_date_format: String,
+    pub transaction_reference: String,
+    pub transaction_description: String,
+    pub transaction_value: String,
+    pub dividend_regex: String,
+    pub interest_regex: String,
+}
+
+impl HlSelectors {
+    // The shipped defaults, overridden by any in the config directory's `hl_selectors.toml`
+    pub fn load(config_path: Option<&str>) -> Result<Self> {
+        let mut builder = config::Config::builder()
+            .add_source(config::File::from_str(DEFAULT_SELECTORS, FileFormat::Toml));
+
+        if let Some(config_path) = config_path {
+            builder = builder.add_source(
+                config::File::with_name(&format!("{}/{}", config_path, SELECTORS_FILENAME))
+                    .required(false),
+            );
+        }
+
+        Ok(builder.build()?.try_deserialize::<HlSelectors>()?)
+    }
+}
+
+// The pages of HL's login that are scraped
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HlPage {
+    Login,
+    SecureNumber,
+    Accounts,
+    CapitalTransactions,
+}
+
+impl HlPage {
+    pub const ALL: [HlPage; 4] = [
+        HlPage::Login,
+        HlPage::SecureNumber,
+        HlPage::Accounts,
+        HlPage::CapitalTransactions,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            HlPage::Login => "login",
+            HlPage::SecureNumber => "secure-number",
+            HlPage::Accounts => "accounts",
+            HlPage::CapitalTransactions => "capital-transactions",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        HlPage::ALL.into_iter().find(|page| page.name() == name)
+    }
+
+    // The checked-in, scrubbed copy of the page, which `scrape-test` checks the selectors against
+    // when it isn't given a page, so a selector change that breaks parsing is caught offline
+    pub fn fixture(&self) -> &'static str {
+        match self {
+            HlPage::Login => include_str!("../../fixtures/hl/login.html"),
+            HlPage::SecureNumber => include_str!("../../fixtures/hl/secure-number.html"),
+            HlPage::Accounts => include_str!("../../fixtures/hl/accounts.html"),
+            HlPage::CapitalTransactions => {
+                include_str!("../../fixtures/hl/capital-transactions.html")
+            }
+        }
+    }
+}
+
+// Whether a selector matched a page, and what was taken from it
+#[derive(Clone, Debug)]
+pub struct SelectorReport {
+    pub name: String,
+    pub selector: String,
+    pub matched: Option<String>,
+}
+
+// A login's browser, with its cookies, & the accounts page it logged in to
+struct Session {
+    logged_in_at: Instant,
+    browser: Browser,
+    home_page: String,
+}
+
+type SharedSession = Arc<tokio::sync::Mutex<Option<Session>>>;
+
+// The session of a login, shared by its accounts, so e.g. a SIPP & an ISA log in once between
+// them, & by an account's balance & income. It's held while logging in, so the second waits for
+// the first's session.
+fn shared_session(config_path: &str, username: &str) -> Result<SharedSession> {
+    static SESSIONS: OnceLock<Mutex<HashMap<(String, String), SharedSession>>> = OnceLock::new();
+
+    let mut sessions = SESSIONS
+        .get_or_init(Default::default)
+        .lock()
+        .map_err(|_| anyhow!("HL's shared sessions' lock is poisoned"))?;
+
+    Ok(sessions
+        .entry((config_path.to_owned(), username.to_owned()))
+        .or_default()
+        .clone())
+}
+
+#[derive(Clone, Debug)]
+pub struct Hl {
+    account: String,
+    account_config: AccountConfig,
+    config: HlConfig,
+    selectors: HlSelectors,
+    ynab_config: Config,
+}
+
+impl Hl {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        Ok(Hl {
+            account: account.to_owned(),
+            account_config: account_config.clone(),
+            config: account_config.provider_config()?,
+            selectors: HlSelectors::load(Some(&ynab_config.config_path))?,
+            ynab_config: ynab_config.clone(),
+        })
+    }
+
+    // Logs in, keeping a scrubbed copy of each page on the way to be checked in as a fixture.
+    // This spends one of the day's login attempts.
+    pub async fn record_fixtures(&self) -> Result<Vec<(HlPage, String)>> {
+        let config = &self.config;
+        let selectors = &self.selectors;
+
+        spend_login_attempt(&self.ynab_config, &self.account, &self.account_config)?;
+
+        let mut browser = Browser::new(&config.browser, &self.ynab_config.http)?;
+
+        let login_page = browser.get(HL_LOGIN_URL).await?;
+        let hl_vt = find_hl_vt(selectors, &login_page)?;
+
+        browser.pause().await;
+
+        login_step_one(config, &browser, hl_vt.as_str()).await?;
+
+        browser.pause().await;
+
+        let secure_number_page = browser
+            .get("https://online.hl.co.uk/my-accounts/login-step-two")
+            .await?;
+        let secure_number_indices = find_secure_number_indices(selectors, &secure_number_page)?;
+
+        browser.pause().await;
+
+        let accounts_page =
+            submit_secure_number(config, selectors, &browser, hl_vt, &secure_number_indices)
+                .await?;
+
+        let account_id = find_account_id(selectors, config.hl_account.as_deref(), &accounts_page)?;
+
+        browser.pause().await;
+
+        let capital_transactions_page = browser
+            .get(
+                &selectors
+                    .capital_transactions_url
+                    .replace("{}", &account_id),
+            )
+            .await?;
+
+        Ok(vec![
+            (HlPage::Login, scrub(config, &login_page)?),
+            (HlPage::SecureNumber, scrub(config, &secure_number_page)?),
+            (HlPage::Accounts, scrub(config, &accounts_page)?),
+            (
+                HlPage::CapitalTransactions,
+                scrub(config, &capital_transactions_page)?,
+            ),
+        ])
+    }
+
+    async fn login(&self, browser: &mut Browser) -> Result<String> {
+        let config = &self.config;
+
+        spend_login_attempt(&self.ynab_config, &self.account, &self.account_config)?;
+
+        let selectors = &self.selectors;
+
+        let device_guard = if config.hl_remember_device {
+            Some(self.device_store().lock().await?)
+        } else {
+            None
+        };
+        let device_remembered = match &device_guard {
+            Some(device_guard) => self.add_remembered_device(browser, device_guard)?,
+            None => false,
+        };
+
+        let hl_vt = get_hl_vt(selectors, browser).await?;
+
+        browser.pause().await;
+
+        login_step_one(config, browser, hl_vt.as_str()).await?;
+
+        browser.pause().await;
+
+        let secure_number_indices = login_step_two(selectors, browser).await?;
+
+        // e.g. the cookie's expired, or the device was removed from HL's list
+        if device_remembered && !secure_number_indices.is_empty() {
+            info!("HL didn't accept the remembered device, it'll be remembered again");
+        }
+
+        browser.pause().await;
+
+        let accounts_page =
+            submit_secure_number(config, selectors, browser, hl_vt, &secure_number_indices).await?;
+
+        if let Some(device_guard) = &device_guard {
+            if has_total(selectors, &accounts_page)? {
+                self.remember_device(browser, device_guard)?;
+            }
+        }
+
+        Ok(accounts_page)
+    }
+
+    fn device_store(&self) -> TokenStore {
+        TokenStore::new(
+            &self.ynab_config.config_path,
+            &format!("{}_device.json", self.account),
+        )
+    }
+
+    // The login's password & secure number, so the remembered device is forgotten once either
+    // changes
+    fn device_passphrase(&self) -> SecretString {
+        std::iter::once(&self.config.hl_password)
+            .chain(&self.config.hl_secure_numbers)
+            .map(|secret| secret.expose_secret())
+            .collect::<String>()
+            .into()
+    }
+
+    // Whether there was a remembered device to add to the browser
+    fn add_remembered_device(
+        &self,
+        browser: &Browser,
+        device_guard: &TokenStoreGuard,
+    ) -> Result<bool> {
+        let Some((sealed, _)) = device_guard.read::<Sealed>()? else {
+            return Ok(false);
+        };
+
+        match sealed.open(&self.device_passphrase()) {
+            Ok(cookie) => {
+                browser.add_cookies(
+                    HL_ACCOUNTS_URL,
+                    &format!(
+                        "{}={}",
+                        self.selectors.remember_device_cookie,
+                        cookie.expose_secret()
+                    ),
+                )?;
+                Ok(true)
+            }
+            Err(e) => {
+                info!("Forgetting the remembered HL device: {:#}", e);
+                device_guard.clear()?;
+                Ok(false)
+            }
+        }
+    }
+
+    // The cookie HL set for the device, or refreshed, kept for the next login
+    fn remember_device(&self, browser: &Browser, device_guard: &TokenStoreGuard) -> Result<()> {
+        match browser.cookie(HL_ACCOUNTS_URL, &self.selectors.remember_device_cookie)? {
+            Some(cookie) => {
+                device_guard.write(&Sealed::seal(&self.device_passphrase(), &cookie.into())?)
+            }
+            None => {
+                warn!(
+                    "HL didn't set a {} cookie to remember the device by, check REMEMBER_DEVICE_COOKIE & REMEMBER_DEVICE_FIELD",
+                    self.selectors.remember_device_cookie
+                );
+                device_guard.clear()
+            }
+        }
+    }
+
+    // The accounts page using a session from a login finished by hand, if it's still logged in
+    async fn get_home_page_with_session(
+        &self,
+        browser: &mut Browser,
+        cookies: &str,
+    ) -> Result<Option<String>> {
+        browser.add_cookies(HL_ACCOUNTS_URL, cookies)?;
+
+        let home_page = browser.get(HL_ACCOUNTS_URL).await?;
+
+        Ok(has_total(&self.selectors, &home_page)?.then_some(home_page))
+    }
+}
+
+impl GetYnabAccountConfig for Hl {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.account_config.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl Hl {
+    async fn log_in(&self) -> Result<Session> {
+        let mut browser = Browser::new(&self.config.browser, &self.ynab_config.http)?;
+
+        let home_page = self.get_home_page(&mut browser).await?;
+
+        Ok(Session {
+            logged_in_at: Instant::now(),
+            browser,
+            home_page,
+        })
+    }
+
+    async fn get_home_page(&self, browser: &mut Browser) -> Result<String> {
+        // A session handed back after the previous run's captcha
+        if let Some(cookies) = manual_login::take_session(&self.ynab_config, &self.account).await? {
+            if let Some(home_page) = self.get_home_page_with_session(browser, &cookies).await? {
+                return Ok(home_page);
+            }
+            info!("The session from the manual login has expired, logging in");
+        }
+
+        match self.login(browser).await {
+            Err(e) if e.downcast_ref::<CaptchaDetected>().is_some() => {
+                info!("{}, falling back to a manual login", e);
+
+                let cookies = manual_login::request_session(
+                    &self.ynab_config,
+                    &self.account,
+                    "HL",
+                    HL_LOGIN_URL,
+                )
+                .await?;
+
+                self.get_home_page_with_session(browser, &cookies)
+                    .await?
+                    .ok_or_else(|| anyhow!("The session from the manual login isn't logged in"))
+            }
+            home_page => home_page,
+        }
+    }
+
+    // The login's session from its last few minutes, otherwise a new one
+    async fn session(&self) -> Result<OwnedMappedMutexGuard<Option<Session>, Session>> {
+        let mut session = shared_session(&self.ynab_config.config_path, &self.config.hl_username)?
+            .lock_owned()
+            .await;
+
+        match &*session {
+            Some(session) if session.logged_in_at.elapsed() < SESSION_TTL => {
+                info!("Using the login from its last few minutes")
+            }
+            _ => *session = Some(self.log_in().await?),
+        }
+
+        Ok(OwnedMutexGuard::map(session, |session| {
+            session.as_mut().expect("The session was just logged in")
+        }))
+    }
+}
+
+impl GetBalance for Hl {
+    async fn get(&self) -> Result<f32> {
+        let home_page = self.session().await?.home_page.clone();
+
+        let hl_balance = match &self.config.hl_account {
+            Some(hl_account) => get_account_value(&self.selectors, hl_account, &home_page)?,
+            None => get_total(&self.selectors, &home_page)?,
+        };
+
+        Ok(hl_balance)
+    }
+}
+
+impl GetIncome for Hl {
+    // From the account's capital transactions page, each dividend or interest payment being one
+    async fn get_income(&self, from: NaiveDate, until: NaiveDate) -> Result<Vec<Income>> {
+        let mut session = self.session().await?;
+
+        let account_id = find_account_id(
+            &self.selectors,
+            self.config.hl_account.as_deref(),
+            &session.home_page,
+        )?;
+
+        session.browser.pause().await;
+
+        let page = session
+            .browser
+            .get(
+                &self
+                    .selectors
+                    .capital_transactions_url
+                    .replace("{}", &account_id),
+            )
+            .await?;
+
+        drop(session);
+
+        find_income(&self.selectors, &page, from, until)
+    }
+}
+
+fn parse_selector(selector: &str) -> Result<Selector> {
+    Selector::parse(selector).map_err(|e| anyhow!("Invalid selector {:?}: {:?}", selector, e))
+}
+
+fn has_total(selectors: &HlSelectors, home_page: &str) -> Result<bool> {
+    let document = Html::parse_fragment(home_page);
+
+    for column in &selectors.total_columns {
+        let selector = parse_selector(&selectors.total.replace("{}", &column.to_string()))?;
+        if document.select(&selector).next().is_none() {
+            return Ok(false);
+        }
+    }
+
+    Ok(true)
+}
+
+// Removes the login's details, form tokens, scripts & amounts from a page before it's checked in.
+// Amounts are replaced rather than removed so the totals' regex is still tested. Check a page for
+// anything else personal, e.g. names or account numbers, before checking it in.
+fn scrub(config: &HlConfig, html: &str) -> Result<String> {
+    let mut html = html.to_owned();
+
+    for secret in [
+        config.hl_username.as_str(),
+        config.hl_date_of_birth.as_str(),
+        config.hl_password.expose_secret(),
+    ] {
+        if !secret.is_empty() {
+            html = html.replace(secret, "REDACTED");
+        }
+    }
+
+    let html = Regex::new(r"(?s)<script\b[^>]*>.*?</script>")?.replace_all(&html, "");
+    let html =
+        Regex::new(r#"(<input\b[^>]*\bvalue=")[^"]*""#)?.replace_all(&html, r#"${1}REDACTED""#);
+    let html = Regex::new(r"£\s*[\d,]+(\.\d{2})?")?.replace_all(&html, "£1,234.56");
+
+    Ok(html.into_owned())
+}
+
+// Reports which of the selectors for the page match it, or all of them when the page isn't given,
+// to check them against a site change
+pub fn scrape_test(
+    selectors: &HlSelectors,
+    page: Option<HlPage>,
+    html: &str,
+) -> Result<Vec<SelectorReport>> {
+    let document = Html::parse_fragment(html);
+    let is_page = |p: HlPage| page.is_none() || page == Some(p);
+
+    let mut reports = vec![];
+
+    if is_page(HlPage::Login) {
+        let selector = parse_selector(&selectors.hl_vt)?;
+        reports.push(SelectorReport {
+            name: "HL_VT".to_owned(),
+            selector: selectors.hl_vt.clone(),
+            matched: document
+                .select(&selector)
+                .next()
+                .and_then(|node| node.value().attr("value"))
+                .map(str::to_owned),
+        });
+    }
+
+    if is_page(HlPage::SecureNumber) {
+        let regex = Regex::new(&selectors.secure_number_digit_regex)?;
+        for i in 1..=3 {
+            let selector_string = selectors.secure_number.replace("{}", &i.to_string());
+            let selector = parse_selector(&selector_string)?;
+            reports.push(SelectorReport {
+                name: format!("SECURE_NUMBER {}", i),
+                selector: selector_string,
+                matched: document
+                    .select(&selector)
+                    .next()
+                    .and_then(|node| node.value().attr("title"))
+                    .and_then(|title| regex.captures(title))
+                    .and_then(|captures| captures.get(1))
+                    .map(|digit| format!("digit {}", digit.as_str())),
+            });
+        }
+    }
+
+    if is_page(HlPage::Accounts) {
+        let selector = parse_selector(&selectors.account_rows)?;
+        let names = document
+            .select(&selector)
+            .map(|row| account_name(selectors, row))
+            .collect::<Result<Option<Vec<_>>>>()?;
+        let first_name = names.as_ref().and_then(|names| names.first().cloned());
+        reports.push(SelectorReport {
+            name: "ACCOUNT_ROWS".to_owned(),
+            selector: format!("{} {}", selectors.account_rows, selectors.account_name),
+            matched: names
+                .filter(|names| !names.is_empty())
+                .map(|names| names.join(", ")),
+        });
+
+        reports.push(SelectorReport {
+            name: "ACCOUNT_LINK".to_owned(),
+            selector: format!("{} {}", selectors.account_rows, selectors.account_link),
+            matched: find_account_id(selectors, first_name.as_deref(), html)
+                .ok()
+                .map(|id| format!("account {}", id)),
+        });
+
+        let regex = Regex::new(&selectors.total_regex)?;
+        for column in &selectors.total_columns {
+            let selector_string = selectors.total.replace("{}", &column.to_string());
+            let selector = parse_selector(&selector_string)?;
+            reports.push(SelectorReport {
+                name: format!("TOTAL {}", column),
+                selector: selector_string,
+                matched: document
+                    .select(&selector)
+                    .next()
+                    .and_then(|node| node.text().next())
+                    .and_then(|text| regex.captures(text))
+                    .and_then(|captures| captures.get(1))
+                    .map(|total| total.as_str().to_owned()),
+            });
+        }
+    }
+
+    if is_page(HlPage::CapitalTransactions) {
+        let income = find_income(selectors, html, NaiveDate::MIN, NaiveDate::MAX)?;
+        reports.push(SelectorReport {
+            name: "TRANSACTION_ROWS".to_owned(),
+            selector: selectors.transaction_rows.clone(),
+            matched: Some(income.len())
+                .filter(|&count| count > 0)
+                .map(|count| format!("{} dividends & interest payments", count)),
+        });
+    }
+
+    Ok(reports)
+}
+
+async fn get_hl_vt(selectors: &HlSelectors, browser: &mut Browser) -> Result<String> {
+    let text = browser.get(HL_LOGIN_URL).await?;
+    find_hl_vt(selectors, &text)
+}
+
+fn find_hl_vt(selectors: &HlSelectors, text: &str) -> Result<String> {
+    let document = Html::parse_fragment(text);
+    let selector_string = selectors.hl_vt.as_str();
+    let selector = parse_selector(selector_string)?;
+    let hl_vt = document
+        .select(&selector)
+        .next()
+        .ok_or_else(|| anyhow!("HL_VT {:?} didn't match the login page", selector_string))?
+        .value()
+        .attr("value")
+        .ok_or_else(|| {
+            anyhow!(
+                "HL_VT {:?} matched an element without a value",
+                selector_string
+            )
+        })?
+        .to_owned();
+
+    Ok(hl_vt)
+}
+
+async fn login_step_one(config: &HlConfig, browser: &Browser, hl_vt: &str) -> Result<()> {
+    let params = [
+        ("hl_vt", hl_vt),
+        ("username", config.hl_username.as_str()),
+        ("date-of-birth", config.hl_date_of_birth.as_str()),
+    ];
+    browser
+        .post_form(
+            "https://online.hl.co.uk/my-accounts/login-step-one",
+            &params,
+        )
+        .await?;
+    Ok(())
+}
+
+async fn login_step_two(selectors: &HlSelectors, browser: &mut Browser) -> Result<Vec<usize>> {
+    let text = browser
+        .get("https://online.hl.co.uk/my-accounts/login-step-two")
+        .await?;
+    find_secure_number_indices(selectors, &text)
+}
+
+// Empty when HL doesn't ask for the secure number, i.e. it accepted the remembered device
+fn find_secure_number_indices(selectors: &HlSelectors, text: &str) -> Result<Vec<usize>> {
+    let document = Html::parse_fragment(text);
+
+    let first = parse_selector(&selectors.secure_number.replace("{}", "1"))?;
+    if document.select(&first).next().is_none() {
+        return Ok(vec![]);
+    }
+
+    let regex = Regex::new(&selectors.secure_number_digit_regex)
+        .context("SECURE_NUMBER_DIGIT_REGEX is invalid")?;
+
+    let titles = (1..=3)
+        .map(|i| -> Result<usize> {
+            let selector_string = selectors.secure_number.replace("{}", &i.to_string());
+            let selector = parse_selector(&selector_string)?;
+            let title = document
+                .select(&selector)
+                .next()
+                .ok_or_else(|| {
+                    anyhow!(
+                        "SECURE_NUMBER {:?} didn't match the secure number page",
+                        selector_string
+                    )
+                })?
+                .value()
+                .attr("title")
+                .ok_or_else(|| {
+                    anyhow!(
+                        "SECURE_NUMBER {:?} matched an element without a title",
+                        selector_string
+                    )
+                })?;
+
+            let digit_match = regex
+                .captures(title)
+                .and_then(|captures| captures.get(1))
+                .ok_or_else(|| {
+                    anyhow!(
+                        "SECURE_NUMBER_DIGIT_REGEX didn't match the title {:?}",
+                        title
+                    )
+                })?
+                .as_str();
+            match digit_match.parse::<usize>() {
+                Ok(digit @ 1..=6) => Ok(digit - 1),
+                _ => Err(anyhow!(
+                    "SECURE_NUMBER_DIGIT_REGEX's group {:?} isn't a digit from 1 to 6",
+                    digit_match
+                )),
+            }
+        })
+        .try_collect::<Vec<_>>();
+
+    titles
+}
+
+async fn submit_secure_number(
+    config: &HlConfig,
+    selectors: &HlSelectors,
+    browser: &Browser,
+    hl_vt: String,
+    secure_number_indices: &[usize],
+) -> Result<String> {
+    let secure_number_fields = (1..)
+        .map(|i| format!("secure-number[{}]", i))
+        .zip(secure_number_indices)
+        .collect::<Vec<_>>();
+
+    let mut params = vec![
+        ("hl_vt", hl_vt.as_str()),
+        (
+            "online-password-verification",
+            config.hl_password.expose_secret(),
+        ),
+    ];
+    params.extend(secure_number_fields.iter().map(|(name, index)| {
+        (
+            name.as_str(),
+            config.hl_secure_numbers[**index].expose_secret(),
+        )
+    }));
+    if config.hl_remember_device {
+        params.push((selectors.remember_device_field.as_str(), "on"));
+    }
+    params.push(("submit", " Log in   "));
+
+    let text = browser
+        .post_form(
+            "https://online.hl.co.uk/my-accounts/login-step-two",
+            &params,
+        )
+        .await?;
+
+    Ok(text)
+}
+
+fn get_total(selectors: &HlSelectors, home_page: &str) -> Result<f32> {
+    let document = Html::parse_fragment(home_page);
+
+    let regex = Regex::new(&selectors.total_regex).context("TOTAL_REGEX is invalid")?;
+
+    selectors
+        .total_columns
+        .iter()
+        .map(|i| {
+            let selector_string = selectors.total.replace("{}", &i.to_string());
+            let selector = parse_selector(&selector_string)?;
+
+            let text = document
+                .select(&selector)
+                .next()
+                .and_then(|node| node.text().next())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "TOTAL {:?} didn't match any text on the accounts page",
+                        selector_string
+                    )
+                })?;
+
+            let value = regex
+                .captures(text)
+                .and_then(|captures| captures.get(1))
+                .ok_or_else(|| anyhow!("TOTAL_REGEX didn't match the total {:?}", text))?
+                .as_str();
+
+            amount::parse(value)
+        })
+        .sum()
+}
+
+fn account_name(selectors: &HlSelectors, row: ElementRef) -> Result<Option<String>> {
+    let selector = parse_selector(&selectors.account_name)?;
+
+    Ok(row
+        .select(&selector)
+        .next()
+        .map(|cell| cell.text().collect::<String>().trim().to_owned()))
+}
+
+// One account's row of the accounts page, summed like the total
+fn get_account_value(selectors: &HlSelectors, hl_account: &str, home_page: &str) -> Result<f32> {
+    let document = Html::parse_fragment(home_page);
+
+    let selector = parse_selector(&selectors.account_rows)?;
+    let rows = document
+        .select(&selector)
+        .map(|row| Ok((account_name(selectors, row)?, row)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let (_, row) = rows
+        .iter()
+        .find(|(name, _)| name.as_deref() == Some(hl_account))
+        .ok_or_else(|| {
+            anyhow!(
+                "No HL account {} on the accounts page, the accounts are: {}",
+                hl_account,
+                rows.iter()
+                    .filter_map(|(name, _)| name.as_deref())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )
+        })?;
+
+    let regex = Regex::new(&selectors.total_regex).context("TOTAL_REGEX is invalid")?;
+
+    selectors
+        .total_columns
+        .iter()
+        .map(|i| {
+            let selector_string = selectors.account_column.replace("{}", &i.to_string());
+            let selector = parse_selector(&selector_string)?;
+
+            let text = row
+                .select(&selector)
+                .next()
+                .and_then(|cell| cell.text().next())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "ACCOUNT_COLUMN {:?} didn't match any text in {}'s row",
+                        selector_string,
+                        hl_account
+                    )
+                })?;
+
+            let value = regex
+                .captures(text)
+                .and_then(|captures| captures.get(1))
+                .ok_or_else(|| {
+                    anyhow!("TOTAL_REGEX didn't match {}'s value {:?}", hl_account, text)
+                })?
+                .as_str();
+
+            amount::parse(value)
+        })
+        .sum()
+}
+
+// The id in the login's account's ACCOUNT_LINK, by its name like its value, or the only account's
+fn find_account_id(
+    selectors: &HlSelectors,
+    hl_account: Option<&str>,
+    home_page: &str,
+) -> Result<String> {
+    let document = Html::parse_fragment(home_page);
+
+    let selector = parse_selector(&selectors.account_rows)?;
+    let rows = document
+        .select(&selector)
+        .map(|row| Ok((account_name(selectors, row)?, row)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let row = match hl_account {
+        Some(hl_account) => rows
+            .iter()
+            .find(|(name, _)| name.as_deref() == Some(hl_account))
+            .ok_or_else(|| anyhow!("No HL account {} on the accounts page", hl_account))?,
+        None => match rows.as_slice() {
+            [row] => row,
+            _ => {
+                return Err(anyhow!(
+                    "The HL login has {} accounts, set HL_ACCOUNT to the one whose income to post",
+                    rows.len()
+                ))
+            }
+        },
+    };
+
+    let link_selector = parse_selector(&selectors.account_link)?;
+    let regex = Regex::new(&selectors.account_id_regex).context("ACCOUNT_ID_REGEX is invalid")?;
+
+    let href = row
+        .1
+        .select(&link_selector)
+        .next()
+        .and_then(|link| link.value().attr("href"))
+        .ok_or_else(|| anyhow!("ACCOUNT_LINK didn't match a link in the account's row"))?;
+
+    Ok(regex
+        .captures(href)
+        .and_then(|captures| captures.get(1))
+        .ok_or_else(|| {
+            anyhow!(
+                "ACCOUNT_ID_REGEX didn't match the account's link {:?}",
+                href
+            )
+        })?
+        .as_str()
+        .to_owned())
+}
+
+fn cell_text(row: ElementRef, selector: &Selector) -> String {
+    row.select(selector)
+        .next()
+        .map(|cell| cell.text().collect::<String>().trim().to_owned())
+        .unwrap_or_default()
+}
+
+// The dividends & interest on a capital transactions page from `from` up to but not including
+// `until`, skipping its other transactions
+fn find_income(
+    selectors: &HlSelectors,
+    page: &str,
+    from: NaiveDate,
+    until: NaiveDate,
+) -> Result<Vec<Income>> {
+    let document = Html::parse_fragment(page);
+
+    let rows = parse_selector(&selectors.transaction_rows)?;
+    let date = parse_selector(&selectors.transaction_date)?;
+    let reference = parse_selector(&selectors.transaction_reference)?;
+    let description = parse_selector(&selectors.transaction_description)?;
+    let value = parse_selector(&selectors.transaction_value)?;
+
+    let dividend_regex =
+        Regex::new(&selectors.dividend_regex).context("DIVIDEND_REGEX is invalid")?;
+    let interest_regex =
+        Regex::new(&selectors.interest_regex).context("INTEREST_REGEX is invalid")?;
+
+    document
+        .select(&rows)
+        .filter_map(|row| {
+            let description = cell_text(row, &description);
+
+            let (kind, name) = match dividend_regex.captures(&description) {
+                Some(captures) => (
+                    IncomeKind::Dividend,
+                    captures.get(1).map_or(&*description, |name| name.as_str()),
+                ),
+                None if interest_regex.is_match(&description) => (IncomeKind::Interest, "HL"),
+                None => return None,
+            };
+            let name = name.trim().to_owned();
+
+            Some((|| {
+                let date_text = cell_text(row, &date);
+                let value_text = cell_text(row, &value);
+
+                let date =
+                    NaiveDate::parse_from_str(&date_text, &selectors.transaction_date_format)
+                        .with_context(|| {
+                            format!("{:?}'s date {:?} is invalid", description, date_text)
+                        })?;
+
+                // Without a reference, it's told apart by what's shown of it
+                let id = match cell_text(row, &reference) {
+                    reference if reference.is_empty() => {
+                        format!("{}:{}:{}", date_text, description, value_text)
+                    }
+                    reference => reference,
+                };
+
+                Ok(Income {
+                    id,
+                    date,
+                    kind,
+                    name,
+                    amount: amount::parse(&value_text)?,
+                })
+            })())
+        })
+        .filter(|income| {
+            income
+                .as_ref()
+                .map_or(true, |income| from <= income.date && income.date < until)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn selectors() -> HlSelectors {
+        HlSelectors::load(None).unwrap()
+    }
+
+    fn date(date: &str) -> NaiveDate {
+        date.parse().unwrap()
+    }
+
+    #[test]
+    fn finds_hl_vt_on_the_login_page() {
+        let hl_vt = find_hl_vt(&selectors(), HlPage::Login.fixture()).unwrap();
+
+        assert_eq!(hl_vt, "REDACTED");
+    }
+
+    #[test]
+    fn finds_secure_number_indices() {
+        let indices =
+            find_secure_number_indices(&selectors(), HlPage::SecureNumber.fixture()).unwrap();
+
+        assert_eq!(indices, vec![0, 3, 5]);
+    }
+
+    #[test]
+    fn no_secure_number_indices_for_a_remembered_device() {
+        let indices = find_secure_number_indices(&selectors(), HlPage::Accounts.fixture()).unwrap();
+
+        assert!(indices.is_empty());
+    }
+
+    #[test]
+    fn sums_the_total_columns() {
+        let total = get_total(&selectors(), HlPage::Accounts.fixture()).unwrap();
+
+        assert_eq!(total, 2469.12);
+    }
+
+    #[test]
+    fn sums_an_accounts_columns() {
+        let value = get_account_value(
+            &selectors(),
+            "Stocks & Shares ISA",
+            HlPage::Accounts.fixture(),
+        )
+        .unwrap();
+
+        assert_eq!(value, 2469.12);
+    }
+
+    #[test]
+    fn an_unknown_account_lists_the_accounts() {
+        let e = get_account_value(&selectors(), "SIPP", HlPage::Accounts.fixture()).unwrap_err();
+
+        assert!(e.to_string().contains("Stocks & Shares ISA"), "{}", e);
+    }
+
+    #[test]
+    fn finds_the_accounts_id_in_its_link() {
+        let selectors = selectors();
+        let page = HlPage::Accounts.fixture();
+
+        assert_eq!(find_account_id(&selectors, None, page).unwrap(), "22");
+        assert_eq!(
+            find_account_id(&selectors, Some("Stocks & Shares ISA"), page).unwrap(),
+            "22"
+        );
+        assert!(find_account_id(&selectors, Some("SIPP"), page).is_err());
+    }
+
+    #[test]
+    fn finds_dividends_and_interest_on_the_capital_transactions_page() {
+        let income = find_income(
+            &selectors(),
+            HlPage::CapitalTransactions.fixture(),
+            date("2026-09-01"),
+            date("2026-10-03"),
+        )
+        .unwrap();
+
+        let income = income
+            .iter()
+            .map(|income| {
+                (
+                    income.id.as_str(),
+                    income.date.to_string(),
+                    income.kind,
+                    income.name.as_str(),
+                    income.amount,
+                )
+            })
+            .collect::<Vec<_>>();
+
+        assert_eq!(
+            income,
+            vec![
+                (
+                    "INT261002",
+                    "2026-10-02".to_owned(),
+                    IncomeKind::Interest,
+                    "HL",
+                    1.23
+                ),
+                (
+                    "DIV2609281",
+                    "2026-09-28".to_owned(),
+                    IncomeKind::Dividend,
+                    "Vanguard FTSE Global All Cap Index",
+                    12.34
+                ),
+                (
+                    "01/09/2026:Dividend Legal & General Group plc:5.60",
+                    "2026-09-01".to_owned(),
+                    IncomeKind::Dividend,
+                    "Legal & General Group plc",
+                    5.6
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn only_finds_income_in_the_dates() {
+        let income = find_income(
+            &selectors(),
+            HlPage::CapitalTransactions.fixture(),
+            date("2026-09-02"),
+            date("2026-10-02"),
+        )
+        .unwrap();
+
+        let ids = income
+            .iter()
+            .map(|income| income.id.as_str())
+            .collect::<Vec<_>>();
+
+        assert_eq!(ids, vec!["DIV2609281"]);
+    }
+
+    #[test]
+    fn the_accounts_page_has_no_income() {
+        let income = find_income(
+            &selectors(),
+            HlPage::Accounts.fixture(),
+            NaiveDate::MIN,
+            NaiveDate::MAX,
+        )
+        .unwrap();
+
+        assert!(income.is_empty());
+    }
+
+    #[test]
+    fn the_login_page_has_no_total() {
+        let e = get_total(&selectors(), HlPage::Login.fixture()).unwrap_err();
+
+        assert!(e.to_string().starts_with("TOTAL "), "{}", e);
+    }
+}
@@ -0,0 +1,41 @@
+use anyhow::Result;
+use serde::Deserialize;
+
+use crate::{AccountConfig, GetBalance, GetYnabAccountConfig, YnabAccountConfig};
+
+// Reports a fixed balance, for trying out a config without logging in to an institution
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct MockConfig {
+    #[serde(default)]
+    pub balance: f32,
+}
+
+#[derive(Clone, Debug)]
+pub struct Mock {
+    ynab_account_id: String,
+    config: MockConfig,
+}
+
+impl Mock {
+    pub fn new(account: &AccountConfig) -> Result<Self> {
+        Ok(Mock {
+            ynab_account_id: account.ynab_account_id.clone(),
+            config: account.provider_config()?,
+        })
+    }
+}
+
+impl GetYnabAccountConfig for Mock {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for Mock {
+    async fn get(&self) -> Result<f32> {
+        Ok(self.config.balance)
+    }
+}
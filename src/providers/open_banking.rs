@@ -0,0 +1,865 @@
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::URL_SAFE_NO_PAD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use config::FileFormat;
+use log::{info, warn};
+use openssl::{
+    hash::MessageDigest,
+    pkey::PKey,
+    rsa::Padding,
+    sign::{RsaPssSaltlen, Signer},
+};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use crate::{
+    error::ConfigInvalid,
+    history::{History, RunState},
+    http, is_renewal_due,
+    oauth::{self, OAuthClient, TokenResponse},
+    token_store::{TokenStore, TokenStoreGuard},
+    AccountConfig, AuthPending, Config, GetBalance, GetYnabAccountConfig, YnabAccountConfig,
+};
+
+static PRESETS_DIRNAME: &str = "open_banking_presets";
+
+// The presets shipped with the updater, by name
+static SHIPPED_PRESETS: [(&str, &str); 3] = [
+    (
+        "barclays",
+        include_str!("open_banking_presets/barclays.toml"),
+    ),
+    ("lloyds", include_str!("open_banking_presets/lloyds.toml")),
+    ("natwest", include_str!("open_banking_presets/natwest.toml")),
+];
+
+// An account access consent lasts 90 days, after which it's given again
+static CONSENT_DAYS: i64 = 90;
+// FAPI's longest, so a login link that isn't opened within it is replaced by the next run's
+static REQUEST_OBJECT_MINUTES: i64 = 60;
+
+// The order a balance's type is preferred in, the booked balance being what the bank's statement
+// would show
+static BALANCE_TYPES: [&str; 4] = [
+    "InterimBooked",
+    "ClosingBooked",
+    "InterimAvailable",
+    "Expected",
+];
+
+// Where a bank's Open Banking APIs are, either its live ones or its sandbox's
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct BankEndpoints {
+    // The bank's organisation ID in the Open Banking Directory
+    pub financial_id: String,
+    // The request object's audience
+    pub issuer: String,
+    pub auth_url: String,
+    pub token_url: String,
+    // The Account & Transaction API's base, e.g. `…/open-banking/v3.1/aisp`
+    pub api_url: String,
+}
+
+// A UK bank's account read through Open Banking's Account & Transaction API, as an AISP. That
+// needs the updater registered with the bank, with an OBWAC certificate for mTLS & an OBSeal key
+// the login's request object is signed with. A bank is a preset, either one of `SHIPPED_PRESETS`
+// or read from `open_banking_presets/<PRESET>.toml` in the config directory, which overrides a
+// shipped one's settings, as can the account.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct OpenBankingPreset {
+    pub title: String,
+    pub live: BankEndpoints,
+    pub sandbox: Option<BankEndpoints>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct OpenBankingConfig {
+    // The registration's client ID, & its secret for a bank that doesn't authenticate the client
+    // by its certificate
+    pub open_banking_client_id: String,
+    pub open_banking_client_secret: Option<SecretString>,
+    // Paths to the transport certificate & its key, as PEM
+    pub open_banking_transport_certificate: String,
+    pub open_banking_transport_key: String,
+    // The path to the signing key, as PEM, & its `kid` in the directory
+    pub open_banking_signing_key: String,
+    pub open_banking_signing_key_id: String,
+    // Which of the consented accounts it is, by its number's last digits, for a consent to more
+    // than one
+    pub open_banking_account: Option<String>,
+    // Uses the preset's sandbox rather than the bank
+    #[serde(default)]
+    pub open_banking_sandbox: bool,
+
+    // As for `schwab`
+    pub callback_host: String,
+    #[serde(default = "default_callback_port")]
+    pub callback_port: u16,
+    pub callback_path: Option<String>,
+    // Has to be one of the registration's redirect URIs
+    pub open_banking_redirect_uri: Option<String>,
+    #[serde(default = "default_callback_tls")]
+    pub callback_tls: bool,
+}
+
+fn default_callback_port() -> u16 {
+    9999
+}
+
+fn default_callback_tls() -> bool {
+    true
+}
+
+#[derive(Clone, Debug)]
+pub struct OpenBanking {
+    account: String,
+    ynab_account_id: String,
+    listen_addr: String,
+    redirect_uri: String,
+    callback_path: Option<String>,
+    config: OpenBankingConfig,
+    title: String,
+    endpoints: BankEndpoints,
+    ynab_config: Config,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ClientToken {
+    access_token: SecretString,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Response<T> {
+    data: T,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Consent {
+    consent_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Accounts {
+    #[serde(default)]
+    account: Vec<Account>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Account {
+    account_id: String,
+    // e.g. a sort code & account number
+    #[serde(default)]
+    account: Vec<AccountIdentification>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AccountIdentification {
+    identification: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Balances {
+    #[serde(default)]
+    balance: Vec<Balance>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Balance {
+    amount: BalanceAmount,
+    credit_debit_indicator: String,
+    #[serde(rename = "Type")]
+    kind: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BalanceAmount {
+    amount: String,
+}
+
+impl OpenBankingPreset {
+    pub fn load(config_path: &str, account_config: &AccountConfig) -> Result<Self> {
+        let mut builder = config::Config::builder();
+
+        if let Some(preset) = account_config
+            .settings
+            .get("PRESET")
+            .and_then(|preset| preset.as_str())
+        {
+            let shipped = SHIPPED_PRESETS
+                .iter()
+                .find(|(name, _)| *name == preset)
+                .map(|(_, shipped)| *shipped);
+
+            if let Some(shipped) = shipped {
+                builder = builder.add_source(config::File::from_str(shipped, FileFormat::Toml));
+            }
+
+            builder = builder.add_source(
+                config::File::with_name(&format!(
+                    "{}/{}/{}.toml",
+                    config_path, PRESETS_DIRNAME, preset
+                ))
+                .required(shipped.is_none()),
+            );
+        }
+
+        let builder = builder.add_source(config::File::from_str(
+            &serde_json::to_string(&account_config.settings)?,
+            FileFormat::Json,
+        ));
+
+        Ok(builder.build()?.try_deserialize::<OpenBankingPreset>()?)
+    }
+
+    // The sandbox's with OPEN_BANKING_SANDBOX, otherwise the bank's own
+    pub fn endpoints(&self, sandbox: bool) -> Result<&BankEndpoints> {
+        match (sandbox, &self.sandbox) {
+            (false, _) => Ok(&self.live),
+            (true, Some(sandbox)) => Ok(sandbox),
+            (true, None) => Err(anyhow!(
+                "{}'s preset has no SANDBOX for OPEN_BANKING_SANDBOX",
+                self.title
+            )
+            .context(ConfigInvalid)),
+        }
+    }
+}
+
+// How long before its consent expires it's given again
+pub fn renewal_window(config: &Config) -> Duration {
+    Duration::days(config.token_expiry_warning_days)
+}
+
+impl OpenBanking {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        let config = account_config.provider_config::<OpenBankingConfig>()?;
+        let preset = OpenBankingPreset::load(&ynab_config.config_path, account_config)
+            .context(ConfigInvalid)?;
+
+        let listen_addr = format!("{}:{}", config.callback_host, config.callback_port);
+
+        let (redirect_uri, callback_path) = oauth::callback_redirect(
+            &listen_addr,
+            config.callback_tls,
+            &ynab_config.callback_access,
+            config.open_banking_redirect_uri.as_deref(),
+            config.callback_path.as_deref(),
+        );
+
+        Ok(OpenBanking {
+            account: account.to_owned(),
+            ynab_account_id: account_config.ynab_account_id.clone(),
+            listen_addr,
+            redirect_uri,
+            callback_path,
+            endpoints: preset.endpoints(config.open_banking_sandbox)?.clone(),
+            title: preset.title,
+            config,
+            ynab_config: ynab_config.clone(),
+        })
+    }
+
+    pub async fn auth(&self) -> Result<()> {
+        let client = self.client()?;
+
+        let token_guard = self.token_store().lock().await?;
+
+        self.login(&client, &token_guard, None).await?;
+
+        History::open(&self.ynab_config.config_path)?.set_run_state(
+            &self.account,
+            RunState::Idle,
+            None,
+        )?;
+
+        println!("Logged in to {}", self.title);
+
+        Ok(())
+    }
+
+    // Counted from when the consent was given
+    pub fn consent_expires_at(&self) -> Result<Option<DateTime<Utc>>> {
+        Ok(History::open(&self.ynab_config.config_path)?
+            .get_consent_started_at(&self.account)?
+            .map(|started_at| started_at + Duration::days(CONSENT_DAYS)))
+    }
+
+    fn token_store(&self) -> TokenStore {
+        TokenStore::new(
+            &self.ynab_config.config_path,
+            &format!("{}_token.json", self.account),
+        )
+    }
+
+    // Every request to the bank is over mTLS with the transport certificate
+    fn client(&self) -> Result<reqwest::Client> {
+        let read =
+            |path: &str| std::fs::read(path).with_context(|| format!("Failed to read {}", path));
+
+        let identity = reqwest::Identity::from_pkcs8_pem(
+            &read(&self.config.open_banking_transport_certificate)?,
+            &read(&self.config.open_banking_transport_key)?,
+        )?;
+
+        Ok(http::client_builder(&self.ynab_config.http)?
+            .identity(identity)
+            .build()?)
+    }
+
+    fn oauth_client(&self, auth_params: Vec<(String, String)>) -> OAuthClient {
+        OAuthClient {
+            provider: self.account.clone(),
+            title: self.title.clone(),
+            auth_url: self.endpoints.auth_url.clone(),
+            token_url: self.endpoints.token_url.clone(),
+            client_id: self.config.open_banking_client_id.clone(),
+            client_secret: self
+                .config
+                .open_banking_client_secret
+                .clone()
+                .unwrap_or_else(|| SecretString::from(String::new())),
+            basic_auth: false,
+            redirect_uri: self.redirect_uri.clone(),
+            scope: Some("openid accounts".to_owned()),
+            listen_addr: self.listen_addr.clone(),
+            callback_path: self.callback_path.clone(),
+            access: self.ynab_config.callback_access.clone(),
+            resolve_authorize_redirect: false,
+            tls: self.config.callback_tls,
+            http: self.ynab_config.http.clone(),
+            auth_params,
+        }
+    }
+
+    // The client's own token, for creating the consent
+    async fn client_token(&self, client: &reqwest::Client) -> Result<SecretString> {
+        let mut params = vec![
+            ("grant_type", "client_credentials"),
+            ("scope", "accounts"),
+            ("client_id", self.config.open_banking_client_id.as_str()),
+        ];
+        if let Some(client_secret) = &self.config.open_banking_client_secret {
+            params.push(("client_secret", client_secret.expose_secret()));
+        }
+
+        let token = http::send(client, client.post(&self.endpoints.token_url).form(&params))
+            .await?
+            .error_for_status()?
+            .json::<ClientToken>()
+            .await?;
+
+        Ok(token.access_token)
+    }
+
+    // A consent to read the accounts & their balances, which the login then authorises
+    async fn create_consent(&self, client: &reqwest::Client) -> Result<String> {
+        let client_token = self.client_token(client).await?;
+
+        let consent = http::send(
+            client,
+            client
+                .post(format!(
+                    "{}/account-access-consents",
+                    self.endpoints.api_url
+                ))
+                .bearer_auth(client_token.expose_secret())
+                .header("x-fapi-financial-id", &self.endpoints.financial_id)
+                .json(&json!({
+                    "Data": {
+                        "Permissions": ["ReadAccountsBasic", "ReadAccountsDetail", "ReadBalances"],
+                    },
+                    "Risk": {},
+                })),
+        )
+        .await?
+        .error_for_status()?
+        .json::<Response<Consent>>()
+        .await
+        .with_context(|| format!("Failed to read {}'s consent", self.title))?;
+
+        Ok(consent.data.consent_id)
+    }
+
+    // The login link's parameters, which are all signed into its request object
+    async fn consent_params(&self, client: &reqwest::Client) -> Result<Vec<(String, String)>> {
+        let consent_id = self.create_consent(client).await?;
+
+        let state = oauth::new_state();
+        let nonce = oauth::new_state();
+        let now = Utc::now();
+
+        let claims = json!({
+            "iss": self.config.open_banking_client_id,
+            "aud": self.endpoints.issuer,
+            "response_type": "code id_token",
+            "client_id": self.config.open_banking_client_id,
+            "redirect_uri": self.redirect_uri,
+            "scope": "openid accounts",
+            "state": state,
+            "nonce": nonce,
+            "iat": now.timestamp(),
+            "nbf": now.timestamp(),
+            "exp": (now + Duration::minutes(REQUEST_OBJECT_MINUTES)).timestamp(),
+            "claims": {
+                "userinfo": {
+                    "openbanking_intent_id": { "value": consent_id, "essential": true },
+                },
+                "id_token": {
+                    "openbanking_intent_id": { "value": consent_id, "essential": true },
+                    "acr": { "essential": true, "values": ["urn:openbanking:psd2:sca"] },
+                },
+            },
+        });
+
+        let signing_key = std::fs::read(&self.config.open_banking_signing_key)
+            .with_context(|| format!("Failed to read {}", self.config.open_banking_signing_key))?;
+
+        let request = sign(
+            &signing_key,
+            &self.config.open_banking_signing_key_id,
+            &claims,
+        )?;
+
+        Ok([
+            ("response_type", "code id_token".to_owned()),
+            ("state", state),
+            ("nonce", nonce),
+            ("request", request),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value))
+        .collect())
+    }
+
+    // A pending login's link is resumed while its request object's good, otherwise there's a new
+    // consent to log in to
+    async fn auth_params(&self, client: &reqwest::Client) -> Result<Vec<(String, String)>> {
+        match oauth::pending_login(&self.ynab_config.config_path, &self.account)? {
+            Some(pending_auth)
+                if Utc::now() - pending_auth.created_at
+                    < Duration::minutes(REQUEST_OBJECT_MINUTES) =>
+            {
+                Ok(vec![])
+            }
+            Some(_) => {
+                History::open(&self.ynab_config.config_path)?.clear_pending_auth(&self.account)?;
+                self.consent_params(client).await
+            }
+            None => self.consent_params(client).await,
+        }
+    }
+
+    async fn login(
+        &self,
+        client: &reqwest::Client,
+        token_guard: &TokenStoreGuard,
+        ynab_config: Option<&Config>,
+    ) -> Result<TokenResponse> {
+        self.oauth_client(self.auth_params(client).await?)
+            .login(
+                client,
+                &self.ynab_config.config_path,
+                token_guard,
+                ynab_config,
+            )
+            .await
+    }
+
+    // A renewal's waited for without the token's lock, so other runs carry on meanwhile, & the
+    // token's read again if it isn't answered, as it may have expired during the wait
+    async fn get_access_token(&self, client: &reqwest::Client) -> Result<TokenResponse> {
+        let token = self.current_access_token(client).await?;
+
+        let Some(expires_at) = self.consent_expires_at()? else {
+            return Ok(token);
+        };
+
+        if !is_renewal_due(
+            &self.ynab_config,
+            &self.account,
+            expires_at,
+            renewal_window(&self.ynab_config),
+        )? {
+            return Ok(token);
+        }
+
+        info!(
+            "{}'s consent expires at {}, renewing it",
+            self.account, expires_at
+        );
+
+        match self
+            .oauth_client(self.auth_params(client).await?)
+            .renew(
+                client,
+                &self.ynab_config.config_path,
+                &self.token_store(),
+                &self.ynab_config,
+                expires_at,
+            )
+            .await
+        {
+            Ok(renewed_token) => Ok(renewed_token),
+            Err(e) if e.downcast_ref::<AuthPending>().is_some() => {
+                info!("{}, using the current consent", e);
+                self.current_access_token(client).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // The cached token while it's valid, refreshed once it isn't, or a new consent's once the 90
+    // days are up
+    async fn current_access_token(&self, client: &reqwest::Client) -> Result<TokenResponse> {
+        let token_guard = self.token_store().lock().await?;
+
+        let expires_at = self.consent_expires_at()?;
+
+        let token = match token_guard.read::<TokenResponse>()? {
+            // Refreshed a minute early so it can't expire mid-run
+            Some((token, written_at))
+                if Utc::now() < written_at + Duration::seconds(token.expires_in as i64 - 60) =>
+            {
+                token
+            }
+            Some((token, _)) if expires_at.is_some_and(|expires_at| Utc::now() < expires_at) => {
+                match self
+                    .oauth_client(vec![])
+                    .refresh(client, &token.refresh_token)
+                    .await
+                {
+                    Ok(token) => {
+                        token_guard.write(&token)?;
+                        token
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Failed to refresh the {} token, logging in again: {:#?}",
+                            self.title, e
+                        );
+                        return self
+                            .login(client, &token_guard, Some(&self.ynab_config))
+                            .await;
+                    }
+                }
+            }
+            _ => {
+                return self
+                    .login(client, &token_guard, Some(&self.ynab_config))
+                    .await
+            }
+        };
+
+        Ok(token)
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        client: &reqwest::Client,
+        token: &TokenResponse,
+        path: &str,
+    ) -> Result<T> {
+        Ok(http::send(
+            client,
+            client
+                .get(format!("{}{}", self.endpoints.api_url, path))
+                .bearer_auth(token.access_token.expose_secret())
+                .header("x-fapi-financial-id", &self.endpoints.financial_id)
+                .header(reqwest::header::ACCEPT, "application/json"),
+        )
+        .await?
+        .error_for_status()?
+        .json::<Response<T>>()
+        .await
+        .with_context(|| format!("Failed to read {}'s {}", self.title, path))?
+        .data)
+    }
+}
+
+// A JWS signed with PS256, as Open Banking's request objects are
+fn sign(key_pem: &[u8], key_id: &str, claims: &Value) -> Result<String> {
+    let key = PKey::private_key_from_pem(key_pem)?;
+
+    let header = json!({ "alg": "PS256", "kid": key_id, "typ": "JWT" });
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(&header)?),
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims)?)
+    );
+
+    let mut signer = Signer::new(MessageDigest::sha256(), &key)?;
+    signer.set_rsa_padding(Padding::PKCS1_PSS)?;
+    signer.set_rsa_mgf1_md(MessageDigest::sha256())?;
+    signer.set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)?;
+    signer.update(signing_input.as_bytes())?;
+
+    Ok(format!(
+        "{}.{}",
+        signing_input,
+        URL_SAFE_NO_PAD.encode(signer.sign_to_vec()?)
+    ))
+}
+
+// The account whose number ends with `account_number`, otherwise the consent's only one
+fn find_account<'a>(accounts: &'a [Account], account_number: Option<&str>) -> Result<&'a Account> {
+    let numbers = || {
+        accounts
+            .iter()
+            .flat_map(|account| &account.account)
+            .map(|identification| {
+                let number = &identification.identification;
+                &number[number.len().saturating_sub(4)..]
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    };
+
+    match (account_number, accounts) {
+        (Some(account_number), _) => accounts
+            .iter()
+            .find(|account| {
+                account
+                    .account
+                    .iter()
+                    .any(|identification| identification.identification.ends_with(account_number))
+            })
+            .ok_or_else(|| {
+                anyhow!(
+                    "No consented account ends with {}, they end with: {}",
+                    account_number,
+                    numbers()
+                )
+                .context(ConfigInvalid)
+            }),
+        (None, [account]) => Ok(account),
+        (None, []) => Err(anyhow!("The consent has no accounts")),
+        (None, _) => Err(anyhow!(
+            "The consent has accounts ending with {}, so OPEN_BANKING_ACCOUNT has to say which",
+            numbers()
+        )
+        .context(ConfigInvalid)),
+    }
+}
+
+// The most preferred of `BALANCE_TYPES` there is, negative when it's owed
+fn pick_balance(balances: &[Balance]) -> Result<f32> {
+    let balance = BALANCE_TYPES
+        .iter()
+        .find_map(|kind| balances.iter().find(|balance| &balance.kind == kind))
+        .or_else(|| balances.first())
+        .ok_or_else(|| anyhow!("The account has no balances"))?;
+
+    let amount = balance
+        .amount
+        .amount
+        .parse::<f32>()
+        .with_context(|| format!("Failed to read the balance {:?}", balance.amount.amount))?;
+
+    Ok(match balance.credit_debit_indicator.as_str() {
+        "Debit" => -amount.abs(),
+        _ => amount,
+    })
+}
+
+impl GetYnabAccountConfig for OpenBanking {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for OpenBanking {
+    async fn get(&self) -> Result<f32> {
+        let client = self.client()?;
+
+        let token = self.get_access_token(&client).await?;
+
+        let accounts = self.get::<Accounts>(&client, &token, "/accounts").await?;
+        let account = find_account(
+            &accounts.account,
+            self.config.open_banking_account.as_deref(),
+        )?;
+
+        let balances = self
+            .get::<Balances>(
+                &client,
+                &token,
+                &format!("/accounts/{}/balances", account.account_id),
+            )
+            .await?;
+
+        pick_balance(&balances.balance)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::{rsa::Rsa, sign::Verifier};
+
+    fn account_config(settings: Value) -> AccountConfig {
+        let mut account_config = json!({
+            "PROVIDER": "open_banking",
+            "YNAB_ACCOUNT_ID": "account",
+        });
+        account_config
+            .as_object_mut()
+            .unwrap()
+            .extend(settings.as_object().unwrap().clone());
+
+        serde_json::from_value(account_config).unwrap()
+    }
+
+    #[test]
+    fn loads_the_shipped_presets_with_their_sandboxes() {
+        for (name, _) in SHIPPED_PRESETS {
+            let preset =
+                OpenBankingPreset::load("/nonexistent", &account_config(json!({ "PRESET": name })))
+                    .unwrap();
+
+            assert_eq!(preset.title.to_lowercase(), name);
+
+            let live = preset.endpoints(false).unwrap();
+            let sandbox = preset.endpoints(true).unwrap();
+            assert_ne!(live.api_url, sandbox.api_url);
+        }
+    }
+
+    #[test]
+    fn the_account_overrides_a_shipped_preset() {
+        let preset = OpenBankingPreset::load(
+            "/nonexistent",
+            &account_config(json!({
+                "PRESET": "natwest",
+                "SANDBOX": { "API_URL": "https://localhost/aisp" },
+            })),
+        )
+        .unwrap();
+
+        let sandbox = preset.endpoints(true).unwrap();
+        assert_eq!(sandbox.api_url, "https://localhost/aisp");
+        assert_eq!(sandbox.financial_id, preset.live.financial_id);
+    }
+
+    #[test]
+    fn a_preset_without_a_sandbox_is_invalid_in_one() {
+        let preset = OpenBankingPreset {
+            title: "Bank".to_owned(),
+            live: OpenBankingPreset::load(
+                "/nonexistent",
+                &account_config(json!({ "PRESET": "barclays" })),
+            )
+            .unwrap()
+            .live,
+            sandbox: None,
+        };
+
+        assert!(preset
+            .endpoints(true)
+            .unwrap_err()
+            .downcast_ref::<ConfigInvalid>()
+            .is_some());
+    }
+
+    #[test]
+    fn signs_the_request_object_with_ps256() {
+        let key = PKey::from_rsa(Rsa::generate(2048).unwrap()).unwrap();
+
+        let jws = sign(
+            &key.private_key_to_pem_pkcs8().unwrap(),
+            "kid",
+            &json!({ "state": "state" }),
+        )
+        .unwrap();
+
+        let (signing_input, signature) = jws.rsplit_once('.').unwrap();
+        let (header, claims) = signing_input.split_once('.').unwrap();
+
+        let header: Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(header).unwrap()).unwrap();
+        assert_eq!(header["alg"], "PS256");
+        assert_eq!(header["kid"], "kid");
+
+        let claims: Value =
+            serde_json::from_slice(&URL_SAFE_NO_PAD.decode(claims).unwrap()).unwrap();
+        assert_eq!(claims["state"], "state");
+
+        let mut verifier = Verifier::new(MessageDigest::sha256(), &key).unwrap();
+        verifier.set_rsa_padding(Padding::PKCS1_PSS).unwrap();
+        verifier.set_rsa_mgf1_md(MessageDigest::sha256()).unwrap();
+        verifier
+            .set_rsa_pss_saltlen(RsaPssSaltlen::DIGEST_LENGTH)
+            .unwrap();
+        verifier.update(signing_input.as_bytes()).unwrap();
+        assert!(verifier
+            .verify(&URL_SAFE_NO_PAD.decode(signature).unwrap())
+            .unwrap());
+    }
+
+    fn accounts() -> Vec<Account> {
+        serde_json::from_value::<Response<Accounts>>(json!({
+            "Data": {
+                "Account": [
+                    {
+                        "AccountId": "a",
+                        "Account": [{ "SchemeName": "UK.OBIE.SortCodeAccountNumber", "Identification": "20000312345678" }],
+                    },
+                    {
+                        "AccountId": "b",
+                        "Account": [{ "SchemeName": "UK.OBIE.SortCodeAccountNumber", "Identification": "20000387654321" }],
+                    },
+                ],
+            },
+        }))
+        .unwrap()
+        .data
+        .account
+    }
+
+    #[test]
+    fn finds_the_account_by_its_number() {
+        assert_eq!(
+            find_account(&accounts(), Some("4321")).unwrap().account_id,
+            "b"
+        );
+        assert!(find_account(&accounts(), None)
+            .unwrap_err()
+            .downcast_ref::<ConfigInvalid>()
+            .is_some());
+    }
+
+    #[test]
+    fn prefers_the_booked_balance_with_debits_negative() {
+        let balances = serde_json::from_value::<Response<Balances>>(json!({
+            "Data": {
+                "Balance": [
+                    { "Amount": { "Amount": "80.00", "Currency": "GBP" }, "CreditDebitIndicator": "Credit", "Type": "InterimAvailable" },
+                    { "Amount": { "Amount": "120.50", "Currency": "GBP" }, "CreditDebitIndicator": "Debit", "Type": "InterimBooked" },
+                ],
+            },
+        }))
+        .unwrap()
+        .data
+        .balance;
+
+        assert_eq!(pick_balance(&balances).unwrap(), -120.5);
+        assert_eq!(pick_balance(&balances[..1]).unwrap(), 80.0);
+    }
+}
@@ -0,0 +1,242 @@
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use log::info;
+use secrecy::SecretString;
+use serde::Deserialize;
+use std::collections::BTreeMap;
+
+use crate::{
+    backfill::GetHistory,
+    history::History,
+    http::{self, HttpConfig},
+    AccountConfig, Config, GetBalance, GetYnabAccountConfig, YnabAccountConfig,
+};
+
+// A home's value, for a YNAB asset tracking account. A value set by hand, with `set-value` or
+// through the web UI, is used as it is. Otherwise it's index-linked: the value at `BASE_DATE`
+// moved by a house price index since, given in the config or fetched as CSV, e.g. the UK HPI's.
+// The web UI's page for it is `/value/<user>/<account>?token=<VALUE_TOKEN>`, which isn't served
+// for an account without a VALUE_TOKEN.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct PropertyConfig {
+    pub base_value: Option<f32>,
+    pub base_date: Option<NaiveDate>,
+
+    // e.g. `INDEX = { "2024-01-01" = 100.0, "2025-01-01" = 103.2 }`
+    #[serde(default)]
+    pub index: BTreeMap<NaiveDate, f32>,
+    pub index_csv_url: Option<String>,
+    #[serde(default = "default_index_date_column")]
+    pub index_date_column: String,
+    #[serde(default = "default_index_value_column")]
+    pub index_value_column: String,
+    // Only the rows with these values, e.g. `INDEX_FILTER = { RegionName = "Bristol" }`
+    #[serde(default)]
+    pub index_filter: BTreeMap<String, String>,
+
+    pub value_token: Option<SecretString>,
+}
+
+fn default_index_date_column() -> String {
+    "Date".to_owned()
+}
+
+fn default_index_value_column() -> String {
+    "Index".to_owned()
+}
+
+#[derive(Clone, Debug)]
+pub struct Property {
+    account: String,
+    ynab_account_id: String,
+    config: PropertyConfig,
+    config_path: String,
+    http: HttpConfig,
+}
+
+impl Property {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        Ok(Property {
+            account: account.to_owned(),
+            ynab_account_id: account_config.ynab_account_id.clone(),
+            config: account_config.provider_config()?,
+            config_path: ynab_config.config_path.clone(),
+            http: ynab_config.http.clone(),
+        })
+    }
+
+    async fn get_index(&self) -> Result<BTreeMap<NaiveDate, f32>> {
+        let config = &self.config;
+
+        let mut index = config.index.clone();
+
+        if let Some(index_csv_url) = &config.index_csv_url {
+            let client = http::client(&self.http)?;
+
+            let csv = http::send(&client, client.get(index_csv_url))
+                .await?
+                .error_for_status()?
+                .text()
+                .await?;
+
+            index.extend(parse_index_csv(config, &csv)?);
+        }
+
+        Ok(index)
+    }
+}
+
+// Splits a CSV line on commas outside of quotes, unquoting the fields
+fn parse_csv_line(line: &str) -> Vec<String> {
+    let mut fields = vec![];
+    let mut field = String::new();
+    let mut quoted = false;
+    let mut chars = line.trim_end_matches('\r').chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                field.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            ',' if !quoted => fields.push(std::mem::take(&mut field)),
+            c => field.push(c),
+        }
+    }
+    fields.push(field);
+
+    fields
+}
+
+fn parse_index_csv(config: &PropertyConfig, csv: &str) -> Result<BTreeMap<NaiveDate, f32>> {
+    let mut lines = csv.lines().filter(|line| !line.trim().is_empty());
+
+    let header = parse_csv_line(lines.next().ok_or_else(|| anyhow!("The index is empty"))?);
+    let column = |name: &str| {
+        header
+            .iter()
+            .position(|column| column == name)
+            .ok_or_else(|| anyhow!("The index has no {} column", name))
+    };
+
+    let date_column = column(&config.index_date_column)?;
+    let value_column = column(&config.index_value_column)?;
+    let filter_columns = config
+        .index_filter
+        .iter()
+        .map(|(name, value)| Ok((column(name)?, value)))
+        .collect::<Result<Vec<_>>>()?;
+
+    lines
+        .map(parse_csv_line)
+        .filter(|row| {
+            filter_columns
+                .iter()
+                .all(|(column, value)| row.get(*column) == Some(*value))
+        })
+        .map(|row| {
+            let date = row.get(date_column).map(String::as_str).unwrap_or_default();
+            let date = NaiveDate::parse_from_str(date, "%Y-%m-%d")
+                .or_else(|_| NaiveDate::parse_from_str(date, "%d/%m/%Y"))
+                .map_err(|_| anyhow!("The index has an unreadable date {:?}", date))?;
+            let value = row
+                .get(value_column)
+                .map(String::as_str)
+                .unwrap_or_default()
+                .parse::<f32>()?;
+            Ok((date, value))
+        })
+        .collect()
+}
+
+fn base_index(base_date: NaiveDate, index: &BTreeMap<NaiveDate, f32>) -> Result<f32> {
+    index
+        .range(..=base_date)
+        .next_back()
+        .map(|(_, base_index)| *base_index)
+        .ok_or_else(|| anyhow!("The index starts after BASE_DATE {}", base_date))
+}
+
+// The base value scaled by how far the index has moved since the base date
+fn index_linked_value(
+    base_value: f32,
+    base_date: NaiveDate,
+    index: &BTreeMap<NaiveDate, f32>,
+) -> Result<f32> {
+    let base_index = base_index(base_date, index)?;
+    let (latest_date, latest_index) = index
+        .last_key_value()
+        .ok_or_else(|| anyhow!("The index is empty"))?;
+
+    info!(
+        "Index moved from {} at {} to {} at {}",
+        base_index, base_date, latest_index, latest_date
+    );
+
+    Ok(base_value * latest_index / base_index)
+}
+
+impl GetYnabAccountConfig for Property {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for Property {
+    async fn get(&self) -> Result<f32> {
+        if let Some((value, set_at)) =
+            History::open(&self.config_path)?.get_manual_value(&self.account)?
+        {
+            info!("Using {}'s value set by hand at {}", self.account, set_at);
+            return Ok(value);
+        }
+
+        match (self.config.base_value, self.config.base_date) {
+            (Some(base_value), Some(base_date)) => {
+                let value = index_linked_value(base_value, base_date, &self.get_index().await?)?;
+
+                // YNAB's amounts only go to the penny
+                Ok((value * 100.0).round() / 100.0)
+            }
+            _ => Err(anyhow!(
+                "{} has no value, set one with `ynab-updater set-value {} <value>` or configure BASE_VALUE & BASE_DATE",
+                self.account,
+                self.account
+            )),
+        }
+    }
+}
+
+impl GetHistory for Property {
+    // The index-linked value at each of the index's dates. A value set by hand is only today's.
+    async fn get_history(&self, from: NaiveDate) -> Result<BTreeMap<NaiveDate, f32>> {
+        match (self.config.base_value, self.config.base_date) {
+            (Some(base_value), Some(base_date)) => {
+                let index = self.get_index().await?;
+                let base_index = base_index(base_date, &index)?;
+
+                Ok(index
+                    .range(from..)
+                    .map(|(date, index)| {
+                        (
+                            *date,
+                            (base_value * index / base_index * 100.0).round() / 100.0,
+                        )
+                    })
+                    .collect())
+            }
+            _ => Err(anyhow!(
+                "{} has no history without BASE_VALUE & BASE_DATE",
+                self.account
+            )),
+        }
+    }
+}
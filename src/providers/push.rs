@@ -0,0 +1,98 @@
+use anyhow::{anyhow, Result};
+use chrono::{Duration, Utc};
+use log::info;
+use secrecy::SecretString;
+use serde::Deserialize;
+
+use crate::{
+    fx, history::History, AccountConfig, Config, GetBalance, GetYnabAccountConfig,
+    YnabAccountConfig,
+};
+
+// A balance sent to the web UI by something else, e.g. a phone automation or another service,
+// for an institution that can only be read from there. It's POSTed to `/balances/<account>` with
+// `Authorization: Bearer <TOKEN>` as e.g.
+// `{ "balance": 123.45, "currency": "GBP", "as_of": "2024-01-01T06:00:00Z" }`, and reconciled
+// straight away. The account's own runs reconcile the latest one again.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct PushConfig {
+    pub token: SecretString,
+    // A balance older than this fails the run rather than reconciling a stale balance
+    #[serde(default = "default_max_age_hours")]
+    pub max_age_hours: i64,
+}
+
+fn default_max_age_hours() -> i64 {
+    48
+}
+
+#[derive(Clone, Debug)]
+pub struct Push {
+    account: String,
+    ynab_account_id: String,
+    // The account's CURRENCY, which a balance in another currency is converted to
+    currency: Option<String>,
+    config: PushConfig,
+    ynab_config: Config,
+}
+
+impl Push {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        Ok(Push {
+            account: account.to_owned(),
+            ynab_account_id: account_config.ynab_account_id.clone(),
+            currency: account_config.currency.clone(),
+            config: account_config.provider_config()?,
+            ynab_config: ynab_config.clone(),
+        })
+    }
+}
+
+impl GetYnabAccountConfig for Push {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for Push {
+    async fn get(&self) -> Result<f32> {
+        let pushed_balance = History::open(&self.ynab_config.config_path)?
+            .get_pushed_balance(&self.account)?
+            .ok_or_else(|| anyhow!("No balance has been pushed for {} yet", self.account))?;
+
+        let as_of = pushed_balance.as_of.unwrap_or(pushed_balance.received_at);
+
+        if Utc::now() - as_of > Duration::hours(self.config.max_age_hours) {
+            return Err(anyhow!(
+                "{}'s balance is from {}, older than MAX_AGE_HOURS",
+                self.account,
+                as_of
+            ));
+        }
+
+        info!(
+            "Using {}'s balance pushed at {}",
+            self.account, pushed_balance.received_at
+        );
+
+        // Without the account's CURRENCY there's no telling whether the balance is in the budget's
+        match (pushed_balance.currency, &self.currency) {
+            (None, _) => Ok(pushed_balance.balance),
+            (Some(from), Some(to)) => {
+                fx::convert(&self.ynab_config, pushed_balance.balance, &from, to).await
+            }
+            (Some(from), None) => Err(anyhow!(
+                "{}'s balance is in {}, set the account's CURRENCY for it to be converted to the budget's",
+                self.account,
+                from
+            )),
+        }
+    }
+}
@@ -0,0 +1,520 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use log::info;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use std::{collections::BTreeMap, env};
+
+use crate::{
+    backfill::GetHistory,
+    contributions::GetCashflows,
+    history::{History, RunState},
+    http,
+    income::{GetIncome, Income, IncomeKind},
+    is_renewal_due,
+    oauth::{self, OAuthClient, TokenResponse},
+    token_store::{Expiring, TokenStore, TokenStoreGuard},
+    AccountConfig, AuthPending, Config, GetBalance, GetYnabAccountConfig, YnabAccountConfig,
+};
+
+static SAXO_AUTH_URL: &str = "https://live.logonvalidation.net/authorize";
+static SAXO_ACCESS_URL: &str = "https://live.logonvalidation.net/token";
+static SAXO_API_URL: &str = "https://gateway.saxobank.com/openapi/";
+
+static ACCESS_TOKEN_FILENAME: &str = "access_token.json";
+
+pub static PROVIDER: &str = "saxo";
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct SaxoConfig {
+    // Falls back to `YNAB_TAILSCALE_IP`, which is how the flat config has always set it
+    #[serde(alias = "tailscale_ip")]
+    pub tailscale_ip: Option<String>,
+
+    pub saxo_client_id: String,
+    pub saxo_client_secret: SecretString,
+
+    // Where the login's callback listener binds, defaulting to the Tailscale IP. Bound to
+    // localhost it can be exposed with `tailscale serve` or `tailscale funnel`, with
+    // `SAXO_REDIRECT_URI` set to the served URL.
+    pub callback_host: Option<String>,
+    #[serde(default = "default_callback_port")]
+    pub callback_port: u16,
+    // The only path the redirect is accepted on, `/` unless the redirect_uri is configured
+    pub callback_path: Option<String>,
+    // Derived from the callback's host, port & path, so they can't drift apart, unless the
+    // callback is served from elsewhere
+    pub saxo_redirect_uri: Option<String>,
+    // Serves the callback over HTTPS with a self-signed certificate, for browsers that upgrade
+    // the redirect to it anyway, e.g. on iOS
+    #[serde(default)]
+    pub callback_tls: bool,
+    // How long a login can be refreshed for before Saxo asks for it again, which the token
+    // response doesn't say. It's renewed TOKEN_EXPIRY_WARNING_DAYS before, if it's set.
+    pub saxo_login_lifetime_days: Option<i64>,
+}
+
+fn default_callback_port() -> u16 {
+    9999
+}
+
+#[derive(Clone, Debug)]
+pub struct Saxo {
+    account: String,
+    ynab_account_id: String,
+    listen_addr: String,
+    redirect_uri: String,
+    // Any path is accepted for a redirect_uri configured without one, as it always was
+    callback_path: Option<String>,
+    config: SaxoConfig,
+    // The user's config, whose path the token is cached under & whose notifier gets login links
+    ynab_config: Config,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct AccountResponse {
+    total_value: f32,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct ClientResponse {
+    client_key: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct BookingsResponse {
+    data: Vec<Booking>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct Booking {
+    // A number or a string, depending on the report
+    booking_id: serde_json::Value,
+    date: String,
+    amount: f32,
+    // e.g. `Cash Dividend` or `Interest`
+    bk_amount_type: String,
+    #[serde(default)]
+    instrument_description: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PerformanceResponse {
+    balance: PerformanceBalance,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PerformanceBalance {
+    #[serde(default)]
+    account_value: Vec<PerformanceValue>,
+    // Each day's cash deposited less withdrawn
+    #[serde(default)]
+    cash_transfer: Vec<PerformanceValue>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct PerformanceValue {
+    date: String,
+    value: f32,
+}
+
+impl Saxo {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        let config = account_config.provider_config::<SaxoConfig>()?;
+
+        let callback_host = match &config.callback_host {
+            Some(callback_host) => callback_host.clone(),
+            None => config
+                .tailscale_ip
+                .clone()
+                .or_else(|| env::var("YNAB_TAILSCALE_IP").ok())
+                .ok_or_else(|| {
+                    anyhow!(
+                        "TAILSCALE_IP or CALLBACK_HOST must be configured for {}",
+                        account
+                    )
+                })?,
+        };
+
+        let listen_addr = format!("{}:{}", callback_host, config.callback_port);
+
+        let (redirect_uri, callback_path) = oauth::callback_redirect(
+            &listen_addr,
+            config.callback_tls,
+            &ynab_config.callback_access,
+            config.saxo_redirect_uri.as_deref(),
+            config.callback_path.as_deref(),
+        );
+
+        Ok(Saxo {
+            account: account.to_owned(),
+            ynab_account_id: account_config.ynab_account_id.clone(),
+            listen_addr,
+            redirect_uri,
+            callback_path,
+            config,
+            ynab_config: ynab_config.clone(),
+        })
+    }
+
+    pub async fn auth(&self) -> Result<()> {
+        let client = http::client_without_redirects(&self.ynab_config.http)?;
+
+        let token_guard = self.token_store().lock().await?;
+
+        self.oauth_client()
+            .login(&client, &self.ynab_config.config_path, &token_guard, None)
+            .await?;
+
+        History::open(&self.ynab_config.config_path)?.set_run_state(
+            &self.account,
+            RunState::Idle,
+            None,
+        )?;
+
+        println!("Logged in to Saxo");
+
+        Ok(())
+    }
+
+    // The login's lifetime once it's configured, otherwise when its refresh token lapses if it
+    // isn't used
+    pub async fn consent_expires_at(&self) -> Result<Option<DateTime<Utc>>> {
+        match self.login_expires_at()? {
+            Some(expires_at) => Ok(Some(expires_at)),
+            None => self
+                .token_store()
+                .lock()
+                .await?
+                .expires_at::<TokenResponse>(),
+        }
+    }
+
+    // Counted from the login, as each refresh moves the refresh token's expiry on, so it's no
+    // deadline to renew before
+    fn login_expires_at(&self) -> Result<Option<DateTime<Utc>>> {
+        let Some(lifetime_days) = self.config.saxo_login_lifetime_days else {
+            return Ok(None);
+        };
+
+        Ok(History::open(&self.ynab_config.config_path)?
+            .get_consent_started_at(&self.account)?
+            .map(|started_at| started_at + Duration::days(lifetime_days)))
+    }
+
+    // The default account keeps the original filename, so existing caches carry over
+    fn token_store(&self) -> TokenStore {
+        let filename = if self.account == PROVIDER {
+            ACCESS_TOKEN_FILENAME.to_owned()
+        } else {
+            format!("{}_{}", self.account, ACCESS_TOKEN_FILENAME)
+        };
+
+        TokenStore::new(&self.ynab_config.config_path, &filename)
+    }
+
+    fn oauth_client(&self) -> OAuthClient {
+        OAuthClient {
+            provider: self.account.clone(),
+            title: "Saxo".to_owned(),
+            auth_url: SAXO_AUTH_URL.to_owned(),
+            token_url: SAXO_ACCESS_URL.to_owned(),
+            client_id: self.config.saxo_client_id.clone(),
+            client_secret: self.config.saxo_client_secret.clone(),
+            basic_auth: false,
+            redirect_uri: self.redirect_uri.clone(),
+            scope: None,
+            listen_addr: self.listen_addr.clone(),
+            callback_path: self.callback_path.clone(),
+            access: self.ynab_config.callback_access.clone(),
+            resolve_authorize_redirect: true,
+            tls: self.config.callback_tls,
+            http: self.ynab_config.http.clone(),
+            auth_params: vec![],
+        }
+    }
+
+    // A renewal's waited for without the token's lock, so other runs carry on meanwhile, & the
+    // token's refreshed again if it isn't answered, as it may have expired during the wait
+    async fn get_refreshed_access_token(&self, client: &reqwest::Client) -> Result<TokenResponse> {
+        let refreshed_access_token = self.refresh_access_token(client).await?;
+
+        let Some(expires_at) = self.login_expires_at()? else {
+            return Ok(refreshed_access_token);
+        };
+
+        if !is_renewal_due(
+            &self.ynab_config,
+            &self.account,
+            expires_at,
+            Duration::days(self.ynab_config.token_expiry_warning_days),
+        )? {
+            return Ok(refreshed_access_token);
+        }
+
+        info!(
+            "{}'s login expires at {}, renewing it",
+            self.account, expires_at
+        );
+
+        match self
+            .oauth_client()
+            .renew(
+                client,
+                &self.ynab_config.config_path,
+                &self.token_store(),
+                &self.ynab_config,
+                expires_at,
+            )
+            .await
+        {
+            Ok(renewed_access_token) => Ok(renewed_access_token),
+            Err(e) if e.downcast_ref::<AuthPending>().is_some() => {
+                info!("{}, using the current login", e);
+                self.refresh_access_token(client).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn refresh_access_token(&self, client: &reqwest::Client) -> Result<TokenResponse> {
+        // Saxo invalidates the refresh token once it's used, so concurrent runs mustn't interleave
+        let token_guard = self.token_store().lock().await?;
+
+        let access_token = self
+            .get_cached_or_live_access_token(client, &token_guard)
+            .await?;
+
+        let refreshed_access_token = self
+            .oauth_client()
+            .refresh(client, &access_token.refresh_token)
+            .await?;
+
+        token_guard.write(&refreshed_access_token)?;
+
+        Ok(refreshed_access_token)
+    }
+
+    async fn get_cached_or_live_access_token(
+        &self,
+        client: &reqwest::Client,
+        token_guard: &TokenStoreGuard,
+    ) -> Result<TokenResponse> {
+        let valid_refresh_token_o = token_guard
+            .read::<TokenResponse>()?
+            .filter(|(access_token, modified_at)| {
+                access_token
+                    .expires_at(*modified_at)
+                    .is_none_or(|expires_at| Utc::now() <= expires_at)
+            })
+            .map(|(access_token, _)| access_token);
+
+        match valid_refresh_token_o {
+            Some(valid_refresh_token) => Ok(valid_refresh_token),
+            _ => {
+                self.oauth_client()
+                    .login(
+                        client,
+                        &self.ynab_config.config_path,
+                        token_guard,
+                        Some(&self.ynab_config),
+                    )
+                    .await
+            }
+        }
+    }
+}
+
+impl GetYnabAccountConfig for Saxo {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for Saxo {
+    async fn get(&self) -> Result<f32> {
+        let client = http::client_without_redirects(&self.ynab_config.http)?;
+
+        let refreshed_access_token = self.get_refreshed_access_token(&client).await?;
+
+        let account_response = get_account_value(&client, &refreshed_access_token).await?;
+
+        Ok(account_response.total_value)
+    }
+}
+
+async fn get_account_value(
+    client: &reqwest::Client,
+    access_token: &TokenResponse,
+) -> Result<AccountResponse> {
+    let resp = http::send(
+        client,
+        client
+            .get(format!("{}/port/v1/balances/me", SAXO_API_URL))
+            .bearer_auth(access_token.access_token.expose_secret()),
+    )
+    .await?
+    .json::<AccountResponse>()
+    .await?;
+
+    Ok(resp)
+}
+
+impl Saxo {
+    // The client's key, which the reports are requested by
+    async fn get_client_key(
+        &self,
+        client: &reqwest::Client,
+        access_token: &TokenResponse,
+    ) -> Result<String> {
+        Ok(http::send(
+            client,
+            client
+                .get(format!("{}/port/v1/clients/me", SAXO_API_URL))
+                .bearer_auth(access_token.access_token.expose_secret()),
+        )
+        .await?
+        .error_for_status()?
+        .json::<ClientResponse>()
+        .await?
+        .client_key)
+    }
+
+    // The account's performance time series, from `from` to `to`
+    async fn get_performance(&self, from: NaiveDate, to: NaiveDate) -> Result<PerformanceBalance> {
+        let client = http::client_without_redirects(&self.ynab_config.http)?;
+
+        let access_token = self.get_refreshed_access_token(&client).await?;
+
+        let client_key = self.get_client_key(&client, &access_token).await?;
+
+        let performance = http::send(
+            &client,
+            client
+                .get(format!("{}/hist/v4/performance/timeseries", SAXO_API_URL))
+                .query(&[
+                    ("ClientKey", client_key),
+                    ("FieldGroups", "Balance".to_owned()),
+                    ("FromDate", from.to_string()),
+                    ("ToDate", to.to_string()),
+                ])
+                .bearer_auth(access_token.access_token.expose_secret()),
+        )
+        .await?
+        .error_for_status()?
+        .json::<PerformanceResponse>()
+        .await?;
+
+        Ok(performance.balance)
+    }
+}
+
+// A date, or a date & time at midnight
+fn parse_date(date: &str) -> Result<NaiveDate> {
+    let date = date.get(..10).unwrap_or(date);
+
+    NaiveDate::parse_from_str(date, "%Y-%m-%d")
+        .map_err(|_| anyhow!("Saxo responded with an unreadable date {:?}", date))
+}
+
+fn by_date(values: Vec<PerformanceValue>) -> Result<BTreeMap<NaiveDate, f32>> {
+    values
+        .into_iter()
+        .map(|value| Ok((parse_date(&value.date)?, value.value)))
+        .collect()
+}
+
+impl GetHistory for Saxo {
+    // The account's value at the end of each day
+    async fn get_history(&self, from: NaiveDate) -> Result<BTreeMap<NaiveDate, f32>> {
+        let performance = self
+            .get_performance(from, Local::now().date_naive())
+            .await?;
+
+        by_date(performance.account_value)
+    }
+}
+
+impl GetCashflows for Saxo {
+    async fn get_cashflows(&self, from: NaiveDate, until: NaiveDate) -> Result<f32> {
+        let performance = self.get_performance(from, until).await?;
+
+        Ok(by_date(performance.cash_transfer)?
+            .range(from..until)
+            .map(|(_, value)| value)
+            .sum())
+    }
+}
+
+impl GetIncome for Saxo {
+    // From the account's bookings, each dividend or interest payment being one
+    async fn get_income(&self, from: NaiveDate, until: NaiveDate) -> Result<Vec<Income>> {
+        let client = http::client_without_redirects(&self.ynab_config.http)?;
+
+        let access_token = self.get_refreshed_access_token(&client).await?;
+
+        let client_key = self.get_client_key(&client, &access_token).await?;
+
+        let bookings = http::send(
+            &client,
+            client
+                .get(format!(
+                    "{}/cs/v1/reports/bookings/{}",
+                    SAXO_API_URL, client_key
+                ))
+                .query(&[
+                    ("FromDate", from.to_string()),
+                    ("ToDate", until.pred_opt().unwrap_or(until).to_string()),
+                ])
+                .bearer_auth(access_token.access_token.expose_secret()),
+        )
+        .await?
+        .error_for_status()?
+        .json::<BookingsResponse>()
+        .await?;
+
+        bookings
+            .data
+            .into_iter()
+            .filter_map(|booking| {
+                let kind = if booking.bk_amount_type.contains("Dividend") {
+                    IncomeKind::Dividend
+                } else if booking.bk_amount_type.contains("Interest") {
+                    IncomeKind::Interest
+                } else {
+                    return None;
+                };
+
+                Some(parse_date(&booking.date).map(|date| {
+                    Income {
+                        id: match &booking.booking_id {
+                            serde_json::Value::String(id) => id.clone(),
+                            id => id.to_string(),
+                        },
+                        date,
+                        kind,
+                        name: booking
+                            .instrument_description
+                            .unwrap_or_else(|| "Saxo".to_owned()),
+                        amount: booking.amount,
+                    }
+                }))
+            })
+            .filter(|income| income.as_ref().map_or(true, |i| i.date < until))
+            .collect()
+    }
+}
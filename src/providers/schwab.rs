@@ -0,0 +1,350 @@
+use anyhow::{anyhow, Result};
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+
+use crate::{
+    history::{History, RunState},
+    http, is_renewal_due,
+    oauth::{self, OAuthClient, TokenResponse},
+    token_store::{TokenStore, TokenStoreGuard},
+    AccountConfig, AuthPending, Config, GetBalance, GetYnabAccountConfig, YnabAccountConfig,
+};
+
+static SCHWAB_AUTH_URL: &str = "https://api.schwabapi.com/v1/oauth/authorize";
+static SCHWAB_TOKEN_URL: &str = "https://api.schwabapi.com/v1/oauth/token";
+static SCHWAB_API_URL: &str = "https://api.schwabapi.com/trader/v1";
+
+// Schwab's refresh token lasts a week from the login, however often it's used, which the token
+// response doesn't say
+static LOGIN_LIFETIME_DAYS: i64 = 7;
+// So it's renewed in its last day, rather than TOKEN_EXPIRY_WARNING_DAYS before, which would be
+// straight after each login
+static RENEWAL_WINDOW_DAYS: i64 = 1;
+
+// A US brokerage account read through Schwab's Trader API, with an app registered at
+// developer.schwab.com. Its value is the account's liquidation value, so it suits a YNAB tracking
+// account. Each of the login's accounts is its own account here, by its number, each with its own
+// login.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct SchwabConfig {
+    // The app's key & secret
+    pub schwab_client_id: String,
+    pub schwab_client_secret: SecretString,
+    // Which of the login's accounts it is, by its number or its last digits, as Schwab shows it,
+    // e.g. `1234`
+    pub schwab_account_number: String,
+
+    // Where the login's callback listener binds, e.g. a Tailscale IP, or localhost to be exposed
+    // with `tailscale serve`, with `SCHWAB_REDIRECT_URI` set to the served URL
+    pub callback_host: String,
+    #[serde(default = "default_callback_port")]
+    pub callback_port: u16,
+    pub callback_path: Option<String>,
+    // Has to match the app's callback URL exactly
+    pub schwab_redirect_uri: Option<String>,
+    // Schwab only redirects to HTTPS, so the listener serves it unless it's served from elsewhere
+    #[serde(default = "default_callback_tls")]
+    pub callback_tls: bool,
+}
+
+fn default_callback_port() -> u16 {
+    9999
+}
+
+fn default_callback_tls() -> bool {
+    true
+}
+
+#[derive(Clone, Debug)]
+pub struct Schwab {
+    account: String,
+    ynab_account_id: String,
+    listen_addr: String,
+    redirect_uri: String,
+    callback_path: Option<String>,
+    config: SchwabConfig,
+    // The user's config, whose path the token is cached under & whose notifier gets login links
+    ynab_config: Config,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct AccountResponse {
+    securities_account: SecuritiesAccount,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SecuritiesAccount {
+    account_number: String,
+    current_balances: Balances,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Balances {
+    // Cash & positions, less any margin
+    liquidation_value: f32,
+}
+
+// How long before its login expires it's renewed
+pub fn renewal_window(config: &Config) -> Duration {
+    Duration::days(config.token_expiry_warning_days.min(RENEWAL_WINDOW_DAYS))
+}
+
+impl Schwab {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        let config = account_config.provider_config::<SchwabConfig>()?;
+
+        let listen_addr = format!("{}:{}", config.callback_host, config.callback_port);
+
+        let (redirect_uri, callback_path) = oauth::callback_redirect(
+            &listen_addr,
+            config.callback_tls,
+            &ynab_config.callback_access,
+            config.schwab_redirect_uri.as_deref(),
+            config.callback_path.as_deref(),
+        );
+
+        Ok(Schwab {
+            account: account.to_owned(),
+            ynab_account_id: account_config.ynab_account_id.clone(),
+            listen_addr,
+            redirect_uri,
+            callback_path,
+            config,
+            ynab_config: ynab_config.clone(),
+        })
+    }
+
+    pub async fn auth(&self) -> Result<()> {
+        let client = http::client(&self.ynab_config.http)?;
+
+        let token_guard = self.token_store().lock().await?;
+
+        self.oauth_client()
+            .login(&client, &self.ynab_config.config_path, &token_guard, None)
+            .await?;
+
+        History::open(&self.ynab_config.config_path)?.set_run_state(
+            &self.account,
+            RunState::Idle,
+            None,
+        )?;
+
+        println!("Logged in to Schwab");
+
+        Ok(())
+    }
+
+    // Counted from when the login was recorded
+    pub fn consent_expires_at(&self) -> Result<Option<DateTime<Utc>>> {
+        Ok(History::open(&self.ynab_config.config_path)?
+            .get_consent_started_at(&self.account)?
+            .map(|started_at| started_at + Duration::days(LOGIN_LIFETIME_DAYS)))
+    }
+
+    fn token_store(&self) -> TokenStore {
+        TokenStore::new(
+            &self.ynab_config.config_path,
+            &format!("{}_token.json", self.account),
+        )
+    }
+
+    fn oauth_client(&self) -> OAuthClient {
+        OAuthClient {
+            provider: self.account.clone(),
+            title: "Schwab".to_owned(),
+            auth_url: SCHWAB_AUTH_URL.to_owned(),
+            token_url: SCHWAB_TOKEN_URL.to_owned(),
+            client_id: self.config.schwab_client_id.clone(),
+            client_secret: self.config.schwab_client_secret.clone(),
+            basic_auth: true,
+            redirect_uri: self.redirect_uri.clone(),
+            scope: None,
+            listen_addr: self.listen_addr.clone(),
+            callback_path: self.callback_path.clone(),
+            access: self.ynab_config.callback_access.clone(),
+            resolve_authorize_redirect: false,
+            tls: self.config.callback_tls,
+            http: self.ynab_config.http.clone(),
+            auth_params: vec![],
+        }
+    }
+
+    async fn login(
+        &self,
+        client: &reqwest::Client,
+        token_guard: &TokenStoreGuard,
+    ) -> Result<TokenResponse> {
+        self.oauth_client()
+            .login(
+                client,
+                &self.ynab_config.config_path,
+                token_guard,
+                Some(&self.ynab_config),
+            )
+            .await
+    }
+
+    // A renewal's waited for without the token's lock, so other runs carry on meanwhile, & the
+    // token's read again if it isn't answered, as it may have expired during the wait
+    async fn get_access_token(&self, client: &reqwest::Client) -> Result<TokenResponse> {
+        let token = self.current_access_token(client).await?;
+
+        let Some(expires_at) = self.consent_expires_at()? else {
+            return Ok(token);
+        };
+
+        if !is_renewal_due(
+            &self.ynab_config,
+            &self.account,
+            expires_at,
+            renewal_window(&self.ynab_config),
+        )? {
+            return Ok(token);
+        }
+
+        info!(
+            "{}'s login expires at {}, renewing it",
+            self.account, expires_at
+        );
+
+        match self
+            .oauth_client()
+            .renew(
+                client,
+                &self.ynab_config.config_path,
+                &self.token_store(),
+                &self.ynab_config,
+                expires_at,
+            )
+            .await
+        {
+            Ok(renewed_token) => Ok(renewed_token),
+            Err(e) if e.downcast_ref::<AuthPending>().is_some() => {
+                info!("{}, using the current login", e);
+                self.current_access_token(client).await
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    // The cached token while it's valid, refreshed once it isn't, or a new login's once the
+    // week's up
+    async fn current_access_token(&self, client: &reqwest::Client) -> Result<TokenResponse> {
+        let token_guard = self.token_store().lock().await?;
+
+        let expires_at = self.consent_expires_at()?;
+
+        let token = match token_guard.read::<TokenResponse>()? {
+            // Refreshed a minute early so it can't expire mid-run
+            Some((token, written_at))
+                if Utc::now() < written_at + Duration::seconds(token.expires_in as i64 - 60) =>
+            {
+                token
+            }
+            Some((token, _)) if expires_at.is_some_and(|expires_at| Utc::now() < expires_at) => {
+                match self
+                    .oauth_client()
+                    .refresh(client, &token.refresh_token)
+                    .await
+                {
+                    Ok(token) => {
+                        token_guard.write(&token)?;
+                        token
+                    }
+                    Err(e) => {
+                        warn!(
+                            "Failed to refresh the Schwab token, logging in again: {:#?}",
+                            e
+                        );
+                        return self.login(client, &token_guard).await;
+                    }
+                }
+            }
+            _ => return self.login(client, &token_guard).await,
+        };
+
+        Ok(token)
+    }
+
+    // The account by its number, or the one account whose number ends with it
+    fn find_account(&self, accounts: Vec<AccountResponse>) -> Result<SecuritiesAccount> {
+        let account_number = &self.config.schwab_account_number;
+
+        let numbers = accounts
+            .iter()
+            .map(|account| account.securities_account.account_number.clone())
+            .collect::<Vec<_>>();
+
+        let mut matches = accounts
+            .into_iter()
+            .map(|account| account.securities_account)
+            .filter(|account| account.account_number.ends_with(account_number.as_str()))
+            .collect::<Vec<_>>();
+
+        if let Some(i) = matches
+            .iter()
+            .position(|account| &account.account_number == account_number)
+        {
+            return Ok(matches.swap_remove(i));
+        }
+
+        match matches.len() {
+            1 => Ok(matches.remove(0)),
+            0 => Err(anyhow!(
+                "No Schwab account {} under the login, its accounts end with: {}",
+                account_number,
+                numbers
+                    .iter()
+                    .map(|number| &number[number.len().saturating_sub(4)..])
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )),
+            _ => Err(anyhow!(
+                "More than one Schwab account ends with {}, give more of SCHWAB_ACCOUNT_NUMBER",
+                account_number
+            )),
+        }
+    }
+}
+
+impl GetYnabAccountConfig for Schwab {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for Schwab {
+    async fn get(&self) -> Result<f32> {
+        let client = http::client(&self.ynab_config.http)?;
+
+        let token = self.get_access_token(&client).await?;
+
+        let accounts = http::send(
+            &client,
+            client
+                .get(format!("{}/accounts", SCHWAB_API_URL))
+                .bearer_auth(token.access_token.expose_secret()),
+        )
+        .await?
+        .error_for_status()?
+        .json::<Vec<AccountResponse>>()
+        .await?;
+
+        Ok(self
+            .find_account(accounts)?
+            .current_balances
+            .liquidation_value)
+    }
+}
@@ -0,0 +1,237 @@
+use anyhow::{anyhow, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use chrono::{DateTime, Duration, Utc};
+use log::{info, warn};
+use reqwest::Url;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    fx,
+    http::{self, HttpConfig},
+    token_store::{serialize_secret, TokenStore, TokenStoreGuard},
+    AccountConfig, Config, GetBalance, GetYnabAccountConfig, YnabAccountConfig,
+};
+
+static ACCESS_URLS_FILENAME: &str = "simplefin_access_urls.json";
+
+// An account read through a SimpleFIN bridge, e.g. SimpleFIN Bridge's, which connects to many US
+// institutions at once. The setup token it gives out is claimed for an access URL on the first
+// run, and that's kept, so each of the bridge's accounts can share one token. The bridge only
+// allows a couple of dozen requests a day, so its accounts are best run once or twice a day.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct SimpleFinConfig {
+    pub simplefin_setup_token: SecretString,
+    // Which of the bridge's accounts to read, by ID or name
+    pub simplefin_account: String,
+    // A balance older than this, e.g. from a connection that needs attention, fails the run
+    #[serde(default = "default_max_age_hours")]
+    pub max_age_hours: i64,
+}
+
+fn default_max_age_hours() -> i64 {
+    48
+}
+
+// A claimed setup token's access URL. A setup token can only be claimed once.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct AccessUrl {
+    #[serde(serialize_with = "serialize_secret")]
+    setup_token: SecretString,
+    #[serde(serialize_with = "serialize_secret")]
+    access_url: SecretString,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Account {
+    id: String,
+    name: String,
+    // An ISO code, or a URL for a custom currency
+    currency: String,
+    balance: String,
+    #[serde(rename = "balance-date", with = "chrono::serde::ts_seconds")]
+    balance_date: DateTime<Utc>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct AccountSet {
+    // e.g. a connection that needs logging in to again
+    #[serde(default)]
+    errors: Vec<String>,
+    accounts: Vec<Account>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SimpleFin {
+    account: String,
+    ynab_account_id: String,
+    // The account's CURRENCY, which a balance in another currency is converted to
+    currency: Option<String>,
+    config: SimpleFinConfig,
+    ynab_config: Config,
+    http: HttpConfig,
+}
+
+impl SimpleFin {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        Ok(SimpleFin {
+            account: account.to_owned(),
+            ynab_account_id: account_config.ynab_account_id.clone(),
+            currency: account_config.currency.clone(),
+            config: account_config.provider_config()?,
+            ynab_config: ynab_config.clone(),
+            http: ynab_config.http.clone(),
+        })
+    }
+
+    fn token_store(&self) -> TokenStore {
+        TokenStore::new(&self.ynab_config.config_path, ACCESS_URLS_FILENAME)
+    }
+
+    // The setup token is the claim URL, base64 encoded
+    async fn claim(&self, token_guard: &TokenStoreGuard) -> Result<SecretString> {
+        let setup_token = self.config.simplefin_setup_token.expose_secret().trim();
+
+        let mut access_urls = token_guard
+            .read::<Vec<AccessUrl>>()?
+            .map(|(access_urls, _)| access_urls)
+            .unwrap_or_default();
+
+        if let Some(access_url) = access_urls
+            .iter()
+            .find(|a| a.setup_token.expose_secret() == setup_token)
+        {
+            return Ok(access_url.access_url.clone());
+        }
+
+        let claim_url = String::from_utf8(
+            STANDARD
+                .decode(setup_token)
+                .context("SIMPLEFIN_SETUP_TOKEN isn't a setup token")?,
+        )
+        .context("SIMPLEFIN_SETUP_TOKEN isn't a setup token")?;
+
+        info!("Claiming the SimpleFIN setup token");
+
+        let client = http::client(&self.http)?;
+
+        let access_url: SecretString = http::send(
+            &client,
+            client
+                .post(claim_url)
+                .header(reqwest::header::CONTENT_LENGTH, 0),
+        )
+        .await?
+        .error_for_status()
+        .context("Failed to claim SIMPLEFIN_SETUP_TOKEN, it may have been claimed already")?
+        .text()
+        .await?
+        .trim()
+        .into();
+
+        access_urls.push(AccessUrl {
+            setup_token: setup_token.into(),
+            access_url: access_url.clone(),
+        });
+        token_guard.write(&access_urls)?;
+
+        Ok(access_url)
+    }
+
+    async fn get_accounts(&self) -> Result<AccountSet> {
+        let access_url = {
+            let token_guard = self.token_store().lock().await?;
+            self.claim(&token_guard).await?
+        };
+
+        // The access URL carries its credentials, which are sent as basic auth
+        let mut url = Url::parse(access_url.expose_secret())
+            .context("The SimpleFIN access URL is invalid")?;
+        let username = url.username().to_owned();
+        let password = url.password().map(str::to_owned);
+        url.set_username("")
+            .and_then(|_| url.set_password(None))
+            .map_err(|_| anyhow!("The SimpleFIN access URL is invalid"))?;
+
+        let client = http::client(&self.http)?;
+
+        let accounts = http::send(
+            &client,
+            client
+                .get(format!("{}/accounts", url.as_str().trim_end_matches('/')))
+                .query(&[("balances-only", "1")])
+                .basic_auth(username, password),
+        )
+        .await?
+        .error_for_status()?
+        .json::<AccountSet>()
+        .await?;
+
+        for error in &accounts.errors {
+            warn!("SimpleFIN: {}", error);
+        }
+
+        Ok(accounts)
+    }
+}
+
+impl GetYnabAccountConfig for SimpleFin {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for SimpleFin {
+    async fn get(&self) -> Result<f32> {
+        let account_set = self.get_accounts().await?;
+
+        let wanted = &self.config.simplefin_account;
+
+        let account = account_set
+            .accounts
+            .iter()
+            .find(|a| &a.id == wanted)
+            .or_else(|| account_set.accounts.iter().find(|a| &a.name == wanted))
+            .ok_or_else(|| {
+                anyhow!(
+                    "No SimpleFIN account {}, the accounts are: {}",
+                    wanted,
+                    account_set
+                        .accounts
+                        .iter()
+                        .map(|a| format!("{} ({})", a.name, a.id))
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })?;
+
+        if Utc::now() - account.balance_date > Duration::hours(self.config.max_age_hours) {
+            return Err(anyhow!(
+                "{}'s balance is from {}, older than MAX_AGE_HOURS",
+                self.account,
+                account.balance_date
+            ));
+        }
+
+        let balance = account.balance.parse::<f32>().with_context(|| {
+            format!(
+                "SimpleFIN's balance {:?} for {} isn't a number",
+                account.balance, self.account
+            )
+        })?;
+
+        // Unlike a command's, SimpleFIN's balances always have a currency, so without the
+        // account's CURRENCY it's taken to be the budget's
+        match &self.currency {
+            Some(to) => fx::convert(&self.ynab_config, balance, &account.currency, to).await,
+            None => Ok(balance),
+        }
+    }
+}
@@ -0,0 +1,229 @@
+use anyhow::{anyhow, Result};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    http::{self, HttpConfig},
+    AccountConfig, Config, GetBalance, GetYnabAccountConfig, YnabAccountConfig,
+};
+
+static STARLING_API_URL: &str = "https://api.starlingbank.com/api/v2";
+
+// Spaces are money set aside, e.g. for bills, so by default they aren't counted as the account's
+// spendable balance. Each can be counted in with `INCLUDE_SPACES`, or given its own YNAB account by
+// an account that reads only that `SPACE`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct StarlingConfig {
+    // A personal access token with the `account:read`, `balance:read` & `space:read` scopes
+    pub starling_access_token: SecretString,
+    // Which of the token's accounts to read, by name, otherwise its first
+    pub starling_account_name: Option<String>,
+    #[serde(default)]
+    pub include_spaces: Vec<String>,
+    pub space: Option<String>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Starling {
+    ynab_account_id: String,
+    config: StarlingConfig,
+    http: HttpConfig,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct CurrencyAndAmount {
+    minor_units: i64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Account {
+    account_uid: String,
+    name: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct AccountsResponse {
+    accounts: Vec<Account>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BalanceResponse {
+    // Includes pending transactions, like the app's balance
+    effective_balance: CurrencyAndAmount,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SavingsGoal {
+    name: String,
+    total_saved: CurrencyAndAmount,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpendingSpace {
+    name: String,
+    balance: CurrencyAndAmount,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SpacesResponse {
+    #[serde(default)]
+    savings_goals: Vec<SavingsGoal>,
+    #[serde(default)]
+    spending_spaces: Vec<SpendingSpace>,
+}
+
+impl SpacesResponse {
+    // Both kinds of space by name, in minor units
+    fn balances(&self) -> Vec<(&str, i64)> {
+        self.savings_goals
+            .iter()
+            .map(|goal| (goal.name.as_str(), goal.total_saved.minor_units))
+            .chain(
+                self.spending_spaces
+                    .iter()
+                    .map(|space| (space.name.as_str(), space.balance.minor_units)),
+            )
+            .collect()
+    }
+
+    fn balance(&self, name: &str) -> Result<i64> {
+        let balances = self.balances();
+
+        balances
+            .iter()
+            .find(|(space, _)| *space == name)
+            .map(|(_, minor_units)| *minor_units)
+            .ok_or_else(|| {
+                anyhow!(
+                    "No Starling space named {}, the spaces are: {}",
+                    name,
+                    balances
+                        .iter()
+                        .map(|(space, _)| *space)
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                )
+            })
+    }
+}
+
+impl Starling {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        let config = account_config.provider_config::<StarlingConfig>()?;
+
+        if config.space.is_some() && !config.include_spaces.is_empty() {
+            return Err(anyhow!(
+                "{} can set either SPACE or INCLUDE_SPACES, not both",
+                account
+            ));
+        }
+
+        Ok(Starling {
+            ynab_account_id: account_config.ynab_account_id.clone(),
+            config,
+            http: ynab_config.http.clone(),
+        })
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        client: &reqwest::Client,
+        path: &str,
+    ) -> Result<T> {
+        let response = http::send(
+            client,
+            client
+                .get(format!("{}{}", STARLING_API_URL, path))
+                .bearer_auth(self.config.starling_access_token.expose_secret()),
+        )
+        .await?
+        .error_for_status()?
+        .json::<T>()
+        .await?;
+
+        Ok(response)
+    }
+
+    async fn get_account(&self, client: &reqwest::Client) -> Result<Account> {
+        let accounts = self
+            .get::<AccountsResponse>(client, "/accounts")
+            .await?
+            .accounts;
+
+        match &self.config.starling_account_name {
+            Some(name) => accounts
+                .into_iter()
+                .find(|account| &account.name == name)
+                .ok_or_else(|| anyhow!("No Starling account named {}", name)),
+            None => accounts
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("The Starling token has no accounts")),
+        }
+    }
+}
+
+impl GetYnabAccountConfig for Starling {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for Starling {
+    async fn get(&self) -> Result<f32> {
+        let client = http::client(&self.http)?;
+
+        let account = self.get_account(&client).await?;
+
+        let minor_units = match &self.config.space {
+            Some(space) => self
+                .get::<SpacesResponse>(&client, &format!("/account/{}/spaces", account.account_uid))
+                .await?
+                .balance(space)?,
+            None => {
+                let balance = self
+                    .get::<BalanceResponse>(
+                        &client,
+                        &format!("/accounts/{}/balance", account.account_uid),
+                    )
+                    .await?
+                    .effective_balance
+                    .minor_units;
+
+                let included = match self.config.include_spaces.as_slice() {
+                    [] => 0,
+                    include_spaces => {
+                        let spaces = self
+                            .get::<SpacesResponse>(
+                                &client,
+                                &format!("/account/{}/spaces", account.account_uid),
+                            )
+                            .await?;
+
+                        include_spaces
+                            .iter()
+                            .map(|space| spaces.balance(space))
+                            .sum::<Result<i64>>()?
+                    }
+                };
+
+                balance + included
+            }
+        };
+
+        Ok(minor_units as f32 / 100.0)
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::{anyhow, Result};
+use chrono::{Local, NaiveDate};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+use crate::{
+    amount,
+    http::{self, HttpConfig},
+    AccountConfig, Config, GetBalance, GetYnabAccountConfig, YnabAccountConfig,
+};
+
+// A car's value, for a YNAB asset tracking account. It's either looked up from a valuation API,
+// taking the number at `VALUATION_JSON_POINTER` in its response, or worked out from the purchase
+// price & a depreciation curve without any external service.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct VehicleConfig {
+    pub valuation_url: Option<String>,
+    // e.g. `/valuation/private_sale`, see RFC 6901
+    pub valuation_json_pointer: Option<String>,
+    // e.g. an API key
+    #[serde(default)]
+    pub valuation_headers: BTreeMap<String, String>,
+
+    pub purchase_price: Option<f32>,
+    pub purchase_date: Option<NaiveDate>,
+    // The value lost in each year of ownership, as a percentage of the value at its start, e.g.
+    // `[20, 15, 12, 10]`. The last year's repeats, and within a year it's lost evenly.
+    #[serde(default)]
+    pub depreciation_percent_by_year: Vec<f32>,
+}
+
+#[derive(Clone, Debug)]
+pub struct Vehicle {
+    ynab_account_id: String,
+    config: VehicleConfig,
+    http: HttpConfig,
+}
+
+impl Vehicle {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        let config = account_config.provider_config::<VehicleConfig>()?;
+
+        let is_curve = config.purchase_price.is_some()
+            && config.purchase_date.is_some()
+            && !config.depreciation_percent_by_year.is_empty();
+
+        match (&config.valuation_url, &config.valuation_json_pointer) {
+            (Some(_), None) => {
+                return Err(anyhow!(
+                    "{} sets VALUATION_URL, which needs VALUATION_JSON_POINTER",
+                    account
+                ))
+            }
+            (None, _) if !is_curve => {
+                return Err(anyhow!(
+                    "{} needs either VALUATION_URL, or PURCHASE_PRICE, PURCHASE_DATE & DEPRECIATION_PERCENT_BY_YEAR",
+                    account
+                ))
+            }
+            _ => {}
+        }
+
+        Ok(Vehicle {
+            ynab_account_id: account_config.ynab_account_id.clone(),
+            config,
+            http: ynab_config.http.clone(),
+        })
+    }
+
+    async fn get_valuation(&self, valuation_url: &str, json_pointer: &str) -> Result<f32> {
+        let client = http::client(&self.http)?;
+
+        let mut request = client.get(valuation_url);
+        for (name, value) in &self.config.valuation_headers {
+            request = request.header(name, value);
+        }
+
+        let response = http::send(&client, request)
+            .await?
+            .error_for_status()?
+            .json::<Value>()
+            .await?;
+
+        let valuation = response
+            .pointer(json_pointer)
+            .ok_or_else(|| anyhow!("The valuation has nothing at {}", json_pointer))?;
+
+        // Some APIs give amounts as strings
+        match valuation {
+            Value::Number(number) => number
+                .as_f64()
+                .map(|number| number as f32)
+                .ok_or_else(|| anyhow!("The valuation {} isn't a number", number)),
+            Value::String(string) => amount::parse(string),
+            valuation => Err(anyhow!("The valuation {} isn't a number", valuation)),
+        }
+    }
+}
+
+// The purchase price less each year's depreciation, compounded, up to `today`
+fn depreciated_value(
+    purchase_price: f32,
+    purchase_date: NaiveDate,
+    depreciation_percent_by_year: &[f32],
+    today: NaiveDate,
+) -> f32 {
+    let years = ((today - purchase_date).num_days().max(0) as f32) / 365.25;
+
+    let mut value = purchase_price;
+
+    for year in 0..=(years as usize) {
+        let percent = depreciation_percent_by_year
+            .get(year)
+            .or(depreciation_percent_by_year.last())
+            .copied()
+            .unwrap_or_default();
+
+        let fraction = (years - year as f32).min(1.0);
+
+        value *= 1.0 - percent / 100.0 * fraction;
+    }
+
+    value
+}
+
+impl GetYnabAccountConfig for Vehicle {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        Ok(YnabAccountConfig {
+            ynab_account_id: self.ynab_account_id.clone(),
+        })
+    }
+}
+
+impl GetBalance for Vehicle {
+    async fn get(&self) -> Result<f32> {
+        let config = &self.config;
+
+        match (
+            &config.valuation_url,
+            &config.valuation_json_pointer,
+            config.purchase_price,
+            config.purchase_date,
+        ) {
+            (Some(valuation_url), Some(json_pointer), _, _) => {
+                self.get_valuation(valuation_url, json_pointer).await
+            }
+            (None, _, Some(purchase_price), Some(purchase_date)) => {
+                let value = depreciated_value(
+                    purchase_price,
+                    purchase_date,
+                    &config.depreciation_percent_by_year,
+                    Local::now().date_naive(),
+                );
+
+                // YNAB's amounts only go to the penny
+                Ok((value * 100.0).round() / 100.0)
+            }
+            _ => Err(anyhow!("The vehicle has no way to be valued")),
+        }
+    }
+}
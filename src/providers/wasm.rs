@@ -0,0 +1,198 @@
+use anyhow::{anyhow, Result};
+use log::info;
+use serde::Deserialize;
+use std::time::Duration;
+use wasmtime::{
+    component::{Component, Linker},
+    Engine, Store,
+};
+
+use crate::{
+    http::{self, HttpConfig},
+    AccountConfig, Config, GetBalance, GetYnabAccountConfig, YnabAccountConfig,
+};
+
+// Kept apart since the world's `account-config` would clash with `AccountConfig`
+mod bindings {
+    wasmtime::component::bindgen!({
+        path: "wit/provider.wit",
+        world: "provider",
+        async: true,
+    });
+}
+
+use bindings::{ynab_updater::provider::host, Provider};
+
+// A balance from a plugin compiled to a WASM component implementing `wit/provider.wit`, e.g.
+// `PLUGIN = "/home/me/my_bank.wasm"`. Unlike an `exec` command it's sandboxed: it only sees its
+// own `SETTINGS` & can only reach `ALLOWED_HOSTS`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct WasmConfig {
+    pub plugin: String,
+    #[serde(default)]
+    pub allowed_hosts: Vec<String>,
+    #[serde(default)]
+    pub settings: serde_json::Map<String, serde_json::Value>,
+    #[serde(default = "default_timeout_secs")]
+    pub timeout_secs: u64,
+}
+
+fn default_timeout_secs() -> u64 {
+    120
+}
+
+// The plugin's view of the updater
+struct PluginHost {
+    account: String,
+    allowed_hosts: Vec<String>,
+    http: HttpConfig,
+}
+
+impl PluginHost {
+    async fn http_get(
+        &self,
+        url: &str,
+        headers: Vec<(String, String)>,
+    ) -> Result<host::HttpResponse> {
+        let parsed = reqwest::Url::parse(url)?;
+        let host = parsed.host_str().unwrap_or_default();
+
+        if !self.allowed_hosts.iter().any(|allowed| allowed == host) {
+            return Err(anyhow!("{} isn't in ALLOWED_HOSTS", host));
+        }
+
+        let client = http::client(&self.http)?;
+
+        let mut request = client.get(parsed);
+        for (name, value) in headers {
+            request = request.header(name, value);
+        }
+
+        let response = http::send(&client, request).await?;
+
+        Ok(host::HttpResponse {
+            status: response.status().as_u16(),
+            body: response.text().await?,
+        })
+    }
+}
+
+#[async_trait::async_trait]
+impl host::Host for PluginHost {
+    async fn http_get(
+        &mut self,
+        url: String,
+        headers: Vec<(String, String)>,
+    ) -> Result<host::HttpResponse, String> {
+        PluginHost::http_get(self, &url, headers)
+            .await
+            .map_err(|e| format!("{:#}", e))
+    }
+
+    async fn log(&mut self, message: String) {
+        info!("{}'s plugin: {}", self.account, message);
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Wasm {
+    account: String,
+    ynab_account_id: String,
+    config: WasmConfig,
+    http: HttpConfig,
+}
+
+impl Wasm {
+    pub fn new(
+        ynab_config: &Config,
+        account: &str,
+        account_config: &AccountConfig,
+    ) -> Result<Self> {
+        Ok(Wasm {
+            account: account.to_owned(),
+            ynab_account_id: account_config.ynab_account_id.clone(),
+            config: account_config.provider_config()?,
+            http: ynab_config.http.clone(),
+        })
+    }
+
+    // Each call gets a fresh instance, so nothing's kept between them
+    async fn instantiate(&self) -> Result<(Store<PluginHost>, Provider)> {
+        let mut engine_config = wasmtime::Config::new();
+        // Fuel is only used to yield now & then, so a plugin that never returns can be timed out
+        engine_config.async_support(true).consume_fuel(true);
+
+        let engine = Engine::new(&engine_config)?;
+
+        let component = Component::from_file(&engine, &self.config.plugin)
+            .map_err(|e| anyhow!("Failed to load {}: {:#}", self.config.plugin, e))?;
+
+        let mut linker = Linker::new(&engine);
+        Provider::add_to_linker(&mut linker, |host: &mut PluginHost| host)?;
+
+        let mut store = Store::new(
+            &engine,
+            PluginHost {
+                account: self.account.clone(),
+                allowed_hosts: self.config.allowed_hosts.clone(),
+                http: self.http.clone(),
+            },
+        );
+        store.set_fuel(u64::MAX)?;
+        store.fuel_async_yield_interval(Some(10_000))?;
+
+        let provider = Provider::instantiate_async(&mut store, &component, &linker).await?;
+
+        Ok((store, provider))
+    }
+
+    fn settings(&self) -> Result<String> {
+        Ok(serde_json::to_string(&self.config.settings)?)
+    }
+
+    async fn with_timeout<T>(&self, f: impl std::future::Future<Output = Result<T>>) -> Result<T> {
+        tokio::time::timeout(Duration::from_secs(self.config.timeout_secs), f)
+            .await
+            .map_err(|_| {
+                anyhow!(
+                    "{} didn't finish within {}s",
+                    self.config.plugin,
+                    self.config.timeout_secs
+                )
+            })?
+    }
+}
+
+impl GetYnabAccountConfig for Wasm {
+    async fn get(&self) -> Result<YnabAccountConfig> {
+        let account_config = self
+            .with_timeout(async {
+                let (mut store, provider) = self.instantiate().await?;
+                provider
+                    .call_get_account_config(&mut store, &self.settings()?)
+                    .await
+            })
+            .await?
+            .map_err(|e| anyhow!("{} failed: {}", self.config.plugin, e))?;
+
+        Ok(YnabAccountConfig {
+            ynab_account_id: account_config
+                .ynab_account_id
+                .unwrap_or_else(|| self.ynab_account_id.clone()),
+        })
+    }
+}
+
+impl GetBalance for Wasm {
+    async fn get(&self) -> Result<f32> {
+        self.with_timeout(async {
+            let (mut store, provider) = self.instantiate().await?;
+            provider
+                .call_get_balance(&mut store, &self.settings()?)
+                .await
+        })
+        .await?
+        .map_err(|e| anyhow!("{} failed: {}", self.config.plugin, e))
+    }
+}
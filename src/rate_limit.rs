@@ -0,0 +1,219 @@
+use anyhow::{anyhow, Result};
+use log::info;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+// YNAB allows each token 200 requests an hour, of which the limiter uses all but the margin, e.g.
+// 180. The margin's share of those, e.g. 18, can go at once, so concurrent runs can burst, & the
+// rest refill over the hour, so no hour's over the 180.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct RateLimitConfig {
+    #[serde(default = "default_requests_per_hour")]
+    pub requests_per_hour: u32,
+    #[serde(default = "default_safety_margin_percent")]
+    pub safety_margin_percent: u32,
+}
+
+impl Default for RateLimitConfig {
+    fn default() -> Self {
+        RateLimitConfig {
+            requests_per_hour: default_requests_per_hour(),
+            safety_margin_percent: default_safety_margin_percent(),
+        }
+    }
+}
+
+fn default_requests_per_hour() -> u32 {
+    200
+}
+
+fn default_safety_margin_percent() -> u32 {
+    10
+}
+
+#[derive(Debug)]
+struct Bucket {
+    tokens: f64,
+    capacity: f64,
+    // Tokens per second
+    rate: f64,
+    refilled_at: Instant,
+}
+
+impl Bucket {
+    fn new(config: &RateLimitConfig) -> Self {
+        let margin = config.safety_margin_percent.min(100) as f64 / 100.0;
+        let budget = config.requests_per_hour as f64 * (1.0 - margin);
+        let capacity = (budget * margin).max(1.0);
+
+        Bucket {
+            tokens: capacity,
+            capacity,
+            // A full bucket & an hour's refill are the budget
+            rate: (budget - capacity).max(0.0) / 3600.0,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    // Takes a token, or says how long until there's one
+    fn take(&mut self) -> Option<Duration> {
+        let now = Instant::now();
+        self.tokens = (self.tokens
+            + now.duration_since(self.refilled_at).as_secs_f64() * self.rate)
+            .min(self.capacity);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            return None;
+        }
+
+        if self.rate <= 0.0 {
+            return Some(Duration::from_secs(3600));
+        }
+
+        // At least a millisecond, as one that rounds down to nothing wouldn't wait for anything
+        Some(Duration::from_secs_f64((1.0 - self.tokens) / self.rate).max(Duration::from_millis(1)))
+    }
+}
+
+// Shared by every run in the process with the same YNAB token, since that's what YNAB counts by.
+// They're found by the token's hash, so the token isn't kept for the life of the process.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+impl RateLimiter {
+    pub fn shared(bearer_token: &SecretString, config: &RateLimitConfig) -> Result<Self> {
+        static LIMITERS: OnceLock<Mutex<HashMap<[u8; 32], RateLimiter>>> = OnceLock::new();
+
+        let mut limiters = LIMITERS
+            .get_or_init(Default::default)
+            .lock()
+            .map_err(|_| anyhow!("The rate limiters' lock is poisoned"))?;
+
+        let limiter = limiters
+            .entry(openssl::sha::sha256(
+                bearer_token.expose_secret().as_bytes(),
+            ))
+            .or_insert_with(|| RateLimiter {
+                bucket: Arc::new(Mutex::new(Bucket::new(config))),
+            })
+            .clone();
+
+        Ok(limiter)
+    }
+
+    // Waits until a request can be made
+    pub async fn acquire(&self) -> Result<()> {
+        loop {
+            let wait = self
+                .bucket
+                .lock()
+                .map_err(|_| anyhow!("The rate limiter's lock is poisoned"))?
+                .take();
+
+            match wait {
+                None => return Ok(()),
+                Some(wait) => {
+                    info!("Waiting {:.0?} for YNAB's rate limit", wait);
+                    tokio::time::sleep(wait).await;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limiter(requests_per_hour: u32, safety_margin_percent: u32) -> RateLimiter {
+        RateLimiter {
+            bucket: Arc::new(Mutex::new(Bucket::new(&RateLimitConfig {
+                requests_per_hour,
+                safety_margin_percent,
+            }))),
+        }
+    }
+
+    // When each of `count` requests went, from the first
+    async fn acquire(limiter: &RateLimiter, count: usize) -> Vec<Duration> {
+        let started = Instant::now();
+        let mut times = vec![];
+
+        for _ in 0..count {
+            limiter.acquire().await.unwrap();
+            times.push(started.elapsed());
+        }
+
+        times
+    }
+
+    // The most requests that went in any hour, starting from one of them
+    fn busiest_hour(times: &[Duration]) -> usize {
+        times
+            .iter()
+            .map(|start| {
+                times
+                    .iter()
+                    .filter(|time| *start <= **time && **time < *start + Duration::from_secs(3600))
+                    .count()
+            })
+            .max()
+            .unwrap_or(0)
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn keeps_every_hour_within_the_margin() {
+        for (requests_per_hour, safety_margin_percent, budget) in [
+            (200, 10, 180),
+            (200, 0, 200),
+            (200, 50, 100),
+            (1000, 25, 750),
+        ] {
+            let times = acquire(
+                &limiter(requests_per_hour, safety_margin_percent),
+                budget * 3,
+            )
+            .await;
+
+            assert!(
+                busiest_hour(&times) <= budget,
+                "{} an hour with {}%'s margin went over {}",
+                requests_per_hour,
+                safety_margin_percent,
+                budget
+            );
+        }
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn lets_the_margins_share_through_at_once() {
+        let times = acquire(&limiter(200, 10), 19).await;
+
+        assert!(times[..18].iter().all(|time| time.is_zero()));
+        assert!(!times[18].is_zero());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn uses_the_whole_budget_over_time() {
+        let times = acquire(&limiter(200, 10), 180 * 3).await;
+
+        // Each hour's the burst & an hour's refill, less the one that lands on the hour, & the
+        // refill's used as it comes, give or take the timer's milliseconds
+        assert!(busiest_hour(&times) >= 179);
+        assert!(
+            *times.last().unwrap()
+                <= Duration::from_secs_f64((540.0 - 18.0) / 162.0 * 3600.0 + 1.0)
+        );
+    }
+}
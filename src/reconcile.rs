@@ -0,0 +1,309 @@
+use chrono::{Datelike, NaiveDate};
+
+use crate::{
+    currency,
+    ynab::{CurrencyFormat, TransactionDetail, TransactionFlagColor},
+};
+
+// An amount in YNAB's units, thousandths of the budget's currency
+pub type Milliunits = i64;
+
+#[derive(Clone, Debug)]
+pub struct Policy {
+    // Transactions to this payee are the updater's reconciliations
+    pub reconciliation_payee_id: String,
+    // The 1st's reconciliations are flagged with this, marking them as snapshots
+    pub snapshot_flag_color: Option<TransactionFlagColor>,
+    // The rest are flagged with the account's own
+    pub flag_color: Option<TransactionFlagColor>,
+    // Whether an adjustment's merged into the last reconciliation, which a split can't be
+    pub merges: bool,
+}
+
+impl Policy {
+    pub fn is_reconciliation(&self, transaction: &TransactionDetail) -> bool {
+        transaction.payee_id.as_deref() == Some(self.reconciliation_payee_id.as_str())
+    }
+
+    // The flag for a reconciliation dated `date`
+    pub fn flag_color_on(&self, date: NaiveDate) -> Option<TransactionFlagColor> {
+        match date.day() {
+            1 => self.snapshot_flag_color.or(self.flag_color),
+            _ => self.flag_color,
+        }
+    }
+
+    // A snapshot is never folded into. Once flagged it stays one even if it's moved off the 1st.
+    pub fn is_snapshot(&self, transaction: &TransactionDetail) -> bool {
+        transaction.date.day() == 1
+            || self
+                .snapshot_flag_color
+                .is_some_and(|flag_color| transaction.flag_color == Some(flag_color))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SkipReason {
+    BalancesEqual,
+    AlreadyReconciledOnThe1st,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Decision {
+    Skip(SkipReason),
+    // Moves the last reconciliation to today, absorbing the adjustment into it
+    Update {
+        transaction_id: String,
+        amount: Milliunits,
+        adjustment: Milliunits,
+    },
+    // Posts a new reconciliation of the adjustment
+    Create {
+        adjustment: Milliunits,
+    },
+}
+
+// What to do about the difference between the real & YNAB balances, without doing any of it.
+// Every day's reconciliation is folded into the last one, except on the 1st, whose is kept to
+// record the account's value over time.
+pub fn decide(
+    real: Milliunits,
+    ynab: Milliunits,
+    last_transaction: Option<&TransactionDetail>,
+    today: NaiveDate,
+    policy: &Policy,
+) -> Decision {
+    let adjustment = real - ynab;
+
+    if adjustment == 0 {
+        return Decision::Skip(SkipReason::BalancesEqual);
+    }
+
+    match last_transaction {
+        Some(last_transaction) if today.day() == 1 && last_transaction.date.day() == 1 => {
+            Decision::Skip(SkipReason::AlreadyReconciledOnThe1st)
+        }
+        Some(last_transaction)
+            if policy.merges
+                && policy.is_reconciliation(last_transaction)
+                && !policy.is_snapshot(last_transaction) =>
+        {
+            Decision::Update {
+                transaction_id: last_transaction.id.clone(),
+                amount: last_transaction.amount + adjustment,
+                adjustment,
+            }
+        }
+        _ => Decision::Create { adjustment },
+    }
+}
+
+fn signed(currency: Option<&CurrencyFormat>, amount: Milliunits) -> String {
+    format!(
+        "{}{}",
+        if amount > 0 { "+" } else { "" },
+        currency::format(currency, amount as f32 / 1000.0)
+    )
+}
+
+// What a decision's about to do & why, for checking a large adjustment, e.g.
+//
+// ```text
+// YNAB: £1,000.00
+// Provider: £1,250.00
+// Merge: moves the reconciliation of 2023-10-10 to today, from +£100.00 to +£350.00
+// ```
+pub fn preview(
+    decision: &Decision,
+    real: Milliunits,
+    ynab: Milliunits,
+    last_transaction: Option<&TransactionDetail>,
+    policy: &Policy,
+    currency: Option<&CurrencyFormat>,
+) -> Option<String> {
+    let change = match decision {
+        Decision::Skip(_) => return None,
+        Decision::Update {
+            amount, adjustment, ..
+        } => format!(
+            "Merge: moves the reconciliation of {} to today, from {} to {}",
+            last_transaction.map_or("before".to_owned(), |t| t.date.to_string()),
+            signed(currency, amount - adjustment),
+            signed(currency, *amount)
+        ),
+        Decision::Create { adjustment } => format!(
+            "Create: posts a reconciliation of {}, as {}",
+            signed(currency, *adjustment),
+            match last_transaction {
+                None => "there's no transaction to merge into",
+                Some(_) if !policy.merges => "it's split",
+                Some(t) if !policy.is_reconciliation(t) => "the last transaction isn't one",
+                Some(_) => "the last one's a snapshot",
+            }
+        ),
+    };
+
+    Some(format!(
+        "YNAB: {}\nProvider: {}\n{}",
+        currency::format(currency, ynab as f32 / 1000.0),
+        currency::format(currency, real as f32 / 1000.0),
+        change
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ynab::TransactionClearedStatus;
+
+    static PAYEE_ID: &str = "reconciliation";
+
+    fn policy() -> Policy {
+        Policy {
+            reconciliation_payee_id: PAYEE_ID.to_owned(),
+            snapshot_flag_color: Some(TransactionFlagColor::Blue),
+            flag_color: None,
+            merges: true,
+        }
+    }
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 10, day).unwrap()
+    }
+
+    fn transaction(day: u32, amount: Milliunits, payee_id: &str) -> TransactionDetail {
+        TransactionDetail {
+            id: "last".to_owned(),
+            date: date(day),
+            amount,
+            payee_id: Some(payee_id.to_owned()),
+            cleared: TransactionClearedStatus::Cleared,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn skips_equal_balances() {
+        let last = transaction(10, 5000, PAYEE_ID);
+
+        assert_eq!(
+            decide(1000, 1000, Some(&last), date(15), &policy()),
+            Decision::Skip(SkipReason::BalancesEqual)
+        );
+        assert_eq!(
+            decide(0, 0, None, date(1), &policy()),
+            Decision::Skip(SkipReason::BalancesEqual)
+        );
+    }
+
+    #[test]
+    fn creates_without_a_last_transaction() {
+        assert_eq!(
+            decide(1500, 1000, None, date(15), &policy()),
+            Decision::Create { adjustment: 500 }
+        );
+    }
+
+    #[test]
+    fn skips_the_1st_once_its_reconciled() {
+        let last = transaction(1, 5000, PAYEE_ID);
+
+        assert_eq!(
+            decide(1500, 1000, Some(&last), date(1), &policy()),
+            Decision::Skip(SkipReason::AlreadyReconciledOnThe1st)
+        );
+    }
+
+    // It's then the 1st's, so kept as a snapshot
+    #[test]
+    fn moves_the_last_reconciliation_to_the_1st() {
+        let last = transaction(20, 5000, PAYEE_ID);
+
+        assert_eq!(
+            decide(1500, 1000, Some(&last), date(1), &policy()),
+            Decision::Update {
+                transaction_id: "last".to_owned(),
+                amount: 5500,
+                adjustment: 500,
+            }
+        );
+    }
+
+    #[test]
+    fn updates_the_last_reconciliation() {
+        let last = transaction(10, 5000, PAYEE_ID);
+
+        assert_eq!(
+            decide(1500, 1000, Some(&last), date(15), &policy()),
+            Decision::Update {
+                transaction_id: "last".to_owned(),
+                amount: 5500,
+                adjustment: 500,
+            }
+        );
+    }
+
+    #[test]
+    fn creates_after_a_transaction_that_isnt_a_reconciliation() {
+        let last = transaction(10, 5000, "groceries");
+
+        assert_eq!(
+            decide(1500, 1000, Some(&last), date(15), &policy()),
+            Decision::Create { adjustment: 500 }
+        );
+    }
+
+    #[test]
+    fn creates_after_a_snapshot() {
+        let on_the_1st = transaction(1, 5000, PAYEE_ID);
+
+        assert_eq!(
+            decide(1500, 1000, Some(&on_the_1st), date(15), &policy()),
+            Decision::Create { adjustment: 500 }
+        );
+
+        // Still a snapshot once it's been moved off the 1st
+        let flagged = TransactionDetail {
+            flag_color: Some(TransactionFlagColor::Blue),
+            ..transaction(3, 5000, PAYEE_ID)
+        };
+
+        assert_eq!(
+            decide(1500, 1000, Some(&flagged), date(15), &policy()),
+            Decision::Create { adjustment: 500 }
+        );
+    }
+
+    #[test]
+    fn creates_when_it_doesnt_merge() {
+        let last = transaction(10, 5000, PAYEE_ID);
+        let split = Policy {
+            merges: false,
+            ..policy()
+        };
+
+        assert_eq!(
+            decide(1500, 1000, Some(&last), date(15), &split),
+            Decision::Create { adjustment: 500 }
+        );
+    }
+
+    #[test]
+    fn updates_by_a_negative_adjustment() {
+        let last = transaction(10, 300, PAYEE_ID);
+
+        // The reconciliation can end up negative when the balance falls by more than it
+        assert_eq!(
+            decide(500, 1000, Some(&last), date(15), &policy()),
+            Decision::Update {
+                transaction_id: "last".to_owned(),
+                amount: -200,
+                adjustment: -500,
+            }
+        );
+        assert_eq!(
+            decide(-1500, -1000, None, date(15), &policy()),
+            Decision::Create { adjustment: -500 }
+        );
+    }
+}
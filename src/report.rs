@@ -0,0 +1,99 @@
+use serde::{Deserialize, Serialize};
+
+use crate::{logging, reconcile, sinks::SinkResult};
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SkipReason {
+    BalancesEqual,
+    AlreadyReconciledOnThe1st,
+    Paused,
+    // Reconciled within MIN_RECONCILE_INTERVAL_HOURS, or outside its MARKET_HOURS
+    NotDue,
+    AwaitingAuth,
+    AwaitingApproval,
+    // By a SCRIPT hook
+    Script,
+    // The YNAB account's closed or deleted, until it's reopened or reconfigured
+    Closed,
+    // e.g. the balance was queued while YNAB was unreachable, see the report's warning
+    Warning,
+}
+
+impl From<reconcile::SkipReason> for SkipReason {
+    fn from(reason: reconcile::SkipReason) -> Self {
+        match reason {
+            reconcile::SkipReason::BalancesEqual => SkipReason::BalancesEqual,
+            reconcile::SkipReason::AlreadyReconciledOnThe1st => {
+                SkipReason::AlreadyReconciledOnThe1st
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum RunAction {
+    Created,
+    Updated,
+    Skipped(SkipReason),
+}
+
+impl RunAction {
+    // Whether YNAB's balance is now the real one, whether or not it needed adjusting
+    pub fn is_reconciled(&self) -> bool {
+        matches!(
+            self,
+            RunAction::Created
+                | RunAction::Updated
+                | RunAction::Skipped(SkipReason::BalancesEqual)
+                | RunAction::Skipped(SkipReason::AlreadyReconciledOnThe1st)
+        )
+    }
+}
+
+// What an account's run did, for whatever's reporting on it
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct RunReport {
+    pub account: String,
+    // The YNAB account's name
+    pub ynab_account: Option<String>,
+    pub real_balance: Option<f32>,
+    pub ynab_balance: Option<f32>,
+    pub adjustment: Option<f32>,
+    // What was paid in less what was taken out, posted apart from the adjustment
+    #[serde(default)]
+    pub contributions: Option<f32>,
+    // Dividends & interest, posted apart from the adjustment
+    #[serde(default)]
+    pub income: Option<f32>,
+    pub action: RunAction,
+    // The reconciliation that was created or updated
+    pub transaction_id: Option<String>,
+    pub warning: Option<String>,
+    // For finding the run's lines in the logs
+    #[serde(default)]
+    pub run_id: Option<String>,
+    // How writing the reconciliation to each of the account's sinks went
+    #[serde(default)]
+    pub sinks: Vec<SinkResult>,
+}
+
+impl RunReport {
+    pub fn skipped(account: &str, reason: SkipReason) -> Self {
+        RunReport {
+            account: account.to_owned(),
+            ynab_account: None,
+            real_balance: None,
+            ynab_balance: None,
+            adjustment: None,
+            contributions: None,
+            income: None,
+            action: RunAction::Skipped(reason),
+            transaction_id: None,
+            warning: None,
+            run_id: logging::run_id(),
+            sinks: vec![],
+        }
+    }
+}
@@ -0,0 +1,196 @@
+// A new provider's module, generated from a template for the kind of integration it is & registered
+// in `providers`, so adding a bank starts from code that builds rather than a blank file. It's run
+// from a checkout, & leaves TODOs where the institution's specifics go.
+
+use anyhow::{anyhow, Context, Result};
+use std::path::{Path, PathBuf};
+
+static API_TEMPLATE: &str = include_str!("scaffold/api.rs.tmpl");
+static OAUTH_TEMPLATE: &str = include_str!("scaffold/oauth.rs.tmpl");
+static SCRAPER_TEMPLATE: &str = include_str!("scaffold/scraper.rs.tmpl");
+
+static PROVIDERS_PATH: &str = "src/providers.rs";
+
+#[derive(Clone, Copy, Debug, PartialEq, clap::ValueEnum)]
+pub enum Template {
+    // An API read with a token from the account's settings
+    Api,
+    // An API read with an OAuth login, which the `auth` command starts
+    Oauth,
+    // A website logged in to with a form & scraped
+    Scraper,
+}
+
+impl Template {
+    fn source(&self) -> &'static str {
+        match self {
+            Template::Api => API_TEMPLATE,
+            Template::Oauth => OAUTH_TEMPLATE,
+            Template::Scraper => SCRAPER_TEMPLATE,
+        }
+    }
+}
+
+// e.g. `my_bank` to `MyBank`
+fn type_name(name: &str) -> String {
+    name.split('_')
+        .map(|word| {
+            let mut chars = word.chars();
+            chars
+                .next()
+                .map(|first| first.to_ascii_uppercase().to_string() + chars.as_str())
+                .unwrap_or_default()
+        })
+        .collect()
+}
+
+// Inserted before the first of `lines` that sorts after it, along with any attribute on that line,
+// or after the last
+fn insert_sorted(source: &str, line: &str, is_listed: impl Fn(&str) -> bool) -> Result<String> {
+    let lines = source.lines().collect::<Vec<_>>();
+
+    let listed = lines
+        .iter()
+        .enumerate()
+        .filter(|(_, l)| is_listed(l))
+        .map(|(i, l)| (i, *l))
+        .collect::<Vec<_>>();
+
+    let (last, _) = listed
+        .last()
+        .ok_or_else(|| anyhow!("Found nowhere to add {:?}", line))?;
+
+    let at = match listed.iter().find(|(_, l)| *l > line) {
+        Some((i, _)) if *i > 0 && lines[i - 1].starts_with("#[") => i - 1,
+        Some((i, _)) => *i,
+        None => last + 1,
+    };
+
+    let mut lines = lines.iter().map(|l| l.to_string()).collect::<Vec<_>>();
+    lines.insert(at, line.to_owned());
+
+    Ok(lines.join("\n") + "\n")
+}
+
+fn insert_before(source: &str, anchor: &str, text: &str) -> Result<String> {
+    if !source.contains(anchor) {
+        return Err(anyhow!(
+            "{} has no {:?} to add the provider before, it'll have to be registered by hand",
+            PROVIDERS_PATH,
+            anchor.trim()
+        ));
+    }
+
+    Ok(source.replacen(anchor, &format!("{}{}", text, anchor), 1))
+}
+
+// The provider added to `ProviderKind` & each of its matches, running its `auth` for an OAuth one
+fn register(source: &str, name: &str, type_name: &str, template: Template) -> Result<String> {
+    let kind = format!("ProviderKind::{}", type_name);
+
+    let source = insert_sorted(source, &format!("pub mod {};", name), |line| {
+        line.starts_with("pub mod ")
+    })?;
+    // Only the providers' own, e.g. `use hl::Hl;`
+    let modules = source
+        .lines()
+        .filter_map(|line| line.strip_prefix("pub mod ")?.strip_suffix(';'))
+        .collect::<Vec<_>>();
+    let source = insert_sorted(&source, &format!("use {}::{};", name, type_name), |line| {
+        line.strip_prefix("use ")
+            .and_then(|line| line.split_once("::"))
+            .is_some_and(|(module, _)| modules.contains(&module))
+    })?;
+
+    let source = insert_before(&source, "    Mock,\n}", &format!("    {},\n", type_name))?;
+    let source = insert_before(
+        &source,
+        "            ProviderKind::Mock => \"mock\",",
+        &format!("            {} => \"{}\",\n", kind, name),
+    )?;
+    let source = insert_before(
+        &source,
+        "        ProviderKind::Mock => update_ynab(",
+        &format!(
+            "        {} => {{\n            update_ynab(config, account, {}::new(config, account, account_config)?).await\n        }}\n",
+            kind, type_name
+        ),
+    )?;
+
+    // Every capability it doesn't have
+    let mut source = source.replace(
+        "        | ProviderKind::Mock =>",
+        &format!("        | {}\n        | ProviderKind::Mock =>", kind),
+    );
+
+    if template == Template::Oauth {
+        let start = source
+            .find("pub async fn auth_account(")
+            .ok_or_else(|| anyhow!("{} has no auth_account", PROVIDERS_PATH))?;
+        let end = start
+            + source[start..]
+                .find("\n}\n")
+                .ok_or_else(|| anyhow!("{}'s auth_account doesn't end", PROVIDERS_PATH))?;
+
+        let auth_account = source[start..end]
+            .replacen(&format!("        | {}\n", kind), "", 1)
+            .replacen(
+                "        ProviderKind::Hl\n",
+                &format!(
+                    "        {} => {}::new(config, account, account_config)?.auth().await,\n        ProviderKind::Hl\n",
+                    kind, type_name
+                ),
+                1,
+            );
+
+        source.replace_range(start..end, &auth_account);
+    }
+
+    Ok(source)
+}
+
+// The new module's path, once it's written & registered
+pub fn new_provider(dir: &Path, name: &str, template: Template) -> Result<PathBuf> {
+    let is_valid = name.starts_with(|c: char| c.is_ascii_lowercase())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_');
+
+    if !is_valid {
+        return Err(anyhow!(
+            "{:?} isn't a valid provider name, which is lowercase letters, digits & underscores, e.g. my_bank",
+            name
+        ));
+    }
+
+    let providers_path = dir.join(PROVIDERS_PATH);
+    let module_path = dir.join("src/providers").join(format!("{}.rs", name));
+
+    let providers = std::fs::read_to_string(&providers_path).with_context(|| {
+        format!(
+            "Failed to read {}, new-provider is run from a checkout",
+            providers_path.display()
+        )
+    })?;
+
+    if module_path.exists() || providers.contains(&format!("pub mod {};", name)) {
+        return Err(anyhow!("There's already a provider named {}", name));
+    }
+
+    let type_name = type_name(name);
+
+    let module = template
+        .source()
+        .replace("__NAME__", &name.to_uppercase())
+        .replace("__name__", name)
+        .replace("__Type__", &type_name);
+
+    let providers = register(&providers, name, &type_name, template)?;
+
+    std::fs::write(&module_path, module)
+        .with_context(|| format!("Failed to write {}", module_path.display()))?;
+    std::fs::write(&providers_path, providers)
+        .with_context(|| format!("Failed to write {}", providers_path.display()))?;
+
+    Ok(module_path)
+}
@@ -0,0 +1,211 @@
+use anyhow::{anyhow, Result};
+use chrono::Local;
+use log::{info, warn};
+use serde::Serialize;
+use std::fmt;
+
+use crate::{
+    digest::DigestEntry,
+    report::{RunAction, RunReport},
+    Config,
+};
+
+// Hooks from a Rhai script, e.g. `SCRIPT = "/home/me/hooks.rhai"`, for rules that aren't worth a
+// setting. Each is passed the run so far & is optional:
+//
+// - `on_balance_fetched(report)` can return a number to use as the balance instead
+// - `before_post(report)` can return `#{ memo: "..." }` to post with another memo
+// - `after_post(report)` is told what was posted
+//
+// The first two can also return `false` to skip the run, or a string to skip it with that as a
+// warning, e.g. `if report.adjustment.abs() > 1000.0 { "That's a big change" }`.
+#[derive(Clone, Debug)]
+pub struct ScriptSkip {
+    pub hook: &'static str,
+    pub warning: Option<String>,
+}
+
+impl fmt::Display for ScriptSkip {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.warning {
+            Some(warning) => write!(f, "SCRIPT's {} skipped the run: {}", self.hook, warning),
+            None => write!(f, "SCRIPT's {} skipped the run", self.hook),
+        }
+    }
+}
+
+impl std::error::Error for ScriptSkip {}
+
+// What a hook's given, as a map, e.g. `report.real_balance` or `report.weekday == "Sat"`
+#[derive(Clone, Debug, Serialize)]
+struct HookReport<'a> {
+    account: &'a str,
+    ynab_account: Option<&'a str>,
+    real_balance: Option<f32>,
+    ynab_balance: Option<f32>,
+    adjustment: Option<f32>,
+    // Set once it's decided
+    action: Option<RunAction>,
+    memo: Option<&'a str>,
+    transaction_id: Option<&'a str>,
+    run_id: Option<String>,
+    date: String,
+    weekday: String,
+}
+
+impl<'a> HookReport<'a> {
+    fn from_entry(entry: &'a DigestEntry) -> Self {
+        let now = Local::now();
+
+        HookReport {
+            account: &entry.provider,
+            ynab_account: entry.account.as_deref(),
+            real_balance: entry.real_balance,
+            ynab_balance: entry.ynab_balance,
+            adjustment: entry.adjustment,
+            action: None,
+            memo: None,
+            transaction_id: None,
+            run_id: crate::logging::run_id(),
+            date: now.format("%Y-%m-%d").to_string(),
+            weekday: now.format("%a").to_string(),
+        }
+    }
+}
+
+// Only made by the scripting feature's `call`
+#[cfg_attr(not(feature = "scripting"), allow(dead_code))]
+enum HookResult {
+    Continue,
+    Balance(f32),
+    Memo(String),
+    Skip(Option<String>),
+}
+
+#[cfg(feature = "scripting")]
+fn call(path: &str, hook: &'static str, report: &HookReport) -> Result<HookResult> {
+    use rhai::{Dynamic, Engine, Map, Scope};
+
+    let mut engine = Engine::new();
+    // A script that loops forever fails the run rather than hanging it
+    engine.set_max_operations(10_000_000);
+    // Rather than printing to stdout, which may be `--output json`'s
+    engine.on_print(|message| info!("SCRIPT: {}", message));
+    engine.on_debug(|message, _, _| info!("SCRIPT: {}", message));
+
+    let ast = engine
+        .compile_file(path.into())
+        .map_err(|e| anyhow!("Failed to load SCRIPT {}: {}", path, e))?;
+
+    if !ast
+        .iter_functions()
+        .any(|f| f.name == hook && f.params.len() == 1)
+    {
+        return Ok(HookResult::Continue);
+    }
+
+    let report = rhai::serde::to_dynamic(report).map_err(|e| anyhow!("{}", e))?;
+
+    let result = engine
+        .call_fn::<Dynamic>(&mut Scope::new(), &ast, hook, (report,))
+        .map_err(|e| anyhow!("SCRIPT's {} failed: {}", hook, e))?;
+
+    if result.is_unit() || result.as_bool() == Ok(true) {
+        Ok(HookResult::Continue)
+    } else if result.as_bool() == Ok(false) {
+        Ok(HookResult::Skip(None))
+    } else if result.is_string() {
+        Ok(HookResult::Skip(Some(
+            result.into_string().unwrap_or_default(),
+        )))
+    } else if let Ok(balance) = result.as_float() {
+        Ok(HookResult::Balance(balance as f32))
+    } else if let Ok(balance) = result.as_int() {
+        Ok(HookResult::Balance(balance as f32))
+    } else if let Some(memo) = result
+        .try_cast::<Map>()
+        .and_then(|map| map.get("memo").cloned())
+        .and_then(|memo| memo.into_string().ok())
+    {
+        Ok(HookResult::Memo(memo))
+    } else {
+        Err(anyhow!("SCRIPT's {} returned something it can't", hook))
+    }
+}
+
+#[cfg(not(feature = "scripting"))]
+fn call(_path: &str, _hook: &'static str, _report: &HookReport) -> Result<HookResult> {
+    Err(anyhow!(
+        "SCRIPT is set, but the updater was built without the scripting feature"
+    ))
+}
+
+fn skip(hook: &'static str, warning: Option<String>) -> anyhow::Error {
+    ScriptSkip { hook, warning }.into()
+}
+
+// The balance to reconcile to
+pub fn on_balance_fetched(config: &Config, entry: &DigestEntry, balance: f32) -> Result<f32> {
+    let path = match &config.script {
+        Some(path) => path,
+        None => return Ok(balance),
+    };
+
+    let hook = "on_balance_fetched";
+
+    match call(path, hook, &HookReport::from_entry(entry))? {
+        HookResult::Continue => Ok(balance),
+        HookResult::Balance(replaced) => {
+            info!("SCRIPT replaced the balance {} with {}", balance, replaced);
+            Ok(replaced)
+        }
+        HookResult::Skip(warning) => Err(skip(hook, warning)),
+        HookResult::Memo(_) => Err(anyhow!("SCRIPT's {} can't set the memo", hook)),
+    }
+}
+
+// The memo to post with
+pub fn before_post(
+    config: &Config,
+    entry: &DigestEntry,
+    action: RunAction,
+    memo: Option<String>,
+) -> Result<Option<String>> {
+    let path = match &config.script {
+        Some(path) => path,
+        None => return Ok(memo),
+    };
+
+    let hook = "before_post";
+
+    let report = HookReport {
+        action: Some(action),
+        memo: memo.as_deref(),
+        ..HookReport::from_entry(entry)
+    };
+
+    match call(path, hook, &report)? {
+        HookResult::Continue => Ok(memo),
+        HookResult::Memo(memo) => Ok(Some(memo)),
+        HookResult::Skip(warning) => Err(skip(hook, warning)),
+        HookResult::Balance(_) => Err(anyhow!("SCRIPT's {} can't change the balance", hook)),
+    }
+}
+
+// Only warned about if it fails, since the adjustment's already posted
+pub fn after_post(config: &Config, entry: &DigestEntry, report: &RunReport) {
+    let path = match &config.script {
+        Some(path) => path,
+        None => return,
+    };
+
+    let report = HookReport {
+        action: Some(report.action),
+        transaction_id: report.transaction_id.as_deref(),
+        ..HookReport::from_entry(entry)
+    };
+
+    if let Err(e) = call(path, "after_post", &report) {
+        warn!("{:#}", e);
+    }
+}
@@ -0,0 +1,81 @@
+// A cached secret encrypted with a key derived from a passphrase, e.g. the login it's for, so a
+// copy of the cache, e.g. in a backup, is no use without the login. AES-256-GCM, so a changed
+// passphrase or a tampered cache fails to open rather than giving garbage.
+
+use anyhow::{anyhow, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use openssl::{
+    hash::MessageDigest,
+    pkcs5::pbkdf2_hmac,
+    rand::rand_bytes,
+    symm::{decrypt_aead, encrypt_aead, Cipher},
+};
+use secrecy::{zeroize::Zeroizing, ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+static PBKDF2_ITERATIONS: usize = 600_000;
+static SALT_BYTES: usize = 16;
+static NONCE_BYTES: usize = 12;
+static TAG_BYTES: usize = 16;
+
+// Each part's base64
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Sealed {
+    salt: String,
+    nonce: String,
+    tag: String,
+    ciphertext: String,
+}
+
+fn key(passphrase: &SecretString, salt: &[u8]) -> Result<Zeroizing<[u8; 32]>> {
+    let mut key = Zeroizing::new([0; 32]);
+    pbkdf2_hmac(
+        passphrase.expose_secret().as_bytes(),
+        salt,
+        PBKDF2_ITERATIONS,
+        MessageDigest::sha256(),
+        key.as_mut(),
+    )?;
+
+    Ok(key)
+}
+
+impl Sealed {
+    pub fn seal(passphrase: &SecretString, secret: &SecretString) -> Result<Self> {
+        let mut salt = vec![0; SALT_BYTES];
+        rand_bytes(&mut salt)?;
+        let mut nonce = vec![0; NONCE_BYTES];
+        rand_bytes(&mut nonce)?;
+        let mut tag = vec![0; TAG_BYTES];
+
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(),
+            key(passphrase, &salt)?.as_ref(),
+            Some(&nonce),
+            &[],
+            secret.expose_secret().as_bytes(),
+            &mut tag,
+        )?;
+
+        Ok(Sealed {
+            salt: STANDARD.encode(salt),
+            nonce: STANDARD.encode(nonce),
+            tag: STANDARD.encode(tag),
+            ciphertext: STANDARD.encode(ciphertext),
+        })
+    }
+
+    pub fn open(&self, passphrase: &SecretString) -> Result<SecretString> {
+        let secret = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            key(passphrase, &STANDARD.decode(&self.salt)?)?.as_ref(),
+            Some(&STANDARD.decode(&self.nonce)?),
+            &[],
+            &STANDARD.decode(&self.ciphertext)?,
+            &STANDARD.decode(&self.tag)?,
+        )
+        .map_err(|_| anyhow!("It was sealed with another passphrase, or it's been changed"))?;
+
+        Ok(String::from_utf8(secret)?.into())
+    }
+}
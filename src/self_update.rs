@@ -0,0 +1,312 @@
+// The running binary replaced by the latest GitHub release's, for installs where rebuilding with
+// nightly Rust is a chore. Each release's binaries are signed with minisign, & the download is only
+// installed if its signature verifies against the public key the updater was built with.
+
+use anyhow::{anyhow, Context, Result};
+use log::info;
+use openssl::{
+    base64,
+    hash::{self, MessageDigest},
+    pkey::{Id, PKey},
+    sign::Verifier,
+};
+use serde::Deserialize;
+use std::{env, fs, path::Path};
+
+use crate::http::{self, HttpConfig};
+
+static LATEST_RELEASE_URL: &str =
+    "https://api.github.com/repos/jcarrag/ynab-updater/releases/latest";
+
+// Set by the release workflow when it builds the binaries, as the minisign public key's base64
+static PUBLIC_KEY: Option<&str> = option_env!("YNAB_UPDATER_MINISIGN_PUBLIC_KEY");
+
+#[derive(Clone, Debug, Deserialize)]
+struct Release {
+    tag_name: String,
+    assets: Vec<Asset>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct Asset {
+    name: String,
+    browser_download_url: String,
+}
+
+// What the release workflow names each binary, e.g. `ynab-updater-x86_64-linux`
+fn asset_name() -> String {
+    format!(
+        "ynab-updater-{}-{}{}",
+        env::consts::ARCH,
+        env::consts::OS,
+        env::consts::EXE_SUFFIX
+    )
+}
+
+// e.g. `v0.2.0` to [0, 2, 0], so versions compare numerically
+fn parse_version(version: &str) -> Result<Vec<u64>> {
+    version
+        .trim_start_matches('v')
+        .split('.')
+        .map(|part| {
+            part.parse::<u64>()
+                .map_err(|_| anyhow!("{:?} isn't a version like v0.2.0", version))
+        })
+        .collect()
+}
+
+// A minisign public key or signature line's bytes, after its 2 byte algorithm & 8 byte key id
+fn decode_minisign(line: &str, len: usize) -> Result<(Vec<u8>, Vec<u8>)> {
+    let bytes = base64::decode_block(line.trim())?;
+
+    if bytes.len() != 10 + len {
+        return Err(anyhow!(
+            "Expected {} bytes of minisign key or signature",
+            10 + len
+        ));
+    }
+
+    Ok((bytes[..10].to_vec(), bytes[10..].to_vec()))
+}
+
+fn verify_ed25519(public_key: &[u8], signature: &[u8], message: &[u8]) -> Result<bool> {
+    let public_key = PKey::public_key_from_raw_bytes(public_key, Id::ED25519)?;
+
+    let mut verifier = Verifier::new_without_digest(&public_key)?;
+
+    Ok(verifier.verify_oneshot(signature, message)?)
+}
+
+// A `.minisig` file: an untrusted comment, the signature of the file, or of its BLAKE2b hash, a
+// trusted comment & a signature of the first signature with the trusted comment
+pub fn verify(public_key: &str, minisig: &str, file: &[u8]) -> Result<()> {
+    let (key_header, public_key) = decode_minisign(public_key, 32)?;
+
+    let lines = minisig.lines().collect::<Vec<_>>();
+    let (signature_line, trusted_comment, global_signature_line) = match lines.as_slice() {
+        [_, signature, trusted_comment, global_signature, ..] => (
+            signature,
+            trusted_comment
+                .strip_prefix("trusted comment: ")
+                .ok_or_else(|| anyhow!("The signature has no trusted comment"))?,
+            global_signature,
+        ),
+        _ => return Err(anyhow!("The signature isn't a minisign signature")),
+    };
+
+    let (signature_header, signature) = decode_minisign(signature_line, 64)?;
+
+    if signature_header[2..] != key_header[2..] {
+        return Err(anyhow!("The release was signed with a different key"));
+    }
+
+    let message = match &signature_header[..2] {
+        b"Ed" => file.to_vec(),
+        b"ED" => {
+            let blake2b = MessageDigest::from_name("BLAKE2b512")
+                .ok_or_else(|| anyhow!("OpenSSL doesn't support BLAKE2b"))?;
+            hash::hash(blake2b, file)?.to_vec()
+        }
+        _ => return Err(anyhow!("The signature's algorithm isn't supported")),
+    };
+
+    if !verify_ed25519(&public_key, &signature, &message)? {
+        return Err(anyhow!("The release's signature doesn't match it"));
+    }
+
+    let global_signature = base64::decode_block(global_signature_line.trim())?;
+    let signed_comment = [signature.as_slice(), trusted_comment.as_bytes()].concat();
+
+    if !verify_ed25519(&public_key, &global_signature, &signed_comment)? {
+        return Err(anyhow!("The signature's trusted comment doesn't match it"));
+    }
+
+    Ok(())
+}
+
+async fn download(client: &reqwest::Client, url: &str) -> Result<Vec<u8>> {
+    let response = http::send(client, client.get(url))
+        .await?
+        .error_for_status()?;
+
+    Ok(response.bytes().await?.to_vec())
+}
+
+// Written beside the binary then renamed over it, so it's never left half written. Windows won't
+// replace a running binary, but will rename it out of the way.
+fn replace_binary(exe: &Path, binary: &[u8]) -> Result<()> {
+    let new_path = exe.with_extension("new");
+
+    fs::write(&new_path, binary)
+        .with_context(|| format!("Failed to write {}", new_path.display()))?;
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        fs::set_permissions(&new_path, fs::Permissions::from_mode(0o755))?;
+    }
+
+    #[cfg(windows)]
+    {
+        let old_path = exe.with_extension("old");
+        let _ = fs::remove_file(&old_path);
+        fs::rename(exe, &old_path)?;
+    }
+
+    fs::rename(&new_path, exe).with_context(|| format!("Failed to replace {}", exe.display()))?;
+
+    Ok(())
+}
+
+// The release's tag if it's newer than this binary, once it's installed unless `check_only`
+pub async fn self_update(
+    http_config: &HttpConfig,
+    check_only: bool,
+    force: bool,
+) -> Result<Option<String>> {
+    let public_key = PUBLIC_KEY.ok_or_else(|| {
+        anyhow!("This updater was built without a release signing key, so can't verify an update. Build it from source instead.")
+    })?;
+
+    let client = http::client(http_config)?;
+
+    let release = http::send(
+        &client,
+        client
+            .get(LATEST_RELEASE_URL)
+            .header("Accept", "application/vnd.github+json"),
+    )
+    .await?
+    .error_for_status()?
+    .json::<Release>()
+    .await?;
+
+    let current = env!("CARGO_PKG_VERSION");
+    if !force && parse_version(&release.tag_name)? <= parse_version(current)? {
+        info!("{} is the latest release", current);
+        return Ok(None);
+    }
+
+    if check_only {
+        return Ok(Some(release.tag_name));
+    }
+
+    let asset_name = asset_name();
+    let signature_name = format!("{}.minisig", asset_name);
+    let find_asset = |name: &str| {
+        release
+            .assets
+            .iter()
+            .find(|asset| asset.name == name)
+            .ok_or_else(|| anyhow!("Release {} has no {}", release.tag_name, name))
+    };
+
+    let binary = download(&client, &find_asset(&asset_name)?.browser_download_url).await?;
+    let signature = download(&client, &find_asset(&signature_name)?.browser_download_url).await?;
+
+    verify(public_key, &String::from_utf8(signature)?, &binary)
+        .with_context(|| format!("Failed to verify {}", asset_name))?;
+
+    replace_binary(&env::current_exe()?, &binary)?;
+
+    Ok(Some(release.tag_name))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A key pair made for these tests, whose secret key's bytes are 0 to 31, with the key id
+    // a1b2c3d4e5f60718
+    static TEST_PUBLIC_KEY: &str = "RWShssPU5fYHGAOhB7/zzhC+HXDdGOdLwJln5NYwm6UNXx3chmQSVTG4";
+
+    static FILE: &[u8] = b"ynab-updater release binary\n";
+
+    // Signed like `minisign -S`, which signs the file's BLAKE2b hash
+    static HASHED_MINISIG: &str = "untrusted comment: signature from minisign secret key
+RUShssPU5fYHGEqUaflYRMiM1jBEqo4w2eSOj9YlFizrytwWrJQhvTARuGEjFUh0XbUb+O4A1WUeSU5ZfOBjVAoHNLmJAUKNhg0=
+trusted comment: timestamp:1760000000\tfile:ynab-updater-x86_64-linux\thashed
+NLe6N929IpkmNA+sfH5yOQIRQcH0ZXFKkyTY5AMCFztPJ2kg5t5rYmM1ilX5igCJIdTT5020dp46AxXVcp2jBQ==
+";
+
+    // Signed like `minisign -S -l`, which signs the file itself
+    static LEGACY_MINISIG: &str = "untrusted comment: signature from minisign secret key
+RWShssPU5fYHGCr6RjMoqLIV7LbgUwFamIvUDOnWa5tSM3f9VvbeTgTkZDCBRrmlFIHJIw7DWAyVqr2BanSK+xP5Y2yWeZXIwwQ=
+trusted comment: timestamp:1760000000\tfile:ynab-updater-x86_64-linux
+6CMoVgS0O7CO65QBdVvxrwcs4qjmFD5v7ejP7T0W5B+526j5F12dIDv/q8GCbmIRd5FIgL95EO5tfs3nSDwMDw==
+";
+
+    // The minisig with its signature line's bytes changed
+    fn with_signature_bytes(minisig: &str, change: impl Fn(&mut Vec<u8>)) -> String {
+        let mut lines = minisig.lines().map(str::to_owned).collect::<Vec<_>>();
+
+        let mut bytes = base64::decode_block(&lines[1]).unwrap();
+        change(&mut bytes);
+        lines[1] = base64::encode_block(&bytes);
+
+        lines.join("\n")
+    }
+
+    #[test]
+    fn accepts_a_valid_signature() {
+        verify(TEST_PUBLIC_KEY, HASHED_MINISIG, FILE).unwrap();
+        verify(TEST_PUBLIC_KEY, LEGACY_MINISIG, FILE).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_changed_file() {
+        let mut file = FILE.to_vec();
+        file[0] ^= 1;
+
+        for minisig in [HASHED_MINISIG, LEGACY_MINISIG] {
+            let e = verify(TEST_PUBLIC_KEY, minisig, &file).unwrap_err();
+
+            assert_eq!(e.to_string(), "The release's signature doesn't match it");
+        }
+    }
+
+    #[test]
+    fn rejects_a_signature_from_another_key() {
+        let minisig = with_signature_bytes(HASHED_MINISIG, |bytes| bytes[2] ^= 1);
+
+        let e = verify(TEST_PUBLIC_KEY, &minisig, FILE).unwrap_err();
+
+        assert_eq!(e.to_string(), "The release was signed with a different key");
+    }
+
+    #[test]
+    fn rejects_a_changed_trusted_comment() {
+        let minisig = HASHED_MINISIG.replace("timestamp:1760000000", "timestamp:1760000001");
+
+        let e = verify(TEST_PUBLIC_KEY, &minisig, FILE).unwrap_err();
+
+        assert_eq!(
+            e.to_string(),
+            "The signature's trusted comment doesn't match it"
+        );
+    }
+
+    #[test]
+    fn rejects_a_signature_relabelled_as_the_other_algorithm() {
+        let unhashed =
+            with_signature_bytes(HASHED_MINISIG, |bytes| bytes[..2].copy_from_slice(b"Ed"));
+        let hashed =
+            with_signature_bytes(LEGACY_MINISIG, |bytes| bytes[..2].copy_from_slice(b"ED"));
+
+        for minisig in [unhashed, hashed] {
+            let e = verify(TEST_PUBLIC_KEY, &minisig, FILE).unwrap_err();
+
+            assert_eq!(e.to_string(), "The release's signature doesn't match it");
+        }
+    }
+
+    #[test]
+    fn rejects_an_unknown_algorithm() {
+        let minisig =
+            with_signature_bytes(HASHED_MINISIG, |bytes| bytes[..2].copy_from_slice(b"Xx"));
+
+        let e = verify(TEST_PUBLIC_KEY, &minisig, FILE).unwrap_err();
+
+        assert_eq!(e.to_string(), "The signature's algorithm isn't supported");
+    }
+}
@@ -0,0 +1,74 @@
+use log::warn;
+use serde::{Deserialize, Serialize};
+
+use crate::{export, firefly, report::RunReport, ynab::CurrencyFormat, Config};
+
+// Where an account's reconciliations are written besides its budget, e.g.
+// `SINKS = ["firefly", "export"]`. Without SINKS it's each of them that's configured.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SinkKind {
+    Firefly,
+    Export,
+}
+
+// How writing a reconciliation to a sink went, kept in the run's report
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SinkResult {
+    pub sink: SinkKind,
+    pub error: Option<String>,
+}
+
+fn configured(config: &Config, account: &str) -> Vec<SinkKind> {
+    let account_config = config.accounts.get(account);
+
+    if let Some(sinks) = account_config.and_then(|a| a.sinks.clone()) {
+        return sinks;
+    }
+
+    let mut sinks = vec![];
+    if config.firefly.is_some() && account_config.is_some_and(|a| a.firefly_account_id.is_some()) {
+        sinks.push(SinkKind::Firefly);
+    }
+    if config.export.is_some() {
+        sinks.push(SinkKind::Export);
+    }
+
+    sinks
+}
+
+// Writes the reconciliation to each of the account's sinks. YNAB's already reconciled, so one
+// failing is only warned about & doesn't stop the others.
+pub async fn write(
+    config: &Config,
+    report: &RunReport,
+    currency: Option<&CurrencyFormat>,
+) -> Vec<SinkResult> {
+    let adjustment = match report.adjustment {
+        Some(adjustment) if adjustment != 0.0 => adjustment,
+        _ => return vec![],
+    };
+
+    let mut results = vec![];
+
+    for sink in configured(config, &report.account) {
+        let result = match sink {
+            SinkKind::Firefly => firefly::mirror(config, report, adjustment).await,
+            SinkKind::Export => export::export(config, report, adjustment, currency),
+        };
+
+        if let Err(e) = &result {
+            warn!(
+                "Failed to write {} to the {:?} sink: {:#}",
+                report.account, sink, e
+            );
+        }
+
+        results.push(SinkResult {
+            sink,
+            error: result.err().map(|e| format!("{:#}", e)),
+        });
+    }
+
+    results
+}
@@ -0,0 +1,109 @@
+// An adjustment split between categories, for a balance that's more than one thing in the budget,
+// e.g. a LISA whose government bonus is tracked apart from what was paid in:
+//
+// ```toml
+// SPLIT = [
+//     { PERCENT = 20, CATEGORY_ID = "…", MEMO = "Government bonus" },
+//     { CATEGORY_ID = "…" },
+// ]
+// ```
+//
+// YNAB can't change a split's parts once it's posted, so a split adjustment is always posted as a
+// new reconciliation rather than merged into the last one.
+
+use anyhow::{anyhow, Result};
+use serde::Deserialize;
+
+use crate::{
+    currency,
+    error::ConfigInvalid,
+    reconcile::Milliunits,
+    ynab::{CurrencyFormat, SaveSubTransaction},
+};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct SplitPart {
+    // Its share of the adjustment. The one part without one takes what's left.
+    pub percent: Option<f32>,
+    pub category_id: Option<String>,
+    pub memo: Option<String>,
+}
+
+fn check(parts: &[SplitPart]) -> Result<()> {
+    let remainders = parts.iter().filter(|part| part.percent.is_none()).count();
+    let percent = parts.iter().filter_map(|part| part.percent).sum::<f32>();
+
+    let error = match remainders {
+        _ if parts.len() < 2 => Some("SPLIT needs at least two parts".to_owned()),
+        _ if parts
+            .iter()
+            .any(|part| part.percent.is_some_and(|percent| percent <= 0.0)) =>
+        {
+            Some("SPLIT's PERCENTs have to be more than 0".to_owned())
+        }
+        0 if (percent - 100.0).abs() > 0.001 => Some(format!(
+            "SPLIT's PERCENTs add up to {}, not 100, & no part takes what's left",
+            percent
+        )),
+        1 if percent >= 100.0 => Some(format!(
+            "SPLIT's PERCENTs add up to {}, leaving nothing for the part without one",
+            percent
+        )),
+        0 | 1 => None,
+        _ => Some("Only one of SPLIT's parts can be without a PERCENT".to_owned()),
+    };
+
+    match error {
+        Some(error) => Err(anyhow!(error).context(ConfigInvalid)),
+        None => Ok(()),
+    }
+}
+
+// The adjustment's parts, in the budget's currency's precision, adding up to it exactly. What
+// rounding leaves over goes to the part without a PERCENT, or the last.
+pub fn split(
+    adjustment: Milliunits,
+    parts: &[SplitPart],
+    payee_id: &str,
+    currency: Option<&CurrencyFormat>,
+) -> Result<Vec<SaveSubTransaction>> {
+    check(parts)?;
+
+    let amounts = parts
+        .iter()
+        .map(|part| {
+            part.percent.map(|percent| {
+                currency::to_milliunits(currency, adjustment as f32 / 1000.0 * percent / 100.0)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let remainder_index = amounts
+        .iter()
+        .position(Option::is_none)
+        .unwrap_or(amounts.len() - 1);
+    let remainder = adjustment
+        - amounts
+            .iter()
+            .enumerate()
+            .filter(|(i, _)| *i != remainder_index)
+            .filter_map(|(_, amount)| *amount)
+            .sum::<Milliunits>();
+
+    Ok(parts
+        .iter()
+        .zip(amounts)
+        .enumerate()
+        .map(|(i, (part, amount))| SaveSubTransaction {
+            amount: match i == remainder_index {
+                true => remainder,
+                false => amount.unwrap_or_default(),
+            },
+            payee_id: Some(payee_id.to_owned()),
+            category_id: part.category_id.clone(),
+            memo: part.memo.clone(),
+            ..Default::default()
+        })
+        .collect())
+}
@@ -0,0 +1,43 @@
+use crate::history::Fetch;
+
+// How reliable & quick a provider's been, e.g. to tell which scrapers are worth replacing with an
+// API
+#[derive(Clone, Debug)]
+pub struct FetchStats {
+    pub fetches: usize,
+    pub success_rate: f32,
+    // Of the successful fetches, since a failure's duration is mostly how long it took to time out
+    pub p50_ms: Option<i64>,
+    pub p95_ms: Option<i64>,
+}
+
+// The nearest-rank percentile of sorted durations
+fn percentile(sorted: &[i64], percent: usize) -> Option<i64> {
+    if sorted.is_empty() {
+        return None;
+    }
+
+    let rank = (sorted.len() * percent).div_ceil(100).max(1);
+
+    Some(sorted[rank - 1])
+}
+
+pub fn summarise(fetches: &[Fetch]) -> Option<FetchStats> {
+    if fetches.is_empty() {
+        return None;
+    }
+
+    let mut durations = fetches
+        .iter()
+        .filter(|fetch| fetch.succeeded)
+        .map(|fetch| fetch.duration_ms)
+        .collect::<Vec<_>>();
+    durations.sort_unstable();
+
+    Some(FetchStats {
+        fetches: fetches.len(),
+        success_rate: durations.len() as f32 / fetches.len() as f32,
+        p50_ms: percentile(&durations, 50),
+        p95_ms: percentile(&durations, 95),
+    })
+}
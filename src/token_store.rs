@@ -0,0 +1,177 @@
+use anyhow::Result;
+use chrono::prelude::*;
+use fs2::FileExt;
+use log::{info, warn};
+use secrecy::{ExposeSecret, SecretString};
+use serde::{de::DeserializeOwned, Serialize, Serializer};
+use std::fs::{File, OpenOptions};
+use std::io::{ErrorKind, Write};
+use std::path::Path;
+use std::time::Duration;
+
+use crate::{fd_store, permissions};
+
+static LOCK_POLL_MILLIS: u64 = 500;
+
+// A cached login that has to be renewed by logging in again once it expires, e.g. a refresh
+// token or a 90 day Open Banking consent
+pub trait Expiring {
+    // `None` when it lasts until it's revoked
+    fn expires_at(&self, written_at: DateTime<Utc>) -> Option<DateTime<Utc>>;
+}
+
+// For a cached secret's `#[serde(serialize_with)]`, since a SecretString can't be serialized, so
+// that it isn't by accident anywhere else
+pub fn serialize_secret<S: Serializer>(
+    secret: &SecretString,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(secret.expose_secret())
+}
+
+#[derive(Clone, Debug)]
+pub struct TokenStore {
+    path: String,
+}
+
+// Holds an exclusive advisory lock on the token cache until dropped
+#[derive(Debug)]
+pub struct TokenStoreGuard {
+    path: String,
+    _lock_file: File,
+}
+
+impl TokenStore {
+    pub fn new(config_path: &str, filename: &str) -> Self {
+        TokenStore {
+            path: format!("{}/{}", config_path, filename),
+        }
+    }
+
+    // The lock should be held for the whole read → refresh → write cycle, since providers like
+    // Saxo invalidate the previous refresh token as soon as it's used
+    pub async fn lock(&self) -> Result<TokenStoreGuard> {
+        let lock_file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .open(format!("{}.lock", self.path))?;
+
+        let mut waiting = false;
+
+        loop {
+            match lock_file.try_lock_exclusive() {
+                Ok(()) => break,
+                Err(e) if e.kind() == fs2::lock_contended_error().kind() => {
+                    if !waiting {
+                        info!("Waiting for another process to release {}", self.path);
+                        waiting = true;
+                    }
+                    tokio::time::sleep(Duration::from_millis(LOCK_POLL_MILLIS)).await;
+                }
+                Err(e) => return Err(e.into()),
+            }
+        }
+
+        Ok(TokenStoreGuard {
+            path: self.path.clone(),
+            _lock_file: lock_file,
+        })
+    }
+}
+
+impl TokenStoreGuard {
+    // Returns the cached token along with when it was written, falling back to the previous
+    // generation if the current one is missing or unreadable
+    pub fn read<T: DeserializeOwned>(&self) -> Result<Option<(T, DateTime<Utc>)>> {
+        if fd_store::is_enabled() {
+            return match fd_store::read(&self.path)? {
+                Some((token, written_at)) => {
+                    Ok(Some((serde_json::from_slice(&token)?, written_at)))
+                }
+                None => Ok(None),
+            };
+        }
+
+        match read_token(&self.path) {
+            Ok(Some(token)) => Ok(Some(token)),
+            result => {
+                let backup_path = format!("{}.bak", self.path);
+                match read_token(&backup_path)? {
+                    Some(token) => {
+                        warn!("Using previous token from {}", backup_path);
+                        Ok(Some(token))
+                    }
+                    None => result,
+                }
+            }
+        }
+    }
+
+    // When the cached login expires, `None` if there isn't one or it doesn't expire
+    pub fn expires_at<T: DeserializeOwned + Expiring>(&self) -> Result<Option<DateTime<Utc>>> {
+        Ok(self
+            .read::<T>()?
+            .and_then(|(token, written_at)| token.expires_at(written_at)))
+    }
+
+    // Refresh tokens are rotated on use, so losing the new one to a torn write would mean
+    // logging in again. It's written to a temp file, fsynced & atomically renamed into place,
+    // with the previous generation kept as `.bak`
+    pub fn write<T: Serialize>(&self, token: &T) -> Result<()> {
+        if fd_store::is_enabled() {
+            return fd_store::write(&self.path, serde_json::to_string(token)?.as_bytes());
+        }
+
+        let tmp_path = format!("{}.tmp", self.path);
+
+        let mut tmp_file = permissions::create_private(Path::new(&tmp_path))?;
+        tmp_file.write_all(serde_json::to_string(token)?.as_bytes())?;
+        tmp_file.sync_all()?;
+
+        // A hard link keeps the previous generation's mtime, which its expiry is derived from
+        if Path::new(&self.path).exists() {
+            let backup_path = format!("{}.bak", self.path);
+            match std::fs::remove_file(&backup_path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+            std::fs::hard_link(&self.path, backup_path)?;
+        }
+
+        std::fs::rename(&tmp_path, &self.path)?;
+
+        if let Some(dir) = Path::new(&self.path).parent() {
+            File::open(dir)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+
+    // Removes the token & its previous generation, e.g. for a session that's only used once
+    pub fn clear(&self) -> Result<()> {
+        if fd_store::is_enabled() {
+            return fd_store::remove(&self.path);
+        }
+
+        for path in [self.path.clone(), format!("{}.bak", self.path)] {
+            match std::fs::remove_file(&path) {
+                Err(e) if e.kind() != ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn read_token<T: DeserializeOwned>(path: &str) -> Result<Option<(T, DateTime<Utc>)>> {
+    let modified = match std::fs::metadata(path) {
+        Ok(stat) => stat.modified()?,
+        Err(e) if e.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+
+    let token = serde_json::from_slice::<T>(&std::fs::read(path)?)?;
+
+    Ok(Some((token, DateTime::<Utc>::from(modified))))
+}
@@ -0,0 +1,290 @@
+// The codes an authenticator app shows for a login's 2FA, from the secret it was set up with, so a
+// provider can log in without anyone reading them off a phone. The secret's either as the app shows
+// it, with RFC 6238's defaults of HMAC-SHA1, 6 digits & 30 seconds, or the `otpauth://` URI its QR
+// code holds, which can say otherwise.
+
+use anyhow::{anyhow, Context, Result};
+use chrono::{DateTime, Utc};
+use log::info;
+use openssl::{hash::MessageDigest, pkey::PKey, sign::Signer};
+use secrecy::{zeroize::Zeroizing, ExposeSecret, SecretString};
+
+static DEFAULT_PERIOD_SECS: i64 = 30;
+static DEFAULT_DIGITS: u32 = 6;
+// A code with less left than this could expire on its way to the login
+static MIN_REMAINING_SECS: i64 = 5;
+
+static BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+struct Totp {
+    key: Zeroizing<Vec<u8>>,
+    digest: MessageDigest,
+    digits: u32,
+    period_secs: i64,
+}
+
+// The secret as apps show it, e.g. `JBSW Y3DP EHPK 3PXP`, in any case & with or without padding
+fn decode_base32(secret: &str) -> Result<Zeroizing<Vec<u8>>> {
+    let mut bytes = Zeroizing::new(vec![]);
+    let mut buffer = 0u64;
+    let mut bits = 0;
+
+    for c in secret.chars().filter(|c| !c.is_whitespace() && *c != '=') {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a as char == c.to_ascii_uppercase())
+            .ok_or_else(|| anyhow!("The TOTP secret isn't base32"))?;
+
+        buffer = (buffer << 5) | value as u64;
+        bits += 5;
+
+        if bits >= 8 {
+            bits -= 8;
+            bytes.push((buffer >> bits) as u8);
+        }
+    }
+
+    Ok(bytes)
+}
+
+impl Totp {
+    fn parse(secret: &SecretString) -> Result<Self> {
+        let secret = secret.expose_secret().trim();
+
+        if !secret.starts_with("otpauth://") {
+            return Ok(Totp {
+                key: decode_base32(secret)?,
+                digest: MessageDigest::sha1(),
+                digits: DEFAULT_DIGITS,
+                period_secs: DEFAULT_PERIOD_SECS,
+            });
+        }
+
+        // Parsed without an error that could quote it
+        let uri = reqwest::Url::parse(secret).map_err(|_| anyhow!("The TOTP URI is invalid"))?;
+
+        if uri.host_str() != Some("totp") {
+            return Err(anyhow!("The OTP URI isn't for a TOTP, e.g. it's a HOTP"));
+        }
+
+        let mut totp = Totp {
+            key: Zeroizing::new(vec![]),
+            digest: MessageDigest::sha1(),
+            digits: DEFAULT_DIGITS,
+            period_secs: DEFAULT_PERIOD_SECS,
+        };
+        let mut has_secret = false;
+
+        for (name, value) in uri.query_pairs() {
+            match name.as_ref() {
+                "secret" => {
+                    totp.key = decode_base32(&value)?;
+                    has_secret = true;
+                }
+                "algorithm" => {
+                    totp.digest = match value.to_ascii_uppercase().as_str() {
+                        "SHA1" => MessageDigest::sha1(),
+                        "SHA256" => MessageDigest::sha256(),
+                        "SHA512" => MessageDigest::sha512(),
+                        algorithm => return Err(anyhow!("Unknown TOTP algorithm {}", algorithm)),
+                    }
+                }
+                "digits" => {
+                    totp.digits = value
+                        .parse()
+                        .ok()
+                        .filter(|digits| (6..=10).contains(digits))
+                        .context("The TOTP URI's digits isn't 6 to 10")?
+                }
+                "period" => {
+                    totp.period_secs = value
+                        .parse()
+                        .ok()
+                        .filter(|period_secs| *period_secs > 0)
+                        .context("The TOTP URI's period isn't a number of seconds")?
+                }
+                _ => {}
+            }
+        }
+
+        if !has_secret {
+            return Err(anyhow!("The TOTP URI has no secret"));
+        }
+
+        Ok(totp)
+    }
+
+    fn code(&self, at: DateTime<Utc>) -> Result<String> {
+        let key = PKey::hmac(&self.key)?;
+        let counter = at.timestamp().div_euclid(self.period_secs) as u64;
+
+        let mut signer = Signer::new(self.digest, &key)?;
+        signer.update(&counter.to_be_bytes())?;
+        let hmac = signer.sign_to_vec()?;
+
+        // RFC 4226's dynamic truncation
+        let offset = (hmac[hmac.len() - 1] & 0x0f) as usize;
+        let truncated = u32::from_be_bytes([
+            hmac[offset] & 0x7f,
+            hmac[offset + 1],
+            hmac[offset + 2],
+            hmac[offset + 3],
+        ]);
+
+        Ok(format!(
+            "{:0width$}",
+            truncated as u64 % 10u64.pow(self.digits),
+            width = self.digits as usize
+        ))
+    }
+}
+
+pub fn code(secret: &SecretString, at: DateTime<Utc>) -> Result<String> {
+    Totp::parse(secret)?.code(at)
+}
+
+// The current code for a login, waiting for the next one if it's about to expire
+pub async fn fresh_code(secret: &SecretString) -> Result<String> {
+    let totp = Totp::parse(secret)?;
+
+    let now = Utc::now();
+    let remaining_secs = totp.period_secs - now.timestamp().rem_euclid(totp.period_secs);
+
+    if remaining_secs >= MIN_REMAINING_SECS.min(totp.period_secs) {
+        return totp.code(now);
+    }
+
+    info!("Waiting {}s for the next TOTP code", remaining_secs);
+
+    tokio::time::sleep(std::time::Duration::from_secs(remaining_secs as u64)).await;
+
+    totp.code(Utc::now())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // RFC 6238's keys, as base32: "12345678901234567890" repeated to each hash's length
+    static SHA1_KEY: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+    static SHA256_KEY: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZA====";
+    static SHA512_KEY: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQGEZDGNA=";
+
+    fn secret(secret: &str) -> SecretString {
+        SecretString::from(secret.to_owned())
+    }
+
+    fn at(timestamp: i64) -> DateTime<Utc> {
+        DateTime::from_timestamp(timestamp, 0).unwrap()
+    }
+
+    #[test]
+    fn matches_rfc_6238s_test_vectors() {
+        let vectors = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+
+        for (timestamp, sha1, sha256, sha512) in vectors {
+            for (algorithm, key, expected) in [
+                ("SHA1", SHA1_KEY, sha1),
+                ("SHA256", SHA256_KEY, sha256),
+                ("SHA512", SHA512_KEY, sha512),
+            ] {
+                let uri = format!(
+                    "otpauth://totp/Test?secret={}&algorithm={}&digits=8",
+                    key, algorithm
+                );
+
+                assert_eq!(
+                    code(&secret(&uri), at(timestamp)).unwrap(),
+                    expected,
+                    "{} at {}",
+                    algorithm,
+                    timestamp
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn decodes_base32_as_apps_show_it() {
+        for encoded in [
+            "JBSWY3DPEHPK3PXP",
+            "jbswy3dpehpk3pxp",
+            "JBSW Y3DP EHPK 3PXP",
+        ] {
+            assert_eq!(
+                decode_base32(encoded).unwrap().as_slice(),
+                b"Hello!\xde\xad\xbe\xef",
+                "{}",
+                encoded
+            );
+        }
+
+        for encoded in ["MZXW6===", "MZXW6", "mzxw6"] {
+            assert_eq!(decode_base32(encoded).unwrap().as_slice(), b"foo");
+        }
+
+        assert_eq!(decode_base32("MZXW6YQ=").unwrap().as_slice(), b"foob");
+        assert!(decode_base32("MZXW1").is_err());
+    }
+
+    #[test]
+    fn defaults_a_bare_secret_to_sha1_6_digits_every_30s() {
+        assert_eq!(code(&secret(SHA1_KEY), at(59)).unwrap(), "287082");
+        assert_eq!(
+            code(&secret(&SHA1_KEY.to_lowercase()), at(59)).unwrap(),
+            "287082"
+        );
+        assert_eq!(
+            code(
+                &secret(&format!("otpauth://totp/Test?secret={}", SHA1_KEY)),
+                at(59)
+            )
+            .unwrap(),
+            "287082"
+        );
+    }
+
+    #[test]
+    fn takes_the_uris_digits_and_period() {
+        let uri = |params: &str| {
+            secret(&format!(
+                "otpauth://totp/Test?secret={}&{}",
+                SHA1_KEY, params
+            ))
+        };
+
+        assert_eq!(code(&uri("digits=7"), at(59)).unwrap(), "4287082");
+        assert_eq!(code(&uri("digits=8"), at(59)).unwrap(), "94287082");
+
+        // 60s periods count half as fast as 30s ones
+        assert_eq!(
+            code(&uri("period=60&digits=8"), at(119)).unwrap(),
+            code(&uri("digits=8"), at(59)).unwrap()
+        );
+        assert_ne!(
+            code(&uri("period=60"), at(60)).unwrap(),
+            code(&uri("period=60"), at(59)).unwrap()
+        );
+    }
+
+    #[test]
+    fn rejects_an_invalid_uri() {
+        for uri in [
+            format!("otpauth://hotp/Test?secret={}&counter=1", SHA1_KEY),
+            "otpauth://totp/Test?digits=8".to_owned(),
+            format!("otpauth://totp/Test?secret={}&algorithm=MD5", SHA1_KEY),
+            format!("otpauth://totp/Test?secret={}&digits=4", SHA1_KEY),
+            format!("otpauth://totp/Test?secret={}&period=0", SHA1_KEY),
+            "otpauth://totp/Test?secret=not-base32".to_owned(),
+        ] {
+            assert!(code(&secret(&uri), at(59)).is_err(), "{}", uri);
+        }
+    }
+}
@@ -0,0 +1,219 @@
+use anyhow::{anyhow, Result};
+use log::info;
+use reqwest::header;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{env, fs, sync::Mutex};
+
+// Response fields holding secrets, replaced when they're recorded
+static SCRUBBED_FIELDS: [&str; 3] = ["access_token", "refresh_token", "client_secret"];
+
+static CASSETTE: Mutex<Option<Cassette>> = Mutex::new(None);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Record,
+    Replay,
+}
+
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct Interactions {
+    interactions: Vec<Interaction>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+struct Interaction {
+    method: String,
+    url: String,
+    status: u16,
+    content_type: Option<String>,
+    body: String,
+}
+
+#[derive(Debug)]
+struct Cassette {
+    path: String,
+    mode: Mode,
+    interactions: Interactions,
+    // Replayed interactions are used up, so repeated requests get the responses in the order
+    // they were recorded
+    replayed: Vec<bool>,
+}
+
+// The cassette named by YNAB_CASSETTE, if any. It's recorded to when YNAB_CASSETTE_MODE is
+// `record`, otherwise replayed from
+fn cassette() -> Result<Option<(String, Mode)>> {
+    let path = match env::var("YNAB_CASSETTE") {
+        Ok(path) => path,
+        Err(_) => return Ok(None),
+    };
+
+    let mode = match env::var("YNAB_CASSETTE_MODE").as_deref() {
+        Ok("record") => Mode::Record,
+        Ok("replay") | Err(_) => Mode::Replay,
+        Ok(mode) => return Err(anyhow!("Unknown YNAB_CASSETTE_MODE {}", mode)),
+    };
+
+    Ok(Some((path, mode)))
+}
+
+fn with_cassette<T>(f: impl FnOnce(&mut Cassette) -> Result<T>) -> Result<Option<T>> {
+    let (path, mode) = match cassette()? {
+        Some(cassette) => cassette,
+        None => return Ok(None),
+    };
+
+    let mut cassette = CASSETTE
+        .lock()
+        .map_err(|_| anyhow!("Cassette lock poisoned"))?;
+
+    if cassette.is_none() {
+        let interactions = match mode {
+            Mode::Replay => serde_json::from_str(&fs::read_to_string(&path)?)?,
+            Mode::Record => Interactions::default(),
+        };
+        info!("Using cassette {} in {:?} mode", path, mode);
+        *cassette = Some(Cassette {
+            replayed: vec![false; interactions.interactions.len()],
+            path,
+            mode,
+            interactions,
+        });
+    }
+
+    f(cassette.as_mut().unwrap()).map(Some)
+}
+
+// Query parameters can carry auth codes & tokens too
+fn scrub_url(url: &reqwest::Url) -> String {
+    let mut url = url.clone();
+    let pairs = url
+        .query_pairs()
+        .map(|(key, value)| match key.as_ref() {
+            "code" | "state" | "access_token" | "refresh_token" => {
+                (key.into_owned(), "REDACTED".to_owned())
+            }
+            _ => (key.into_owned(), value.into_owned()),
+        })
+        .collect::<Vec<_>>();
+
+    if !pairs.is_empty() {
+        url.query_pairs_mut().clear().extend_pairs(pairs);
+    }
+
+    url.to_string()
+}
+
+fn scrub_value(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            for (key, value) in map.iter_mut() {
+                if SCRUBBED_FIELDS.contains(&key.as_str()) {
+                    *value = Value::String("REDACTED".to_owned());
+                } else {
+                    scrub_value(value);
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(scrub_value),
+        _ => {}
+    }
+}
+
+// Only JSON bodies are scrubbed. Check a cassette for anything else personal, e.g. account names,
+// before checking it in.
+fn scrub_body(body: &str) -> String {
+    match serde_json::from_str::<Value>(body) {
+        Ok(mut value) => {
+            scrub_value(&mut value);
+            serde_json::to_string_pretty(&value).unwrap_or_else(|_| body.to_owned())
+        }
+        Err(_) => body.to_owned(),
+    }
+}
+
+fn to_response(interaction: &Interaction) -> Result<reqwest::Response> {
+    let mut response = ::http::Response::builder().status(interaction.status);
+    if let Some(content_type) = &interaction.content_type {
+        response = response.header(header::CONTENT_TYPE, content_type);
+    }
+
+    Ok(response.body(interaction.body.clone())?.into())
+}
+
+// The recorded response to a request, or `None` when it should be sent for real
+pub fn replay(request: &reqwest::Request) -> Result<Option<reqwest::Response>> {
+    let method = request.method().to_string();
+    let url = scrub_url(request.url());
+
+    let replayed = with_cassette(|cassette| {
+        if cassette.mode == Mode::Record {
+            return Ok(None);
+        }
+
+        let index = cassette
+            .interactions
+            .interactions
+            .iter()
+            .enumerate()
+            .position(|(i, interaction)| {
+                !cassette.replayed[i] && interaction.method == method && interaction.url == url
+            })
+            .ok_or_else(|| anyhow!("No recorded response to {} {}", method, url))?;
+
+        cassette.replayed[index] = true;
+
+        Ok(Some(to_response(
+            &cassette.interactions.interactions[index],
+        )?))
+    })?;
+
+    Ok(replayed.flatten())
+}
+
+// Records the response to a request when recording, handing back an equivalent response since
+// its body has been read
+pub async fn record(
+    request: (String, reqwest::Url),
+    response: reqwest::Response,
+) -> Result<reqwest::Response> {
+    if !matches!(cassette()?, Some((_, Mode::Record))) {
+        return Ok(response);
+    }
+
+    let status = response.status();
+    let content_type = response
+        .headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    let body = response.text().await?;
+
+    let (method, url) = request;
+
+    with_cassette(|cassette| {
+        cassette.interactions.interactions.push(Interaction {
+            method,
+            url: scrub_url(&url),
+            status: status.as_u16(),
+            content_type: content_type.clone(),
+            body: scrub_body(&body),
+        });
+
+        // Written after each request so a failed run still leaves what it recorded
+        fs::write(
+            &cassette.path,
+            serde_json::to_string_pretty(&cassette.interactions)?,
+        )?;
+
+        Ok(())
+    })?;
+
+    to_response(&Interaction {
+        method: String::new(),
+        url: String::new(),
+        status: status.as_u16(),
+        content_type,
+        body,
+    })
+}
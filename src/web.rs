@@ -0,0 +1,573 @@
+use anyhow::Result;
+use axum::{
+    extract::{ConnectInfo, Form, Json, Path, State},
+    http::{header::AUTHORIZATION, HeaderMap, Request, StatusCode},
+    middleware::{self, Next},
+    response::{Html, Response},
+    routing::{get, post},
+    Router,
+};
+use chrono::{DateTime, Utc};
+use log::{error, info, warn};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use std::{net::SocketAddr, sync::Arc};
+use tokio::sync::{mpsc, watch};
+
+use crate::{
+    access::AccessConfig,
+    digest::escape,
+    history::{History, PendingApproval, PendingManualLogin, PendingOtp, PushedBalance},
+    manual_login::{self, ManualSession},
+    otp_relay::{self, OtpCode},
+    providers::{push::PushConfig, ProviderKind},
+    User,
+};
+#[cfg(feature = "property")]
+use {
+    crate::{amount, providers::property::PropertyConfig},
+    axum::extract::Query,
+};
+
+#[derive(Clone, Debug, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub struct WebUiConfig {
+    pub listen_addr: String,
+    // Where the UI is reached from the device that gets the notifications, e.g. over the tailnet
+    pub url: String,
+    #[serde(flatten)]
+    pub access: AccessConfig,
+}
+
+// A push account whose balance was just received, for the daemon to reconcile
+#[derive(Clone, Debug)]
+pub struct PushedAccount {
+    pub user: String,
+    pub account: String,
+}
+
+#[derive(Clone)]
+struct AppState {
+    users: Arc<Vec<User>>,
+    pushed: mpsc::UnboundedSender<PushedAccount>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct LoginForm {
+    cookies: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct OtpForm {
+    code: SecretString,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct ApprovalForm {
+    decision: String,
+}
+
+#[cfg(feature = "property")]
+#[derive(Clone, Debug, Deserialize)]
+struct ValueForm {
+    // Empty to clear it
+    value: String,
+}
+
+#[cfg(feature = "property")]
+#[derive(Clone, Debug, Deserialize)]
+struct ValueQuery {
+    token: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+struct BalancePayload {
+    balance: f32,
+    currency: Option<String>,
+    as_of: Option<DateTime<Utc>>,
+}
+
+pub async fn serve(
+    config: WebUiConfig,
+    users: Vec<User>,
+    pushed: mpsc::UnboundedSender<PushedAccount>,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let routes = Router::new()
+        .route("/login/:state", get(login_page).post(submit_login))
+        .route("/otp/:state", get(otp_page).post(submit_otp))
+        .route("/approve/:state", get(approval_page).post(submit_approval))
+        .route("/balances/:account", post(submit_balance));
+
+    #[cfg(feature = "property")]
+    let routes = routes.route("/value/:user/:account", get(value_page).post(submit_value));
+
+    let prefix = config.access.path_prefix();
+    let app = match prefix.as_str() {
+        "" => routes,
+        prefix => Router::new().nest(prefix, routes),
+    }
+    .layer(middleware::from_fn_with_state(
+        Arc::new(config.access.clone()),
+        allow_client,
+    ))
+    .with_state(AppState {
+        users: Arc::new(users),
+        pushed,
+    });
+
+    let addr = config.listen_addr.parse::<SocketAddr>()?;
+
+    info!("Serving the web UI on {}", addr);
+
+    axum::Server::bind(&addr)
+        .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+        .with_graceful_shutdown(async move {
+            let _ = shutdown.changed().await;
+        })
+        .await?;
+
+    Ok(())
+}
+
+async fn allow_client<B>(
+    State(access): State<Arc<AccessConfig>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Result<Response, StatusCode> {
+    if !access.allows(addr.ip()) {
+        warn!("Rejected a web UI request from {}", addr);
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    Ok(next.run(request).await)
+}
+
+// The state in the link is the only thing identifying the login, so it's looked up across every
+// user's history
+fn find_pending_manual_login(
+    users: &[User],
+    state: &str,
+) -> Result<Option<(User, PendingManualLogin)>> {
+    for user in users {
+        if let Some(pending_manual_login) =
+            History::open(&user.config.config_path)?.find_pending_manual_login(state)?
+        {
+            return Ok(Some((user.clone(), pending_manual_login)));
+        }
+    }
+
+    Ok(None)
+}
+
+fn find_pending_otp(users: &[User], state: &str) -> Result<Option<(User, PendingOtp)>> {
+    for user in users {
+        if let Some(pending_otp) =
+            History::open(&user.config.config_path)?.find_pending_otp(state)?
+        {
+            return Ok(Some((user.clone(), pending_otp)));
+        }
+    }
+
+    Ok(None)
+}
+
+fn find_pending_approval(users: &[User], state: &str) -> Result<Option<(User, PendingApproval)>> {
+    for user in users {
+        if let Some(pending_approval) =
+            History::open(&user.config.config_path)?.find_pending_approval(state)?
+        {
+            return Ok(Some((user.clone(), pending_approval)));
+        }
+    }
+
+    Ok(None)
+}
+
+// Only property accounts have values set by hand, & only with their VALUE_TOKEN, since the page
+// sets what's reconciled into YNAB. Without one the value's only set with `set-value`.
+#[cfg(feature = "property")]
+fn find_property_account(
+    users: &[User],
+    user: &str,
+    account: &str,
+    token: Option<&str>,
+) -> Result<User, StatusCode> {
+    let user = users
+        .iter()
+        .find(|u| u.name == user)
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let property_config = match user.config.accounts.get(account) {
+        Some(account_config) if account_config.provider == ProviderKind::Property => account_config
+            .provider_config::<PropertyConfig>()
+            .map_err(internal_error)?,
+        _ => return Err(StatusCode::NOT_FOUND),
+    };
+
+    match (token, &property_config.value_token) {
+        (Some(token), Some(value_token)) if tokens_equal(token, value_token.expose_secret()) => {
+            Ok(user.clone())
+        }
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+// Push accounts are only named in the URL, so the token picks out whose it is. Any user's push
+// account of that name is `UNAUTHORIZED` with the wrong token, rather than `NOT_FOUND`.
+fn find_push_account(
+    users: &[User],
+    account: &str,
+    token: Option<&str>,
+) -> Result<User, StatusCode> {
+    let mut found = false;
+
+    for user in users {
+        let push_config = match user.config.accounts.get(account) {
+            Some(account_config) if account_config.provider == ProviderKind::Push => account_config
+                .provider_config::<PushConfig>()
+                .map_err(internal_error)?,
+            _ => continue,
+        };
+
+        found = true;
+
+        if token.is_some_and(|token| tokens_equal(token, push_config.token.expose_secret())) {
+            return Ok(user.clone());
+        }
+    }
+
+    Err(if found {
+        StatusCode::UNAUTHORIZED
+    } else {
+        StatusCode::NOT_FOUND
+    })
+}
+
+// Compares every byte, so how long it takes doesn't give away how much of a guess was right
+fn tokens_equal(a: &str, b: &str) -> bool {
+    a.len() == b.len()
+        && a.bytes()
+            .zip(b.bytes())
+            .fold(0, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
+
+fn internal_error(e: anyhow::Error) -> StatusCode {
+    error!("Web UI request failed: {:#?}", e);
+    StatusCode::INTERNAL_SERVER_ERROR
+}
+
+// The provider's cookies can't be read from another origin, so once logged in the user pastes
+// the `Cookie` header their browser sent, from its developer tools
+async fn login_page(
+    State(app): State<AppState>,
+    Path(state): Path<String>,
+) -> Result<Html<String>, StatusCode> {
+    let (_, pending_manual_login) = find_pending_manual_login(&app.users, &state)
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let title = escape(&pending_manual_login.title);
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta name="viewport" content="width=device-width, initial-scale=1"><title>Log in to {title}</title></head>
+<body>
+<h1>Log in to {title}</h1>
+<ol>
+<li>Open <a href="{login_uri}" target="_blank" rel="noopener noreferrer">{title}'s login page</a> & log in, completing the captcha.</li>
+<li>From the browser's developer tools, copy the <code>Cookie</code> header of a request made once logged in & paste it below.</li>
+</ol>
+<form method="post">
+<textarea name="cookies" rows="8" cols="60" required></textarea>
+<p><button type="submit">Continue</button></p>
+</form>
+</body>
+</html>"#,
+        title = title,
+        login_uri = escape(&pending_manual_login.login_uri),
+    )))
+}
+
+async fn submit_login(
+    State(app): State<AppState>,
+    Path(state): Path<String>,
+    Form(form): Form<LoginForm>,
+) -> Result<Html<String>, StatusCode> {
+    let (user, pending_manual_login) = find_pending_manual_login(&app.users, &state)
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let session = ManualSession {
+        cookies: form.cookies.trim().to_owned(),
+    };
+
+    manual_login::save_session(
+        &user.config.config_path,
+        &pending_manual_login.provider,
+        &session,
+    )
+    .await
+    .map_err(internal_error)?;
+
+    History::open(&user.config.config_path)
+        .and_then(|history| history.clear_pending_manual_login(&pending_manual_login.provider))
+        .map_err(internal_error)?;
+
+    info!(
+        "Got {}'s {} session from the web UI",
+        user.name, pending_manual_login.provider
+    );
+
+    Ok(Html(format!(
+        "<!DOCTYPE html><html><body><p>Thanks, {} will carry on from here.</p></body></html>",
+        escape(&pending_manual_login.title)
+    )))
+}
+
+async fn otp_page(
+    State(app): State<AppState>,
+    Path(state): Path<String>,
+) -> Result<Html<String>, StatusCode> {
+    let (_, pending_otp) = find_pending_otp(&app.users, &state)
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let title = escape(&pending_otp.title);
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta name="viewport" content="width=device-width, initial-scale=1"><title>{title}'s code</title></head>
+<body>
+<h1>{title}'s code</h1>
+<p>{prompt}</p>
+<form method="post">
+<input name="code" autocomplete="one-time-code" inputmode="numeric" required autofocus>
+<p><button type="submit">Continue</button></p>
+</form>
+</body>
+</html>"#,
+        title = title,
+        prompt = escape(&pending_otp.prompt),
+    )))
+}
+
+async fn submit_otp(
+    State(app): State<AppState>,
+    Path(state): Path<String>,
+    Form(form): Form<OtpForm>,
+) -> Result<Html<String>, StatusCode> {
+    let (user, pending_otp) = find_pending_otp(&app.users, &state)
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let code = OtpCode {
+        code: form.code.expose_secret().trim().to_owned().into(),
+    };
+
+    otp_relay::save_code(&user.config.config_path, &pending_otp.provider, &code)
+        .await
+        .map_err(internal_error)?;
+
+    // The login clears it once it's taken the code, but the link's done with either way
+    History::open(&user.config.config_path)
+        .and_then(|history| history.clear_pending_otp(&pending_otp.provider))
+        .map_err(internal_error)?;
+
+    info!(
+        "Got {}'s {} code from the web UI",
+        user.name, pending_otp.provider
+    );
+
+    Ok(Html(format!(
+        "<!DOCTYPE html><html><body><p>Thanks, {} will carry on from here.</p></body></html>",
+        escape(&pending_otp.title)
+    )))
+}
+
+async fn approval_page(
+    State(app): State<AppState>,
+    Path(state): Path<String>,
+) -> Result<Html<String>, StatusCode> {
+    let (_, pending_approval) = find_pending_approval(&app.users, &state)
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let provider = escape(&pending_approval.provider);
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta name="viewport" content="width=device-width, initial-scale=1"><title>Approve {provider}'s balance</title></head>
+<body>
+<h1>Approve {provider}'s balance</h1>
+<p>It moved from {previous:.2} to {balance:.2} since it was last fetched. Approve it to reconcile YNAB to it, or reject it if it looks wrong.</p>
+<form method="post">
+<button type="submit" name="decision" value="approve">Approve</button>
+<button type="submit" name="decision" value="reject">Reject</button>
+</form>
+</body>
+</html>"#,
+        provider = provider,
+        previous = pending_approval.previous_balance,
+        balance = pending_approval.balance,
+    )))
+}
+
+async fn submit_approval(
+    State(app): State<AppState>,
+    Path(state): Path<String>,
+    Form(form): Form<ApprovalForm>,
+) -> Result<Html<String>, StatusCode> {
+    let (user, pending_approval) = find_pending_approval(&app.users, &state)
+        .map_err(internal_error)?
+        .ok_or(StatusCode::NOT_FOUND)?;
+
+    let approved = match form.decision.as_str() {
+        "approve" => true,
+        "reject" => false,
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+
+    History::open(&user.config.config_path)
+        .and_then(|history| {
+            history.set_pending_approval(&PendingApproval {
+                approved: Some(approved),
+                ..pending_approval.clone()
+            })
+        })
+        .map_err(internal_error)?;
+
+    info!(
+        "{}'s {} balance was {} from the web UI",
+        user.name,
+        pending_approval.provider,
+        if approved { "approved" } else { "rejected" }
+    );
+
+    Ok(Html(format!(
+        "<!DOCTYPE html><html><body><p>Thanks, {} will carry on from here.</p></body></html>",
+        escape(&pending_approval.provider)
+    )))
+}
+
+#[cfg(feature = "property")]
+async fn value_page(
+    State(app): State<AppState>,
+    Path((user, account)): Path<(String, String)>,
+    Query(query): Query<ValueQuery>,
+) -> Result<Html<String>, StatusCode> {
+    let user = find_property_account(&app.users, &user, &account, query.token.as_deref())?;
+
+    let manual_value = History::open(&user.config.config_path)
+        .and_then(|history| history.get_manual_value(&account))
+        .map_err(internal_error)?;
+
+    let current = match manual_value {
+        Some((value, set_at)) => format!(
+            "It's {:.2}, set at {}.",
+            value,
+            set_at.format("%Y-%m-%d %H:%M")
+        ),
+        None => "It isn't set, so it follows its index.".to_owned(),
+    };
+
+    let account = escape(&account);
+
+    Ok(Html(format!(
+        r#"<!DOCTYPE html>
+<html>
+<head><meta name="viewport" content="width=device-width, initial-scale=1"><title>Set {account}'s value</title></head>
+<body>
+<h1>Set {account}'s value</h1>
+<p>{current} It's reconciled on the account's next run. Leave it empty to go back to the index.</p>
+<form method="post">
+<input name="value" inputmode="decimal" value="{value}">
+<p><button type="submit">Save</button></p>
+</form>
+</body>
+</html>"#,
+        account = account,
+        current = current,
+        value = manual_value
+            .map(|(value, _)| format!("{:.2}", value))
+            .unwrap_or_default(),
+    )))
+}
+
+// The form's posted back to the page's URL, token & all
+#[cfg(feature = "property")]
+async fn submit_value(
+    State(app): State<AppState>,
+    Path((user, account)): Path<(String, String)>,
+    Query(query): Query<ValueQuery>,
+    Form(form): Form<ValueForm>,
+) -> Result<Html<String>, StatusCode> {
+    let user = find_property_account(&app.users, &user, &account, query.token.as_deref())?;
+
+    let value = match form.value.trim() {
+        "" => None,
+        value => Some(amount::parse(value).map_err(|_| StatusCode::BAD_REQUEST)?),
+    };
+
+    History::open(&user.config.config_path)
+        .and_then(|history| history.set_manual_value(&account, value))
+        .map_err(internal_error)?;
+
+    info!("{}'s {} value was set from the web UI", user.name, account);
+
+    Ok(Html(format!(
+        "<!DOCTYPE html><html><body><p>Thanks, {}'s value is saved.</p></body></html>",
+        escape(&account)
+    )))
+}
+
+async fn submit_balance(
+    State(app): State<AppState>,
+    Path(account): Path<String>,
+    headers: HeaderMap,
+    Json(payload): Json<BalancePayload>,
+) -> Result<StatusCode, StatusCode> {
+    let token = headers
+        .get(AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+
+    let user = find_push_account(&app.users, &account, token)?;
+
+    if !payload.balance.is_finite() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+
+    History::open(&user.config.config_path)
+        .and_then(|history| {
+            history.set_pushed_balance(&PushedBalance {
+                provider: account.clone(),
+                balance: payload.balance,
+                currency: payload.currency,
+                as_of: payload.as_of,
+                received_at: Utc::now(),
+            })
+        })
+        .map_err(internal_error)?;
+
+    info!("{}'s {} balance was pushed", user.name, account);
+
+    // Shutting down, so it's left for the account's next run
+    if app
+        .pushed
+        .send(PushedAccount {
+            user: user.name.clone(),
+            account,
+        })
+        .is_err()
+    {
+        warn!("Not reconciling the pushed balance while shutting down");
+    }
+
+    Ok(StatusCode::ACCEPTED)
+}
@@ -0,0 +1,402 @@
+// YNAB's API, on top of the types build.rs generates from its OpenAPI spec in
+// `ynab/open_api_spec.yaml`, named as they are in the spec. A new endpoint's types come from
+// copying its schemas into the spec from https://api.ynab.com/papi/open_api_spec.yaml. Nullable
+// & optional fields are `Option`s, and what the spec doesn't have is kept in `other` so nothing
+// is lost when YNAB adds fields.
+
+use anyhow::{anyhow, Result};
+use chrono::NaiveDate;
+use log::{debug, info};
+use reqwest::{Method, StatusCode};
+use secrecy::{ExposeSecret, SecretString};
+use std::time::Instant;
+
+use crate::{
+    budget::BudgetSink,
+    error::{YnabError, YnabResponseExt},
+    http,
+    rate_limit::RateLimiter,
+    Config,
+};
+
+include!(concat!(env!("OUT_DIR"), "/ynab_types.rs"));
+
+static YNAB_API_URL: &str = "https://api.ynab.com/v1";
+
+#[derive(Clone, Debug)]
+pub struct YnabClient {
+    client: reqwest::Client,
+    api_url: String,
+    bearer_token: SecretString,
+    budget_id: String,
+    limiter: RateLimiter,
+}
+
+impl YnabClient {
+    pub fn new(config: &Config, bearer_token: &SecretString) -> Result<Self> {
+        Ok(YnabClient {
+            client: http::client(&config.http)?,
+            api_url: config
+                .ynab_api_url
+                .clone()
+                .unwrap_or_else(|| YNAB_API_URL.to_owned()),
+            bearer_token: bearer_token.clone(),
+            budget_id: config.ynab_budget_id.clone(),
+            limiter: RateLimiter::shared(bearer_token, &config.ynab_rate_limit)?,
+        })
+    }
+
+    // The client is shared with the rest of the run, so the token's added to each request
+    fn request(&self, method: Method, url: String) -> reqwest::RequestBuilder {
+        self.client
+            .request(method, url)
+            .bearer_auth(self.bearer_token.expose_secret())
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> Result<reqwest::Response> {
+        self.limiter.acquire().await?;
+
+        http::send(&self.client, request).await
+    }
+
+    // Only YNAB has goals, so it's not part of `BudgetSink`
+    pub async fn get_category(&self, category_id: &str) -> Result<Category> {
+        let category = self
+            .send(self.request(
+                Method::GET,
+                format!(
+                    "{}/budgets/{}/categories/{}",
+                    self.api_url, self.budget_id, category_id
+                ),
+            ))
+            .await?
+            .ynab_error_for_status()
+            .await?
+            .json::<CategoryResponse>()
+            .await?
+            .data
+            .category;
+
+        Ok(category)
+    }
+}
+
+impl BudgetSink for YnabClient {
+    async fn get_account(&self, account_id: &str) -> Result<Option<Account>> {
+        let response = self
+            .send(self.request(
+                Method::GET,
+                format!(
+                    "{}/budgets/{}/accounts/{}",
+                    self.api_url, self.budget_id, account_id
+                ),
+            ))
+            .await?
+            .ynab_error_for_status()
+            .await;
+
+        match response {
+            Ok(response) => Ok(Some(response.json::<AccountResponse>().await?.data.account)),
+            Err(e)
+                if e.downcast_ref::<YnabError>()
+                    .is_some_and(|e| e.status == StatusCode::NOT_FOUND) =>
+            {
+                Ok(None)
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    async fn get_budget_settings(&self) -> Result<BudgetSettings> {
+        let settings = self
+            .send(self.request(
+                Method::GET,
+                format!("{}/budgets/{}/settings", self.api_url, self.budget_id),
+            ))
+            .await?
+            .ynab_error_for_status()
+            .await?
+            .json::<BudgetSettingsResponse>()
+            .await?
+            .data
+            .settings;
+
+        Ok(settings)
+    }
+
+    // Only the transactions on or after `since_date`, when the older ones aren't needed, since an
+    // account's whole history is most of a run's download
+    async fn get_transactions(
+        &self,
+        account_id: &str,
+        since_date: Option<NaiveDate>,
+    ) -> Result<Vec<TransactionDetail>> {
+        let mut request = self.request(
+            Method::GET,
+            format!(
+                "{}/budgets/{}/accounts/{}/transactions",
+                self.api_url, self.budget_id, account_id
+            ),
+        );
+        if let Some(since_date) = since_date {
+            request = request.query(&[("since_date", since_date.to_string())]);
+        }
+
+        let body = self
+            .send(request)
+            .await?
+            .ynab_error_for_status()
+            .await?
+            .bytes()
+            .await?;
+
+        let started = Instant::now();
+
+        let transactions = serde_json::from_slice::<TransactionsResponse>(&body)?
+            .data
+            .transactions;
+
+        debug!(
+            "Read {} transactions from {} bytes in {:?}",
+            transactions.len(),
+            body.len(),
+            started.elapsed()
+        );
+
+        Ok(transactions)
+    }
+
+    async fn update_transaction(
+        &self,
+        transaction_id: &str,
+        transaction: &ExistingTransaction,
+    ) -> Result<()> {
+        let response = self
+            .send(
+                self.request(
+                    Method::PUT,
+                    format!(
+                        "{}/budgets/{}/transactions/{}",
+                        self.api_url, self.budget_id, transaction_id
+                    ),
+                )
+                .json(&PutTransactionWrapper {
+                    transaction: transaction.clone(),
+                    ..Default::default()
+                }),
+            )
+            .await?
+            .ynab_error_for_status()
+            .await?;
+
+        info!("PUT response {:#?}", response.status());
+
+        Ok(())
+    }
+
+    async fn update_transactions(
+        &self,
+        transactions: &[SaveTransactionWithIdOrImportId],
+    ) -> Result<()> {
+        let response = self
+            .send(
+                self.request(
+                    Method::PATCH,
+                    format!("{}/budgets/{}/transactions", self.api_url, self.budget_id),
+                )
+                .json(&PatchTransactionsWrapper {
+                    transactions: transactions.to_vec(),
+                    ..Default::default()
+                }),
+            )
+            .await?
+            .ynab_error_for_status()
+            .await?;
+
+        info!("PATCH response {:#?}", response.status());
+
+        Ok(())
+    }
+
+    async fn create_transaction(&self, transaction: &NewTransaction) -> Result<String> {
+        let response = self
+            .send(
+                self.request(
+                    Method::POST,
+                    format!("{}/budgets/{}/transactions", self.api_url, self.budget_id),
+                )
+                .json(&PostTransactionsWrapper {
+                    transaction: Some(transaction.clone()),
+                    ..Default::default()
+                }),
+            )
+            .await?
+            .ynab_error_for_status()
+            .await?;
+
+        info!("POST response {:#?}", response.status());
+
+        response
+            .json::<SaveTransactionsResponse>()
+            .await?
+            .data
+            .transaction_ids
+            .into_iter()
+            .next()
+            .ok_or_else(|| anyhow!("YNAB didn't return the created transaction's id"))
+    }
+
+    async fn delete_transaction(&self, transaction_id: &str) -> Result<()> {
+        let response = self
+            .send(self.request(
+                Method::DELETE,
+                format!(
+                    "{}/budgets/{}/transactions/{}",
+                    self.api_url, self.budget_id, transaction_id
+                ),
+            ))
+            .await?
+            .ynab_error_for_status()
+            .await?;
+
+        info!("DELETE response {:#?}", response.status());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::{json, Value};
+
+    // Shaped like the examples in YNAB's OpenAPI spec
+    static ACCOUNT: &str = include_str!("../fixtures/ynab/account.json");
+    static NEW_ACCOUNT: &str = include_str!("../fixtures/ynab/account_new.json");
+    static TRANSACTIONS: &str = include_str!("../fixtures/ynab/transactions.json");
+    static SETTINGS: &str = include_str!("../fixtures/ynab/settings.json");
+    static CATEGORY: &str = include_str!("../fixtures/ynab/category.json");
+
+    fn account(json: &str) -> Account {
+        serde_json::from_str::<AccountResponse>(json)
+            .unwrap()
+            .data
+            .account
+    }
+
+    #[test]
+    fn deserializes_an_account() {
+        let account = account(ACCOUNT);
+
+        assert_eq!(account.name, "Stocks & Shares ISA");
+        assert_eq!(account.balance, 12345670);
+        assert_eq!(account.cleared_balance, 12345670);
+        assert_eq!(account.uncleared_balance, 0);
+        assert_eq!(
+            account.last_reconciled_at.as_deref(),
+            Some("2026-10-01T06:00:12.000Z")
+        );
+        assert!(!account.closed);
+        assert!(!account.deleted);
+        assert_eq!(account.r#type, AccountType::OtherAsset);
+    }
+
+    #[test]
+    fn deserializes_a_new_account_without_a_reconciliation() {
+        let account = account(NEW_ACCOUNT);
+
+        assert_eq!(account.balance, -250000000);
+        assert_eq!(account.last_reconciled_at, None);
+        assert!(!account.closed);
+        assert_eq!(account.r#type, AccountType::Mortgage);
+        assert_eq!(account.debt_interest_rates.unwrap()["2026-01-01"], 4125);
+    }
+
+    #[test]
+    fn keeps_fields_it_doesnt_know() {
+        let mut json = serde_json::from_str::<Value>(ACCOUNT).unwrap();
+        json["data"]["account"]["future_field"] = Value::from("kept");
+
+        let account = account(&json.to_string());
+
+        assert_eq!(account.other["future_field"], "kept");
+        assert_eq!(
+            serde_json::to_value(&account).unwrap()["future_field"],
+            "kept"
+        );
+    }
+
+    #[test]
+    fn deserializes_transactions() {
+        let transactions = serde_json::from_str::<TransactionsResponse>(TRANSACTIONS)
+            .unwrap()
+            .data
+            .transactions;
+
+        assert_eq!(transactions.len(), 4);
+
+        let snapshot = &transactions[1];
+        assert_eq!(snapshot.date, NaiveDate::from_ymd_opt(2026, 9, 1).unwrap());
+        assert_eq!(snapshot.amount, 1500000);
+        assert_eq!(snapshot.cleared, TransactionClearedStatus::Reconciled);
+        assert_eq!(snapshot.flag_color, Some(TransactionFlagColor::Blue));
+        assert_eq!(
+            snapshot.import_id.as_deref(),
+            Some("YNAB-UPDATER:2026-09-01:3kq9x0ab")
+        );
+
+        // A transfer has no payee
+        let transfer = &transactions[2];
+        assert_eq!(transfer.payee_id, None);
+        assert_eq!(transfer.cleared, TransactionClearedStatus::Uncleared);
+
+        let split = &transactions[3];
+        assert_eq!(split.subtransactions.len(), 2);
+        assert_eq!(split.subtransactions[0].amount, 69134);
+    }
+
+    #[test]
+    fn deserializes_budget_settings() {
+        let settings = serde_json::from_str::<BudgetSettingsResponse>(SETTINGS)
+            .unwrap()
+            .data
+            .settings;
+
+        let currency_format = settings.currency_format.unwrap();
+        assert_eq!(currency_format.iso_code, "GBP");
+        assert_eq!(currency_format.decimal_digits, 2);
+        assert_eq!(currency_format.currency_symbol, "£");
+        assert!(currency_format.symbol_first);
+    }
+
+    #[test]
+    fn deserializes_a_category_with_a_goal() {
+        let category = serde_json::from_str::<CategoryResponse>(CATEGORY)
+            .unwrap()
+            .data
+            .category;
+
+        assert_eq!(category.name, "House deposit");
+        assert_eq!(category.balance, 12800000);
+        assert_eq!(category.goal_type, Some(CategoryGoalType::Tb));
+        assert_eq!(category.goal_target, Some(20000000));
+        assert_eq!(category.goal_percentage_complete, Some(64));
+    }
+
+    #[test]
+    fn an_update_only_has_the_fields_that_are_set() {
+        let transaction = ExistingTransaction {
+            cleared: Some(TransactionClearedStatus::Reconciled),
+            ..Default::default()
+        };
+
+        assert_eq!(
+            serde_json::to_value(PutTransactionWrapper {
+                transaction,
+                ..Default::default()
+            })
+            .unwrap(),
+            json!({ "transaction": { "cleared": "reconciled" } })
+        );
+    }
+}
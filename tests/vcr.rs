@@ -0,0 +1,87 @@
+// Replays a run that creates a reconciliation & one that updates it, from YNAB's recorded
+// responses. Run with `cargo test --features vcr`.
+#![cfg(feature = "vcr")]
+
+use std::{env, fs, path::PathBuf};
+
+use ynab_updater::{
+    providers::mock::Mock,
+    report::{RunAction, RunReport},
+    update_ynab, Config,
+};
+
+static ACCOUNT: &str = "investments";
+
+static CONFIG: &str = r#"
+PUSHOVER_USER_KEY = "user"
+PUSHOVER_API_KEY = "token"
+YNAB_BEARER_TOKEN = "token"
+YNAB_BUDGET_ID = "7a6f1c2e-3b4d-4e5f-8a9b-0c1d2e3f4a5b"
+YNAB_RECONCILIATION_PAYEE_ID = "9e8d7c6b-5a4f-4e3d-2c1b-0a9f8e7d6c5b"
+# Fetches all of the account's transactions, so the requests don't depend on the day
+SNAPSHOT_FLAG_COLOR = "blue"
+NOTIFIER = "pushover"
+
+[accounts.investments]
+PROVIDER = "mock"
+YNAB_ACCOUNT_ID = "1b2c3d4e-5f6a-4b7c-8d9e-0f1a2b3c4d5e"
+BALANCE = 1250.0
+"#;
+
+fn config(config_path: &str) -> Config {
+    let mut config = config::Config::builder()
+        .add_source(config::File::from_str(CONFIG, config::FileFormat::Toml))
+        .build()
+        .unwrap()
+        .try_deserialize::<Config>()
+        .unwrap();
+
+    config.config_path = config_path.to_owned();
+
+    config
+}
+
+async fn run(config: &Config, balance: f32) -> RunReport {
+    let mut account_config = config.accounts[ACCOUNT].clone();
+    account_config
+        .settings
+        .insert("BALANCE".to_owned(), balance.into());
+
+    update_ynab(config, ACCOUNT, Mock::new(&account_config).unwrap())
+        .await
+        .unwrap()
+}
+
+#[tokio::test]
+async fn creates_then_updates_a_reconciliation() {
+    let config_path = env::temp_dir().join(format!("ynab-updater-vcr-{}", std::process::id()));
+    fs::create_dir_all(&config_path).unwrap();
+
+    let cassette = PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/cassettes/ynab_create_then_update.json");
+    env::set_var("YNAB_CASSETTE", cassette);
+    env::set_var("YNAB_CASSETTE_MODE", "replay");
+
+    let config = config(config_path.to_str().unwrap());
+
+    // YNAB has £1,000.00 & the last transaction is the starting balance, so it's a new one
+    let created = run(&config, 1250.0).await;
+
+    assert_eq!(created.action, RunAction::Created);
+    assert_eq!(created.ynab_balance, Some(1000.0));
+    assert_eq!(created.adjustment, Some(250.0));
+    assert_eq!(
+        created.transaction_id.as_deref(),
+        Some("4c5d6e7f-8a9b-4c0d-9e1f-2a3b4c5d6e7f")
+    );
+
+    // Now the last transaction is that reconciliation, so it's folded into
+    let updated = run(&config, 1300.0).await;
+
+    assert_eq!(updated.action, RunAction::Updated);
+    assert_eq!(updated.ynab_balance, Some(1250.0));
+    assert_eq!(updated.adjustment, Some(50.0));
+    assert_eq!(updated.transaction_id, created.transaction_id);
+
+    fs::remove_dir_all(&config_path).unwrap();
+}